@@ -125,7 +125,7 @@ async fn login(
             if !initial.load(Ordering::SeqCst) {
                 for (_room_id, room_info) in response.rooms.join {
                     for event in room_info.timeline.events {
-                        if let AnySyncRoomEvent::Message(event) = event {
+                        if let AnySyncRoomEvent::Message(event) = event.event {
                             match event {
                                 AnySyncMessageEvent::RoomMessage(m) => {
                                     if let MessageType::VerificationRequest(_) = &m.content.msgtype
@@ -58,7 +58,8 @@ impl WasmBot {
 
         for (room_id, room) in response.rooms.join {
             for event in room.timeline.events {
-                if let AnySyncRoomEvent::Message(AnySyncMessageEvent::RoomMessage(ev)) = event {
+                if let AnySyncRoomEvent::Message(AnySyncMessageEvent::RoomMessage(ev)) = event.event
+                {
                     self.on_room_message(&room_id, &ev).await
                 }
             }
@@ -0,0 +1,797 @@
+// Copyright 2021 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Application service support.
+//!
+//! An [`Appservice`] is created from the registration file of the application
+//! service. It serves the `/transactions` endpoint the homeserver pushes events
+//! to and hands out a [`Client`] for every virtual user of the application
+//! service. Each of those clients asserts the identity of its virtual user on
+//! outgoing requests and keeps its own state of the rooms the user is part of.
+//!
+//! # Example
+//! ```no_run
+//! # use futures::executor::block_on;
+//! # use matrix_sdk::appservice::{Appservice, AppserviceRegistration};
+//! # use matrix_sdk::identifiers::server_name;
+//! # block_on(async {
+//! let registration = AppserviceRegistration::try_from_yaml_file("registration.yaml").unwrap();
+//! let appservice = Appservice::new(
+//!     "http://localhost:8008",
+//!     server_name!("localhost"),
+//!     registration,
+//! )
+//! .unwrap();
+//!
+//! appservice.register_virtual_user("_bridge_alice").await.unwrap();
+//! let alice = appservice.client(Some("_bridge_alice")).await.unwrap();
+//!
+//! appservice.run([127, 0, 0, 1], 9000).await;
+//! # })
+//! ```
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    convert::{Infallible, TryInto},
+    fs,
+    net::IpAddr,
+    ops::Deref,
+    path::Path,
+    sync::Arc,
+};
+
+use dashmap::DashMap;
+use http::StatusCode;
+use matrix_sdk_common::{
+    api::r0::{
+        account::register::{LoginType, Request as RegistrationRequest},
+        sync::sync_events,
+    },
+    appservice_api::Registration,
+    assign,
+    events::{AnyRoomEvent, AnySyncRoomEvent},
+    identifiers::{DeviceIdBox, RoomId, ServerName, ServerNameBox, UserId},
+    locks::Mutex,
+    Raw,
+};
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::{json, Value as JsonValue};
+use tracing::{debug, warn};
+use url::Url;
+use warp::{filters::BoxedFilter, Filter, Reply};
+
+use crate::{
+    http_client::client_with_config, room, Client, ClientConfig, HttpSend, Result, Session,
+};
+
+/// The device id that is used for the sessions of virtual users.
+const APPSERVICE_DEVICE_ID: &str = "APPSERVICE";
+
+/// The number of recently processed transaction ids that are remembered, the
+/// homeserver retries transactions that weren't acknowledged.
+const RECENT_TRANSACTIONS: usize = 100;
+
+/// The registration of an application service, as it's passed to the
+/// homeserver.
+#[derive(Clone, Debug)]
+pub struct AppserviceRegistration {
+    inner: Registration,
+}
+
+impl AppserviceRegistration {
+    /// Parse a registration from a YAML string.
+    ///
+    /// # Arguments
+    ///
+    /// * `yaml` - The content of the registration file.
+    pub fn try_from_yaml_str(yaml: impl AsRef<str>) -> Result<Self> {
+        Ok(Self {
+            inner: serde_yaml::from_str(yaml.as_ref())?,
+        })
+    }
+
+    /// Load and parse a registration from a YAML file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the registration file.
+    pub fn try_from_yaml_file(path: impl AsRef<Path>) -> Result<Self> {
+        let yaml = fs::read_to_string(path)?;
+        Self::try_from_yaml_str(yaml)
+    }
+}
+
+impl From<Registration> for AppserviceRegistration {
+    fn from(inner: Registration) -> Self {
+        Self { inner }
+    }
+}
+
+impl Deref for AppserviceRegistration {
+    type Target = Registration;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+/// The parts of a pushed event that are needed to route it to the clients of
+/// the virtual users.
+#[derive(Deserialize)]
+struct EventDetails {
+    room_id: RoomId,
+    #[serde(rename = "type")]
+    event_type: String,
+    state_key: Option<String>,
+    #[serde(default)]
+    content: JsonValue,
+}
+
+impl EventDetails {
+    /// Get the membership of the given user if this is a membership event
+    /// concerning them.
+    fn membership_of(&self, user_id: &UserId) -> Option<&str> {
+        if self.event_type == "m.room.member" && self.state_key.as_deref() == Some(user_id.as_str())
+        {
+            self.content.get("membership").and_then(|m| m.as_str())
+        } else {
+            None
+        }
+    }
+}
+
+/// The body of a transaction the homeserver pushes to the application service.
+#[derive(Deserialize)]
+struct Transaction {
+    events: Vec<Raw<AnyRoomEvent>>,
+}
+
+/// The query parameters of a transaction the homeserver pushes to the
+/// application service.
+#[derive(Deserialize)]
+struct TransactionQuery {
+    access_token: Option<String>,
+}
+
+/// An application service.
+///
+/// The `Appservice` can be cloned cheaply, all clones share the same clients
+/// and connection pool.
+#[derive(Clone, Debug)]
+pub struct Appservice {
+    homeserver_url: Arc<Url>,
+    server_name: Arc<ServerNameBox>,
+    registration: Arc<AppserviceRegistration>,
+    user_namespaces: Arc<Vec<Regex>>,
+    http_client: Arc<dyn HttpSend>,
+    clients: Arc<DashMap<UserId, Client>>,
+    login_lock: Arc<Mutex<()>>,
+    recent_transactions: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl Appservice {
+    /// Create a new application service.
+    ///
+    /// # Arguments
+    ///
+    /// * `homeserver_url` - The homeserver the application service is
+    /// registered with.
+    ///
+    /// * `server_name` - The server name of the homeserver, used to build the
+    /// user ids of virtual users.
+    ///
+    /// * `registration` - The registration of the application service.
+    pub fn new(
+        homeserver_url: impl TryInto<Url>,
+        server_name: &ServerName,
+        registration: AppserviceRegistration,
+    ) -> Result<Self> {
+        Self::new_with_config(
+            homeserver_url,
+            server_name,
+            registration,
+            ClientConfig::default(),
+        )
+    }
+
+    /// Create a new application service with the given client configuration.
+    ///
+    /// The configuration is used to build the connection pool that is shared
+    /// between all virtual users and for the client of the `sender_localpart`
+    /// user of the registration. The clients of other virtual users keep their
+    /// state in memory.
+    ///
+    /// # Arguments
+    ///
+    /// * `homeserver_url` - The homeserver the application service is
+    /// registered with.
+    ///
+    /// * `server_name` - The server name of the homeserver, used to build the
+    /// user ids of virtual users.
+    ///
+    /// * `registration` - The registration of the application service.
+    ///
+    /// * `client_config` - The configuration of the clients.
+    pub fn new_with_config(
+        homeserver_url: impl TryInto<Url>,
+        server_name: &ServerName,
+        registration: AppserviceRegistration,
+        client_config: ClientConfig,
+    ) -> Result<Self> {
+        let homeserver_url = if let Ok(u) = homeserver_url.try_into() {
+            u
+        } else {
+            panic!("Error parsing homeserver url")
+        };
+
+        let http_client = if let Some(client) = client_config.client.clone() {
+            client
+        } else {
+            Arc::new(client_with_config(&client_config)?)
+        };
+
+        let user_namespaces = registration
+            .namespaces
+            .users
+            .iter()
+            // The namespace regexes need to match the whole user id.
+            .map(|n| Regex::new(&format!("^(?:{})$", n.regex)))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        // The configuration can't be cloned since it may contain a store, the
+        // client of the `sender_localpart` user is thus created right away.
+        let main_user_id =
+            UserId::parse_with_server_name(registration.sender_localpart.as_str(), server_name)?;
+        let main_client = Client::new_with_config(
+            homeserver_url.clone(),
+            virtual_user_config(client_config, http_client.clone()),
+        )?;
+
+        let clients = DashMap::new();
+        clients.insert(main_user_id, main_client);
+
+        Ok(Self {
+            homeserver_url: Arc::new(homeserver_url),
+            server_name: Arc::new(server_name.to_owned()),
+            registration: Arc::new(registration),
+            user_namespaces: Arc::new(user_namespaces),
+            http_client,
+            clients: Arc::new(clients),
+            login_lock: Arc::new(Mutex::new(())),
+            recent_transactions: Arc::new(Mutex::new(VecDeque::with_capacity(RECENT_TRANSACTIONS))),
+        })
+    }
+
+    /// Get the registration of the application service.
+    pub fn registration(&self) -> &AppserviceRegistration {
+        &self.registration
+    }
+
+    /// Get the user id of a virtual user.
+    ///
+    /// # Arguments
+    ///
+    /// * `localpart` - The localpart of the virtual user, if `None` the
+    /// `sender_localpart` of the registration is used.
+    pub fn user_id(&self, localpart: Option<&str>) -> Result<UserId> {
+        let localpart = localpart.unwrap_or(&self.registration.sender_localpart);
+
+        Ok(UserId::parse_with_server_name(
+            localpart,
+            &self.server_name,
+        )?)
+    }
+
+    /// Is the given user id part of the user namespaces of the application
+    /// service.
+    ///
+    /// The `sender_localpart` user of the registration is always considered to
+    /// be part of the namespace.
+    pub fn user_id_is_in_namespace(&self, user_id: &UserId) -> bool {
+        if user_id.server_name() != &**self.server_name {
+            return false;
+        }
+
+        user_id.localpart() == self.registration.sender_localpart
+            || self
+                .user_namespaces
+                .iter()
+                .any(|r| r.is_match(user_id.as_str()))
+    }
+
+    /// Get the client of a virtual user.
+    ///
+    /// The client is created the first time this is called for a virtual user
+    /// and reused afterwards. It uses the `as_token` of the registration and
+    /// asserts the identity of the virtual user on every request.
+    ///
+    /// # Arguments
+    ///
+    /// * `localpart` - The localpart of the virtual user, if `None` the client
+    /// of the `sender_localpart` user of the registration is returned.
+    pub async fn client(&self, localpart: Option<&str>) -> Result<Client> {
+        let user_id = self.user_id(localpart)?;
+
+        let client = if let Some(client) = self.clients.get(&user_id) {
+            client.clone()
+        } else {
+            let config = virtual_user_config(ClientConfig::default(), self.http_client.clone());
+            let client = Client::new_with_config(self.homeserver_url.as_ref().clone(), config)?;
+
+            self.clients
+                .entry(user_id.clone())
+                .or_insert(client)
+                .clone()
+        };
+
+        let _guard = self.login_lock.lock().await;
+
+        if !client.logged_in().await {
+            let session = Session {
+                access_token: self.registration.as_token.clone(),
+                user_id,
+                device_id: DeviceIdBox::from(APPSERVICE_DEVICE_ID),
+            };
+            client.restore_login(session).await?;
+        }
+
+        Ok(client)
+    }
+
+    /// Register a virtual user on the homeserver.
+    ///
+    /// # Arguments
+    ///
+    /// * `localpart` - The localpart of the virtual user, the resulting user
+    /// id needs to be part of the user namespaces of the application service.
+    pub async fn register_virtual_user(&self, localpart: &str) -> Result<()> {
+        let request = assign!(RegistrationRequest::new(), {
+            username: Some(localpart),
+            login_type: Some(&LoginType::ApplicationService),
+        });
+
+        let client = self.client(None).await?;
+        let config = client.http_client.request_config.force_auth();
+        client.send(request, Some(config)).await?;
+
+        Ok(())
+    }
+
+    /// Does the given token match the `hs_token` of the registration.
+    fn hs_token_matches(&self, token: Option<&str>) -> bool {
+        token == Some(self.registration.hs_token.as_str())
+    }
+
+    /// Feed the events of a transaction into the clients of the virtual users.
+    ///
+    /// The client of the `sender_localpart` user receives all events, the
+    /// clients of the other virtual users receive the events of rooms they
+    /// know about and the membership events that concern them.
+    ///
+    /// This is called by the `/transactions` endpoint, it only needs to be
+    /// called manually if the endpoint is served by something else than the
+    /// [`warp_filter`].
+    ///
+    /// # Arguments
+    ///
+    /// * `transaction_id` - The id of the transaction.
+    ///
+    /// * `events` - The events the homeserver pushed.
+    ///
+    /// [`warp_filter`]: #method.warp_filter
+    pub async fn receive_transaction(
+        &self,
+        transaction_id: &str,
+        events: Vec<Raw<AnyRoomEvent>>,
+    ) -> Result<()> {
+        let mut recent_transactions = self.recent_transactions.lock().await;
+
+        if recent_transactions.iter().any(|t| t == transaction_id) {
+            debug!("Ignoring already processed transaction {}", transaction_id);
+            return Ok(());
+        }
+
+        let mut routed_events = Vec::with_capacity(events.len());
+
+        for event in events {
+            match serde_json::from_str::<EventDetails>(event.json().get()) {
+                Ok(details) => routed_events.push((details, event)),
+                Err(e) => warn!("Received an event without a room id: {}", e),
+            }
+        }
+
+        let main_client = self.client(None).await?;
+        let main_user_id = self.user_id(None)?;
+
+        let clients: Vec<Client> = self
+            .clients
+            .iter()
+            .filter(|c| c.key() != &main_user_id)
+            .map(|c| c.value().clone())
+            .collect();
+
+        let response = transaction_to_sync_response(
+            transaction_id,
+            &main_client,
+            &main_user_id,
+            &routed_events,
+            |_| true,
+        );
+        main_client.receive_sync_response(response).await?;
+
+        for client in clients {
+            let user_id = if let Some(u) = client.user_id().await {
+                u
+            } else {
+                continue;
+            };
+
+            let response = transaction_to_sync_response(
+                transaction_id,
+                &client,
+                &user_id,
+                &routed_events,
+                |d| {
+                    client.get_room(&d.room_id).is_some()
+                        || d.state_key.as_deref() == Some(user_id.as_str())
+                },
+            );
+
+            client.receive_sync_response(response).await?;
+        }
+
+        if recent_transactions.len() == RECENT_TRANSACTIONS {
+            recent_transactions.pop_front();
+        }
+        recent_transactions.push_back(transaction_id.to_owned());
+
+        Ok(())
+    }
+
+    /// Get a warp filter that serves the `/transactions` endpoint of the
+    /// application service.
+    ///
+    /// This can be used to combine the endpoint with other routes, use
+    /// [`run`] to just serve the application service.
+    ///
+    /// [`run`]: #method.run
+    pub fn warp_filter(&self) -> BoxedFilter<(impl Reply,)> {
+        let appservice = self.clone();
+
+        let legacy_path = warp::path!("transactions" / String);
+        let path = warp::path!("_matrix" / "app" / "v1" / "transactions" / String);
+
+        warp::put()
+            .and(path.or(legacy_path).unify())
+            .and(warp::query::<TransactionQuery>())
+            .and(warp::body::json())
+            .and_then(
+                move |transaction_id: String, query: TransactionQuery, transaction: Transaction| {
+                    let appservice = appservice.clone();
+
+                    async move {
+                        let (status, body) =
+                            if !appservice.hs_token_matches(query.access_token.as_deref()) {
+                                (StatusCode::FORBIDDEN, json!({ "errcode": "M_FORBIDDEN" }))
+                            } else if let Err(e) = appservice
+                                .receive_transaction(&transaction_id, transaction.events)
+                                .await
+                            {
+                                warn!("Error processing transaction {}: {}", transaction_id, e);
+                                (
+                                    StatusCode::INTERNAL_SERVER_ERROR,
+                                    json!({ "errcode": "M_UNKNOWN", "error": e.to_string() }),
+                                )
+                            } else {
+                                (StatusCode::OK, json!({}))
+                            };
+
+                        Ok::<_, Infallible>(warp::reply::with_status(
+                            warp::reply::json(&body),
+                            status,
+                        ))
+                    }
+                },
+            )
+            .boxed()
+    }
+
+    /// Serve the `/transactions` endpoint of the application service.
+    ///
+    /// This method will never return, if cancellation is needed the method
+    /// should be wrapped in a cancelable task.
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - The address the application service should listen on.
+    ///
+    /// * `port` - The port the application service should listen on.
+    pub async fn run(&self, host: impl Into<IpAddr>, port: u16) {
+        warp::serve(self.warp_filter())
+            .run((host.into(), port))
+            .await
+    }
+}
+
+/// Create the configuration of a virtual user's client from the given
+/// configuration.
+///
+/// The client uses the shared connection pool and asserts the identity of its
+/// user on every request.
+fn virtual_user_config(config: ClientConfig, http_client: Arc<dyn HttpSend>) -> ClientConfig {
+    let config = config.client(http_client);
+    let request_config = config.request_config.assert_identity();
+
+    config.request_config(request_config)
+}
+
+/// Convert the events of a transaction into a sync response containing the
+/// events that pass the given filter.
+///
+/// Rooms are sorted into the joined, invited or left rooms of the response
+/// depending on the membership of the given user. The membership is taken
+/// from the membership events of the transaction, or from the current state
+/// of the client if the transaction doesn't change it. Rooms for which the
+/// membership of the user is unknown are treated as joined rooms.
+fn transaction_to_sync_response(
+    transaction_id: &str,
+    client: &Client,
+    user_id: &UserId,
+    events: &[(EventDetails, Raw<AnyRoomEvent>)],
+    filter: impl Fn(&EventDetails) -> bool,
+) -> sync_events::Response {
+    let mut rooms: BTreeMap<RoomId, (Option<&str>, Vec<&(EventDetails, Raw<AnyRoomEvent>)>)> =
+        BTreeMap::new();
+
+    for item in events.iter().filter(|(d, _)| filter(d)) {
+        let (membership, room_events) = rooms.entry(item.0.room_id.clone()).or_default();
+
+        if let Some(m) = item.0.membership_of(user_id) {
+            *membership = Some(m);
+        }
+
+        room_events.push(item);
+    }
+
+    let mut response = sync_events::Response::new(transaction_id.to_owned());
+
+    for (room_id, (membership, events)) in rooms {
+        let membership = membership.or_else(|| match client.get_room(&room_id) {
+            Some(room::Room::Invited(_)) => Some("invite"),
+            Some(room::Room::Left(_)) => Some("leave"),
+            _ => None,
+        });
+
+        match membership {
+            Some("invite") => {
+                let room = response.rooms.invite.entry(room_id).or_default();
+                room.invite_state.events = events
+                    .into_iter()
+                    .filter(|(d, _)| d.state_key.is_some())
+                    .map(|(_, e)| Raw::from_json(e.json().to_owned()))
+                    .collect();
+            }
+            Some("leave") | Some("ban") => {
+                let room = response.rooms.leave.entry(room_id).or_default();
+                room.timeline.events = events
+                    .into_iter()
+                    .map(|(_, e)| Raw::from_json(e.json().to_owned()))
+                    .collect();
+            }
+            _ => {
+                let room = response.rooms.join.entry(room_id).or_default();
+                room.timeline.events = events
+                    .into_iter()
+                    .map(|(_, e)| Raw::from_json(e.json().to_owned()))
+                    .collect();
+            }
+        }
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod test {
+    use matrix_sdk_common::identifiers::{room_id, server_name, user_id};
+    use matrix_sdk_test::test_json;
+    use mockito::{mock, Matcher};
+    use serde_json::json;
+
+    use super::{Appservice, AppserviceRegistration, RECENT_TRANSACTIONS};
+
+    const REGISTRATION: &str = r#"
+id: "bridge"
+url: "http://localhost:9000"
+as_token: "as_token"
+hs_token: "hs_token"
+sender_localpart: "_bridge"
+rate_limited: false
+namespaces:
+  users:
+    - exclusive: true
+      regex: "@_bridge_.*:localhost"
+  aliases: []
+  rooms: []
+"#;
+
+    fn appservice() -> Appservice {
+        let registration = AppserviceRegistration::try_from_yaml_str(REGISTRATION).unwrap();
+
+        Appservice::new(
+            mockito::server_url().as_str(),
+            server_name!("localhost"),
+            registration,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn registration() {
+        let appservice = appservice();
+
+        assert_eq!(appservice.registration().as_token, "as_token");
+        assert_eq!(appservice.registration().sender_localpart, "_bridge");
+
+        let user_id = appservice.user_id(Some("_bridge_alice")).unwrap();
+        assert!(appservice.user_id_is_in_namespace(&user_id));
+        assert!(appservice.user_id_is_in_namespace(&appservice.user_id(None).unwrap()));
+
+        let user_id = appservice.user_id(Some("alice")).unwrap();
+        assert!(!appservice.user_id_is_in_namespace(&user_id));
+
+        // The namespace has to match the whole user id.
+        assert!(!appservice.user_namespaces[0].is_match("@alice@_bridge_bob:localhost"));
+        assert!(!appservice.user_namespaces[0].is_match("@_bridge_bob:localhost.evil"));
+    }
+
+    #[tokio::test]
+    async fn virtual_user_asserts_identity() {
+        let appservice = appservice();
+
+        let _m = mock("GET", "/_matrix/client/r0/devices")
+            .match_query(Matcher::UrlEncoded(
+                "user_id".to_owned(),
+                "@_bridge_alice:localhost".to_owned(),
+            ))
+            .match_header("authorization", "Bearer as_token")
+            .with_status(200)
+            .with_body(test_json::DEVICES.to_string())
+            .create();
+
+        let client = appservice.client(Some("_bridge_alice")).await.unwrap();
+        assert!(client.devices().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn register_virtual_user() {
+        let appservice = appservice();
+
+        let _m = mock(
+            "POST",
+            Matcher::Regex(r"^/_matrix/client/r0/register\?.*$".to_owned()),
+        )
+        .match_header("authorization", "Bearer as_token")
+        .match_body(Matcher::PartialJson(json!({
+            "username": "_bridge_alice",
+            "type": "m.login.application_service",
+        })))
+        .with_status(200)
+        .with_body(test_json::LOGIN.to_string())
+        .create();
+
+        appservice
+            .register_virtual_user("_bridge_alice")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn transactions() {
+        let appservice = appservice();
+        let filter = appservice.warp_filter();
+
+        let mut event = test_json::MEMBER.clone();
+        event["room_id"] = json!("!room:localhost");
+        let transaction = json!({ "events": [event] });
+
+        let response = warp::test::request()
+            .method("PUT")
+            .path("/_matrix/app/v1/transactions/1?access_token=wrong_token")
+            .json(&transaction)
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 403);
+
+        let response = warp::test::request()
+            .method("PUT")
+            .path("/_matrix/app/v1/transactions/1?access_token=hs_token")
+            .json(&transaction)
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 200);
+
+        let client = appservice.client(None).await.unwrap();
+        let room = client
+            .get_joined_room(&room_id!("!room:localhost"))
+            .unwrap();
+        assert!(room
+            .get_member(&user_id!("@example:localhost"))
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn recent_transactions() {
+        let appservice = appservice();
+
+        for i in 0..=RECENT_TRANSACTIONS {
+            appservice
+                .receive_transaction(&i.to_string(), Vec::new())
+                .await
+                .unwrap();
+        }
+
+        // Older transactions are forgotten, the recent ones are remembered.
+        let recent_transactions = appservice.recent_transactions.lock().await;
+        assert_eq!(recent_transactions.len(), RECENT_TRANSACTIONS);
+        assert!(!recent_transactions.contains(&"0".to_owned()));
+        assert!(recent_transactions.contains(&"1".to_owned()));
+        assert!(recent_transactions.contains(&RECENT_TRANSACTIONS.to_string()));
+    }
+
+    #[tokio::test]
+    async fn transaction_membership_routing() {
+        let appservice = appservice();
+        let filter = appservice.warp_filter();
+
+        let member = |room_id: &str, membership: &str| {
+            let mut event = test_json::MEMBER.clone();
+            event["room_id"] = json!(room_id);
+            event["state_key"] = json!("@_bridge:localhost");
+            event["content"]["membership"] = json!(membership);
+            event
+        };
+
+        let transaction = json!({
+            "events": [
+                member("!invited:localhost", "invite"),
+                member("!left:localhost", "leave"),
+                member("!joined:localhost", "join"),
+            ]
+        });
+
+        let response = warp::test::request()
+            .method("PUT")
+            .path("/_matrix/app/v1/transactions/1?access_token=hs_token")
+            .json(&transaction)
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 200);
+
+        let client = appservice.client(None).await.unwrap();
+        assert!(client
+            .get_invited_room(&room_id!("!invited:localhost"))
+            .is_some());
+        assert!(client.get_left_room(&room_id!("!left:localhost")).is_some());
+        assert!(client
+            .get_joined_room(&room_id!("!joined:localhost"))
+            .is_some());
+        assert!(client
+            .get_joined_room(&room_id!("!invited:localhost"))
+            .is_none());
+    }
+}
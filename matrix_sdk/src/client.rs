@@ -13,15 +13,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-#[cfg(feature = "encryption")]
-use std::{collections::BTreeMap, io::Write, path::PathBuf};
-#[cfg(feature = "sso_login")]
-use std::{
-    collections::HashMap,
-    io::{Error as IoError, ErrorKind as IoErrorKind},
-    ops::Range,
-};
 use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
     convert::TryInto,
     fmt::{self, Debug},
     future::Future,
@@ -30,16 +24,30 @@ use std::{
     result::Result as StdResult,
     sync::Arc,
 };
+#[cfg(feature = "sso_login")]
+use std::{
+    collections::HashMap,
+    io::{Error as IoError, ErrorKind as IoErrorKind},
+    ops::Range,
+};
+#[cfg(feature = "encryption")]
+use std::{io::Write, path::PathBuf};
 
 use dashmap::DashMap;
+use futures::{
+    channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
+    Stream,
+};
 use futures_timer::Delay as sleep;
-use http::HeaderValue;
 #[cfg(feature = "sso_login")]
 use http::Response;
+use http::{HeaderValue, StatusCode};
 use mime::{self, Mime};
 #[cfg(feature = "sso_login")]
 use rand::{thread_rng, Rng};
 use reqwest::header::InvalidHeaderValue;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{value::to_raw_value, Value as JsonValue};
 #[cfg(feature = "sso_login")]
 use tokio::{net::TcpListener, sync::oneshot};
 #[cfg(feature = "sso_login")]
@@ -51,18 +59,20 @@ use warp::Filter;
 use zeroize::Zeroizing;
 
 #[cfg(feature = "encryption")]
-use tracing::{debug, warn};
-use tracing::{error, info, instrument};
+use tracing::debug;
+use tracing::{error, info, instrument, warn};
 
 use matrix_sdk_base::{
     deserialized_responses::SyncResponse, events::AnyMessageEventContent, identifiers::MxcUri,
-    BaseClient, BaseClientConfig, Session, Store,
+    BaseClient, BaseClientConfig, RoomNameFormatter, Session, Store,
 };
 
 #[cfg(feature = "encryption")]
 use matrix_sdk_base::crypto::{
     decrypt_key_export, encrypt_key_export, olm::InboundGroupSession, store::CryptoStoreError,
-    OutgoingRequests, RoomMessageRequest, ToDeviceRequest,
+    IdentityChange, IdentityChangeCallback, KeyShareDecisionRecord, KeySharePolicy,
+    KeysUploadRequest, OutgoingRequest, OutgoingRequests, RoomMessageRequest, ShareStrategy,
+    SkippedDevice, ToDeviceRequest,
 };
 
 /// Enum controlling if a loop running callbacks should continue or abort.
@@ -80,44 +90,72 @@ pub enum LoopCtrl {
 }
 
 use matrix_sdk_common::{
+    api::error::ErrorKind,
     api::r0::{
-        account::register,
+        account::{
+            add_3pid, change_password, deactivate, delete_3pid, get_3pids, register,
+            request_3pid_management_token_via_email, request_3pid_management_token_via_msisdn,
+            whoami,
+        },
+        config::{get_global_account_data, set_global_account_data},
         device::{delete_devices, get_devices},
         directory::{get_public_rooms, get_public_rooms_filtered},
         filter::{create_filter::Request as FilterUploadRequest, FilterDefinition},
         media::{create_content, get_content, get_content_thumbnail},
         membership::{join_room_by_id, join_room_by_id_or_alias},
         message::send_message_event,
+        presence::{get_presence, set_presence},
         profile::{get_avatar_url, get_display_name, set_avatar_url, set_display_name},
-        room::create_room,
+        room::create_room::{self, RoomPreset},
         session::{get_login_types, login, sso_login},
         sync::sync_events,
         uiaa::AuthData,
     },
+    api::{
+        r0::capabilities::{get_capabilities, Capabilities},
+        unversioned::{discover_homeserver, get_supported_versions},
+    },
     assign,
-    identifiers::{DeviceIdBox, EventId, RoomId, RoomIdOrAliasId, ServerName, UserId},
+    events::{
+        presence::PresenceEvent, room::encryption::EncryptionEventContent, AnyBasicEvent,
+        AnyInitialStateEvent, AnySyncRoomEvent, AnySyncStateEvent, BasicEventContent, EventContent,
+        EventType, InitialStateEvent,
+    },
+    identifiers::{
+        DeviceIdBox, EventEncryptionAlgorithm, EventId, RoomId, RoomIdOrAliasId, ServerName, UserId,
+    },
     instant::{Duration, Instant},
     locks::RwLock,
     presence::PresenceState,
+    thirdparty::Medium,
+    unstable::space::get_hierarchy,
     uuid::Uuid,
-    FromHttpResponseError, UInt,
+    FromHttpResponseError, IncomingResponse, Raw, ServerError, UInt,
 };
 
 #[cfg(feature = "encryption")]
-use matrix_sdk_common::api::r0::{
-    keys::{get_keys, upload_keys, upload_signing_keys::Request as UploadSigningKeysRequest},
-    to_device::send_event_to_device::{
-        Request as RumaToDeviceRequest, Response as ToDeviceResponse,
+use matrix_sdk_common::{
+    api::r0::{
+        keys::{get_keys, upload_keys, upload_signing_keys::Request as UploadSigningKeysRequest},
+        to_device::send_event_to_device::{
+            Request as RumaToDeviceRequest, Response as ToDeviceResponse,
+        },
+    },
+    unstable::{
+        dehydrated_device::{
+            claim_dehydrated_device, get_dehydrated_device, put_dehydrated_device,
+        },
+        keys::{upload_device_keys, upload_keys as unstable_upload_keys},
     },
 };
 
 use matrix_sdk_common::locks::Mutex;
 
 use crate::{
-    error::HttpError,
+    error::{DiscoveryError, HttpError},
     event_handler::Handler,
     http_client::{client_with_config, HttpClient, HttpSend},
-    room, Error, EventHandler, OutgoingRequest, Result,
+    room, Error, EventHandler, OutgoingRequest, Result, SpaceHierarchy, SpaceHierarchyRoom,
 };
 
 #[cfg(feature = "encryption")]
@@ -148,8 +186,11 @@ const SSO_SERVER_BIND_TRIES: u8 = 10;
 pub struct Client {
     /// The URL of the homeserver to connect to.
     homeserver: Arc<Url>,
+    /// The URL of the identity server that was advertised for the homeserver,
+    /// if any.
+    identity_server: Arc<Option<Url>>,
     /// The underlying HTTP client.
-    http_client: HttpClient,
+    pub(crate) http_client: HttpClient,
     /// User session data.
     pub(crate) base_client: BaseClient,
     /// Locks making sure we only have one group session sharing request in
@@ -164,6 +205,21 @@ pub struct Client {
     /// Any implementor of EventHandler will act as the callbacks for various
     /// events.
     event_handler: Arc<RwLock<Option<Handler>>>,
+    /// The cached versions and unstable features the homeserver supports.
+    supported_versions: Arc<RwLock<Option<SupportedVersions>>>,
+    /// The cached capabilities of the homeserver.
+    capabilities: Arc<RwLock<Option<Capabilities>>>,
+    /// Should the replacement rooms of upgraded rooms be joined automatically.
+    follow_tombstones: bool,
+    /// Should upgraded rooms be hidden from the list of joined rooms.
+    hide_replaced_rooms: bool,
+    /// Lock making sure we're only modifying the global account data once at
+    /// a time.
+    account_data_lock: Arc<Mutex<()>>,
+    /// The subscribers of account data events, keyed by the room of the
+    /// account data, `None` for global account data, and the event type.
+    account_data_subscribers:
+        Arc<DashMap<(Option<RoomId>, String), Vec<UnboundedSender<AnyBasicEvent>>>>,
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -195,9 +251,12 @@ pub struct ClientConfig {
     pub(crate) proxy: Option<reqwest::Proxy>,
     pub(crate) user_agent: Option<HeaderValue>,
     pub(crate) disable_ssl_verification: bool,
+    pub(crate) insecure_server_discovery: bool,
     pub(crate) base_config: BaseClientConfig,
     pub(crate) request_config: RequestConfig,
     pub(crate) client: Option<Arc<dyn HttpSend>>,
+    pub(crate) follow_tombstones: bool,
+    pub(crate) hide_replaced_rooms: bool,
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -210,7 +269,10 @@ impl Debug for ClientConfig {
 
         res.field("user_agent", &self.user_agent)
             .field("disable_ssl_verification", &self.disable_ssl_verification)
+            .field("insecure_server_discovery", &self.insecure_server_discovery)
             .field("request_config", &self.request_config)
+            .field("follow_tombstones", &self.follow_tombstones)
+            .field("hide_replaced_rooms", &self.hide_replaced_rooms)
             .finish()
     }
 }
@@ -250,6 +312,16 @@ impl ClientConfig {
         self
     }
 
+    /// Use plain HTTP instead of HTTPS to discover the homeserver of a server
+    /// name.
+    ///
+    /// This should only be used to connect to local servers, e.g. while
+    /// testing.
+    pub fn insecure_server_discovery(mut self) -> Self {
+        self.insecure_server_discovery = true;
+        self
+    }
+
     /// Set a custom HTTP user agent for the client.
     pub fn user_agent(mut self, user_agent: &str) -> StdResult<Self, InvalidHeaderValue> {
         self.user_agent = Some(HeaderValue::from_str(user_agent)?);
@@ -293,6 +365,41 @@ impl ClientConfig {
         self
     }
 
+    /// Set the formatter that names rooms without a name or canonical alias.
+    ///
+    /// # Arguments
+    ///
+    /// * `formatter` - The formatter that should be used instead of the
+    /// default English room names.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use matrix_sdk::{ClientConfig, RoomNameFormatter, RoomNameParts};
+    /// #[derive(Debug)]
+    /// struct GermanRoomNames;
+    ///
+    /// impl RoomNameFormatter for GermanRoomNames {
+    ///     fn format_name(&self, parts: RoomNameParts<'_>) -> String {
+    ///         match parts.heroes.first() {
+    ///             Some(hero) if parts.other_member_count() > 1 => format!(
+    ///                 "{} und {} andere",
+    ///                 hero.name(),
+    ///                 parts.other_member_count() - 1
+    ///             ),
+    ///             Some(hero) => hero.name().to_owned(),
+    ///             None => "Leerer Raum".to_owned(),
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let client_config = ClientConfig::new().room_name_formatter(GermanRoomNames);
+    /// ```
+    pub fn room_name_formatter(mut self, formatter: impl RoomNameFormatter + 'static) -> Self {
+        self.base_config = self.base_config.room_name_formatter(formatter);
+        self
+    }
+
     /// Set the default timeout, fail and retry behavior for all HTTP requests.
     pub fn request_config(mut self, request_config: RequestConfig) -> Self {
         self.request_config = request_config;
@@ -307,6 +414,31 @@ impl ClientConfig {
         self.client = Some(client);
         self
     }
+
+    /// Automatically join the replacement room of joined rooms that get
+    /// upgraded.
+    ///
+    /// When a sync response contains a `m.room.tombstone` event for a joined
+    /// room, the client joins the room the tombstone points to, unless it
+    /// already is a member of it.
+    pub fn follow_tombstones(mut self) -> Self {
+        self.follow_tombstones = true;
+        self
+    }
+
+    /// Hide joined rooms that got replaced by a room upgrade from
+    /// [`Client::joined_rooms`].
+    ///
+    /// A room is only hidden once the client is a member of its replacement
+    /// room, the replaced room can still be accessed with
+    /// [`Client::get_joined_room`].
+    ///
+    /// [`Client::joined_rooms`]: struct.Client.html#method.joined_rooms
+    /// [`Client::get_joined_room`]: struct.Client.html#method.get_joined_room
+    pub fn hide_replaced_rooms(mut self) -> Self {
+        self.hide_replaced_rooms = true;
+        self
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -316,6 +448,7 @@ pub struct SyncSettings<'a> {
     pub(crate) timeout: Option<Duration>,
     pub(crate) token: Option<String>,
     pub(crate) full_state: bool,
+    pub(crate) set_presence: PresenceState,
 }
 
 impl<'a> Default for SyncSettings<'a> {
@@ -325,6 +458,7 @@ impl<'a> Default for SyncSettings<'a> {
             timeout: Some(DEFAULT_SYNC_TIMEOUT),
             token: Default::default(),
             full_state: Default::default(),
+            set_presence: PresenceState::Online,
         }
     }
 }
@@ -378,6 +512,20 @@ impl<'a> SyncSettings<'a> {
         self.full_state = full_state;
         self
     }
+
+    /// Set the presence the user should have while syncing, defaults to
+    /// `PresenceState::Online`.
+    ///
+    /// Use `PresenceState::Offline` to sync without marking the user as
+    /// online.
+    ///
+    /// # Arguments
+    ///
+    /// * `presence` - The presence state of the user while syncing.
+    pub fn set_presence(mut self, presence: PresenceState) -> Self {
+        self.set_presence = presence;
+        self
+    }
 }
 
 /// Configuration for requests the `Client` makes.
@@ -402,6 +550,8 @@ pub struct RequestConfig {
     pub(crate) timeout: Duration,
     pub(crate) retry_limit: Option<u64>,
     pub(crate) retry_timeout: Option<Duration>,
+    pub(crate) force_auth: bool,
+    pub(crate) assert_identity: bool,
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -412,6 +562,8 @@ impl Debug for RequestConfig {
         res.field("timeout", &self.timeout)
             .field("retry_limit", &self.retry_limit)
             .field("retry_timeout", &self.retry_timeout)
+            .field("force_auth", &self.force_auth)
+            .field("assert_identity", &self.assert_identity)
             .finish()
     }
 }
@@ -422,6 +574,8 @@ impl Default for RequestConfig {
             timeout: DEFAULT_REQUEST_TIMEOUT,
             retry_limit: Default::default(),
             retry_timeout: Default::default(),
+            force_auth: false,
+            assert_identity: false,
         }
     }
 }
@@ -456,6 +610,76 @@ impl RequestConfig {
         self.retry_timeout = Some(retry_timeout);
         self
     }
+
+    /// Send the access token with requests even if the endpoint doesn't
+    /// require authentication.
+    ///
+    /// This is needed by application services, e.g. to register virtual users.
+    #[cfg(feature = "appservice")]
+    #[cfg_attr(feature = "docs", doc(cfg(appservice)))]
+    pub fn force_auth(mut self) -> Self {
+        self.force_auth = true;
+        self
+    }
+
+    /// Assert the identity of the logged in user by appending its user id as
+    /// the `user_id` query parameter to requests.
+    ///
+    /// This allows application services to act as one of their virtual users.
+    #[cfg(feature = "appservice")]
+    #[cfg_attr(feature = "docs", doc(cfg(appservice)))]
+    pub fn assert_identity(mut self) -> Self {
+        self.assert_identity = true;
+        self
+    }
+}
+
+/// The versions of the client-server API and the unstable features that a
+/// homeserver supports.
+///
+/// This can be used to decide at runtime if a certain feature or endpoint can
+/// be used with the homeserver.
+#[derive(Clone, Debug, Default)]
+pub struct SupportedVersions {
+    /// The versions of the client-server API the homeserver supports, e.g.
+    /// `r0.6.0`.
+    pub versions: Vec<String>,
+    /// Unstable features the homeserver supports, mapped to a boolean that
+    /// signals if the feature is enabled.
+    pub unstable_features: BTreeMap<String, bool>,
+}
+
+impl SupportedVersions {
+    /// Does the homeserver support the given version of the client-server API.
+    ///
+    /// # Arguments
+    ///
+    /// * `version` - The version of the client-server API, e.g. `r0.6.0`.
+    pub fn supports_version(&self, version: &str) -> bool {
+        self.versions.iter().any(|v| v == version)
+    }
+
+    /// Does the homeserver support and enable the given unstable feature.
+    ///
+    /// # Arguments
+    ///
+    /// * `feature` - The name of the unstable feature, e.g.
+    /// `org.matrix.e2e_cross_signing`.
+    pub fn supports_unstable_feature(&self, feature: &str) -> bool {
+        self.unstable_features
+            .get(feature)
+            .copied()
+            .unwrap_or(false)
+    }
+}
+
+impl From<get_supported_versions::Response> for SupportedVersions {
+    fn from(response: get_supported_versions::Response) -> Self {
+        Self {
+            versions: response.versions,
+            unstable_features: response.unstable_features,
+        }
+    }
 }
 
 impl Client {
@@ -481,17 +705,116 @@ impl Client {
         config: ClientConfig,
     ) -> Result<Self> {
         let homeserver = if let Ok(u) = homeserver_url.try_into() {
-            Arc::new(u)
+            u
         } else {
             panic!("Error parsing homeserver url")
         };
 
-        let client = if let Some(client) = config.client {
+        let client = if let Some(client) = config.client.clone() {
+            client
+        } else {
+            Arc::new(client_with_config(&config)?)
+        };
+
+        Client::new_with_http_client(homeserver, client, config)
+    }
+
+    /// Create a new client for the homeserver of the given user.
+    ///
+    /// The homeserver URL is discovered using the `.well-known/matrix/client`
+    /// file of the server the user id belongs to, as described in the [server
+    /// discovery] section of the spec. The discovered homeserver is validated
+    /// by fetching its supported versions.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The id of the user whose homeserver the client should
+    /// connect to.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use std::convert::TryFrom;
+    /// # use futures::executor::block_on;
+    /// # use matrix_sdk::{Client, identifiers::UserId};
+    /// # block_on(async {
+    /// let alice = UserId::try_from("@alice:example.org").unwrap();
+    /// let client = Client::new_from_user_id(&alice).await.unwrap();
+    ///
+    /// client.login(alice.as_str(), "password", None, None).await.unwrap();
+    /// # })
+    /// ```
+    ///
+    /// [server discovery]: https://matrix.org/docs/spec/client_server/r0.6.1#server-discovery
+    pub async fn new_from_user_id(user_id: &UserId) -> Result<Self> {
+        let config = ClientConfig::new();
+        Client::new_from_user_id_with_config(user_id, config).await
+    }
+
+    /// Create a new client for the homeserver of the given user with the given
+    /// configuration.
+    ///
+    /// See [`new_from_user_id`] for details about the homeserver discovery.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The id of the user whose homeserver the client should
+    /// connect to.
+    ///
+    /// * `config` - Configuration for the client.
+    ///
+    /// [`new_from_user_id`]: #method.new_from_user_id
+    pub async fn new_from_user_id_with_config(
+        user_id: &UserId,
+        config: ClientConfig,
+    ) -> Result<Self> {
+        Client::new_from_server_name_with_config(user_id.server_name(), config).await
+    }
+
+    /// Create a new client for the homeserver of the given server name with
+    /// the given configuration.
+    ///
+    /// See [`new_from_user_id`] for details about the homeserver discovery.
+    ///
+    /// # Arguments
+    ///
+    /// * `server_name` - The server name whose homeserver the client should
+    /// connect to, e.g. `example.org`.
+    ///
+    /// * `config` - Configuration for the client.
+    ///
+    /// [`new_from_user_id`]: #method.new_from_user_id
+    pub async fn new_from_server_name_with_config(
+        server_name: &ServerName,
+        config: ClientConfig,
+    ) -> Result<Self> {
+        let client = if let Some(client) = config.client.clone() {
             client
         } else {
             Arc::new(client_with_config(&config)?)
         };
 
+        let discovered = discover_homeserver_url(
+            &client,
+            server_name,
+            config.insecure_server_discovery,
+            config.request_config,
+        )
+        .await?;
+
+        let mut client = Client::new_with_http_client(discovered.homeserver, client, config)?;
+        client.identity_server = Arc::new(discovered.identity_server);
+        *client.supported_versions.write().await = Some(discovered.supported_versions);
+
+        Ok(client)
+    }
+
+    fn new_with_http_client(
+        homeserver: Url,
+        client: Arc<dyn HttpSend>,
+        config: ClientConfig,
+    ) -> Result<Self> {
+        let homeserver = Arc::new(homeserver);
+
         let base_client = BaseClient::new_with_config(config.base_config)?;
         let session = base_client.session().clone();
 
@@ -504,6 +827,7 @@ impl Client {
 
         Ok(Self {
             homeserver,
+            identity_server: Arc::new(None),
             http_client,
             base_client,
             #[cfg(feature = "encryption")]
@@ -513,6 +837,12 @@ impl Client {
             members_request_locks: Arc::new(DashMap::new()),
             typing_notice_times: Arc::new(DashMap::new()),
             event_handler: Arc::new(RwLock::new(None)),
+            supported_versions: Arc::new(RwLock::new(None)),
+            capabilities: Arc::new(RwLock::new(None)),
+            follow_tombstones: config.follow_tombstones,
+            hide_replaced_rooms: config.hide_replaced_rooms,
+            account_data_lock: Arc::new(Mutex::new(())),
+            account_data_subscribers: Arc::new(DashMap::new()),
         })
     }
 
@@ -526,6 +856,114 @@ impl Client {
         &self.homeserver
     }
 
+    /// The identity server that was advertised in the `.well-known` file of
+    /// the homeserver.
+    ///
+    /// This is only set if the client was created using homeserver discovery,
+    /// e.g. with [`new_from_user_id`].
+    ///
+    /// [`new_from_user_id`]: #method.new_from_user_id
+    pub fn identity_server(&self) -> Option<&Url> {
+        self.identity_server.as_ref().as_ref()
+    }
+
+    /// Get the versions and unstable features the homeserver supports.
+    ///
+    /// The versions are fetched from the homeserver the first time this is
+    /// called and cached afterwards, use [`refresh_server_capabilities`] to
+    /// refetch them.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use futures::executor::block_on;
+    /// # use matrix_sdk::Client;
+    /// # use url::Url;
+    /// # let homeserver = Url::parse("http://example.com").unwrap();
+    /// # block_on(async {
+    /// let client = Client::new(homeserver).unwrap();
+    /// let versions = client.supported_versions().await.unwrap();
+    ///
+    /// if versions.supports_unstable_feature("org.matrix.e2e_cross_signing") {
+    ///     println!("The homeserver supports cross signing");
+    /// }
+    /// # })
+    /// ```
+    ///
+    /// [`refresh_server_capabilities`]: #method.refresh_server_capabilities
+    pub async fn supported_versions(&self) -> Result<SupportedVersions> {
+        if let Some(versions) = self.supported_versions.read().await.as_ref() {
+            return Ok(versions.clone());
+        }
+
+        let mut supported_versions = self.supported_versions.write().await;
+
+        if let Some(versions) = supported_versions.as_ref() {
+            Ok(versions.clone())
+        } else {
+            let request = get_supported_versions::Request::new();
+            let versions: SupportedVersions = self.send(request, None).await?.into();
+            *supported_versions = Some(versions.clone());
+
+            Ok(versions)
+        }
+    }
+
+    /// Get the capabilities of the homeserver.
+    ///
+    /// The capabilities are fetched from the homeserver the first time this is
+    /// called and cached afterwards, use [`refresh_server_capabilities`] to
+    /// refetch them.
+    ///
+    /// This requires the client to be logged in.
+    ///
+    /// [`refresh_server_capabilities`]: #method.refresh_server_capabilities
+    pub async fn capabilities(&self) -> Result<Capabilities> {
+        if let Some(capabilities) = self.capabilities.read().await.as_ref() {
+            return Ok(capabilities.clone());
+        }
+
+        let mut cached_capabilities = self.capabilities.write().await;
+
+        if let Some(capabilities) = cached_capabilities.as_ref() {
+            Ok(capabilities.clone())
+        } else {
+            let request = get_capabilities::Request::new();
+            let capabilities = self.send(request, None).await?.capabilities;
+            *cached_capabilities = Some(capabilities.clone());
+
+            Ok(capabilities)
+        }
+    }
+
+    /// Refetch the supported versions and the capabilities of the homeserver.
+    ///
+    /// The capabilities will only be refetched if the client is logged in.
+    pub async fn refresh_server_capabilities(&self) -> Result<()> {
+        self.supported_versions.write().await.take();
+        self.capabilities.write().await.take();
+
+        self.supported_versions().await?;
+
+        if self.logged_in().await {
+            self.capabilities().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Can the user change their password on the homeserver.
+    ///
+    /// This is decided by the `m.change_password` capability of the
+    /// homeserver, if the homeserver doesn't advertise it the password is
+    /// assumed to be changeable.
+    pub async fn can_change_password(&self) -> Result<bool> {
+        Ok(self
+            .capabilities()
+            .await?
+            .change_password
+            .map_or(true, |c| c.enabled))
+    }
+
     /// Get the user id of the current owner of the client.
     pub async fn user_id(&self) -> Option<UserId> {
         let session = self.base_client.session().read().await;
@@ -657,6 +1095,62 @@ impl Client {
         }
     }
 
+    /// Set the presence of the owner of the client.
+    ///
+    /// # Arguments
+    ///
+    /// * `presence` - The new presence state.
+    ///
+    /// * `status_msg` - An optional status message to show alongside the
+    /// presence state.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use futures::executor::block_on;
+    /// # use matrix_sdk::{Client, presence::PresenceState};
+    /// # use url::Url;
+    /// # let homeserver = Url::parse("http://example.com").unwrap();
+    /// # block_on(async {
+    /// # let client = Client::new(homeserver).unwrap();
+    /// client
+    ///     .set_presence(PresenceState::Unavailable, Some("Out for lunch"))
+    ///     .await
+    ///     .unwrap();
+    /// # })
+    /// ```
+    pub async fn set_presence(
+        &self,
+        presence: PresenceState,
+        status_msg: Option<&str>,
+    ) -> Result<()> {
+        let user_id = self.user_id().await.ok_or(Error::AuthenticationRequired)?;
+        let request = assign!(set_presence::Request::new(&user_id, presence), {
+            status_msg
+        });
+        self.send(request, None).await?;
+        Ok(())
+    }
+
+    /// Fetch the current presence of a user from the homeserver.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user whose presence should be fetched.
+    pub async fn get_presence(&self, user_id: &UserId) -> Result<get_presence::Response> {
+        let request = get_presence::Request::new(user_id);
+        self.send(request, None).await
+    }
+
+    /// Get the latest presence event of a user that was received while
+    /// syncing.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user whose presence event should be returned.
+    pub async fn presence_event(&self, user_id: &UserId) -> Result<Option<PresenceEvent>> {
+        Ok(self.store().get_presence_event(user_id).await?)
+    }
+
     /// Get a reference to the store.
     pub fn store(&self) -> &Store {
         self.base_client.store()
@@ -723,12 +1217,30 @@ impl Client {
     }
 
     /// Returns the joined rooms this client knows about.
+    ///
+    /// If the client was configured to hide replaced rooms, rooms whose
+    /// replacement room is joined as well are left out.
     pub fn joined_rooms(&self) -> Vec<room::Joined> {
-        self.store()
+        let rooms: Vec<_> = self
+            .store()
             .get_rooms()
             .into_iter()
             .filter_map(|room| room::Joined::new(self.clone(), room))
-            .collect()
+            .collect();
+
+        if self.hide_replaced_rooms {
+            rooms
+                .iter()
+                .filter(|room| {
+                    room.successor()
+                        .map(|s| !rooms.iter().any(|r| r.room_id() == &s))
+                        .unwrap_or(true)
+                })
+                .cloned()
+                .collect()
+        } else {
+            rooms
+        }
     }
 
     /// Returns the invited rooms this client knows about.
@@ -760,37 +1272,133 @@ impl Client {
             .map(|room| room::Common::new(self.clone(), room).into())
     }
 
-    /// Get a joined room with the given room id.
+    /// Get the rooms of a space that are known to the client.
+    ///
+    /// The rooms are ordered as the `m.space.child` events of the space
+    /// dictate, children of the space that the client doesn't know about are
+    /// skipped. Use [`space_hierarchy`] to discover those.
     ///
     /// # Arguments
     ///
-    /// `room_id` - The unique id of the room that should be fetched.
-    pub fn get_joined_room(&self, room_id: &RoomId) -> Option<room::Joined> {
-        self.store()
-            .get_room(room_id)
-            .and_then(|room| room::Joined::new(self.clone(), room))
+    /// `space_id` - The room id of the space.
+    ///
+    /// [`space_hierarchy`]: #method.space_hierarchy
+    pub fn space_rooms(&self, space_id: &RoomId) -> Vec<room::Room> {
+        self.get_room(space_id)
+            .map(|space| {
+                space
+                    .space_children()
+                    .into_iter()
+                    .filter_map(|(room_id, _)| self.get_room(&room_id))
+                    .collect()
+            })
+            .unwrap_or_default()
     }
 
-    /// Get an invited room with the given room id.
+    /// Fetch a page of the room hierarchy of a space from the homeserver.
+    ///
+    /// This uses the unstable `/hierarchy` endpoint of [MSC2946].
     ///
     /// # Arguments
     ///
-    /// `room_id` - The unique id of the room that should be fetched.
-    pub fn get_invited_room(&self, room_id: &RoomId) -> Option<room::Invited> {
-        self.store()
-            .get_room(room_id)
-            .and_then(|room| room::Invited::new(self.clone(), room))
-    }
-
-    /// Get a left room with the given room id.
+    /// * `space_id` - The room id of the space.
     ///
-    /// # Arguments
+    /// * `from` - The `next_batch` token of the previous page, `None` to fetch
+    /// the first page.
     ///
-    /// `room_id` - The unique id of the room that should be fetched.
-    pub fn get_left_room(&self, room_id: &RoomId) -> Option<room::Left> {
-        self.store()
-            .get_room(room_id)
-            .and_then(|room| room::Left::new(self.clone(), room))
+    /// * `limit` - The maximum number of rooms the page should contain.
+    ///
+    /// * `suggested_only` - Should only rooms that are suggested by the space
+    /// be returned.
+    ///
+    /// [MSC2946]: https://github.com/matrix-org/matrix-doc/pull/2946
+    pub async fn space_hierarchy(
+        &self,
+        space_id: &RoomId,
+        from: Option<&str>,
+        limit: Option<u32>,
+        suggested_only: bool,
+    ) -> Result<SpaceHierarchy> {
+        let request = assign!(get_hierarchy::Request::new(space_id.clone()), {
+            from: from.map(ToOwned::to_owned),
+            limit: limit.map(UInt::from),
+            suggested_only,
+        });
+
+        let response = self.send(request, None).await?;
+
+        Ok(SpaceHierarchy {
+            rooms: response.rooms,
+            next_batch: response.next_batch,
+        })
+    }
+
+    /// Fetch the whole room hierarchy of a space from the homeserver.
+    ///
+    /// This walks over all pages of the hierarchy, see [`space_hierarchy`].
+    ///
+    /// # Arguments
+    ///
+    /// * `space_id` - The room id of the space.
+    ///
+    /// * `suggested_only` - Should only rooms that are suggested by the space
+    /// be returned.
+    ///
+    /// [`space_hierarchy`]: #method.space_hierarchy
+    pub async fn full_space_hierarchy(
+        &self,
+        space_id: &RoomId,
+        suggested_only: bool,
+    ) -> Result<Vec<SpaceHierarchyRoom>> {
+        let mut rooms = Vec::new();
+        let mut from = None;
+
+        loop {
+            let page = self
+                .space_hierarchy(space_id, from.as_deref(), None, suggested_only)
+                .await?;
+            rooms.extend(page.rooms);
+
+            match page.next_batch {
+                Some(next_batch) if from.as_ref() != Some(&next_batch) => from = Some(next_batch),
+                _ => break,
+            }
+        }
+
+        Ok(rooms)
+    }
+
+    /// Get a joined room with the given room id.
+    ///
+    /// # Arguments
+    ///
+    /// `room_id` - The unique id of the room that should be fetched.
+    pub fn get_joined_room(&self, room_id: &RoomId) -> Option<room::Joined> {
+        self.store()
+            .get_room(room_id)
+            .and_then(|room| room::Joined::new(self.clone(), room))
+    }
+
+    /// Get an invited room with the given room id.
+    ///
+    /// # Arguments
+    ///
+    /// `room_id` - The unique id of the room that should be fetched.
+    pub fn get_invited_room(&self, room_id: &RoomId) -> Option<room::Invited> {
+        self.store()
+            .get_room(room_id)
+            .and_then(|room| room::Invited::new(self.clone(), room))
+    }
+
+    /// Get a left room with the given room id.
+    ///
+    /// # Arguments
+    ///
+    /// `room_id` - The unique id of the room that should be fetched.
+    pub fn get_left_room(&self, room_id: &RoomId) -> Option<room::Left> {
+        self.store()
+            .get_room(room_id)
+            .and_then(|room| room::Left::new(self.clone(), room))
     }
 
     /// Gets the homeserver’s supported login types.
@@ -1360,6 +1968,420 @@ impl Client {
         self.send(request, None).await
     }
 
+    /// Get the joined rooms that have the given tag.
+    ///
+    /// The rooms are sorted by the order of the tag, rooms without an order
+    /// come last.
+    ///
+    /// # Arguments
+    ///
+    /// * `tag` - The name of the tag, e.g. [`FAVOURITE_TAG`].
+    ///
+    /// [`FAVOURITE_TAG`]: constant.FAVOURITE_TAG.html
+    pub fn rooms_with_tag(&self, tag: &str) -> Vec<room::Joined> {
+        let mut rooms: Vec<(Option<f64>, room::Joined)> = self
+            .joined_rooms()
+            .into_iter()
+            .filter_map(|room| room.tag(tag).map(|t| (t.order, room)))
+            .collect();
+
+        rooms.sort_by(|(a, room_a), (b, room_b)| {
+            let order = match (a, b) {
+                (Some(a), Some(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            };
+
+            order.then_with(|| room_a.room_id().cmp(room_b.room_id()))
+        });
+
+        rooms.into_iter().map(|(_, room)| room).collect()
+    }
+
+    /// Get a joined direct message room with the given user.
+    ///
+    /// Returns `None` if none of the joined rooms is marked as a direct
+    /// message with the user in the `m.direct` account data, or if the user
+    /// isn't a joined or invited member of the marked rooms anymore.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user the direct message room is shared with.
+    pub async fn get_dm_room(&self, user_id: &UserId) -> Result<Option<room::Joined>> {
+        for room in self.joined_rooms() {
+            if room.direct_target().as_ref() != Some(user_id) {
+                continue;
+            }
+
+            if room
+                .active_members()
+                .await?
+                .iter()
+                .any(|m| m.user_id() == user_id)
+            {
+                return Ok(Some(room));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Create an encrypted direct message room with the given user.
+    ///
+    /// The user is invited to a new private room that has encryption enabled
+    /// and the room is added to the `m.direct` account data. Use
+    /// [`create_room`] followed by [`mark_as_dm`] to create direct message
+    /// rooms with different settings.
+    ///
+    /// Returns the room id of the new room. If the room was created but the
+    /// `m.direct` account data couldn't be updated an
+    /// [`Error::DirectRoomNotMarked`] containing the room id is returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user the direct message room should be shared with.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use futures::executor::block_on;
+    /// # use matrix_sdk::{Client, identifiers::user_id};
+    /// # use url::Url;
+    /// # let homeserver = Url::parse("http://example.com").unwrap();
+    /// # block_on(async {
+    /// # let client = Client::new(homeserver).unwrap();
+    /// let alice = user_id!("@alice:example.org");
+    ///
+    /// if client.get_dm_room(&alice).await.unwrap().is_none() {
+    ///     client.create_dm(&alice).await.unwrap();
+    /// }
+    /// # })
+    /// ```
+    ///
+    /// [`create_room`]: #method.create_room
+    /// [`mark_as_dm`]: #method.mark_as_dm
+    pub async fn create_dm(&self, user_id: &UserId) -> Result<RoomId> {
+        let invite = [user_id.clone()];
+        let initial_state = [Raw::from(AnyInitialStateEvent::RoomEncryption(
+            InitialStateEvent {
+                content: EncryptionEventContent::new(EventEncryptionAlgorithm::MegolmV1AesSha2),
+                state_key: String::new(),
+            },
+        ))];
+
+        let request = assign!(create_room::Request::new(), {
+            invite: &invite,
+            is_direct: true,
+            preset: Some(RoomPreset::TrustedPrivateChat),
+            initial_state: &initial_state,
+        });
+
+        let response = self.create_room(request).await?;
+
+        match self.mark_as_dm(&response.room_id, user_id).await {
+            Ok(()) => Ok(response.room_id),
+            Err(e) => Err(Error::DirectRoomNotMarked {
+                room_id: response.room_id,
+                source: Box::new(e),
+            }),
+        }
+    }
+
+    /// Mark an existing room as a direct message room with the given user in
+    /// the `m.direct` account data.
+    ///
+    /// The change is reflected in [`Room::is_direct`] after the next sync.
+    ///
+    /// The `m.direct` event is read from the homeserver, modified and written
+    /// back. Concurrent updates of this client are serialized, but changes
+    /// other devices make to it at the same time can get lost.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The room that should be marked as a direct message room.
+    ///
+    /// * `user_id` - The user the direct message room is shared with.
+    ///
+    /// [`Room::is_direct`]: struct.BaseRoom.html#method.is_direct
+    pub async fn mark_as_dm(&self, room_id: &RoomId, user_id: &UserId) -> Result<()> {
+        self.update_direct_rooms(|direct_rooms| {
+            let rooms = direct_rooms.entry(user_id.clone()).or_insert_with(Vec::new);
+
+            if !rooms.contains(room_id) {
+                rooms.push(room_id.clone());
+            }
+        })
+        .await
+    }
+
+    /// Remove the direct message marker of a room from the `m.direct` account
+    /// data.
+    ///
+    /// The change is reflected in [`Room::is_direct`] after the next sync.
+    ///
+    /// The `m.direct` event is read from the homeserver, modified and written
+    /// back. Concurrent updates of this client are serialized, but changes
+    /// other devices make to it at the same time can get lost.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The room that shouldn't be a direct message room anymore.
+    ///
+    /// [`Room::is_direct`]: struct.BaseRoom.html#method.is_direct
+    pub async fn unmark_as_dm(&self, room_id: &RoomId) -> Result<()> {
+        self.update_direct_rooms(|direct_rooms| {
+            for rooms in direct_rooms.values_mut() {
+                rooms.retain(|r| r != room_id);
+            }
+
+            direct_rooms.retain(|_, rooms| !rooms.is_empty());
+        })
+        .await
+    }
+
+    /// Modify the `m.direct` account data.
+    async fn update_direct_rooms(
+        &self,
+        update: impl FnOnce(&mut BTreeMap<UserId, Vec<RoomId>>),
+    ) -> Result<()> {
+        self.update_global_account_data(&EventType::Direct.to_string(), update)
+            .await
+    }
+
+    /// Modify the content of a global account data event.
+    ///
+    /// The current content is fetched from the homeserver so changes from
+    /// other devices aren't lost, concurrent modifications of this client are
+    /// serialized. A missing event is treated as the default content.
+    ///
+    /// The homeserver doesn't offer a way to atomically modify account data,
+    /// so this is a read-modify-write cycle: a change another device makes
+    /// between our read and our write is overwritten. The window is only as
+    /// long as the two requests take, but callers that can't tolerate this
+    /// need to coordinate their devices themselves.
+    async fn update_global_account_data<C>(
+        &self,
+        event_type: &str,
+        update: impl FnOnce(&mut C),
+    ) -> Result<()>
+    where
+        C: Serialize + DeserializeOwned + Default,
+    {
+        let user_id = self.user_id().await.ok_or(Error::AuthenticationRequired)?;
+
+        let _guard = self.account_data_lock.lock().await;
+
+        let request = get_global_account_data::Request::new(&user_id, event_type);
+        let response = self.send(request, None).await;
+
+        let mut content: C = match response {
+            Ok(response) => serde_json::from_str(response.account_data.json().get())?,
+            Err(Error::Http(HttpError::FromHttpResponse(FromHttpResponseError::Http(
+                ServerError::Known(e),
+            )))) if matches!(e.kind, ErrorKind::NotFound) => C::default(),
+            Err(e) => return Err(e),
+        };
+
+        update(&mut content);
+
+        let request =
+            set_global_account_data::Request::new(to_raw_value(&content)?, event_type, &user_id);
+        self.send(request, None).await?;
+
+        Ok(())
+    }
+
+    /// Get the content of a global account data event, e.g. the `m.direct`
+    /// or the push rules event.
+    ///
+    /// The account data is read from the store and kept up to date when
+    /// syncing. Returns `None` if no such event was received.
+    ///
+    /// # Arguments
+    ///
+    /// * `event_type` - The event type of the account data event.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use futures::executor::block_on;
+    /// # use matrix_sdk::Client;
+    /// # use matrix_sdk::events::{push_rules::PushRulesEventContent, EventType};
+    /// # use url::Url;
+    /// # let homeserver = Url::parse("http://example.com").unwrap();
+    /// # block_on(async {
+    /// # let client = Client::new(homeserver).unwrap();
+    /// let push_rules: Option<PushRulesEventContent> =
+    ///     client.account_data(EventType::PushRules).await.unwrap();
+    /// # });
+    /// ```
+    pub async fn account_data<C: DeserializeOwned>(
+        &self,
+        event_type: EventType,
+    ) -> Result<Option<C>> {
+        Ok(self
+            .store()
+            .get_account_data_event(event_type)
+            .await?
+            .map(|e| serde_json::to_value(e.content()).and_then(serde_json::from_value))
+            .transpose()?)
+    }
+
+    /// Set a global account data event.
+    ///
+    /// The event type is taken from the content, custom account data can be
+    /// set using a `CustomEventContent`.
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - The content of the account data event.
+    pub async fn set_account_data(&self, content: impl BasicEventContent) -> Result<()> {
+        let user_id = self.user_id().await.ok_or(Error::AuthenticationRequired)?;
+
+        let request = set_global_account_data::Request::new(
+            to_raw_value(&content)?,
+            content.event_type(),
+            &user_id,
+        );
+        self.send(request, None).await?;
+
+        Ok(())
+    }
+
+    /// Subscribe to a global account data event type.
+    ///
+    /// The returned stream yields every event of the given type that is
+    /// received in a sync, the current content can be fetched with
+    /// [`account_data`]. Dropping the stream ends the subscription.
+    ///
+    /// # Arguments
+    ///
+    /// * `event_type` - The event type of the account data event.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use futures::{executor::block_on, StreamExt};
+    /// # use matrix_sdk::Client;
+    /// # use matrix_sdk::events::EventType;
+    /// # use url::Url;
+    /// # let homeserver = Url::parse("http://example.com").unwrap();
+    /// # block_on(async {
+    /// # let client = Client::new(homeserver).unwrap();
+    /// let mut push_rules = client.account_data_stream(EventType::PushRules);
+    ///
+    /// while let Some(event) = push_rules.next().await {
+    ///     println!("The push rules changed: {:?}", event);
+    /// }
+    /// # });
+    /// ```
+    ///
+    /// [`account_data`]: #method.account_data
+    pub fn account_data_stream(&self, event_type: EventType) -> impl Stream<Item = AnyBasicEvent> {
+        self.subscribe_account_data(None, event_type)
+    }
+
+    /// Register a new subscriber for the account data events of the given
+    /// type, in the given room or globally if `room_id` is `None`.
+    pub(crate) fn subscribe_account_data(
+        &self,
+        room_id: Option<&RoomId>,
+        event_type: EventType,
+    ) -> UnboundedReceiver<AnyBasicEvent> {
+        let (sender, receiver) = unbounded();
+
+        self.account_data_subscribers
+            .entry((room_id.cloned(), event_type.to_string()))
+            .or_insert_with(Vec::new)
+            .push(sender);
+
+        receiver
+    }
+
+    /// Forward the account data events of a sync response to their
+    /// subscribers, forgetting about subscribers that went away.
+    fn notify_account_data_subscribers(&self, response: &SyncResponse) {
+        if self.account_data_subscribers.is_empty() {
+            return;
+        }
+
+        let global = response.account_data.events.iter().map(|e| (None, e));
+        let rooms = response.rooms.join.iter().flat_map(|(room_id, room)| {
+            room.account_data
+                .events
+                .iter()
+                .map(move |e| (Some(room_id.clone()), e))
+        });
+
+        for (room_id, event) in global.chain(rooms) {
+            let key = (room_id, event.content().event_type().to_owned());
+
+            if let Some(mut subscribers) = self.account_data_subscribers.get_mut(&key) {
+                subscribers.retain(|s| s.unbounded_send(event.clone()).is_ok());
+            }
+        }
+    }
+
+    /// Ignore a user, adding them to the `m.ignored_user_list` account data.
+    ///
+    /// Once the account data is synced, messages and invites of the user are
+    /// dropped and won't be passed to the event handler.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user that should be ignored.
+    pub async fn ignore_user(&self, user_id: &UserId) -> Result<()> {
+        self.update_ignored_users(|ignored_users| {
+            ignored_users
+                .entry(user_id.clone())
+                .or_insert_with(|| JsonValue::Object(Default::default()));
+        })
+        .await
+    }
+
+    /// Stop ignoring a user, removing them from the `m.ignored_user_list`
+    /// account data.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user that shouldn't be ignored anymore.
+    pub async fn unignore_user(&self, user_id: &UserId) -> Result<()> {
+        self.update_ignored_users(|ignored_users| {
+            ignored_users.remove(user_id);
+        })
+        .await
+    }
+
+    /// Modify the users in the `m.ignored_user_list` account data.
+    async fn update_ignored_users(
+        &self,
+        update: impl FnOnce(&mut BTreeMap<UserId, JsonValue>),
+    ) -> Result<()> {
+        /// The content of the `m.ignored_user_list` account data event.
+        #[derive(Default, Deserialize, Serialize)]
+        struct IgnoredUserList {
+            #[serde(default)]
+            ignored_users: BTreeMap<UserId, JsonValue>,
+        }
+
+        self.update_global_account_data(
+            &EventType::IgnoredUserList.to_string(),
+            |list: &mut IgnoredUserList| update(&mut list.ignored_users),
+        )
+        .await
+    }
+
+    /// Get the users that are ignored in the `m.ignored_user_list` account
+    /// data.
+    ///
+    /// The list is read from the store and kept up to date when syncing.
+    pub async fn ignored_users(&self) -> Result<Vec<UserId>> {
+        Ok(self
+            .base_client
+            .ignored_users()
+            .await?
+            .into_iter()
+            .collect())
+    }
+
     /// Search the homeserver's directory of public rooms with a filter.
     ///
     /// Sends a request to "_matrix/client/r0/publicRooms", returns
@@ -1695,15 +2717,277 @@ impl Client {
         self.send(request, None).await
     }
 
-    /// Synchronize the client's state with the latest state on the server.
-    ///
-    /// **Note**: You should not use this method to repeatedly sync if encryption
-    /// support is enabled, the [`sync`] method will make additional
-    /// requests between syncs that are needed for E2E encryption to work.
+    /// Get the user id the homeserver associates with our access token.
     ///
-    /// # Arguments
+    /// This can be used to check if the access token is still valid.
     ///
-    /// * `sync_settings` - Settings for the sync call.
+    /// # Example
+    /// ```no_run
+    /// # use futures::executor::block_on;
+    /// # use matrix_sdk::Client;
+    /// # use url::Url;
+    /// # let homeserver = Url::parse("http://example.com").unwrap();
+    /// # block_on(async {
+    /// # let client = Client::new(homeserver).unwrap();
+    /// let response = client.whoami().await.unwrap();
+    /// println!("Logged in as {}", response.user_id);
+    /// # })
+    /// ```
+    pub async fn whoami(&self) -> Result<whoami::Response> {
+        let request = whoami::Request::new();
+
+        self.send(request, None).await
+    }
+
+    /// Change the password of our own account.
+    ///
+    /// # Arguments
+    ///
+    /// * `new_password` - The new password of the account.
+    ///
+    /// * `logout_devices` - Should all devices except the current one be
+    /// logged out.
+    ///
+    /// * `auth_data` - This request requires user interactive auth, the first
+    /// request needs to set this to `None` and will always fail with an
+    /// `UiaaResponse`. The response will contain information for the
+    /// interactive auth and the same request needs to be made but this time
+    /// with some `auth_data` provided.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use matrix_sdk::{api::r0::uiaa::AuthData, Client};
+    /// # use futures::executor::block_on;
+    /// # use serde_json::json;
+    /// # use url::Url;
+    /// # use std::collections::BTreeMap;
+    /// # block_on(async {
+    /// # let homeserver = Url::parse("http://localhost:8080").unwrap();
+    /// # let client = Client::new(homeserver).unwrap();
+    /// if let Err(e) = client.change_password("new_password", false, None).await {
+    ///     if let Some(info) = e.uiaa_response() {
+    ///         let mut auth_parameters = BTreeMap::new();
+    ///
+    ///         let identifier = json!({
+    ///             "type": "m.id.user",
+    ///             "user": "example",
+    ///         });
+    ///         auth_parameters.insert("identifier".to_owned(), identifier);
+    ///         auth_parameters.insert("password".to_owned(), "old_password".into());
+    ///
+    ///         let auth_data = AuthData::DirectRequest {
+    ///             kind: "m.login.password",
+    ///             auth_parameters,
+    ///             session: info.session.as_deref(),
+    ///         };
+    ///
+    ///         client
+    ///             .change_password("new_password", false, Some(auth_data))
+    ///             .await
+    ///             .expect("Can't change the password");
+    ///     }
+    /// }
+    /// # });
+    /// ```
+    pub async fn change_password(
+        &self,
+        new_password: &str,
+        logout_devices: bool,
+        auth_data: Option<AuthData<'_>>,
+    ) -> Result<change_password::Response> {
+        let request = assign!(change_password::Request::new(new_password), {
+            logout_devices,
+            auth: auth_data,
+        });
+
+        self.send(request, None).await
+    }
+
+    /// Deactivate our own account.
+    ///
+    /// **Warning**: This can't be undone, the user id of the account can't be
+    /// reused after the account has been deactivated.
+    ///
+    /// # Arguments
+    ///
+    /// * `id_server` - The identity server from which all third party
+    /// identifiers should be unbound, if `None` the homeserver will use the
+    /// identity server the identifiers were bound with.
+    ///
+    /// * `auth_data` - This request requires user interactive auth, the first
+    /// request needs to set this to `None` and will always fail with an
+    /// `UiaaResponse`. The response will contain information for the
+    /// interactive auth and the same request needs to be made but this time
+    /// with some `auth_data` provided.
+    pub async fn deactivate(
+        &self,
+        id_server: Option<&str>,
+        auth_data: Option<AuthData<'_>>,
+    ) -> Result<deactivate::Response> {
+        let request = assign!(deactivate::Request::new(), {
+            id_server,
+            auth: auth_data,
+        });
+
+        self.send(request, None).await
+    }
+
+    /// Get the third party identifiers, e.g. email addresses or phone numbers,
+    /// that are associated with our own account.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use futures::executor::block_on;
+    /// # use matrix_sdk::Client;
+    /// # use url::Url;
+    /// # let homeserver = Url::parse("http://example.com").unwrap();
+    /// # block_on(async {
+    /// # let client = Client::new(homeserver).unwrap();
+    /// let response = client.third_party_ids().await.unwrap();
+    ///
+    /// for threepid in response.threepids {
+    ///     println!("{:?}: {}", threepid.medium, threepid.address);
+    /// }
+    /// # })
+    /// ```
+    pub async fn third_party_ids(&self) -> Result<get_3pids::Response> {
+        let request = get_3pids::Request::new();
+
+        self.send(request, None).await
+    }
+
+    /// Request a validation token for an email address that should be added to
+    /// our own account.
+    ///
+    /// The homeserver will send an email containing the token to the given
+    /// address. Once the user validated the address, the returned session id
+    /// can be passed to [`add_third_party_id`] to add the address to the
+    /// account.
+    ///
+    /// # Arguments
+    ///
+    /// * `client_secret` - A unique string generated by the client, used to
+    /// identify the validation attempt.
+    ///
+    /// * `email` - The email address that should be validated.
+    ///
+    /// * `send_attempt` - The number of the attempt, the homeserver will only
+    /// send a new email if this is incremented.
+    ///
+    /// [`add_third_party_id`]: #method.add_third_party_id
+    pub async fn request_email_token(
+        &self,
+        client_secret: &str,
+        email: &str,
+        send_attempt: UInt,
+    ) -> Result<request_3pid_management_token_via_email::Response> {
+        let request = request_3pid_management_token_via_email::Request::new(
+            client_secret,
+            email,
+            send_attempt,
+        );
+
+        self.send(request, None).await
+    }
+
+    /// Request a validation token for a phone number that should be added to
+    /// our own account.
+    ///
+    /// The homeserver will send a SMS containing the token to the given phone
+    /// number. Once the user validated the number, the returned session id can
+    /// be passed to [`add_third_party_id`] to add the number to the account.
+    ///
+    /// # Arguments
+    ///
+    /// * `client_secret` - A unique string generated by the client, used to
+    /// identify the validation attempt.
+    ///
+    /// * `country` - The two-letter uppercase ISO-3166-1 alpha-2 country code
+    /// the phone number should be parsed as.
+    ///
+    /// * `phone_number` - The phone number that should be validated.
+    ///
+    /// * `send_attempt` - The number of the attempt, the homeserver will only
+    /// send a new SMS if this is incremented.
+    ///
+    /// [`add_third_party_id`]: #method.add_third_party_id
+    pub async fn request_msisdn_token(
+        &self,
+        client_secret: &str,
+        country: &str,
+        phone_number: &str,
+        send_attempt: UInt,
+    ) -> Result<request_3pid_management_token_via_msisdn::Response> {
+        let request = request_3pid_management_token_via_msisdn::Request::new(
+            client_secret,
+            country,
+            phone_number,
+            send_attempt,
+        );
+
+        self.send(request, None).await
+    }
+
+    /// Add a validated third party identifier to our own account.
+    ///
+    /// # Arguments
+    ///
+    /// * `client_secret` - The client secret that was used to request the
+    /// validation token.
+    ///
+    /// * `sid` - The session id that the homeserver returned when the
+    /// validation token was requested.
+    ///
+    /// * `auth_data` - This request requires user interactive auth, the first
+    /// request needs to set this to `None` and will always fail with an
+    /// `UiaaResponse`. The response will contain information for the
+    /// interactive auth and the same request needs to be made but this time
+    /// with some `auth_data` provided.
+    pub async fn add_third_party_id(
+        &self,
+        client_secret: &str,
+        sid: &str,
+        auth_data: Option<AuthData<'_>>,
+    ) -> Result<add_3pid::Response> {
+        let request = assign!(add_3pid::Request::new(client_secret, sid), {
+            auth: auth_data,
+        });
+
+        self.send(request, None).await
+    }
+
+    /// Remove a third party identifier from our own account.
+    ///
+    /// # Arguments
+    ///
+    /// * `medium` - The medium of the third party identifier.
+    ///
+    /// * `address` - The address of the third party identifier, e.g. the email
+    /// address.
+    ///
+    /// * `id_server` - The identity server from which the identifier should be
+    /// unbound, if `None` the homeserver will use the identity server the
+    /// identifier was bound with.
+    pub async fn delete_third_party_id(
+        &self,
+        medium: Medium,
+        address: &str,
+        id_server: Option<&str>,
+    ) -> Result<delete_3pid::Response> {
+        let request = assign!(delete_3pid::Request::new(medium, address), { id_server });
+
+        self.send(request, None).await
+    }
+
+    /// Synchronize the client's state with the latest state on the server.
+    ///
+    /// **Note**: You should not use this method to repeatedly sync if encryption
+    /// support is enabled, the [`sync`] method will make additional
+    /// requests between syncs that are needed for E2E encryption to work.
+    ///
+    /// # Arguments
+    ///
+    /// * `sync_settings` - Settings for the sync call.
     ///
     /// [`sync`]: #method.sync
     #[instrument]
@@ -1712,7 +2996,7 @@ impl Client {
             filter: sync_settings.filter.as_ref(),
             since: sync_settings.token.as_deref(),
             full_state: sync_settings.full_state,
-            set_presence: &PresenceState::Online,
+            set_presence: &sync_settings.set_presence,
             timeout: sync_settings.timeout,
         });
 
@@ -1724,12 +3008,58 @@ impl Client {
         );
 
         let response = self.send(request, Some(request_config)).await?;
+        self.receive_sync_response(response).await
+    }
+
+    /// Feed a sync response into the state machine and notify the event
+    /// handler about it.
+    pub(crate) async fn receive_sync_response(
+        &self,
+        response: sync_events::Response,
+    ) -> Result<SyncResponse> {
         let sync_response = self.base_client.receive_sync_response(response).await?;
 
         if let Some(handler) = self.event_handler.read().await.as_ref() {
             handler.handle_sync(&sync_response).await;
         }
 
+        self.notify_account_data_subscribers(&sync_response);
+
+        if self.follow_tombstones {
+            for (room_id, room_update) in &sync_response.rooms.join {
+                // Only follow tombstones when they arrive, otherwise a failed
+                // or left replacement room would be joined again on every
+                // sync.
+                let tombstone_received = room_update
+                    .state
+                    .events
+                    .iter()
+                    .any(|e| matches!(e, AnySyncStateEvent::RoomTombstone(_)))
+                    || room_update.timeline.events.iter().any(|e| {
+                        matches!(
+                            e.event,
+                            AnySyncRoomEvent::State(AnySyncStateEvent::RoomTombstone(_))
+                        )
+                    });
+
+                if !tombstone_received {
+                    continue;
+                }
+
+                let room = match self.get_joined_room(room_id) {
+                    Some(r) if r.is_tombstoned() => r,
+                    _ => continue,
+                };
+
+                if let Err(e) = room.join_successor().await {
+                    warn!(
+                        "Couldn't join the replacement room of the upgraded room {}: {:?}",
+                        room_id, e
+                    );
+                }
+            }
+        }
+
         Ok(sync_response)
     }
 
@@ -1909,6 +3239,82 @@ impl Client {
         }
     }
 
+    /// Send an encrypted to-device event to the given devices.
+    ///
+    /// Olm sessions are established with devices that we don't have one with
+    /// yet. Only devices that we know about are considered, i.e. the devices
+    /// of users we share an encrypted room with, see [`get_user_devices`].
+    ///
+    /// # Arguments
+    ///
+    /// * `event_type` - The type of the event that should be sent.
+    ///
+    /// * `content` - The content of the event that should be sent.
+    ///
+    /// * `recipients` - The devices that should receive the event, grouped by
+    /// their owner. An empty list of devices sends the event to all devices of
+    /// the user.
+    ///
+    /// Returns the devices that the event couldn't be sent to, because we
+    /// don't know them or couldn't establish an Olm session with them.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::{collections::BTreeMap, convert::TryFrom};
+    /// # use matrix_sdk::{Client, events::EventType, identifiers::UserId};
+    /// # use serde_json::json;
+    /// # use url::Url;
+    /// # use futures::executor::block_on;
+    /// # let homeserver = Url::parse("http://example.com").unwrap();
+    /// # let client = Client::new(homeserver).unwrap();
+    /// # block_on(async {
+    /// let alice = UserId::try_from("@alice:example.org").unwrap();
+    ///
+    /// let mut recipients = BTreeMap::new();
+    /// recipients.insert(alice, vec![]);
+    ///
+    /// let skipped = client
+    ///     .send_encrypted_to_device(
+    ///         EventType::Custom("org.example.sync".to_owned()),
+    ///         json!({ "position": 42 }),
+    ///         &recipients,
+    ///     )
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// for device in skipped {
+    ///     println!("Couldn't send the event to {} {}", device.user_id, device.device_id);
+    /// }
+    /// # });
+    /// ```
+    ///
+    /// [`get_user_devices`]: #method.get_user_devices
+    #[cfg(feature = "encryption")]
+    #[cfg_attr(feature = "docs", doc(cfg(encryption)))]
+    pub async fn send_encrypted_to_device(
+        &self,
+        event_type: EventType,
+        content: JsonValue,
+        recipients: &BTreeMap<UserId, Vec<DeviceIdBox>>,
+    ) -> Result<Vec<SkippedDevice>> {
+        self.claim_one_time_keys(recipients.keys()).await?;
+
+        let encrypted = self
+            .base_client
+            .encrypt_to_device(event_type, content, recipients)
+            .await?;
+
+        for request in encrypted.requests {
+            let response = self.send_to_device(&request).await?;
+            self.base_client
+                .mark_request_as_sent(&request.txn_id, &response)
+                .await?;
+        }
+
+        Ok(encrypted.skipped_devices)
+    }
+
     /// Claim one-time keys creating new Olm sessions.
     ///
     /// # Arguments
@@ -1937,7 +3343,7 @@ impl Client {
     /// Upload the E2E encryption keys.
     ///
     /// This uploads the long lived device keys as well as the required amount
-    /// of one-time keys.
+    /// of one-time keys and our fallback key.
     ///
     /// # Panics
     ///
@@ -1949,15 +3355,24 @@ impl Client {
     async fn keys_upload(
         &self,
         request_id: &Uuid,
-        request: &upload_keys::Request,
+        request: &KeysUploadRequest,
     ) -> Result<upload_keys::Response> {
         debug!(
-            "Uploading encryption keys device keys: {}, one-time-keys: {}",
+            "Uploading encryption keys device keys: {}, one-time-keys: {}, fallback keys: {}",
             request.device_keys.is_some(),
-            request.one_time_keys.as_ref().map_or(0, |k| k.len())
+            request.one_time_keys.as_ref().map_or(0, |k| k.len()),
+            request.fallback_keys.as_ref().map_or(0, |k| k.len()),
         );
 
-        let response = self.send(request.clone(), None).await?;
+        let request = assign!(unstable_upload_keys::Request::new(), {
+            device_keys: request.device_keys.clone(),
+            one_time_keys: request.one_time_keys.clone(),
+            fallback_keys: request.fallback_keys.clone(),
+        });
+
+        let response = self.send(request, None).await?;
+        let response = upload_keys::Response::new(response.one_time_key_counts);
+
         self.base_client
             .mark_request_as_sent(request_id, &response)
             .await?;
@@ -1965,6 +3380,20 @@ impl Client {
         Ok(response)
     }
 
+    /// Upload the signatures of a signature upload request and mark the
+    /// request as sent.
+    #[cfg(feature = "encryption")]
+    pub(crate) async fn upload_signatures(&self, request: &OutgoingRequest) -> Result<()> {
+        if let OutgoingRequests::SignatureUpload(r) = request.request() {
+            let response = self.send(r.clone(), None).await?;
+            self.base_client
+                .mark_request_as_sent(request.request_id(), &response)
+                .await?;
+        }
+
+        Ok(())
+    }
+
     /// Get the current, if any, sync token of the client.
     /// This will be None if the client didn't sync at least once.
     pub async fn sync_token(&self) -> Option<String> {
@@ -2147,44 +3576,426 @@ impl Client {
         Ok(())
     }
 
-    /// Get a map holding all the devices of an user.
-    ///
-    /// This will always return an empty map if the client hasn't been logged
-    /// in.
+    /// Set the share strategy deciding which devices receive our room keys in
+    /// rooms that don't have their own strategy.
     ///
     /// # Arguments
     ///
-    /// * `user_id` - The unique id of the user that the devices belong to.
+    /// * `strategy` - The strategy that should be used.
     ///
     /// # Example
     ///
     /// ```no_run
-    /// # use std::convert::TryFrom;
-    /// # use matrix_sdk::{Client, identifiers::UserId};
+    /// # use matrix_sdk::{Client, ShareStrategy};
     /// # use url::Url;
     /// # use futures::executor::block_on;
-    /// # let alice = UserId::try_from("@alice:example.org").unwrap();
     /// # let homeserver = Url::parse("http://example.com").unwrap();
     /// # let client = Client::new(homeserver).unwrap();
     /// # block_on(async {
-    /// let devices = client.get_user_devices(&alice).await.unwrap();
-    ///
-    /// for device in devices.devices() {
-    ///     println!("{:?}", device);
-    /// }
+    /// client
+    ///     .set_share_strategy(ShareStrategy::VerifiedDevices)
+    ///     .await
+    ///     .unwrap();
     /// # });
     /// ```
     #[cfg(feature = "encryption")]
     #[cfg_attr(feature = "docs", doc(cfg(encryption)))]
-    pub async fn get_user_devices(
-        &self,
-        user_id: &UserId,
-    ) -> StdResult<UserDevices, CryptoStoreError> {
-        let devices = self.base_client.get_user_devices(user_id).await?;
+    pub async fn set_share_strategy(&self, strategy: ShareStrategy) -> Result<()> {
+        let olm = self
+            .base_client
+            .olm_machine()
+            .await
+            .ok_or(Error::AuthenticationRequired)?;
 
-        Ok(UserDevices {
-            inner: devices,
-            client: self.clone(),
+        Ok(olm.set_share_strategy(strategy).await?)
+    }
+
+    /// Set the share strategy deciding which devices receive our room keys in
+    /// the given room.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The id of the room the strategy should be used in.
+    ///
+    /// * `strategy` - The strategy that should be used, `None` to fall back
+    /// to the global strategy.
+    #[cfg(feature = "encryption")]
+    #[cfg_attr(feature = "docs", doc(cfg(encryption)))]
+    pub async fn set_room_share_strategy(
+        &self,
+        room_id: &RoomId,
+        strategy: Option<ShareStrategy>,
+    ) -> Result<()> {
+        let olm = self
+            .base_client
+            .olm_machine()
+            .await
+            .ok_or(Error::AuthenticationRequired)?;
+
+        Ok(olm.set_room_share_strategy(room_id, strategy).await?)
+    }
+
+    /// Set the policy deciding which incoming room key requests get answered.
+    ///
+    /// The policy isn't persisted, it needs to be set again every time the
+    /// client is restored.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - The policy that should be used for new key requests.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # use matrix_sdk::{Client, KeyShareRequestInfo, KeySharePolicy, KeyshareDecision};
+    /// # use url::Url;
+    /// # use futures::executor::block_on;
+    /// # let homeserver = Url::parse("http://example.com").unwrap();
+    /// # let client = Client::new(homeserver).unwrap();
+    /// # block_on(async {
+    /// // Only answer key requests from verified devices that we originally
+    /// // sent the session to.
+    /// let policy = KeySharePolicy::Custom(Arc::new(|info: &KeyShareRequestInfo| {
+    ///     if info.device.trust_state() && info.shared_index().is_some() {
+    ///         Ok(())
+    ///     } else {
+    ///         Err(KeyshareDecision::RejectedByPolicy(
+    ///             "not a verified recipient".to_owned(),
+    ///         ))
+    ///     }
+    /// }));
+    ///
+    /// client.set_key_share_policy(policy).await.unwrap();
+    /// # });
+    /// ```
+    #[cfg(feature = "encryption")]
+    #[cfg_attr(feature = "docs", doc(cfg(encryption)))]
+    pub async fn set_key_share_policy(&self, policy: KeySharePolicy) -> Result<()> {
+        let olm = self
+            .base_client
+            .olm_machine()
+            .await
+            .ok_or(Error::AuthenticationRequired)?;
+
+        olm.set_key_share_policy(policy);
+
+        Ok(())
+    }
+
+    /// Get the most recent decisions that were made about incoming room key
+    /// requests, oldest first.
+    #[cfg(feature = "encryption")]
+    #[cfg_attr(feature = "docs", doc(cfg(encryption)))]
+    pub async fn key_share_decisions(&self) -> Result<Vec<KeyShareDecisionRecord>> {
+        let olm = self
+            .base_client
+            .olm_machine()
+            .await
+            .ok_or(Error::AuthenticationRequired)?;
+
+        Ok(olm.key_share_decisions())
+    }
+
+    /// Create a new dehydrated device and upload it to the homeserver.
+    ///
+    /// Other devices will share room keys with the dehydrated device, this
+    /// allows a new login to receive room keys that were sent while all the
+    /// devices of the user were offline using [`rehydrate_device`]. An
+    /// existing dehydrated device will be replaced.
+    ///
+    /// Returns the device id of the new dehydrated device.
+    ///
+    /// # Arguments
+    ///
+    /// * `secret` - The user secret that protects the dehydrated device.
+    ///
+    /// * `display_name` - The display name the dehydrated device should have.
+    ///
+    /// * `rounds` - The number of rounds that should be used for the key
+    /// derivation when the secret gets turned into a pickle key. Should be at
+    /// least `10000`, while values in the `100000` ranges should be preferred.
+    /// Devices that use more than `1000000` rounds can't be rehydrated.
+    ///
+    /// [`rehydrate_device`]: #method.rehydrate_device
+    #[cfg(feature = "encryption")]
+    #[cfg_attr(feature = "docs", doc(cfg(encryption)))]
+    pub async fn dehydrate_device(
+        &self,
+        secret: &str,
+        display_name: &str,
+        rounds: u32,
+    ) -> Result<DeviceIdBox> {
+        let olm = self
+            .base_client
+            .olm_machine()
+            .await
+            .ok_or(Error::AuthenticationRequired)?;
+
+        let device = olm
+            .create_dehydrated_device(secret, display_name, rounds)
+            .await;
+
+        let request = device.request().clone();
+        let request = put_dehydrated_device::Request::new(
+            request.device_data,
+            request.initial_device_display_name,
+        );
+        let response = self.send(request, None).await?;
+
+        let keys = device.keys_for_upload(&response.device_id).await;
+        let request = upload_device_keys::Request::new(
+            response.device_id.clone(),
+            keys.device_keys,
+            keys.one_time_keys,
+            keys.fallback_keys,
+        );
+        self.send(request, None).await?;
+
+        Ok(response.device_id)
+    }
+
+    /// Continue the current session as the dehydrated device of the user.
+    ///
+    /// This should be called right after logging in, before the first sync.
+    /// If the dehydrated device can be decrypted it gets claimed, our access
+    /// token then belongs to the dehydrated device and the room keys that
+    /// were sent to it are received in the following syncs.
+    ///
+    /// A claimed device stops being the dehydrated device of the user, use
+    /// [`dehydrate_device`] to create a new one.
+    ///
+    /// Returns true if the session continues as the dehydrated device, false
+    /// if the user doesn't have a dehydrated device or if another login
+    /// claimed it first.
+    ///
+    /// # Arguments
+    ///
+    /// * `secret` - The user secret that protects the dehydrated device.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::Client;
+    /// # use url::Url;
+    /// # use futures::executor::block_on;
+    /// # let homeserver = Url::parse("http://example.com").unwrap();
+    /// # let client = Client::new(homeserver).unwrap();
+    /// # block_on(async {
+    /// let secret = "It's a secret to everybody";
+    ///
+    /// client.login("example", "wordpass", None, None).await.unwrap();
+    ///
+    /// if client.rehydrate_device(secret).await.unwrap() {
+    ///     client
+    ///         .dehydrate_device(secret, "Dehydrated device", 100_000)
+    ///         .await
+    ///         .unwrap();
+    /// }
+    /// # });
+    /// ```
+    ///
+    /// [`dehydrate_device`]: #method.dehydrate_device
+    #[cfg(feature = "encryption")]
+    #[cfg_attr(feature = "docs", doc(cfg(encryption)))]
+    pub async fn rehydrate_device(&self, secret: &str) -> Result<bool> {
+        let olm = self
+            .base_client
+            .olm_machine()
+            .await
+            .ok_or(Error::AuthenticationRequired)?;
+
+        let dehydrated = match self.send(get_dehydrated_device::Request::new(), None).await {
+            Ok(response) => response,
+            Err(Error::Http(HttpError::FromHttpResponse(FromHttpResponseError::Http(
+                ServerError::Known(e),
+            )))) if matches!(e.kind, ErrorKind::NotFound) => return Ok(false),
+            Err(e) => return Err(e),
+        };
+
+        // Make sure that we can use the device before we claim it.
+        let device = olm
+            .rehydrate_device(secret, &dehydrated.device_id, &dehydrated.device_data)
+            .await?;
+
+        let request = claim_dehydrated_device::Request::new(dehydrated.device_id);
+        let response = self.send(request, None).await?;
+
+        if !response.success {
+            return Ok(false);
+        }
+
+        self.base_client.restore_rehydrated_login(device).await?;
+
+        Ok(true)
+    }
+
+    /// Mark the cross signing identity of the given user as verified after it
+    /// was verified out of band, e.g. by comparing the fingerprint of its
+    /// master key.
+    ///
+    /// This uploads the same signatures an interactive verification would.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The id of the user whose identity should be verified.
+    #[cfg(feature = "encryption")]
+    #[cfg_attr(feature = "docs", doc(cfg(encryption)))]
+    pub async fn verify_user_identity(&self, user_id: &UserId) -> Result<()> {
+        let olm = self
+            .base_client
+            .olm_machine()
+            .await
+            .ok_or(Error::AuthenticationRequired)?;
+
+        if let Some(request) = olm.verify_user_identity(user_id).await? {
+            self.upload_signatures(&request).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Get the changes of cross signing identities that weren't acknowledged
+    /// yet.
+    ///
+    /// Room keys are withheld from users with unacknowledged identity changes
+    /// unless the share strategy is `ShareStrategy::AllDevices`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::Client;
+    /// # use url::Url;
+    /// # use futures::executor::block_on;
+    /// # let homeserver = Url::parse("http://example.com").unwrap();
+    /// # let client = Client::new(homeserver).unwrap();
+    /// # block_on(async {
+    /// for change in client.identity_changes().await.unwrap() {
+    ///     println!(
+    ///         "The identity of {} changed, was verified: {}",
+    ///         change.user_id, change.was_verified
+    ///     );
+    ///
+    ///     client
+    ///         .acknowledge_identity_change(&change.user_id)
+    ///         .await
+    ///         .unwrap();
+    /// }
+    /// # });
+    /// ```
+    #[cfg(feature = "encryption")]
+    #[cfg_attr(feature = "docs", doc(cfg(encryption)))]
+    pub async fn identity_changes(&self) -> Result<Vec<IdentityChange>> {
+        let olm = self
+            .base_client
+            .olm_machine()
+            .await
+            .ok_or(Error::AuthenticationRequired)?;
+
+        Ok(olm.identity_changes().await?)
+    }
+
+    /// Set a callback that gets called every time a new change of a cross
+    /// signing identity is detected.
+    ///
+    /// # Arguments
+    ///
+    /// * `callback` - The callback that should be notified about new identity
+    /// changes, `None` removes the current callback.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # use matrix_sdk::{Client, IdentityChange};
+    /// # use url::Url;
+    /// # use futures::executor::block_on;
+    /// # let homeserver = Url::parse("http://example.com").unwrap();
+    /// # let client = Client::new(homeserver).unwrap();
+    /// # block_on(async {
+    /// client
+    ///     .set_identity_change_callback(Some(Arc::new(|change: &IdentityChange| {
+    ///         println!("The identity of {} changed", change.user_id);
+    ///     })))
+    ///     .await
+    ///     .unwrap();
+    /// # });
+    /// ```
+    #[cfg(feature = "encryption")]
+    #[cfg_attr(feature = "docs", doc(cfg(encryption)))]
+    pub async fn set_identity_change_callback(
+        &self,
+        callback: Option<IdentityChangeCallback>,
+    ) -> Result<()> {
+        let olm = self
+            .base_client
+            .olm_machine()
+            .await
+            .ok_or(Error::AuthenticationRequired)?;
+
+        olm.set_identity_change_callback(callback);
+
+        Ok(())
+    }
+
+    /// Acknowledge the change of the cross signing identity of the given user.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The id of the user whose identity change should be
+    /// acknowledged.
+    ///
+    /// Returns true if there was a change to acknowledge, false otherwise.
+    #[cfg(feature = "encryption")]
+    #[cfg_attr(feature = "docs", doc(cfg(encryption)))]
+    pub async fn acknowledge_identity_change(&self, user_id: &UserId) -> Result<bool> {
+        let olm = self
+            .base_client
+            .olm_machine()
+            .await
+            .ok_or(Error::AuthenticationRequired)?;
+
+        Ok(olm.acknowledge_identity_change(user_id).await?)
+    }
+
+    /// Get a map holding all the devices of an user.
+    ///
+    /// This will always return an empty map if the client hasn't been logged
+    /// in.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The unique id of the user that the devices belong to.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::convert::TryFrom;
+    /// # use matrix_sdk::{Client, identifiers::UserId};
+    /// # use url::Url;
+    /// # use futures::executor::block_on;
+    /// # let alice = UserId::try_from("@alice:example.org").unwrap();
+    /// # let homeserver = Url::parse("http://example.com").unwrap();
+    /// # let client = Client::new(homeserver).unwrap();
+    /// # block_on(async {
+    /// let devices = client.get_user_devices(&alice).await.unwrap();
+    ///
+    /// for device in devices.devices() {
+    ///     println!("{:?}", device);
+    /// }
+    /// # });
+    /// ```
+    #[cfg(feature = "encryption")]
+    #[cfg_attr(feature = "docs", doc(cfg(encryption)))]
+    pub async fn get_user_devices(
+        &self,
+        user_id: &UserId,
+    ) -> StdResult<UserDevices, CryptoStoreError> {
+        let devices = self.base_client.get_user_devices(user_id).await?;
+
+        Ok(UserDevices {
+            inner: devices,
+            client: self.clone(),
         })
     }
 
@@ -2330,17 +4141,125 @@ impl Client {
         // TODO remove this unwrap.
         let import = task.await.expect("Task join error").unwrap();
 
-        Ok(olm.import_keys(import, |_, _| {}).await?)
-    }
-}
+        let result = olm.import_keys(import, |_, _| {}).await?;
 
-#[cfg(test)]
+        // The imported keys might decrypt events that we couldn't decrypt
+        // before, emit those as if we just received them.
+        let decrypted_events = self.base_client.retry_undecryptable_events().await;
+
+        if let Some(handler) = self.event_handler.read().await.as_ref() {
+            handler.handle_decrypted_events(&decrypted_events).await;
+        }
+
+        Ok(result)
+    }
+}
+
+/// Get the URL that a server name resolves to if no `.well-known` file is
+/// found.
+fn server_name_to_url(server_name: &ServerName, insecure: bool) -> Result<Url> {
+    let scheme = if insecure { "http" } else { "https" };
+
+    Ok(Url::parse(&format!("{}://{}", scheme, server_name))?)
+}
+
+/// Parse a base URL found in a `.well-known/matrix/client` file.
+///
+/// Trailing slashes are removed as the spec requires and URLs without a scheme
+/// default to HTTPS, parsing the URL normalizes the case of the scheme and the
+/// host so every homeserver is represented by a single URL.
+fn parse_base_url(base_url: &str) -> StdResult<Url, UrlParseError> {
+    let base_url = base_url.trim().trim_end_matches('/');
+
+    if base_url.contains("://") {
+        Url::parse(base_url)
+    } else {
+        Url::parse(&format!("https://{}", base_url))
+    }
+}
+
+/// The result of a successful homeserver discovery.
+struct DiscoveredServer {
+    homeserver: Url,
+    identity_server: Option<Url>,
+    supported_versions: SupportedVersions,
+}
+
+/// Discover the homeserver and the identity server of a server name.
+///
+/// This follows the [server discovery] process of the spec, the discovered
+/// homeserver is validated using a supported versions request.
+///
+/// [server discovery]: https://matrix.org/docs/spec/client_server/r0.6.1#server-discovery
+async fn discover_homeserver_url(
+    client: &Arc<dyn HttpSend>,
+    server_name: &ServerName,
+    insecure: bool,
+    request_config: RequestConfig,
+) -> Result<DiscoveredServer> {
+    let server_url = server_name_to_url(server_name, insecure)?;
+
+    let request = discover_homeserver::Request::new()
+        .try_into_http_request(server_url.as_str(), None)
+        .map_err(HttpError::from)?;
+    let response = client
+        .send_request(request, request_config)
+        .await
+        .map_err(DiscoveryError::WellKnown)?;
+
+    let (homeserver, identity_server) = if response.status() == StatusCode::NOT_FOUND {
+        info!(
+            "No well-known file found for {}, using {} as the homeserver",
+            server_name, server_url
+        );
+        (server_url, None)
+    } else {
+        let well_known = discover_homeserver::Response::try_from_http_response(response)
+            .map_err(|e| DiscoveryError::WellKnown(e.into()))?;
+
+        let homeserver = parse_base_url(&well_known.homeserver.base_url)
+            .map_err(DiscoveryError::InvalidHomeserverUrl)?;
+        let identity_server = well_known
+            .identity_server
+            .map(|i| parse_base_url(&i.base_url))
+            .transpose()
+            .map_err(DiscoveryError::InvalidIdentityServerUrl)?;
+
+        (homeserver, identity_server)
+    };
+
+    let request = get_supported_versions::Request::new()
+        .try_into_http_request(homeserver.as_str(), None)
+        .map_err(HttpError::from)?;
+    let response = client
+        .send_request(request, request_config)
+        .await
+        .map_err(DiscoveryError::InvalidHomeserver)?;
+    let response = get_supported_versions::Response::try_from_http_response(response)
+        .map_err(|e| DiscoveryError::InvalidHomeserver(e.into()))?;
+
+    info!(
+        "Discovered homeserver {} for server name {}",
+        homeserver, server_name
+    );
+
+    Ok(DiscoveredServer {
+        homeserver,
+        identity_server,
+        supported_versions: response.into(),
+    })
+}
+
+#[cfg(test)]
 mod test {
-    use crate::{ClientConfig, HttpError, RequestConfig, RoomMember};
+    use crate::{
+        ClientConfig, DefaultRoomNameFormatter, HttpError, RequestConfig, RoomMember,
+        RoomNameFormatter, RoomNameParts, FAVOURITE_TAG,
+    };
 
     use super::{
-        get_public_rooms, get_public_rooms_filtered, register::RegistrationKind, Client, Session,
-        SyncSettings, Url,
+        get_public_rooms, get_public_rooms_filtered, parse_base_url, register::RegistrationKind,
+        sleep, Client, Session, SyncSettings, Url,
     };
     use matrix_sdk_base::identifiers::mxc_uri;
     use matrix_sdk_common::{
@@ -2351,15 +4270,25 @@ mod test {
         },
         assign,
         directory::Filter,
-        events::{room::message::MessageEventContent, AnyMessageEventContent},
-        identifiers::{event_id, room_id, user_id},
+        events::{
+            custom::CustomEventContent, room::message::MessageEventContent, AnyBasicEvent,
+            AnyMessageEventContent, AnySyncRoomEvent, AnySyncStateEvent, EventType,
+        },
+        identifiers::{event_id, room_id, user_id, RoomVersionId, UserId},
+        presence::PresenceState,
         thirdparty,
     };
     use matrix_sdk_test::{test_json, EventBuilder, EventsJson};
     use mockito::{mock, Matcher};
     use serde_json::json;
 
-    use std::{collections::BTreeMap, convert::TryInto, io::Cursor, str::FromStr, time::Duration};
+    use std::{
+        collections::BTreeMap,
+        convert::{TryFrom, TryInto},
+        io::Cursor,
+        str::FromStr,
+        time::Duration,
+    };
 
     async fn logged_in_client() -> Client {
         let session = Session {
@@ -2408,6 +4337,92 @@ mod test {
         assert!(logged_in, "Client should be logged in");
     }
 
+    #[tokio::test]
+    async fn login_with_discovery() {
+        let server_name = mockito::server_url().replace("http://", "");
+        let user_id = UserId::try_from(format!("@example:{}", server_name)).unwrap();
+
+        let _m_well_known = mock("GET", "/.well-known/matrix/client")
+            .with_status(200)
+            .with_body(
+                test_json::WELL_KNOWN
+                    .to_string()
+                    .replace("HOMESERVER_URL", &format!("{}/", mockito::server_url())),
+            )
+            .create();
+
+        let _m_versions = mock("GET", "/_matrix/client/versions")
+            .with_status(200)
+            .with_body(test_json::VERSIONS.to_string())
+            .create();
+
+        // Mockito only knows how to serve plain HTTP.
+        let config = ClientConfig::new().insecure_server_discovery();
+        let client = Client::new_from_user_id_with_config(&user_id, config)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            client.homeserver(),
+            &Url::parse(&mockito::server_url()).unwrap()
+        );
+        assert_eq!(
+            client.identity_server(),
+            Some(&Url::parse("https://identity.example.org").unwrap())
+        );
+
+        let _m_login = mock("POST", "/_matrix/client/r0/login")
+            .with_status(200)
+            .with_body(test_json::LOGIN.to_string())
+            .create();
+
+        client
+            .login(user_id.as_str(), "wordpass", None, None)
+            .await
+            .unwrap();
+
+        let logged_in = client.logged_in().await;
+        assert!(logged_in, "Client should be logged in");
+
+        let versions = client.supported_versions().await.unwrap();
+        assert!(versions.supports_version("r0.6.0"));
+        assert!(versions.supports_unstable_feature("org.matrix.e2e_cross_signing"));
+        assert!(!versions.supports_unstable_feature("unknown_feature"));
+    }
+
+    #[test]
+    fn base_url_normalization() {
+        let homeserver = Url::parse("https://example.org").unwrap();
+
+        assert_eq!(parse_base_url("https://example.org").unwrap(), homeserver);
+        assert_eq!(parse_base_url("https://example.org/").unwrap(), homeserver);
+        assert_eq!(parse_base_url("HTTPS://Example.org//").unwrap(), homeserver);
+        assert_eq!(parse_base_url(" example.org ").unwrap(), homeserver);
+        assert_eq!(
+            parse_base_url("https://example.org/matrix/").unwrap(),
+            Url::parse("https://example.org/matrix").unwrap()
+        );
+        assert!(parse_base_url("https://").is_err());
+    }
+
+    #[tokio::test]
+    async fn capabilities() {
+        let client = logged_in_client().await;
+
+        let m = mock("GET", "/_matrix/client/r0/capabilities")
+            .with_status(200)
+            .with_body(test_json::CAPABILITIES.to_string())
+            .expect(1)
+            .create();
+
+        let capabilities = client.capabilities().await.unwrap();
+        assert_eq!(capabilities.room_versions.unwrap().default.as_str(), "6");
+
+        // The second call is answered from the cache.
+        assert!(!client.can_change_password().await.unwrap());
+        m.assert();
+    }
+
     #[cfg(feature = "sso_login")]
     #[tokio::test]
     async fn login_with_sso() {
@@ -2497,6 +4512,53 @@ mod test {
         assert!(client.devices().await.is_ok());
     }
 
+    #[tokio::test]
+    async fn whoami() {
+        let client = logged_in_client().await;
+
+        let _m = mock("GET", "/_matrix/client/r0/account/whoami")
+            .with_status(200)
+            .with_body(test_json::WHOAMI.to_string())
+            .create();
+
+        let response = client.whoami().await.unwrap();
+        assert_eq!(response.user_id, user_id!("@example:localhost"));
+    }
+
+    #[tokio::test]
+    async fn third_party_ids() {
+        let client = logged_in_client().await;
+
+        let _m = mock("GET", "/_matrix/client/r0/account/3pid")
+            .with_status(200)
+            .with_body(test_json::THREEPIDS.to_string())
+            .create();
+
+        let response = client.third_party_ids().await.unwrap();
+        assert_eq!(response.threepids.len(), 1);
+        assert_eq!(response.threepids[0].medium, thirdparty::Medium::Email);
+        assert_eq!(response.threepids[0].address, "example@example.org");
+    }
+
+    #[tokio::test]
+    async fn change_password() {
+        let client = logged_in_client().await;
+
+        let _m = mock("POST", "/_matrix/client/r0/account/password")
+            .with_status(200)
+            .match_body(Matcher::PartialJson(json!({
+                "new_password": "new_password",
+                "logout_devices": true,
+            })))
+            .with_body(test_json::LOGOUT.to_string())
+            .create();
+
+        client
+            .change_password("new_password", true, None)
+            .await
+            .unwrap();
+    }
+
     #[tokio::test]
     async fn test_join_leave_room() {
         let homeserver = Url::from_str(&mockito::server_url()).unwrap();
@@ -3133,42 +5195,1007 @@ mod test {
         .with_body(test_json::SYNC.to_string())
         .create();
 
-        let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+        let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+        let _response = client.sync_once(sync_settings).await.unwrap();
+
+        let room_id = room_id!("!SVkFJHzfwvuaIEawgC:localhost");
+
+        let room = client.get_joined_room(&room_id).unwrap();
+
+        let avatar_url = mxc_uri!("mxc://example.org/avA7ar");
+        let member_event = MemberEventContent {
+            avatar_url: Some(avatar_url),
+            membership: MembershipState::Join,
+            is_direct: None,
+            displayname: None,
+            third_party_invite: None,
+        };
+        let content = AnyStateEventContent::RoomMember(member_event);
+        let response = room.send_state_event(content, "").await.unwrap();
+        assert_eq!(event_id!("$h29iv0s8:example.com"), response.event_id);
+    }
+
+    #[tokio::test]
+    async fn room_message_send() {
+        use matrix_sdk_common::uuid::Uuid;
+
+        let client = logged_in_client().await;
+
+        let _m = mock(
+            "PUT",
+            Matcher::Regex(r"^/_matrix/client/r0/rooms/.*/send/".to_string()),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .with_body(test_json::EVENT_ID.to_string())
+        .create();
+
+        let _m = mock(
+            "GET",
+            Matcher::Regex(r"^/_matrix/client/r0/sync\?.*$".to_string()),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .with_body(test_json::SYNC.to_string())
+        .create();
+
+        let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+        let _response = client.sync_once(sync_settings).await.unwrap();
+
+        let room = client
+            .get_joined_room(&room_id!("!SVkFJHzfwvuaIEawgC:localhost"))
+            .unwrap();
+
+        let content =
+            AnyMessageEventContent::RoomMessage(MessageEventContent::text_plain("Hello world"));
+        let txn_id = Uuid::new_v4();
+        let response = room.send(content, Some(txn_id)).await.unwrap();
+
+        assert_eq!(event_id!("$h29iv0s8:example.com"), response.event_id)
+    }
+
+    #[tokio::test]
+    async fn room_attachment_send() {
+        let client = logged_in_client().await;
+
+        let _m = mock(
+            "PUT",
+            Matcher::Regex(r"^/_matrix/client/r0/rooms/.*/send/".to_string()),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .with_body(test_json::EVENT_ID.to_string())
+        .create();
+
+        let _m = mock(
+            "POST",
+            Matcher::Regex(r"^/_matrix/media/r0/upload".to_string()),
+        )
+        .with_status(200)
+        .match_header("content-type", "image/jpeg")
+        .with_body(
+            json!({
+              "content_uri": "mxc://example.com/AQwafuaFswefuhsfAFAgsw"
+            })
+            .to_string(),
+        )
+        .create();
+
+        let _m = mock(
+            "GET",
+            Matcher::Regex(r"^/_matrix/client/r0/sync\?.*$".to_string()),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .with_body(test_json::SYNC.to_string())
+        .create();
+
+        let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+        let _response = client.sync_once(sync_settings).await.unwrap();
+
+        let room = client
+            .get_joined_room(&room_id!("!SVkFJHzfwvuaIEawgC:localhost"))
+            .unwrap();
+
+        let mut media = Cursor::new("Hello world");
+
+        let response = room
+            .send_attachment("image", &mime::IMAGE_JPEG, &mut media, None)
+            .await
+            .unwrap();
+
+        assert_eq!(event_id!("$h29iv0s8:example.com"), response.event_id)
+    }
+
+    #[tokio::test]
+    async fn room_redact() {
+        use matrix_sdk_common::uuid::Uuid;
+
+        let client = logged_in_client().await;
+
+        let _m = mock(
+            "PUT",
+            Matcher::Regex(r"^/_matrix/client/r0/rooms/.*/redact/.*?/.*?".to_string()),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .with_body(test_json::EVENT_ID.to_string())
+        .create();
+
+        let _m = mock(
+            "GET",
+            Matcher::Regex(r"^/_matrix/client/r0/sync\?.*$".to_string()),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .with_body(test_json::SYNC.to_string())
+        .create();
+
+        let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+        let _response = client.sync_once(sync_settings).await.unwrap();
+
+        let room = client
+            .get_joined_room(&room_id!("!SVkFJHzfwvuaIEawgC:localhost"))
+            .unwrap();
+
+        let event_id = event_id!("$xxxxxxxx:example.com");
+
+        let txn_id = Uuid::new_v4();
+        let reason = Some("Indecent material");
+        let response = room.redact(&event_id, reason, Some(txn_id)).await.unwrap();
+
+        assert_eq!(event_id!("$h29iv0s8:example.com"), response.event_id)
+    }
+
+    #[tokio::test]
+    async fn user_presence() {
+        let client = logged_in_client().await;
+
+        let _m = mock(
+            "GET",
+            Matcher::Regex(r"^/_matrix/client/r0/sync\?.*$".to_string()),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .with_body(test_json::SYNC.to_string())
+        .create();
+
+        let _m = mock(
+            "GET",
+            Matcher::Regex(r"^/_matrix/client/r0/rooms/.*/members".to_string()),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .with_body(test_json::MEMBERS.to_string())
+        .create();
+
+        let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+        let _response = client.sync_once(sync_settings).await.unwrap();
+
+        let room = client
+            .get_joined_room(&room_id!("!SVkFJHzfwvuaIEawgC:localhost"))
+            .unwrap();
+        let members: Vec<RoomMember> = room.active_members().await.unwrap();
+
+        assert_eq!(1, members.len());
+        // assert!(room.power_levels.is_some())
+    }
+
+    #[tokio::test]
+    async fn presence() {
+        let client = logged_in_client().await;
+
+        let _m = mock(
+            "GET",
+            Matcher::Regex(r"^/_matrix/client/r0/sync\?.*set_presence=unavailable.*$".to_string()),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .with_body(test_json::SYNC.to_string())
+        .create();
+
+        let sync_settings = SyncSettings::new().set_presence(PresenceState::Unavailable);
+        let _response = client.sync_once(sync_settings).await.unwrap();
+
+        let user_id = user_id!("@example:localhost");
+        let event = client.presence_event(&user_id).await.unwrap().unwrap();
+        assert_eq!(event.content.presence, PresenceState::Online);
+
+        let room = client
+            .get_joined_room(&room_id!("!SVkFJHzfwvuaIEawgC:localhost"))
+            .unwrap();
+        let member = room.get_member(&user_id).await.unwrap().unwrap();
+
+        assert_eq!(member.presence_state(), Some(&PresenceState::Online));
+        assert_eq!(member.status_msg(), Some("Making cupcakes"));
+        assert!(!member.currently_active());
+
+        // The time since the presence event was received is added to the
+        // `last_active_ago` the server sent us.
+        let last_active_ago = member.last_active_ago().unwrap();
+        assert!(last_active_ago >= Duration::from_millis(1));
+        assert!(last_active_ago < Duration::from_secs(60));
+
+        sleep::new(Duration::from_millis(10)).await;
+        let member = room.get_member(&user_id).await.unwrap().unwrap();
+        assert!(member.last_active_ago().unwrap() >= last_active_ago + Duration::from_millis(10));
+
+        let _m = mock(
+            "PUT",
+            Matcher::Regex(r"^/_matrix/client/r0/presence/.*/status$".to_string()),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .match_body(Matcher::Json(json!({
+            "presence": "unavailable",
+            "status_msg": "Out for lunch"
+        })))
+        .with_body("{}")
+        .create();
+
+        client
+            .set_presence(PresenceState::Unavailable, Some("Out for lunch"))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn calculate_room_names_from_summary() {
+        let client = logged_in_client().await;
+
+        let _m = mock(
+            "GET",
+            Matcher::Regex(r"^/_matrix/client/r0/sync\?.*$".to_string()),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .with_body(test_json::DEFAULT_SYNC_SUMMARY.to_string())
+        .create();
+
+        let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+        let _response = client.sync_once(sync_settings).await.unwrap();
+        let room = client
+            .get_joined_room(&room_id!("!SVkFJHzfwvuaIEawgC:localhost"))
+            .unwrap();
+
+        assert_eq!("example2", room.display_name().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn custom_room_name_formatter() {
+        #[derive(Debug)]
+        struct CountingFormatter;
+
+        impl RoomNameFormatter for CountingFormatter {
+            fn format_name(&self, parts: RoomNameParts<'_>) -> String {
+                assert_eq!(parts.own_user_id, &user_id!("@example:localhost"));
+
+                format!(
+                    "{} with {} joined",
+                    parts.heroes[0].name(),
+                    parts.joined_member_count
+                )
+            }
+        }
+
+        let session = Session {
+            access_token: "1234".to_owned(),
+            user_id: user_id!("@example:localhost"),
+            device_id: "DEVICEID".into(),
+        };
+        let homeserver = url::Url::parse(&mockito::server_url()).unwrap();
+        let config = ClientConfig::new().room_name_formatter(CountingFormatter);
+        let client = Client::new_with_config(homeserver, config).unwrap();
+        client.restore_login(session).await.unwrap();
+
+        let _m = mock(
+            "GET",
+            Matcher::Regex(r"^/_matrix/client/r0/sync\?.*$".to_string()),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .with_body(test_json::DEFAULT_SYNC_SUMMARY.to_string())
+        .create();
+
+        let _response = client.sync_once(SyncSettings::new()).await.unwrap();
+        let room = client
+            .get_joined_room(&room_id!("!SVkFJHzfwvuaIEawgC:localhost"))
+            .unwrap();
+
+        assert_eq!("example2 with 2 joined", room.display_name().await.unwrap());
+        assert_eq!(
+            "example2",
+            room.display_name_with(&DefaultRoomNameFormatter)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn invited_rooms() {
+        let client = logged_in_client().await;
+
+        let _m = mock(
+            "GET",
+            Matcher::Regex(r"^/_matrix/client/r0/sync\?.*$".to_string()),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .with_body(test_json::INVITE_SYNC.to_string())
+        .create();
+
+        let _response = client.sync_once(SyncSettings::default()).await.unwrap();
+
+        assert!(client.joined_rooms().is_empty());
+        assert!(client.left_rooms().is_empty());
+        assert!(!client.invited_rooms().is_empty());
+
+        assert!(client
+            .get_invited_room(&room_id!("!696r7674:example.com"))
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn account_data() {
+        let client = logged_in_client().await;
+
+        let _m = mock(
+            "GET",
+            Matcher::Regex(r"^/_matrix/client/r0/sync\?.*$".to_string()),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .with_body(test_json::SYNC.to_string())
+        .create();
+
+        let _response = client.sync_once(SyncSettings::default()).await.unwrap();
+
+        let room = client
+            .get_joined_room(&room_id!("!SVkFJHzfwvuaIEawgC:localhost"))
+            .unwrap();
+
+        let ignored: serde_json::Value = room
+            .account_data(EventType::IgnoredUserList)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            ignored,
+            json!({ "ignored_users": { "@someone:example.org": {} } })
+        );
+        assert!(client
+            .account_data::<serde_json::Value>(EventType::PushRules)
+            .await
+            .unwrap()
+            .is_none());
+
+        let content = CustomEventContent {
+            event_type: "org.example.custom".to_owned(),
+            data: serde_json::from_value(json!({ "key": "value" })).unwrap(),
+        };
+
+        let _m = mock(
+            "PUT",
+            Matcher::Regex(
+                r"^/_matrix/client/r0/user/.*/account_data/org.example.custom$".to_string(),
+            ),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .match_body(Matcher::Json(json!({ "key": "value" })))
+        .with_body("{}")
+        .create();
+
+        client.set_account_data(content.clone()).await.unwrap();
+
+        let _m = mock(
+            "PUT",
+            Matcher::Regex(
+                r"^/_matrix/client/r0/user/.*/rooms/.*/account_data/org.example.custom$"
+                    .to_string(),
+            ),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .match_body(Matcher::Json(json!({ "key": "value" })))
+        .with_body("{}")
+        .create();
+
+        room.set_account_data(content).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn account_data_stream() {
+        use futures::{FutureExt, StreamExt};
+
+        let client = logged_in_client().await;
+        let room_id = room_id!("!SVkFJHzfwvuaIEawgC:localhost");
+
+        let mut direct = client.account_data_stream(EventType::Direct);
+        let mut push_rules = client.account_data_stream(EventType::PushRules);
+
+        let mut sync = test_json::SYNC.clone();
+        sync["account_data"] = json!({
+            "events": [{
+                "type": "m.direct",
+                "content": {
+                    "@example2:localhost": [room_id.as_str()],
+                }
+            }]
+        });
+
+        let _m = mock(
+            "GET",
+            Matcher::Regex(r"^/_matrix/client/r0/sync\?.*$".to_string()),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .with_body(sync.to_string())
+        .create();
+
+        client.sync_once(SyncSettings::new()).await.unwrap();
+
+        assert!(matches!(
+            direct.next().now_or_never(),
+            Some(Some(AnyBasicEvent::Direct(_)))
+        ));
+        assert!(direct.next().now_or_never().is_none());
+        assert!(push_rules.next().now_or_never().is_none());
+
+        let room = client.get_joined_room(&room_id).unwrap();
+        let mut ignored = room.account_data_stream(EventType::IgnoredUserList);
+        drop(push_rules);
+
+        client.sync_once(SyncSettings::new()).await.unwrap();
+
+        assert!(matches!(
+            ignored.next().now_or_never(),
+            Some(Some(AnyBasicEvent::IgnoredUserList(_)))
+        ));
+        assert!(matches!(
+            direct.next().now_or_never(),
+            Some(Some(AnyBasicEvent::Direct(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn room_tags() {
+        let client = logged_in_client().await;
+        let room_id = room_id!("!SVkFJHzfwvuaIEawgC:localhost");
+
+        let response = EventBuilder::default()
+            .add_account(EventsJson::Tag)
+            .build_sync_response();
+
+        client
+            .base_client
+            .receive_sync_response(response)
+            .await
+            .unwrap();
+
+        let room = client.get_joined_room(&room_id).unwrap();
+
+        assert_eq!(room.tag("u.work").unwrap().order, Some(0.9));
+        assert!(!room.is_favourite());
+        assert!(client.rooms_with_tag(FAVOURITE_TAG).is_empty());
+
+        let rooms = client.rooms_with_tag("u.work");
+        assert_eq!(rooms.len(), 1);
+        assert_eq!(rooms[0].room_id(), &room_id);
+
+        let _m = mock(
+            "PUT",
+            Matcher::Regex(r"^/_matrix/client/r0/user/.*/rooms/.*/tags/m.favourite$".to_string()),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .match_body(Matcher::Json(json!({ "order": 0.5 })))
+        .with_body("{}")
+        .create();
+
+        room.add_tag(FAVOURITE_TAG, Some(0.5)).await.unwrap();
+
+        let _m = mock(
+            "DELETE",
+            Matcher::Regex(r"^/_matrix/client/r0/user/.*/rooms/.*/tags/u.work$".to_string()),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .with_body("{}")
+        .create();
+
+        room.remove_tag("u.work").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn ignored_users() {
+        let client = logged_in_client().await;
+
+        let message = |sender: &str, event_id: &str| {
+            json!({
+                "content": { "body": "Hello", "msgtype": "m.text" },
+                "event_id": event_id,
+                "origin_server_ts": 152037280,
+                "sender": sender,
+                "type": "m.room.message"
+            })
+        };
+
+        let sync = json!({
+            "next_batch": "s526_47314_0_7_1_1_1_11444_1",
+            "account_data": {
+                "events": [{
+                    "content": { "ignored_users": { "@spammer:localhost": {} } },
+                    "type": "m.ignored_user_list"
+                }]
+            },
+            "rooms": {
+                "invite": {
+                    "!spam:localhost": {
+                        "invite_state": {
+                            "events": [{
+                                "content": { "membership": "invite" },
+                                "sender": "@spammer:localhost",
+                                "state_key": "@example:localhost",
+                                "type": "m.room.member"
+                            }]
+                        }
+                    }
+                },
+                "join": {
+                    "!SVkFJHzfwvuaIEawgC:localhost": {
+                        "timeline": {
+                            "events": [
+                                message("@spammer:localhost", "$spam:localhost"),
+                                message("@friend:localhost", "$hello:localhost"),
+                                {
+                                    "content": {},
+                                    "event_id": "$redacted:localhost",
+                                    "origin_server_ts": 152037280,
+                                    "sender": "@spammer:localhost",
+                                    "type": "m.room.message",
+                                    "unsigned": {
+                                        "redacted_because": {
+                                            "content": {},
+                                            "event_id": "$redaction:localhost",
+                                            "origin_server_ts": 152037281,
+                                            "redacts": "$redacted:localhost",
+                                            "sender": "@spammer:localhost",
+                                            "type": "m.room.redaction"
+                                        }
+                                    }
+                                },
+                                {
+                                    "content": { "topic": "Spam" },
+                                    "event_id": "$topic:localhost",
+                                    "origin_server_ts": 152037280,
+                                    "sender": "@spammer:localhost",
+                                    "state_key": "",
+                                    "type": "m.room.topic"
+                                }
+                            ],
+                            "limited": false,
+                            "prev_batch": "t392-516_47314_0_7_1_1_1_11444_1"
+                        }
+                    }
+                },
+                "leave": {}
+            }
+        });
+
+        let _m = mock(
+            "GET",
+            Matcher::Regex(r"^/_matrix/client/r0/sync\?.*$".to_string()),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .with_body(sync.to_string())
+        .create();
+
+        let response = client.sync_once(SyncSettings::default()).await.unwrap();
+
+        // Only the message of our friend and the state event of the ignored
+        // user are kept.
+        let timeline = &response.rooms.join[&room_id!("!SVkFJHzfwvuaIEawgC:localhost")].timeline;
+        assert_eq!(timeline.events.len(), 2);
+        assert!(matches!(
+            &timeline.events[1].event,
+            AnySyncRoomEvent::State(AnySyncStateEvent::RoomTopic(_))
+        ));
+        assert!(response.rooms.invite.is_empty());
+        assert!(client.invited_rooms().is_empty());
+        assert_eq!(
+            client.ignored_users().await.unwrap(),
+            vec![user_id!("@spammer:localhost")]
+        );
+
+        let _m = mock(
+            "GET",
+            Matcher::Regex(
+                r"^/_matrix/client/r0/user/.*/account_data/m.ignored_user_list$".to_string(),
+            ),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .with_body(json!({ "ignored_users": { "@spammer:localhost": {} } }).to_string())
+        .create();
+
+        let _m = mock(
+            "PUT",
+            Matcher::Regex(
+                r"^/_matrix/client/r0/user/.*/account_data/m.ignored_user_list$".to_string(),
+            ),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .match_body(Matcher::Json(json!({ "ignored_users": {} })))
+        .with_body("{}")
+        .create();
+
+        client
+            .unignore_user(&user_id!("@spammer:localhost"))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn invite_details() {
+        let client = logged_in_client().await;
+
+        let _m = mock(
+            "GET",
+            Matcher::Regex(r"^/_matrix/client/r0/sync\?.*$".to_string()),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .with_body(test_json::INVITE_DETAILS_SYNC.to_string())
+        .create();
+
+        let _response = client.sync_once(SyncSettings::default()).await.unwrap();
+
+        let room = client
+            .get_invited_room(&room_id!("!696r7674:example.com"))
+            .unwrap();
+        let details = room.invite_details().await.unwrap().unwrap();
+
+        assert_eq!(details.inviter, user_id!("@alice:example.com"));
+        assert_eq!(
+            details.inviter_display_name.as_deref(),
+            Some("Alice Margatroid")
+        );
+        assert_eq!(
+            details.inviter_avatar_url,
+            Some(mxc_uri!("mxc://example.com/SEsfnsuifSDFSSEF"))
+        );
+        assert_eq!(
+            details.reason.as_deref(),
+            Some("Let's talk about the cupcakes")
+        );
+        assert!(details.is_direct);
+        assert_eq!(room.name().as_deref(), Some("My Room Name"));
+
+        let _m = mock(
+            "POST",
+            Matcher::Regex(r"^/_matrix/client/r0/rooms/.*/leave$".to_string()),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .match_body(Matcher::Json(json!({ "reason": "Not interested" })))
+        .with_body("{}")
+        .create();
+
+        let _m = mock(
+            "GET",
+            Matcher::Regex(
+                r"^/_matrix/client/r0/user/.*/account_data/m.ignored_user_list$".to_string(),
+            ),
+        )
+        .with_status(404)
+        .with_body(
+            json!({ "errcode": "M_NOT_FOUND", "error": "Account data not found" }).to_string(),
+        )
+        .create();
+
+        let _m = mock(
+            "PUT",
+            Matcher::Regex(
+                r"^/_matrix/client/r0/user/.*/account_data/m.ignored_user_list$".to_string(),
+            ),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .match_body(Matcher::Json(json!({
+            "ignored_users": { "@alice:example.com": {} }
+        })))
+        .with_body("{}")
+        .create();
+
+        room.reject_and_ignore_inviter(Some("Not interested"))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn left_rooms() {
+        let client = logged_in_client().await;
+
+        let _m = mock(
+            "GET",
+            Matcher::Regex(r"^/_matrix/client/r0/sync\?.*$".to_string()),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .with_body(test_json::LEAVE_SYNC.to_string())
+        .create();
+
+        let _response = client.sync_once(SyncSettings::default()).await.unwrap();
+
+        assert!(client.joined_rooms().is_empty());
+        assert!(!client.left_rooms().is_empty());
+        assert!(client.invited_rooms().is_empty());
+
+        assert!(client
+            .get_left_room(&room_id!("!SVkFJHzfwvuaIEawgC:localhost"))
+            .is_some())
+    }
+
+    #[tokio::test]
+    async fn sync() {
+        let client = logged_in_client().await;
+
+        let _m = mock(
+            "GET",
+            Matcher::Regex(r"^/_matrix/client/r0/sync\?.*$".to_string()),
+        )
+        .with_status(200)
+        .with_body(test_json::SYNC.to_string())
+        .match_header("authorization", "Bearer 1234")
+        .create();
+
+        let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+        let response = client.sync_once(sync_settings).await.unwrap();
+
+        assert_ne!(response.next_batch, "");
+
+        assert!(client.sync_token().await.is_some());
+    }
+
+    #[tokio::test]
+    async fn room_names() {
+        let client = logged_in_client().await;
+
+        let _m = mock(
+            "GET",
+            Matcher::Regex(r"^/_matrix/client/r0/sync\?.*$".to_string()),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .with_body(test_json::SYNC.to_string())
+        .create();
+
+        let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+        let _response = client.sync_once(sync_settings).await.unwrap();
+
+        let room = client
+            .get_joined_room(&room_id!("!SVkFJHzfwvuaIEawgC:localhost"))
+            .unwrap();
+
+        assert_eq!("tutorial".to_string(), room.display_name().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn spaces() {
+        let client = logged_in_client().await;
+
+        let _m = mock(
+            "GET",
+            Matcher::Regex(r"^/_matrix/client/r0/sync\?.*$".to_string()),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .with_body(test_json::SPACE_SYNC.to_string())
+        .create();
+
+        let _response = client.sync_once(SyncSettings::new()).await.unwrap();
+
+        let space_id = room_id!("!space:localhost");
+        let space = client.get_joined_room(&space_id).unwrap();
+
+        assert!(space.is_space());
+        assert_eq!(space.create_type().as_deref(), Some("m.space"));
+
+        let children: Vec<_> = space
+            .space_children()
+            .into_iter()
+            .map(|(room_id, _)| room_id)
+            .collect();
+        assert_eq!(
+            children,
+            vec![
+                room_id!("!second:localhost"),
+                room_id!("!first:localhost"),
+                room_id!("!unknown:example.org")
+            ]
+        );
+
+        let rooms: Vec<_> = client
+            .space_rooms(&space_id)
+            .iter()
+            .map(|r| r.room_id().clone())
+            .collect();
+        assert_eq!(
+            rooms,
+            vec![room_id!("!second:localhost"), room_id!("!first:localhost")]
+        );
+
+        let first = client
+            .get_joined_room(&room_id!("!first:localhost"))
+            .unwrap();
+        assert!(!first.is_space());
+        assert!(first.space_parents()[&space_id].canonical);
+    }
+
+    #[tokio::test]
+    async fn room_upgrades() {
+        let session = Session {
+            access_token: "1234".to_owned(),
+            user_id: user_id!("@example:localhost"),
+            device_id: "DEVICEID".into(),
+        };
+        let homeserver = url::Url::parse(&mockito::server_url()).unwrap();
+        let config = ClientConfig::new().hide_replaced_rooms();
+        let client = Client::new_with_config(homeserver, config).unwrap();
+        client.restore_login(session).await.unwrap();
+
+        let _m = mock(
+            "GET",
+            Matcher::Regex(r"^/_matrix/client/r0/sync\?.*$".to_string()),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .with_body(test_json::UPGRADE_SYNC.to_string())
+        .create();
+
+        let _response = client.sync_once(SyncSettings::new()).await.unwrap();
+
+        let old_id = room_id!("!old:localhost");
+        let new_id = room_id!("!new:localhost");
+
+        let old = client.get_joined_room(&old_id).unwrap();
+        let new = client.get_joined_room(&new_id).unwrap();
+
+        assert!(old.is_tombstoned());
+        assert_eq!(old.successor(), Some(new_id.clone()));
+        assert_eq!(new.predecessor(), Some(old_id));
+
+        let joined: Vec<_> = client
+            .joined_rooms()
+            .iter()
+            .map(|r| r.room_id().clone())
+            .collect();
+        assert_eq!(joined, vec![new_id.clone()]);
+
+        // The replacement room is already joined, no request is sent.
+        assert_eq!(old.join_successor().await.unwrap(), Some(new_id));
+    }
+
+    #[tokio::test]
+    async fn follow_tombstones_once() {
+        let session = Session {
+            access_token: "1234".to_owned(),
+            user_id: user_id!("@example:localhost"),
+            device_id: "DEVICEID".into(),
+        };
+        let homeserver = url::Url::parse(&mockito::server_url()).unwrap();
+        let config = ClientConfig::new().follow_tombstones();
+        let client = Client::new_with_config(homeserver, config).unwrap();
+        client.restore_login(session).await.unwrap();
+
+        let mut sync = test_json::UPGRADE_SYNC.clone();
+        sync["rooms"]["join"]
+            .as_object_mut()
+            .unwrap()
+            .remove("!new:localhost");
+
+        let sync_mock = mock(
+            "GET",
+            Matcher::Regex(r"^/_matrix/client/r0/sync\?.*$".to_string()),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .with_body(sync.to_string())
+        .create();
+
+        let join = mock(
+            "POST",
+            Matcher::Regex(r"^/_matrix/client/r0/join/.*".to_string()),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .with_body(json!({ "room_id": "!new:localhost" }).to_string())
+        .expect(1)
+        .create();
+
+        client.sync_once(SyncSettings::new()).await.unwrap();
+        drop(sync_mock);
+
+        // The old room is still tombstoned but the tombstone isn't part of
+        // the sync response anymore, the replacement room isn't joined again.
+        let _m = mock(
+            "GET",
+            Matcher::Regex(r"^/_matrix/client/r0/sync\?.*$".to_string()),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .with_body(
+            json!({
+                "next_batch": "s526_47314_0_7_1_1_1_11444_4",
+                "rooms": {
+                    "join": {
+                        "!old:localhost": {
+                            "timeline": { "events": [], "limited": false }
+                        }
+                    }
+                }
+            })
+            .to_string(),
+        )
+        .create();
+
+        client.sync_once(SyncSettings::new()).await.unwrap();
+
+        assert!(client
+            .get_joined_room(&room_id!("!old:localhost"))
+            .unwrap()
+            .is_tombstoned());
+        join.assert();
+    }
+
+    #[tokio::test]
+    async fn room_upgrade_send() {
+        let client = logged_in_client().await;
+
+        let _m = mock(
+            "GET",
+            Matcher::Regex(r"^/_matrix/client/r0/sync\?.*$".to_string()),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .with_body(test_json::SYNC.to_string())
+        .create();
+
+        let _response = client.sync_once(SyncSettings::new()).await.unwrap();
 
-        let _response = client.sync_once(sync_settings).await.unwrap();
+        let _m = mock(
+            "POST",
+            Matcher::Regex(r"^/_matrix/client/r0/rooms/.*/upgrade".to_string()),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .match_body(Matcher::PartialJson(json!({ "new_version": "6" })))
+        .with_body(json!({ "replacement_room": "!new:localhost" }).to_string())
+        .create();
 
-        let room_id = room_id!("!SVkFJHzfwvuaIEawgC:localhost");
+        let room = client
+            .get_joined_room(&room_id!("!SVkFJHzfwvuaIEawgC:localhost"))
+            .unwrap();
 
-        let room = client.get_joined_room(&room_id).unwrap();
+        let replacement = room.upgrade(&RoomVersionId::Version6).await.unwrap();
 
-        let avatar_url = mxc_uri!("mxc://example.org/avA7ar");
-        let member_event = MemberEventContent {
-            avatar_url: Some(avatar_url),
-            membership: MembershipState::Join,
-            is_direct: None,
-            displayname: None,
-            third_party_invite: None,
-        };
-        let content = AnyStateEventContent::RoomMember(member_event);
-        let response = room.send_state_event(content, "").await.unwrap();
-        assert_eq!(event_id!("$h29iv0s8:example.com"), response.event_id);
+        assert_eq!(replacement, room_id!("!new:localhost"));
     }
 
     #[tokio::test]
-    async fn room_message_send() {
-        use matrix_sdk_common::uuid::Uuid;
-
+    async fn power_level_permissions() {
         let client = logged_in_client().await;
 
-        let _m = mock(
-            "PUT",
-            Matcher::Regex(r"^/_matrix/client/r0/rooms/.*/send/".to_string()),
-        )
-        .with_status(200)
-        .match_header("authorization", "Bearer 1234")
-        .with_body(test_json::EVENT_ID.to_string())
-        .create();
-
         let _m = mock(
             "GET",
             Matcher::Regex(r"^/_matrix/client/r0/sync\?.*$".to_string()),
@@ -3178,266 +6205,397 @@ mod test {
         .with_body(test_json::SYNC.to_string())
         .create();
 
-        let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
-
-        let _response = client.sync_once(sync_settings).await.unwrap();
+        let _response = client.sync_once(SyncSettings::new()).await.unwrap();
 
         let room = client
             .get_joined_room(&room_id!("!SVkFJHzfwvuaIEawgC:localhost"))
             .unwrap();
 
-        let content =
-            AnyMessageEventContent::RoomMessage(MessageEventContent::text_plain("Hello world"));
-        let txn_id = Uuid::new_v4();
-        let response = room.send(content, Some(txn_id)).await.unwrap();
+        let admin = user_id!("@example:localhost");
+        let user = user_id!("@someone:localhost");
 
-        assert_eq!(event_id!("$h29iv0s8:example.com"), response.event_id)
+        assert_eq!(room.user_power_level(&admin).await.unwrap(), 100);
+        assert_eq!(room.user_power_level(&user).await.unwrap(), 0);
+
+        assert!(room.can_user_ban(&admin).await.unwrap());
+        assert!(!room.can_user_ban(&user).await.unwrap());
+        assert!(!room.can_user_kick(&user).await.unwrap());
+        assert!(!room.can_user_redact(&user).await.unwrap());
+        assert!(room.can_user_invite(&user).await.unwrap());
+
+        assert!(room
+            .can_user_send_message(&user, EventType::RoomMessage)
+            .await
+            .unwrap());
+        assert!(!room
+            .can_user_send_state(&user, EventType::RoomName)
+            .await
+            .unwrap());
+        assert!(room
+            .can_user_send_state(&admin, EventType::RoomPowerLevels)
+            .await
+            .unwrap());
     }
 
     #[tokio::test]
-    async fn room_attachment_send() {
+    async fn set_user_power_level() {
         let client = logged_in_client().await;
 
         let _m = mock(
-            "PUT",
-            Matcher::Regex(r"^/_matrix/client/r0/rooms/.*/send/".to_string()),
+            "GET",
+            Matcher::Regex(r"^/_matrix/client/r0/sync\?.*$".to_string()),
         )
         .with_status(200)
         .match_header("authorization", "Bearer 1234")
-        .with_body(test_json::EVENT_ID.to_string())
+        .with_body(test_json::SYNC.to_string())
         .create();
 
-        let _m = mock(
-            "POST",
-            Matcher::Regex(r"^/_matrix/media/r0/upload".to_string()),
-        )
-        .with_status(200)
-        .match_header("content-type", "image/jpeg")
-        .with_body(
-            json!({
-              "content_uri": "mxc://example.com/AQwafuaFswefuhsfAFAgsw"
-            })
-            .to_string(),
-        )
-        .create();
+        let _response = client.sync_once(SyncSettings::new()).await.unwrap();
 
         let _m = mock(
-            "GET",
-            Matcher::Regex(r"^/_matrix/client/r0/sync\?.*$".to_string()),
+            "PUT",
+            Matcher::Regex(r"^/_matrix/client/r0/rooms/.*/state/m.room.power_levels/".to_string()),
         )
         .with_status(200)
         .match_header("authorization", "Bearer 1234")
-        .with_body(test_json::SYNC.to_string())
+        .match_body(Matcher::PartialJson(json!({
+            "ban": 50,
+            "users": {
+                "@example:localhost": 100,
+                "@someone:localhost": 50
+            }
+        })))
+        .with_body(test_json::EVENT_ID.to_string())
         .create();
 
-        let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
-
-        let _response = client.sync_once(sync_settings).await.unwrap();
-
         let room = client
             .get_joined_room(&room_id!("!SVkFJHzfwvuaIEawgC:localhost"))
             .unwrap();
 
-        let mut media = Cursor::new("Hello world");
-
-        let response = room
-            .send_attachment("image", &mime::IMAGE_JPEG, &mut media, None)
+        room.set_user_power_level(&user_id!("@someone:localhost"), 50.into())
             .await
             .unwrap();
-
-        assert_eq!(event_id!("$h29iv0s8:example.com"), response.event_id)
     }
 
     #[tokio::test]
-    async fn room_redact() {
-        use matrix_sdk_common::uuid::Uuid;
-
+    async fn get_dm_room() {
         let client = logged_in_client().await;
+        let room_id = room_id!("!SVkFJHzfwvuaIEawgC:localhost");
 
-        let _m = mock(
-            "PUT",
-            Matcher::Regex(r"^/_matrix/client/r0/rooms/.*/redact/.*?/.*?".to_string()),
-        )
-        .with_status(200)
-        .match_header("authorization", "Bearer 1234")
-        .with_body(test_json::EVENT_ID.to_string())
-        .create();
+        let mut sync = test_json::SYNC.clone();
+        sync["account_data"] = json!({
+            "events": [{
+                "type": "m.direct",
+                "content": {
+                    "@example2:localhost": [room_id.as_str()],
+                }
+            }]
+        });
 
-        let _m = mock(
+        let m = mock(
             "GET",
             Matcher::Regex(r"^/_matrix/client/r0/sync\?.*$".to_string()),
         )
         .with_status(200)
         .match_header("authorization", "Bearer 1234")
-        .with_body(test_json::SYNC.to_string())
+        .with_body(sync.to_string())
         .create();
 
-        let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
-
-        let _response = client.sync_once(sync_settings).await.unwrap();
+        client.sync_once(SyncSettings::new()).await.unwrap();
+        drop(m);
 
         let room = client
-            .get_joined_room(&room_id!("!SVkFJHzfwvuaIEawgC:localhost"))
+            .get_dm_room(&user_id!("@example2:localhost"))
+            .await
+            .unwrap()
             .unwrap();
+        assert_eq!(room.room_id(), &room_id);
 
-        let event_id = event_id!("$xxxxxxxx:example.com");
+        // The room is still marked as a direct message with a user that isn't
+        // a member of it anymore.
+        sync["account_data"]["events"][0]["content"] = json!({
+            "@someone:localhost": [room_id.as_str()],
+        });
 
-        let txn_id = Uuid::new_v4();
-        let reason = Some("Indecent material");
-        let response = room.redact(&event_id, reason, Some(txn_id)).await.unwrap();
+        let _m = mock(
+            "GET",
+            Matcher::Regex(r"^/_matrix/client/r0/sync\?.*$".to_string()),
+        )
+        .with_status(200)
+        .match_header("authorization", "Bearer 1234")
+        .with_body(sync.to_string())
+        .create();
 
-        assert_eq!(event_id!("$h29iv0s8:example.com"), response.event_id)
+        client.sync_once(SyncSettings::new()).await.unwrap();
+
+        let room = client.get_joined_room(&room_id).unwrap();
+        assert_eq!(room.direct_target(), Some(user_id!("@someone:localhost")));
+        assert!(client
+            .get_dm_room(&user_id!("@someone:localhost"))
+            .await
+            .unwrap()
+            .is_none());
     }
 
     #[tokio::test]
-    async fn user_presence() {
+    async fn mark_as_dm() {
         let client = logged_in_client().await;
 
         let _m = mock(
             "GET",
-            Matcher::Regex(r"^/_matrix/client/r0/sync\?.*$".to_string()),
+            Matcher::Regex(r"^/_matrix/client/r0/user/.*/account_data/m.direct$".to_string()),
         )
         .with_status(200)
         .match_header("authorization", "Bearer 1234")
-        .with_body(test_json::SYNC.to_string())
+        .with_body(json!({ "@alice:localhost": ["!alice:localhost"] }).to_string())
         .create();
 
         let _m = mock(
-            "GET",
-            Matcher::Regex(r"^/_matrix/client/r0/rooms/.*/members".to_string()),
+            "PUT",
+            Matcher::Regex(r"^/_matrix/client/r0/user/.*/account_data/m.direct$".to_string()),
         )
         .with_status(200)
         .match_header("authorization", "Bearer 1234")
-        .with_body(test_json::MEMBERS.to_string())
+        .match_body(Matcher::Json(json!({
+            "@alice:localhost": ["!alice:localhost"],
+            "@bob:localhost": ["!bob:localhost"]
+        })))
+        .with_body("{}")
         .create();
 
-        let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
-
-        let _response = client.sync_once(sync_settings).await.unwrap();
-
-        let room = client
-            .get_joined_room(&room_id!("!SVkFJHzfwvuaIEawgC:localhost"))
+        client
+            .mark_as_dm(&room_id!("!bob:localhost"), &user_id!("@bob:localhost"))
+            .await
             .unwrap();
-        let members: Vec<RoomMember> = room.active_members().await.unwrap();
-
-        assert_eq!(1, members.len());
-        // assert!(room.power_levels.is_some())
     }
 
     #[tokio::test]
-    async fn calculate_room_names_from_summary() {
+    async fn create_dm() {
         let client = logged_in_client().await;
 
+        let _m = mock("POST", "/_matrix/client/r0/createRoom")
+            .with_status(200)
+            .match_header("authorization", "Bearer 1234")
+            .match_body(Matcher::PartialJson(json!({
+                "invite": ["@alice:localhost"],
+                "is_direct": true,
+                "preset": "trusted_private_chat",
+                "initial_state": [{
+                    "type": "m.room.encryption",
+                    "state_key": "",
+                    "content": { "algorithm": "m.megolm.v1.aes-sha2" }
+                }]
+            })))
+            .with_body(test_json::ROOM_ID.to_string())
+            .create();
+
         let _m = mock(
             "GET",
-            Matcher::Regex(r"^/_matrix/client/r0/sync\?.*$".to_string()),
+            Matcher::Regex(r"^/_matrix/client/r0/user/.*/account_data/m.direct$".to_string()),
+        )
+        .with_status(404)
+        .with_body(
+            json!({ "errcode": "M_NOT_FOUND", "error": "Account data not found" }).to_string(),
+        )
+        .create();
+
+        let _m = mock(
+            "PUT",
+            Matcher::Regex(r"^/_matrix/client/r0/user/.*/account_data/m.direct$".to_string()),
         )
         .with_status(200)
-        .match_header("authorization", "Bearer 1234")
-        .with_body(test_json::DEFAULT_SYNC_SUMMARY.to_string())
+        .match_body(Matcher::Json(json!({
+            "@alice:localhost": ["!testroom:example.org"]
+        })))
+        .with_body("{}")
         .create();
 
-        let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
-        let _response = client.sync_once(sync_settings).await.unwrap();
-        let room = client
-            .get_joined_room(&room_id!("!SVkFJHzfwvuaIEawgC:localhost"))
+        let room_id = client
+            .create_dm(&user_id!("@alice:localhost"))
+            .await
             .unwrap();
 
-        assert_eq!("example2", room.display_name().await.unwrap());
+        assert_eq!(room_id, room_id!("!testroom:example.org"));
     }
 
     #[tokio::test]
-    async fn invited_rooms() {
+    async fn create_dm_without_account_data() {
         let client = logged_in_client().await;
 
+        let _m = mock("POST", "/_matrix/client/r0/createRoom")
+            .with_status(200)
+            .with_body(test_json::ROOM_ID.to_string())
+            .create();
+
         let _m = mock(
             "GET",
-            Matcher::Regex(r"^/_matrix/client/r0/sync\?.*$".to_string()),
+            Matcher::Regex(r"^/_matrix/client/r0/user/.*/account_data/m.direct$".to_string()),
         )
-        .with_status(200)
-        .match_header("authorization", "Bearer 1234")
-        .with_body(test_json::INVITE_SYNC.to_string())
+        .with_status(403)
+        .with_body(json!({ "errcode": "M_FORBIDDEN", "error": "Forbidden" }).to_string())
         .create();
 
-        let _response = client.sync_once(SyncSettings::default()).await.unwrap();
-
-        assert!(client.joined_rooms().is_empty());
-        assert!(client.left_rooms().is_empty());
-        assert!(!client.invited_rooms().is_empty());
-
-        assert!(client
-            .get_invited_room(&room_id!("!696r7674:example.com"))
-            .is_some());
+        // The room exists even though it couldn't be marked as a direct
+        // message room, the error tells us which room it is.
+        match client.create_dm(&user_id!("@alice:localhost")).await {
+            Err(crate::Error::DirectRoomNotMarked { room_id, .. }) => {
+                assert_eq!(room_id, room_id!("!testroom:example.org"))
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
     }
 
     #[tokio::test]
-    async fn left_rooms() {
+    async fn space_hierarchy() {
         let client = logged_in_client().await;
+        let space_id = room_id!("!space:localhost");
 
         let _m = mock(
             "GET",
-            Matcher::Regex(r"^/_matrix/client/r0/sync\?.*$".to_string()),
+            Matcher::Regex(
+                r"^/_matrix/client/unstable/org.matrix.msc2946/rooms/.*/hierarchy.*$".to_string(),
+            ),
         )
+        .match_query(Matcher::UrlEncoded("limit".into(), "10".into()))
         .with_status(200)
         .match_header("authorization", "Bearer 1234")
-        .with_body(test_json::LEAVE_SYNC.to_string())
+        .with_body(test_json::SPACE_HIERARCHY.to_string())
         .create();
 
-        let _response = client.sync_once(SyncSettings::default()).await.unwrap();
-
-        assert!(client.joined_rooms().is_empty());
-        assert!(!client.left_rooms().is_empty());
-        assert!(client.invited_rooms().is_empty());
+        let hierarchy = client
+            .space_hierarchy(&space_id, None, Some(10), false)
+            .await
+            .unwrap();
 
-        assert!(client
-            .get_left_room(&room_id!("!SVkFJHzfwvuaIEawgC:localhost"))
-            .is_some())
+        assert_eq!(hierarchy.next_batch.as_deref(), Some("next_page"));
+        assert_eq!(hierarchy.rooms.len(), 2);
+        assert!(hierarchy.rooms[0].is_space());
+        assert_eq!(hierarchy.rooms[0].children_state.len(), 1);
+        assert_eq!(hierarchy.rooms[1].room_id, room_id!("!first:localhost"));
+        assert!(!hierarchy.rooms[1].is_space());
     }
 
     #[tokio::test]
-    async fn sync() {
+    #[cfg(feature = "encryption")]
+    async fn dehydrate_device() {
         let client = logged_in_client().await;
 
-        let _m = mock(
-            "GET",
-            Matcher::Regex(r"^/_matrix/client/r0/sync\?.*$".to_string()),
+        let put = mock(
+            "PUT",
+            "/_matrix/client/unstable/org.matrix.msc2697.v2/dehydrated_device",
         )
         .with_status(200)
-        .with_body(test_json::SYNC.to_string())
         .match_header("authorization", "Bearer 1234")
+        .match_body(Matcher::PartialJson(json!({
+            "device_data": {
+                "algorithm": "org.matrix.msc2697.v1.olm.libolm_pickle",
+                "passphrase": { "iterations": 1000 },
+            },
+            "initial_device_display_name": "Dehydrated device",
+        })))
+        .with_body(json!({ "device_id": "DEHYDRATED1" }).to_string())
         .create();
 
-        let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
-
-        let response = client.sync_once(sync_settings).await.unwrap();
+        // The keys are signed for the device id that the server assigned.
+        let upload = mock("POST", "/_matrix/client/r0/keys/upload/DEHYDRATED1")
+            .with_status(200)
+            .match_body(Matcher::PartialJson(json!({
+                "device_keys": {
+                    "device_id": "DEHYDRATED1",
+                    "user_id": "@example:localhost",
+                },
+            })))
+            .with_body(test_json::KEYS_UPLOAD.to_string())
+            .create();
 
-        assert_ne!(response.next_batch, "");
+        let device_id = client
+            .dehydrate_device("It's a secret to everybody", "Dehydrated device", 1000)
+            .await
+            .unwrap();
 
-        assert!(client.sync_token().await.is_some());
+        assert_eq!(device_id.as_str(), "DEHYDRATED1");
+        put.assert();
+        upload.assert();
     }
 
     #[tokio::test]
-    async fn room_names() {
+    #[cfg(feature = "encryption")]
+    async fn rehydrate_device() {
         let client = logged_in_client().await;
+        let secret = "It's a secret to everybody";
+
+        let m = mock(
+            "GET",
+            "/_matrix/client/unstable/org.matrix.msc2697.v2/dehydrated_device",
+        )
+        .with_status(404)
+        .with_body(
+            json!({
+                "errcode": "M_NOT_FOUND",
+                "error": "No dehydrated device available",
+            })
+            .to_string(),
+        )
+        .create();
+
+        assert!(!client.rehydrate_device(secret).await.unwrap());
+        drop(m);
+
+        let dehydrated = client
+            .base_client
+            .olm_machine()
+            .await
+            .unwrap()
+            .create_dehydrated_device(secret, "Dehydrated device", 1000)
+            .await;
 
         let _m = mock(
             "GET",
-            Matcher::Regex(r"^/_matrix/client/r0/sync\?.*$".to_string()),
+            "/_matrix/client/unstable/org.matrix.msc2697.v2/dehydrated_device",
         )
         .with_status(200)
         .match_header("authorization", "Bearer 1234")
-        .with_body(test_json::SYNC.to_string())
+        .with_body(
+            json!({
+                "device_id": "DEHYDRATED1",
+                "device_data": dehydrated.request().device_data,
+            })
+            .to_string(),
+        )
         .create();
 
-        let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+        // The device isn't claimed if the secret is wrong.
+        let claim = mock(
+            "POST",
+            "/_matrix/client/unstable/org.matrix.msc2697.v2/dehydrated_device/claim",
+        )
+        .with_status(200)
+        .match_body(Matcher::Json(json!({ "device_id": "DEHYDRATED1" })))
+        .with_body(json!({ "success": true }).to_string())
+        .expect(1)
+        .create();
 
-        let _response = client.sync_once(sync_settings).await.unwrap();
+        assert!(matches!(
+            client.rehydrate_device("Wrong secret").await,
+            Err(crate::Error::Dehydration(_))
+        ));
+        assert_eq!(client.device_id().await.unwrap().as_str(), "DEVICEID");
 
-        let room = client
-            .get_joined_room(&room_id!("!SVkFJHzfwvuaIEawgC:localhost"))
-            .unwrap();
+        assert!(client.rehydrate_device(secret).await.unwrap());
+        claim.assert();
 
-        assert_eq!("tutorial".to_string(), room.display_name().await.unwrap());
+        // The session continues as the rehydrated device.
+        assert_eq!(client.device_id().await.unwrap().as_str(), "DEHYDRATED1");
+        assert_eq!(
+            client
+                .base_client
+                .olm_machine()
+                .await
+                .unwrap()
+                .device_id()
+                .as_str(),
+            "DEHYDRATED1"
+        );
     }
 
     #[tokio::test]
@@ -0,0 +1,409 @@
+// Copyright 2021 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    convert::TryInto,
+    future::Future,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use dashmap::DashMap;
+use futures::future::join_all;
+use matrix_sdk_common::{
+    api::r0::session::login, deserialized_responses::SyncResponse, identifiers::UserId,
+};
+use url::Url;
+
+use crate::{
+    http_client::client_with_config, Client, ClientConfig, HttpSend, LoopCtrl, RequestConfig,
+    Result, Session, SyncSettings,
+};
+
+/// A manager hosting the clients of multiple accounts.
+///
+/// All clients of the manager share a single HTTP connection pool. If a
+/// storage root is configured, every account gets its own directory inside of
+/// the storage root for its stores.
+///
+/// The sync loops of all accounts can be driven together using the [`sync`]
+/// and [`sync_with_callback`] methods, the callback receives the client of
+/// the account a sync response belongs to. Event handlers are set per client,
+/// the room an event belongs to knows its own user id.
+///
+/// # Example
+/// ```no_run
+/// # use futures::executor::block_on;
+/// # use matrix_sdk::{ClientManager, LoopCtrl, SyncSettings};
+/// # block_on(async {
+/// let manager = ClientManager::new(Some("/var/lib/bots")).unwrap();
+///
+/// manager
+///     .login("https://example.org", "first_bot", "password", None, None)
+///     .await
+///     .unwrap();
+/// manager
+///     .login("https://example.org", "second_bot", "password", None, None)
+///     .await
+///     .unwrap();
+///
+/// manager
+///     .sync_with_callback(SyncSettings::new(), |client, response| async move {
+///         println!("{:?} got {} joined rooms", client.user_id().await, response.rooms.join.len());
+///         LoopCtrl::Continue
+///     })
+///     .await;
+/// # })
+/// ```
+///
+/// [`sync`]: #method.sync
+/// [`sync_with_callback`]: #method.sync_with_callback
+#[derive(Clone, Debug)]
+pub struct ClientManager {
+    http_client: Arc<dyn HttpSend>,
+    request_config: RequestConfig,
+    store_root: Option<Arc<PathBuf>>,
+    clients: Arc<DashMap<UserId, Client>>,
+}
+
+impl ClientManager {
+    /// Create a new client manager.
+    ///
+    /// # Arguments
+    ///
+    /// * `store_root` - The directory the stores of the accounts should be
+    /// saved in, if `None` the state of the accounts is only kept in memory.
+    /// It is the callers responsibility to make sure that the directory
+    /// exists.
+    pub fn new(store_root: Option<impl AsRef<Path>>) -> Result<Self> {
+        Self::new_with_config(store_root, ClientConfig::new())
+    }
+
+    /// Create a new client manager with the given configuration.
+    ///
+    /// The configuration is used to build the shared HTTP connection pool and
+    /// to configure the requests of all accounts. Store settings of the
+    /// configuration are ignored, the stores are placed inside the
+    /// `store_root`.
+    ///
+    /// # Arguments
+    ///
+    /// * `store_root` - The directory the stores of the accounts should be
+    /// saved in, if `None` the state of the accounts is only kept in memory.
+    ///
+    /// * `config` - Configuration for the clients.
+    pub fn new_with_config(
+        store_root: Option<impl AsRef<Path>>,
+        config: ClientConfig,
+    ) -> Result<Self> {
+        let http_client = if let Some(client) = config.client.clone() {
+            client
+        } else {
+            Arc::new(client_with_config(&config)?)
+        };
+
+        Ok(Self {
+            http_client,
+            request_config: config.request_config,
+            store_root: store_root.map(|p| Arc::new(p.as_ref().to_owned())),
+            clients: Arc::new(DashMap::new()),
+        })
+    }
+
+    /// Get the directory inside of the storage root the stores of the given
+    /// account are placed in.
+    ///
+    /// The directory name is the percent-encoded user id without the leading
+    /// `@`, every character besides ASCII alphanumerics and `.`, `_`, `=` and
+    /// `-` is encoded so different accounts never share a directory.
+    pub fn store_path(&self, user_id: &UserId) -> Option<PathBuf> {
+        self.store_root
+            .as_ref()
+            .map(|root| root.join(encode_user_id(user_id)))
+    }
+
+    fn client_config(&self, user_id: Option<&UserId>) -> ClientConfig {
+        let config = ClientConfig::new()
+            .client(self.http_client.clone())
+            .request_config(self.request_config);
+
+        match user_id.and_then(|u| self.store_path(u)) {
+            Some(path) => config.store_path(path),
+            None => config,
+        }
+    }
+
+    /// Log into an account and add it to the manager.
+    ///
+    /// If the account is already managed, the existing client is replaced.
+    ///
+    /// # Arguments
+    ///
+    /// * `homeserver_url` - The homeserver of the account.
+    ///
+    /// * `user` - The user that should be logged in to the homeserver.
+    ///
+    /// * `password` - The password of the user.
+    ///
+    /// * `device_id` - A unique id that will be associated with this session.
+    ///
+    /// * `initial_device_display_name` - A public facing display name for the
+    /// device.
+    pub async fn login(
+        &self,
+        homeserver_url: impl TryInto<Url>,
+        user: &str,
+        password: &str,
+        device_id: Option<&str>,
+        initial_device_display_name: Option<&str>,
+    ) -> Result<Client> {
+        let homeserver = if let Ok(u) = homeserver_url.try_into() {
+            u
+        } else {
+            panic!("Error parsing homeserver url")
+        };
+
+        // The store path depends on the user id, log in with a temporary
+        // client and restore the session in the final one.
+        let client = Client::new_with_config(homeserver.clone(), self.client_config(None))?;
+        let response: login::Response = client
+            .login(user, password, device_id, initial_device_display_name)
+            .await?;
+
+        let session = Session {
+            access_token: response.access_token,
+            user_id: response.user_id,
+            device_id: response.device_id,
+        };
+
+        self.restore_login(homeserver, session).await
+    }
+
+    /// Add an account with an existing session to the manager.
+    ///
+    /// If the account is already managed, the existing client is replaced.
+    ///
+    /// # Arguments
+    ///
+    /// * `homeserver_url` - The homeserver of the account.
+    ///
+    /// * `session` - A session that the account already has from a previous
+    /// login call.
+    pub async fn restore_login(
+        &self,
+        homeserver_url: impl TryInto<Url>,
+        session: Session,
+    ) -> Result<Client> {
+        let user_id = session.user_id.clone();
+        let client = Client::new_with_config(homeserver_url, self.client_config(Some(&user_id)))?;
+        client.restore_login(session).await?;
+
+        self.clients.insert(user_id, client.clone());
+
+        Ok(client)
+    }
+
+    /// Get the client of a managed account.
+    pub fn client(&self, user_id: &UserId) -> Option<Client> {
+        self.clients.get(user_id).map(|c| c.clone())
+    }
+
+    /// Get the clients of all managed accounts.
+    pub fn clients(&self) -> Vec<Client> {
+        self.clients.iter().map(|c| c.value().clone()).collect()
+    }
+
+    /// Stop managing an account.
+    ///
+    /// This doesn't log the account out, the store of the account is kept.
+    pub fn remove_client(&self, user_id: &UserId) -> Option<Client> {
+        self.clients.remove(user_id).map(|(_, c)| c)
+    }
+
+    /// Repeatedly sync all managed accounts.
+    ///
+    /// This method will never return, if cancellation is needed the method
+    /// should be wrapped in a cancelable task or the [`sync_with_callback`]
+    /// method can be used.
+    ///
+    /// # Arguments
+    ///
+    /// * `sync_settings` - Settings for the sync calls. The sync token of the
+    /// settings is ignored, every account continues from its own token.
+    ///
+    /// [`sync_with_callback`]: #method.sync_with_callback
+    pub async fn sync(&self, sync_settings: SyncSettings<'_>) {
+        self.sync_with_callback(sync_settings, |_, _| async { LoopCtrl::Continue })
+            .await
+    }
+
+    /// Repeatedly sync all managed accounts.
+    ///
+    /// The sync loops of the accounts run concurrently, the method returns
+    /// once the loops of all accounts have been stopped. Accounts that are
+    /// added while the method runs aren't synced.
+    ///
+    /// # Arguments
+    ///
+    /// * `sync_settings` - Settings for the sync calls. The sync token of the
+    /// settings is ignored, every account continues from its own token.
+    ///
+    /// * `callback` - A callback that will be called every time a successful
+    /// response has been fetched for one of the accounts, together with the
+    /// client of that account. If the callback returns `LoopCtrl::Break` the
+    /// sync loop of that account will be stopped.
+    pub async fn sync_with_callback<C>(
+        &self,
+        sync_settings: SyncSettings<'_>,
+        callback: impl Fn(Client, SyncResponse) -> C,
+    ) where
+        C: Future<Output = LoopCtrl>,
+    {
+        let callback = &callback;
+
+        let loops = self.clients().into_iter().map(|client| {
+            let mut sync_settings = sync_settings.clone();
+            sync_settings.token = None;
+
+            async move {
+                client
+                    .sync_with_callback(sync_settings, |response| {
+                        callback(client.clone(), response)
+                    })
+                    .await
+            }
+        });
+
+        join_all(loops).await;
+    }
+}
+
+/// Percent-encode a user id so it can be used as a directory name.
+fn encode_user_id(user_id: &UserId) -> String {
+    user_id.as_str()[1..]
+        .bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'.' | b'_' | b'=' | b'-' => {
+                (b as char).to_string()
+            }
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::TryFrom;
+
+    use matrix_sdk_common::identifiers::{user_id, UserId};
+    use matrix_sdk_test::test_json;
+    use mockito::{mock, Matcher};
+    use tempfile::tempdir;
+
+    use super::ClientManager;
+    use crate::{ClientConfig, LoopCtrl, RequestConfig, Session, SyncSettings};
+
+    #[tokio::test]
+    async fn login() {
+        let dir = tempdir().unwrap();
+        let manager = ClientManager::new(Some(dir.path())).unwrap();
+
+        let _m = mock("POST", "/_matrix/client/r0/login")
+            .with_status(200)
+            .with_body(test_json::LOGIN.to_string())
+            .create();
+
+        let client = manager
+            .login(
+                mockito::server_url().as_str(),
+                "example",
+                "wordpass",
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+
+        let user_id = user_id!("@cheeky_monkey:matrix.org");
+        assert_eq!(client.user_id().await, Some(user_id.clone()));
+        assert!(manager.client(&user_id).is_some());
+        assert_eq!(
+            manager.store_path(&user_id),
+            Some(dir.path().join("cheeky_monkey%3Amatrix.org"))
+        );
+        assert!(dir.path().join("cheeky_monkey%3Amatrix.org").exists());
+
+        assert!(manager.remove_client(&user_id).is_some());
+        assert!(manager.clients().is_empty());
+    }
+
+    #[test]
+    fn store_paths_are_unambiguous() {
+        let manager = ClientManager::new(Some("/stores")).unwrap();
+
+        let first = manager.store_path(&user_id!("@a_b:1")).unwrap();
+        let second = manager.store_path(&user_id!("@a:b:1")).unwrap();
+
+        assert_ne!(first, second);
+        assert_eq!(
+            manager.store_path(&user_id!("@example:localhost:8448")),
+            Some("/stores/example%3Alocalhost%3A8448".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn multiplexed_sync() {
+        let config = ClientConfig::new().request_config(RequestConfig::new().disable_retry());
+        let manager = ClientManager::new_with_config(None::<&str>, config).unwrap();
+
+        for (user, device) in &[("@alice:localhost", "ALICE"), ("@bob:localhost", "BOB")] {
+            let session = Session {
+                access_token: "1234".to_owned(),
+                user_id: UserId::try_from(*user).unwrap(),
+                device_id: (*device).into(),
+            };
+
+            manager
+                .restore_login(mockito::server_url().as_str(), session)
+                .await
+                .unwrap();
+        }
+
+        let _m = mock(
+            "GET",
+            Matcher::Regex(r"^/_matrix/client/r0/sync\?.*$".to_string()),
+        )
+        .with_status(200)
+        .with_body(test_json::SYNC.to_string())
+        .create();
+
+        let synced = dashmap::DashSet::new();
+
+        manager
+            .sync_with_callback(SyncSettings::new(), |client, response| {
+                let synced = &synced;
+
+                async move {
+                    assert!(!response.rooms.join.is_empty());
+                    synced.insert(client.user_id().await.unwrap());
+                    LoopCtrl::Break
+                }
+            })
+            .await;
+
+        assert_eq!(synced.len(), 2);
+        assert!(synced.contains(&user_id!("@alice:localhost")));
+        assert!(synced.contains(&user_id!("@bob:localhost")));
+    }
+}
@@ -71,6 +71,42 @@ impl Device {
         })
     }
 
+    /// Mark the device as verified after it was verified out of band, e.g. by
+    /// comparing the fingerprint of its ed25519 key.
+    ///
+    /// This marks the device as locally verified and uploads the same
+    /// signatures an interactive verification would.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::convert::TryFrom;
+    /// # use matrix_sdk::{Client, identifiers::{DeviceKeyAlgorithm, UserId}};
+    /// # use url::Url;
+    /// # use futures::executor::block_on;
+    /// # let alice = UserId::try_from("@alice:example.org").unwrap();
+    /// # let homeserver = Url::parse("http://example.com").unwrap();
+    /// # let client = Client::new(homeserver).unwrap();
+    /// # let fingerprint = "".to_owned();
+    /// # block_on(async {
+    /// let device = client.get_device(&alice, "DEVICEID".into())
+    ///     .await
+    ///     .unwrap()
+    ///     .unwrap();
+    ///
+    /// if device.get_key(DeviceKeyAlgorithm::Ed25519) == Some(&fingerprint) {
+    ///     device.verify().await.unwrap();
+    /// }
+    /// # });
+    /// ```
+    pub async fn verify(&self) -> Result<()> {
+        if let Some(request) = self.inner.verify().await? {
+            self.client.upload_signatures(&request).await?;
+        }
+
+        Ok(())
+    }
+
     /// Is the device trusted.
     pub fn is_trusted(&self) -> bool {
         self.inner.trust_state()
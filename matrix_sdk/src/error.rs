@@ -21,16 +21,17 @@ use matrix_sdk_common::{
         r0::uiaa::{UiaaInfo, UiaaResponse as UiaaError},
         Error as RumaClientError,
     },
-    identifiers::Error as IdentifierError,
+    identifiers::{Error as IdentifierError, RoomId},
     FromHttpResponseError, IntoHttpError, ServerError,
 };
 use reqwest::Error as ReqwestError;
 use serde_json::Error as JsonError;
 use std::io::Error as IoError;
 use thiserror::Error;
+use url::ParseError as UrlParseError;
 
 #[cfg(feature = "encryption")]
-use matrix_sdk_base::crypto::store::CryptoStoreError;
+use matrix_sdk_base::crypto::{store::CryptoStoreError, DehydrationError};
 
 /// Result type of the rust-sdk.
 pub type Result<T> = std::result::Result<T, Error>;
@@ -104,6 +105,11 @@ pub enum Error {
     #[error(transparent)]
     CryptoStoreError(#[from] CryptoStoreError),
 
+    /// A dehydrated device couldn't be restored.
+    #[cfg(feature = "encryption")]
+    #[error(transparent)]
+    Dehydration(#[from] DehydrationError),
+
     /// An error occured in the state store.
     #[error(transparent)]
     StateStore(#[from] StoreError),
@@ -111,6 +117,89 @@ pub enum Error {
     /// An error encountered when trying to parse an identifier.
     #[error(transparent)]
     Identifier(#[from] IdentifierError),
+
+    /// An error encountered when trying to parse an URL.
+    #[error(transparent)]
+    Url(#[from] UrlParseError),
+
+    /// The homeserver of a server name couldn't be discovered.
+    #[error(transparent)]
+    Discovery(#[from] DiscoveryError),
+
+    /// A direct message room was created but it couldn't be added to the
+    /// `m.direct` account data, [`Client::mark_as_dm`] can be used to retry.
+    ///
+    /// [`Client::mark_as_dm`]: crate::Client::mark_as_dm
+    #[error(
+        "the direct message room {room_id} was created but couldn't be marked as such: {source}"
+    )]
+    DirectRoomNotMarked {
+        /// The id of the room that was created.
+        room_id: RoomId,
+        /// The error that prevented the `m.direct` update.
+        source: Box<Error>,
+    },
+
+    /// The power level of the user in a room is too low for the requested
+    /// action.
+    #[error("the power level of the user is too low, required: {required}, actual: {actual}")]
+    InsufficientPowerLevel {
+        /// The power level that is required for the action.
+        required: i64,
+        /// The power level the user has.
+        actual: i64,
+    },
+
+    /// A change of the power levels of a room would lower our own power
+    /// level.
+    #[error("the change would lower our own power level from {current} to {requested}")]
+    OwnPowerLevelLowered {
+        /// Our current power level.
+        current: i64,
+        /// The power level the change would give us.
+        requested: i64,
+    },
+
+    /// An error encountered when trying to parse an application service
+    /// registration.
+    #[cfg(feature = "appservice")]
+    #[error(transparent)]
+    Yaml(#[from] serde_yaml::Error),
+
+    /// A namespace of an application service registration contains an
+    /// invalid regular expression.
+    #[cfg(feature = "appservice")]
+    #[error(transparent)]
+    Regex(#[from] regex::Error),
+}
+
+/// Errors that can happen while discovering the homeserver of a server name.
+///
+/// The variants map to the `FAIL_PROMPT` and `FAIL_ERROR` outcomes of the
+/// [server discovery] process.
+///
+/// [server discovery]: https://matrix.org/docs/spec/client_server/r0.6.1#server-discovery
+#[derive(Error, Debug)]
+pub enum DiscoveryError {
+    /// The `.well-known/matrix/client` file of the server couldn't be fetched
+    /// or didn't contain valid JSON.
+    #[error("the well-known file of the server couldn't be fetched: {0}")]
+    WellKnown(HttpError),
+
+    /// The `.well-known/matrix/client` file contained an invalid homeserver
+    /// URL.
+    #[error("the well-known file contains an invalid homeserver URL: {0}")]
+    InvalidHomeserverUrl(UrlParseError),
+
+    /// The discovered homeserver didn't respond to a supported versions
+    /// request, it probably isn't a Matrix homeserver.
+    #[error("the discovered homeserver doesn't respond to a versions request: {0}")]
+    InvalidHomeserver(HttpError),
+
+    /// The `.well-known/matrix/client` file contained an invalid identity
+    /// server URL.
+    #[error("the well-known file contains an invalid identity server URL: {0}")]
+    InvalidIdentityServerUrl(UrlParseError),
 }
 
 impl Error {
@@ -12,13 +12,13 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
-use std::ops::Deref;
+use std::{collections::BTreeMap, ops::Deref};
 
 use matrix_sdk_common::{events::AnySyncRoomEvent, identifiers::RoomId};
 use serde_json::value::RawValue as RawJsonValue;
 
 use crate::{
-    deserialized_responses::SyncResponse,
+    deserialized_responses::{SyncResponse, SyncRoomEvent},
     events::{
         call::{
             answer::AnswerEventContent, candidates::CandidatesEventContent,
@@ -71,6 +71,13 @@ impl Handler {
     }
 
     pub(crate) async fn handle_sync(&self, response: &SyncResponse) {
+        for event in &response.account_data.events {
+            self.on_account_data(event).await;
+        }
+
+        self.handle_decrypted_events(&response.decrypted_events)
+            .await;
+
         for (room_id, room_info) in &response.rooms.join {
             if let Some(room) = self.get_room(room_id) {
                 for event in &room_info.ephemeral.events {
@@ -86,7 +93,7 @@ impl Handler {
                 }
 
                 for event in &room_info.timeline.events {
-                    self.handle_timeline_event(room.clone(), event).await;
+                    self.handle_timeline_event(room.clone(), &event.event).await;
                 }
             }
         }
@@ -102,7 +109,7 @@ impl Handler {
                 }
 
                 for event in &room_info.timeline.events {
-                    self.handle_timeline_event(room.clone(), event).await;
+                    self.handle_timeline_event(room.clone(), &event.event).await;
                 }
             }
         }
@@ -120,6 +127,21 @@ impl Handler {
         }
     }
 
+    /// Emit the events of earlier syncs that could be decrypted late as
+    /// timeline events.
+    pub(crate) async fn handle_decrypted_events(
+        &self,
+        events: &BTreeMap<RoomId, Vec<SyncRoomEvent>>,
+    ) {
+        for (room_id, events) in events {
+            if let Some(room) = self.get_room(room_id) {
+                for event in events {
+                    self.handle_timeline_event(room.clone(), &event.event).await;
+                }
+            }
+        }
+    }
+
     async fn handle_timeline_event(&self, room: Room, event: &AnySyncRoomEvent) {
         match event {
             AnySyncRoomEvent::State(event) => match event {
@@ -217,6 +239,8 @@ impl Handler {
     }
 
     pub(crate) async fn handle_account_data_event(&self, room: Room, event: &AnyBasicEvent) {
+        self.on_room_account_data(room.clone(), event).await;
+
         match event {
             AnyBasicEvent::Presence(presence) => self.on_non_room_presence(room, &presence).await,
             AnyBasicEvent::IgnoredUserList(ignored) => {
@@ -437,6 +461,11 @@ pub trait EventHandler: Send + Sync {
     /// This is always a read receipt.
     async fn on_non_room_receipt(&self, _: Room, _: &SyncEphemeralRoomEvent<ReceiptEventContent>) {}
 
+    /// Fires when `Client` receives a global account data event.
+    async fn on_account_data(&self, _: &AnyBasicEvent) {}
+    /// Fires when `Client` receives a room account data event.
+    async fn on_room_account_data(&self, _: Room, _: &AnyBasicEvent) {}
+
     // `PresenceEvent` is a struct so there is only the one method
     /// Fires when `Client` receives a `NonRoomEvent::RoomAliases` event.
     async fn on_presence_event(&self, _: &PresenceEvent) {}
@@ -23,11 +23,11 @@ use reqwest::{Client, Response};
 #[cfg(all(not(target_arch = "wasm32")))]
 use std::sync::atomic::{AtomicU64, Ordering};
 use tracing::trace;
-use url::Url;
+use url::{form_urlencoded, Url};
 
 use matrix_sdk_common::{
-    api::r0::media::create_content, async_trait, locks::RwLock, AsyncTraitDeps, AuthScheme,
-    FromHttpResponseError, IncomingResponse,
+    api::r0::media::create_content, async_trait, identifiers::UserId, locks::RwLock,
+    AsyncTraitDeps, AuthScheme, FromHttpResponseError, IncomingResponse,
 };
 
 use crate::{error::HttpError, ClientConfig, OutgoingRequest, RequestConfig, Session};
@@ -105,28 +105,36 @@ impl HttpClient {
         session: Arc<RwLock<Option<Session>>>,
         config: Option<RequestConfig>,
     ) -> Result<http::Response<Vec<u8>>, HttpError> {
+        let config = match config {
+            Some(config) => config,
+            None => self.request_config,
+        };
+
         let request = {
-            let read_guard;
-            let access_token = match Request::METADATA.authentication {
-                AuthScheme::AccessToken => {
-                    read_guard = session.read().await;
-
-                    if let Some(session) = read_guard.as_ref() {
-                        Some(session.access_token.as_str())
-                    } else {
-                        return Err(HttpError::AuthenticationRequired);
-                    }
-                }
-                AuthScheme::None => None,
+            let session = session.read().await;
+
+            let needs_auth = match Request::METADATA.authentication {
+                AuthScheme::AccessToken => true,
+                AuthScheme::None => config.force_auth,
                 _ => return Err(HttpError::NotClientRequest),
             };
 
-            request.try_into_http_request(&self.homeserver.to_string(), access_token)?
-        };
+            let access_token = if needs_auth {
+                let session = session.as_ref().ok_or(HttpError::AuthenticationRequired)?;
+                Some(session.access_token.as_str())
+            } else {
+                None
+            };
 
-        let config = match config {
-            Some(config) => config,
-            None => self.request_config,
+            let mut request =
+                request.try_into_http_request(&self.homeserver.to_string(), access_token)?;
+
+            if config.assert_identity {
+                let session = session.as_ref().ok_or(HttpError::AuthenticationRequired)?;
+                assert_identity(&mut request, &session.user_id);
+            }
+
+            request
         };
 
         self.inner.send_request(request, config).await
@@ -164,6 +172,21 @@ impl HttpClient {
     }
 }
 
+/// Append the `user_id` query parameter to the request, this is used by
+/// application services to masquerade as one of their virtual users.
+fn assert_identity(request: &mut http::Request<Vec<u8>>, user_id: &UserId) {
+    let user_id: String = form_urlencoded::byte_serialize(user_id.as_str().as_bytes()).collect();
+    let separator = if request.uri().query().is_some() {
+        '&'
+    } else {
+        '?'
+    };
+
+    *request.uri_mut() = format!("{}{}user_id={}", request.uri(), separator, user_id)
+        .parse()
+        .expect("Appending a query parameter to a valid URI can't fail");
+}
+
 /// Build a client with the specified configuration.
 pub(crate) fn client_with_config(config: &ClientConfig) -> Result<Client, HttpError> {
     let http_client = reqwest::Client::builder();
@@ -42,10 +42,17 @@
 //! by default be stored only in memory and thus lost after the client is
 //! destroyed.
 //! * `unstable-synapse-quirks`: Enables support to deal with inconsistencies
-//! of Synapse in compliance with the Matrix API specification.
+//! of Synapse in compliance with the Matrix API specification. This relaxes
+//! the deserialization of the response types themselves and thus can only be
+//! chosen at compile time. Features of the homeserver that can be decided at
+//! runtime, like unstable endpoints or the `m.change_password` capability,
+//! can be queried using [`Client::supported_versions`] and
+//! [`Client::capabilities`].
 //! * `markdown`: Support for sending markdown formatted messages.
 //! * `socks`: Enables SOCKS support in reqwest, the default HTTP client.
 //! * `sso_login`: Enables SSO login with a local http server.
+//! * `appservice`: Enables the application service API, see the
+//! [`appservice`] module.
 
 #![deny(
     missing_debug_implementations,
@@ -68,18 +75,30 @@ compile_error!("only one of 'native-tls' or 'rustls-tls' features can be enabled
 #[cfg(all(feature = "sso_login", target_arch = "wasm32"))]
 compile_error!("'sso_login' cannot be enabled on 'wasm32' arch");
 
+#[cfg(all(feature = "appservice", target_arch = "wasm32"))]
+compile_error!("'appservice' cannot be enabled on 'wasm32' arch");
+
 #[cfg(feature = "encryption")]
 #[cfg_attr(feature = "docs", doc(cfg(encryption)))]
-pub use matrix_sdk_base::crypto::{EncryptionInfo, LocalTrust};
+pub use matrix_sdk_base::crypto::{
+    DehydrationError, EncryptionInfo, IdentityChange, IdentityChangeCallback, KeyShareCallback,
+    KeyShareDecisionRecord, KeySharePolicy, KeyShareRequestInfo, KeyshareDecision, LocalTrust,
+    ShareStrategy, SkipReason, SkippedDevice,
+};
 pub use matrix_sdk_base::{
-    Error as BaseError, Room as BaseRoom, RoomInfo, RoomMember as BaseRoomMember, RoomType,
-    Session, StateChanges, StoreError,
+    DefaultRoomNameFormatter, Error as BaseError, Room as BaseRoom, RoomInfo,
+    RoomMember as BaseRoomMember, RoomNameFormatter, RoomNameParts, RoomType, Session, SpaceChild,
+    SpaceParent, SpecRoomNameFormatter, StateChanges, StoreError, FAVOURITE_TAG, LOW_PRIORITY_TAG,
 };
 
 pub use matrix_sdk_common::*;
 pub use reqwest;
 
+#[cfg(feature = "appservice")]
+#[cfg_attr(feature = "docs", doc(cfg(appservice)))]
+pub mod appservice;
 mod client;
+mod client_manager;
 mod error;
 mod event_handler;
 mod http_client;
@@ -87,6 +106,7 @@ mod http_client;
 pub mod room;
 /// High-level room API
 mod room_member;
+mod space;
 
 #[cfg(feature = "encryption")]
 mod device;
@@ -95,17 +115,19 @@ mod sas;
 #[cfg(feature = "encryption")]
 mod verification_request;
 
-pub use client::{Client, ClientConfig, LoopCtrl, RequestConfig, SyncSettings};
+pub use client::{Client, ClientConfig, LoopCtrl, RequestConfig, SupportedVersions, SyncSettings};
+pub use client_manager::ClientManager;
 #[cfg(feature = "encryption")]
 #[cfg_attr(feature = "docs", doc(cfg(encryption)))]
 pub use device::Device;
-pub use error::{Error, HttpError, Result};
+pub use error::{DiscoveryError, Error, HttpError, Result};
 pub use event_handler::{CustomEvent, EventHandler};
 pub use http_client::HttpSend;
 pub use room_member::RoomMember;
 #[cfg(feature = "encryption")]
 #[cfg_attr(feature = "docs", doc(cfg(encryption)))]
 pub use sas::Sas;
+pub use space::{SpaceHierarchy, SpaceHierarchyRoom};
 
 #[cfg(not(target_arch = "wasm32"))]
 pub(crate) const VERSION: &str = env!("CARGO_PKG_VERSION");
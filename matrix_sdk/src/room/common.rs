@@ -1,13 +1,17 @@
 use matrix_sdk_common::{
     api::r0::{
         media::{get_content, get_content_thumbnail},
-        membership::{get_member_events, join_room_by_id, leave_room},
+        membership::{get_member_events, join_room_by_id, join_room_by_id_or_alias, leave_room},
         message::get_message_events,
     },
+    assign,
+    identifiers::{RoomId, RoomIdOrAliasId, ServerName},
     locks::Mutex,
 };
 
-use std::{ops::Deref, sync::Arc};
+#[cfg(feature = "encryption")]
+use matrix_sdk_common::deserialized_responses::UndecryptableEvent;
+use std::{convert::TryFrom, ops::Deref, sync::Arc};
 
 use crate::{BaseRoom, Client, Result, RoomMember};
 
@@ -61,6 +65,51 @@ impl Common {
         Ok(())
     }
 
+    /// Join the room that replaced this room after it was upgraded.
+    ///
+    /// Returns the room id of the replacement room, or `None` if this room
+    /// hasn't been upgraded. If the client is already a member of the
+    /// replacement room it isn't joined again.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use futures::executor::block_on;
+    /// # use matrix_sdk::Client;
+    /// # use matrix_sdk::identifiers::room_id;
+    /// # use url::Url;
+    /// # let homeserver = Url::parse("http://example.com").unwrap();
+    /// # block_on(async {
+    /// # let client = Client::new(homeserver).unwrap();
+    /// # let room_id = room_id!("!roomid:example.com");
+    /// let room = client.get_joined_room(&room_id).unwrap();
+    ///
+    /// if let Some(replacement) = room.join_successor().await.unwrap() {
+    ///     println!("The room was upgraded, continuing in {}", replacement);
+    /// }
+    /// # })
+    /// ```
+    pub async fn join_successor(&self) -> Result<Option<RoomId>> {
+        let successor = if let Some(s) = self.inner.successor() {
+            s
+        } else {
+            return Ok(None);
+        };
+
+        if self.client.get_joined_room(&successor).is_none() {
+            let room_id = RoomIdOrAliasId::from(successor.clone());
+            let server_names = [<Box<ServerName>>::try_from(
+                successor.server_name().as_str(),
+            )?];
+            let request = assign!(join_room_by_id_or_alias::Request::new(&room_id), {
+                server_name: &server_names,
+            });
+
+            self.client.send(request, None).await?;
+        }
+
+        Ok(Some(successor))
+    }
+
     /// Gets the avatar of this room, if set.
     ///
     /// Returns the avatar. No guarantee on the size of the image is given.
@@ -209,4 +258,18 @@ impl Common {
             .map(|member| RoomMember::new(self.client.clone(), member))
             .collect())
     }
+
+    /// Get the encrypted events of this room that couldn't be decrypted yet,
+    /// together with the reason why they couldn't be decrypted.
+    ///
+    /// The events are decrypted and emitted to the event handler once their
+    /// room keys arrive.
+    #[cfg(feature = "encryption")]
+    #[cfg_attr(feature = "docs", doc(cfg(encryption)))]
+    pub async fn undecryptable_events(&self) -> Vec<UndecryptableEvent> {
+        self.client
+            .base_client
+            .undecryptable_events(self.inner.room_id())
+            .await
+    }
 }
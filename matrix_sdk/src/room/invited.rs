@@ -1,6 +1,31 @@
+use matrix_sdk_common::{
+    api::r0::membership::leave_room,
+    assign,
+    identifiers::{MxcUri, UserId},
+};
+
 use crate::{room::Common, BaseRoom, Client, Result, RoomType};
 use std::ops::Deref;
 
+/// Details about the invitation to a room.
+///
+/// The name, avatar, topic and encryption state of the room are available
+/// on the room itself, they are taken from the stripped state the inviter
+/// shared with us.
+#[derive(Clone, Debug)]
+pub struct InviteDetails {
+    /// The user that invited us.
+    pub inviter: UserId,
+    /// The display name of the inviter, if it was part of the stripped state.
+    pub inviter_display_name: Option<String>,
+    /// The avatar of the inviter, if it was part of the stripped state.
+    pub inviter_avatar_url: Option<MxcUri>,
+    /// The reason the inviter gave for the invitation.
+    pub reason: Option<String>,
+    /// Was the room marked as a direct message by the inviter.
+    pub is_direct: bool,
+}
+
 /// A room in the invited state.
 ///
 /// This struct contains all methodes specific to a `Room` with type `RoomType::Invited`.
@@ -28,11 +53,99 @@ impl Invited {
         }
     }
 
+    /// Get the details of the invitation, e.g. who invited us and why.
+    ///
+    /// Returns `None` if the invite event isn't part of the stripped state of
+    /// the room.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use futures::executor::block_on;
+    /// # use matrix_sdk::Client;
+    /// # use url::Url;
+    /// # let homeserver = Url::parse("http://example.com").unwrap();
+    /// # block_on(async {
+    /// # let client = Client::new(homeserver).unwrap();
+    /// for room in client.invited_rooms() {
+    ///     if let Some(details) = room.invite_details().await.unwrap() {
+    ///         println!(
+    ///             "{} invited us to {}: {:?}",
+    ///             details.inviter,
+    ///             room.display_name().await.unwrap(),
+    ///             details.reason
+    ///         );
+    ///     }
+    /// }
+    /// # });
+    /// ```
+    pub async fn invite_details(&self) -> Result<Option<InviteDetails>> {
+        let store = self.client.store();
+
+        let invite = if let Some(e) = store
+            .get_stripped_member_event(self.room_id(), self.own_user_id())
+            .await?
+        {
+            e
+        } else {
+            return Ok(None);
+        };
+
+        let inviter = store
+            .get_stripped_member_event(self.room_id(), &invite.sender)
+            .await?;
+
+        Ok(Some(InviteDetails {
+            inviter_display_name: inviter.as_ref().and_then(|i| i.content.displayname.clone()),
+            inviter_avatar_url: inviter.and_then(|i| i.content.avatar_url),
+            inviter: invite.sender,
+            reason: self.invite_reason(),
+            is_direct: invite.content.is_direct.unwrap_or(false),
+        }))
+    }
+
     /// Reject the invitation.
     pub async fn reject_invitation(&self) -> Result<()> {
         self.inner.leave().await
     }
 
+    /// Reject the invitation, giving a reason to the inviter.
+    ///
+    /// # Arguments
+    ///
+    /// * `reason` - The reason for rejecting the invitation, shown to the
+    /// inviter.
+    pub async fn reject_invitation_with_reason(&self, reason: Option<&str>) -> Result<()> {
+        let request = assign!(leave_room::Request::new(self.room_id()), { reason });
+        self.client.send(request, None).await?;
+
+        Ok(())
+    }
+
+    /// Reject the invitation and ignore the user that invited us.
+    ///
+    /// Ignoring the inviter hides all further events and invites of the user.
+    ///
+    /// # Arguments
+    ///
+    /// * `reason` - The reason for rejecting the invitation, shown to the
+    /// inviter.
+    pub async fn reject_and_ignore_inviter(&self, reason: Option<&str>) -> Result<()> {
+        let inviter = self
+            .client
+            .store()
+            .get_stripped_member_event(self.room_id(), self.own_user_id())
+            .await?
+            .map(|e| e.sender);
+
+        self.reject_invitation_with_reason(reason).await?;
+
+        if let Some(inviter) = inviter {
+            self.client.ignore_user(&inviter).await?;
+        }
+
+        Ok(())
+    }
+
     /// Accept the invitation.
     pub async fn accept_invitation(&self) -> Result<()> {
         self.inner.join().await
@@ -1,8 +1,14 @@
-use crate::{room::Common, BaseRoom, Client, Result, RoomType};
+use crate::{room::Common, BaseRoom, Client, Error, Result, RoomType};
 use std::{io::Read, ops::Deref, sync::Arc};
 
+use futures::Stream;
+use matrix_sdk_base::SPACE_CHILD_EVENT_TYPES;
+use serde::de::DeserializeOwned;
+use serde_json::{json, value::to_raw_value};
+
 use matrix_sdk_common::{
     api::r0::{
+        config::set_room_account_data,
         membership::{
             ban_user,
             invite_user::{self, InvitationRecipient},
@@ -12,23 +18,29 @@ use matrix_sdk_common::{
         read_marker::set_read_marker,
         receipt::create_receipt,
         redact::redact_event,
+        room::upgrade_room,
         state::send_state_event,
+        tag::{create_tag, delete_tag},
         typing::create_typing_event::{Request as TypingRequest, Typing},
     },
     assign,
     events::{
+        custom::CustomEventContent,
         room::{
             message::{
                 AudioMessageEventContent, FileMessageEventContent, ImageMessageEventContent,
                 MessageEventContent, MessageType, VideoMessageEventContent,
             },
+            power_levels::PowerLevelsEventContent,
             EncryptedFile,
         },
-        AnyMessageEventContent, AnyStateEventContent,
+        tag::TagInfo,
+        AnyBasicEvent, AnyMessageEventContent, AnyStateEventContent, BasicEventContent, EventType,
     },
-    identifiers::{EventId, UserId},
+    identifiers::{EventId, RoomId, RoomVersionId, UserId},
     instant::{Duration, Instant},
     uuid::Uuid,
+    Int,
 };
 
 use mime::{self, Mime};
@@ -253,6 +265,143 @@ impl Joined {
         Ok(())
     }
 
+    /// Get the content of a room account data event of this room, e.g. the
+    /// tags or the fully read marker.
+    ///
+    /// The account data is read from the store and kept up to date when
+    /// syncing. Returns `None` if no such event was received.
+    ///
+    /// # Arguments
+    ///
+    /// * `event_type` - The event type of the account data event.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use futures::executor::block_on;
+    /// # use matrix_sdk::Client;
+    /// # use matrix_sdk::identifiers::room_id;
+    /// # use matrix_sdk::events::{tag::TagEventContent, EventType};
+    /// # use url::Url;
+    /// # let homeserver = Url::parse("http://example.com").unwrap();
+    /// # block_on(async {
+    /// # let client = Client::new(homeserver).unwrap();
+    /// # let room = client.get_joined_room(&room_id!("!test:localhost")).unwrap();
+    /// let tags: Option<TagEventContent> = room.account_data(EventType::Tag).await.unwrap();
+    /// # });
+    /// ```
+    pub async fn account_data<C: DeserializeOwned>(
+        &self,
+        event_type: EventType,
+    ) -> Result<Option<C>> {
+        Ok(self
+            .client
+            .store()
+            .get_room_account_data_event(self.inner.room_id(), event_type)
+            .await?
+            .map(|e| serde_json::to_value(e.content()).and_then(serde_json::from_value))
+            .transpose()?)
+    }
+
+    /// Set a room account data event of this room.
+    ///
+    /// The event type is taken from the content, custom account data can be
+    /// set using a `CustomEventContent`.
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - The content of the account data event.
+    pub async fn set_account_data(&self, content: impl BasicEventContent) -> Result<()> {
+        let user_id = self
+            .client
+            .user_id()
+            .await
+            .ok_or(Error::AuthenticationRequired)?;
+
+        let request = set_room_account_data::Request::new(
+            to_raw_value(&content)?,
+            content.event_type(),
+            self.inner.room_id(),
+            &user_id,
+        );
+
+        self.client.send(request, None).await?;
+        Ok(())
+    }
+
+    /// Subscribe to a room account data event type of this room.
+    ///
+    /// The returned stream yields every event of the given type for this room
+    /// that is received in a sync. Dropping the stream ends the subscription.
+    ///
+    /// # Arguments
+    ///
+    /// * `event_type` - The event type of the account data event.
+    pub fn account_data_stream(&self, event_type: EventType) -> impl Stream<Item = AnyBasicEvent> {
+        self.client
+            .subscribe_account_data(Some(self.inner.room_id()), event_type)
+    }
+
+    /// Add a tag to this room, e.g. [`FAVOURITE_TAG`].
+    ///
+    /// If the room already has the tag, its order is updated. The tags of the
+    /// room are updated after the next sync.
+    ///
+    /// # Arguments
+    ///
+    /// * `tag` - The name of the tag, custom tags should use the `u.` prefix.
+    ///
+    /// * `order` - The position of the room among the rooms with the same
+    /// tag, a number between 0 and 1.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use futures::executor::block_on;
+    /// # use matrix_sdk::{Client, FAVOURITE_TAG};
+    /// # use matrix_sdk::identifiers::room_id;
+    /// # use url::Url;
+    /// # let homeserver = Url::parse("http://example.com").unwrap();
+    /// # block_on(async {
+    /// # let client = Client::new(homeserver).unwrap();
+    /// # let room = client.get_joined_room(&room_id!("!test:localhost")).unwrap();
+    /// room.add_tag(FAVOURITE_TAG, Some(0.5)).await.unwrap();
+    /// # });
+    /// ```
+    ///
+    /// [`FAVOURITE_TAG`]: ../constant.FAVOURITE_TAG.html
+    pub async fn add_tag(&self, tag: &str, order: Option<f64>) -> Result<()> {
+        let user_id = self
+            .client
+            .user_id()
+            .await
+            .ok_or(Error::AuthenticationRequired)?;
+
+        let tag_info = assign!(TagInfo::new(), { order });
+        let request = create_tag::Request::new(&user_id, self.inner.room_id(), tag, tag_info);
+
+        self.client.send(request, None).await?;
+        Ok(())
+    }
+
+    /// Remove a tag from this room.
+    ///
+    /// The tags of the room are updated after the next sync.
+    ///
+    /// # Arguments
+    ///
+    /// * `tag` - The name of the tag.
+    pub async fn remove_tag(&self, tag: &str) -> Result<()> {
+        let user_id = self
+            .client
+            .user_id()
+            .await
+            .ok_or(Error::AuthenticationRequired)?;
+
+        let request = delete_tag::Request::new(&user_id, self.inner.room_id(), tag);
+
+        self.client.send(request, None).await?;
+        Ok(())
+    }
+
     /// Share a group session for the given room.
     ///
     /// This will create Olm sessions with all the users/device pairs in the
@@ -645,4 +794,403 @@ impl Joined {
 
         self.client.send(request, None).await
     }
+
+    /// Upgrade this room to a new room version.
+    ///
+    /// The homeserver creates a replacement room with the given version, copies
+    /// the important state over and tombstones this room. Upgrading requires
+    /// the power level to send `m.room.tombstone` events in this room, which
+    /// is checked before the request is sent.
+    ///
+    /// Returns the room id of the replacement room.
+    ///
+    /// # Arguments
+    ///
+    /// * `new_version` - The version the replacement room should use.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use futures::executor::block_on;
+    /// # use matrix_sdk::Client;
+    /// # use matrix_sdk::identifiers::{room_id, RoomVersionId};
+    /// # use url::Url;
+    /// # let homeserver = Url::parse("http://example.com").unwrap();
+    /// # block_on(async {
+    /// # let client = Client::new(homeserver).unwrap();
+    /// # let room_id = room_id!("!roomid:example.com");
+    /// let room = client.get_joined_room(&room_id).unwrap();
+    /// let replacement = room.upgrade(&RoomVersionId::Version6).await.unwrap();
+    /// # })
+    /// ```
+    pub async fn upgrade(&self, new_version: &RoomVersionId) -> Result<RoomId> {
+        self.ensure_state_power_level(EventType::RoomTombstone)
+            .await?;
+
+        let request = upgrade_room::Request::new(self.inner.room_id(), new_version);
+        let response = self.client.send(request, None).await?;
+
+        Ok(response.replacement_room)
+    }
+
+    /// Check that our own power level allows us to send state events of the
+    /// given type to this room.
+    async fn ensure_state_power_level(&self, event_type: EventType) -> Result<()> {
+        let required = self.inner.state_power_level(event_type).await?;
+        let actual = self.inner.user_power_level(self.own_user_id()).await?;
+
+        if actual >= required {
+            Ok(())
+        } else {
+            Err(Error::InsufficientPowerLevel { required, actual })
+        }
+    }
+
+    /// Change the `m.room.power_levels` of this room.
+    ///
+    /// The current power levels of the room are handed to the given closure,
+    /// the modified power levels are sent to the room afterwards. If the room
+    /// doesn't have power levels yet, the closure receives the defaults.
+    ///
+    /// The change is checked against the authorization rules for power level
+    /// events before it is sent: our own power level needs to allow changing
+    /// the power levels, users that have a power level greater than or equal
+    /// to ours can't be changed, and no user, threshold or event level can be
+    /// changed from or to a value above our own power level. Changes that
+    /// would lower our own power level are rejected as well, since they can't
+    /// be undone without the help of another user.
+    ///
+    /// # Arguments
+    ///
+    /// * `update` - The closure modifying the power levels.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use futures::executor::block_on;
+    /// # use matrix_sdk::Client;
+    /// # use matrix_sdk::identifiers::room_id;
+    /// # use url::Url;
+    /// # let homeserver = Url::parse("http://example.com").unwrap();
+    /// # block_on(async {
+    /// # let client = Client::new(homeserver).unwrap();
+    /// # let room_id = room_id!("!roomid:example.com");
+    /// let room = client.get_joined_room(&room_id).unwrap();
+    ///
+    /// // Only allow moderators to invite users.
+    /// room.update_power_levels(|power_levels| power_levels.invite = 50.into())
+    ///     .await
+    ///     .unwrap();
+    /// # })
+    /// ```
+    pub async fn update_power_levels(
+        &self,
+        update: impl FnOnce(&mut PowerLevelsEventContent),
+    ) -> Result<send_state_event::Response> {
+        self.ensure_state_power_level(EventType::RoomPowerLevels)
+            .await?;
+
+        let previous = self.inner.power_levels_or_default().await?;
+        let mut power_levels = previous.clone();
+
+        update(&mut power_levels);
+
+        check_power_levels_change(self.own_user_id(), &previous, &power_levels)?;
+
+        self.send_state_event(AnyStateEventContent::RoomPowerLevels(power_levels), "")
+            .await
+    }
+
+    /// Set the power level of a user in this room.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The user whose power level should be changed.
+    ///
+    /// * `level` - The new power level of the user, can't be higher than our
+    /// own power level.
+    pub async fn set_user_power_level(
+        &self,
+        user_id: &UserId,
+        level: Int,
+    ) -> Result<send_state_event::Response> {
+        self.update_power_levels(|power_levels| {
+            power_levels.users.insert(user_id.clone(), level);
+        })
+        .await
+    }
+
+    /// Add a room as a child of this space by sending a `m.space.child` state
+    /// event.
+    ///
+    /// If the room already is a child of the space, its `m.space.child` event
+    /// is replaced.
+    ///
+    /// # Arguments
+    ///
+    /// * `child` - The room id of the child room.
+    ///
+    /// * `via` - The servers that can be used to join the child room, must not
+    /// be empty.
+    ///
+    /// * `order` - The string the children of the space should be ordered by.
+    ///
+    /// * `suggested` - Should the child room be suggested to members of the
+    /// space.
+    pub async fn add_space_child(
+        &self,
+        child: &RoomId,
+        via: Vec<String>,
+        order: Option<String>,
+        suggested: bool,
+    ) -> Result<send_state_event::Response> {
+        let mut data = json!({ "via": via, "suggested": suggested });
+
+        if let Some(order) = order {
+            data["order"] = order.into();
+        }
+
+        let content = CustomEventContent {
+            event_type: SPACE_CHILD_EVENT_TYPES[0].to_owned(),
+            data: serde_json::from_value(data)?,
+        };
+
+        self.send_state_event(AnyStateEventContent::Custom(content), child.as_str())
+            .await
+    }
+
+    /// Remove a room from the children of this space by sending an empty
+    /// `m.space.child` state event.
+    ///
+    /// # Arguments
+    ///
+    /// * `child` - The room id of the child room.
+    pub async fn remove_space_child(&self, child: &RoomId) -> Result<send_state_event::Response> {
+        let content = CustomEventContent {
+            event_type: SPACE_CHILD_EVENT_TYPES[0].to_owned(),
+            data: Default::default(),
+        };
+
+        self.send_state_event(AnyStateEventContent::Custom(content), child.as_str())
+            .await
+    }
+}
+
+/// Check a change of the power levels of a room against the authorization
+/// rules for `m.room.power_levels` events, from the point of view of the user
+/// with the given `own_user_id`.
+///
+/// On top of the rules from the spec this refuses to lower our own power
+/// level.
+fn check_power_levels_change(
+    own_user_id: &UserId,
+    previous: &PowerLevelsEventContent,
+    updated: &PowerLevelsEventContent,
+) -> Result<()> {
+    let user_level = |power_levels: &PowerLevelsEventContent, user_id: &UserId| -> i64 {
+        power_levels
+            .users
+            .get(user_id)
+            .copied()
+            .unwrap_or(power_levels.users_default)
+            .into()
+    };
+
+    let own_level = user_level(previous, own_user_id);
+    let new_own_level = user_level(updated, own_user_id);
+
+    if new_own_level < own_level {
+        return Err(Error::OwnPowerLevelLowered {
+            current: own_level,
+            requested: new_own_level,
+        });
+    }
+
+    let check_changed = |old: Option<Int>, new: Option<Int>| -> Result<()> {
+        if old == new {
+            return Ok(());
+        }
+
+        let required = old.into_iter().chain(new).map(i64::from).max();
+
+        match required {
+            Some(required) if required > own_level => Err(Error::InsufficientPowerLevel {
+                required,
+                actual: own_level,
+            }),
+            _ => Ok(()),
+        }
+    };
+
+    check_changed(Some(previous.ban), Some(updated.ban))?;
+    check_changed(Some(previous.kick), Some(updated.kick))?;
+    check_changed(Some(previous.redact), Some(updated.redact))?;
+    check_changed(Some(previous.invite), Some(updated.invite))?;
+    check_changed(Some(previous.events_default), Some(updated.events_default))?;
+    check_changed(Some(previous.state_default), Some(updated.state_default))?;
+    check_changed(Some(previous.users_default), Some(updated.users_default))?;
+    check_changed(
+        Some(previous.notifications.room),
+        Some(updated.notifications.room),
+    )?;
+
+    for event_type in previous.events.keys().chain(updated.events.keys()) {
+        check_changed(
+            previous.events.get(event_type).copied(),
+            updated.events.get(event_type).copied(),
+        )?;
+    }
+
+    for user_id in previous.users.keys().chain(updated.users.keys()) {
+        let old = previous.users.get(user_id).copied();
+        let new = updated.users.get(user_id).copied();
+
+        if old == new {
+            continue;
+        }
+
+        // Users with a power level equal to ours can't be changed either,
+        // unless it's ourselves.
+        if let Some(old) = old.map(i64::from) {
+            if user_id != own_user_id && old >= own_level {
+                return Err(Error::InsufficientPowerLevel {
+                    required: old + 1,
+                    actual: own_level,
+                });
+            }
+        }
+
+        check_changed(old, new)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use matrix_sdk_common::{
+        events::{room::power_levels::PowerLevelsEventContent, EventType},
+        identifiers::{user_id, UserId},
+    };
+
+    use super::check_power_levels_change;
+    use crate::Error;
+
+    fn power_levels(own_user_id: &UserId) -> PowerLevelsEventContent {
+        let mut power_levels = PowerLevelsEventContent::default();
+        power_levels.users.insert(own_user_id.clone(), 50.into());
+        power_levels
+            .users
+            .insert(user_id!("@moderator:localhost"), 50.into());
+        power_levels
+            .users
+            .insert(user_id!("@user:localhost"), 10.into());
+
+        power_levels
+    }
+
+    fn check(update: impl FnOnce(&mut PowerLevelsEventContent)) -> Result<(), Error> {
+        let own_user_id = user_id!("@example:localhost");
+        let previous = power_levels(&own_user_id);
+        let mut updated = previous.clone();
+        update(&mut updated);
+
+        check_power_levels_change(&own_user_id, &previous, &updated)
+    }
+
+    #[test]
+    fn allowed_power_level_changes() {
+        check(|_| {}).unwrap();
+        check(|p| {
+            p.users.insert(user_id!("@user:localhost"), 50.into());
+        })
+        .unwrap();
+        check(|p| {
+            p.users.remove(&user_id!("@user:localhost"));
+        })
+        .unwrap();
+        check(|p| {
+            p.users.insert(user_id!("@new:localhost"), 20.into());
+        })
+        .unwrap();
+        check(|p| p.ban = 40.into()).unwrap();
+        check(|p| {
+            p.events.insert(EventType::RoomName, 50.into());
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn rejected_power_level_changes() {
+        // Users with a power level equal to ours.
+        assert!(matches!(
+            check(|p| {
+                p.users.insert(user_id!("@moderator:localhost"), 0.into());
+            }),
+            Err(Error::InsufficientPowerLevel {
+                required: 51,
+                actual: 50
+            })
+        ));
+        assert!(matches!(
+            check(|p| {
+                p.users.remove(&user_id!("@moderator:localhost"));
+            }),
+            Err(Error::InsufficientPowerLevel { .. })
+        ));
+
+        // Users above our own power level.
+        assert!(matches!(
+            check(|p| {
+                p.users.insert(user_id!("@user:localhost"), 60.into());
+            }),
+            Err(Error::InsufficientPowerLevel {
+                required: 60,
+                actual: 50
+            })
+        ));
+
+        // Our own power level.
+        assert!(matches!(
+            check(|p| {
+                p.users.insert(user_id!("@example:localhost"), 0.into());
+            }),
+            Err(Error::OwnPowerLevelLowered {
+                current: 50,
+                requested: 0
+            })
+        ));
+        assert!(matches!(
+            check(|p| {
+                p.users.remove(&user_id!("@example:localhost"));
+            }),
+            Err(Error::OwnPowerLevelLowered { .. })
+        ));
+
+        // Thresholds above our own power level.
+        assert!(matches!(
+            check(|p| p.ban = 60.into()),
+            Err(Error::InsufficientPowerLevel { required: 60, .. })
+        ));
+        assert!(matches!(
+            check(|p| p.kick = 60.into()),
+            Err(Error::InsufficientPowerLevel { required: 60, .. })
+        ));
+        assert!(matches!(
+            check(|p| p.redact = 60.into()),
+            Err(Error::InsufficientPowerLevel { required: 60, .. })
+        ));
+        assert!(matches!(
+            check(|p| p.state_default = 60.into()),
+            Err(Error::InsufficientPowerLevel { required: 60, .. })
+        ));
+        assert!(matches!(
+            check(|p| p.notifications.room = 60.into()),
+            Err(Error::InsufficientPowerLevel { required: 60, .. })
+        ));
+        assert!(matches!(
+            check(|p| {
+                p.events.insert(EventType::RoomName, 60.into());
+            }),
+            Err(Error::InsufficientPowerLevel { required: 60, .. })
+        ));
+    }
 }
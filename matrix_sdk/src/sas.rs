@@ -87,7 +87,7 @@ impl Sas {
         }
 
         if let Some(s) = signature {
-            self.client.send(s, None).await?;
+            self.client.upload_signatures(&s).await?;
         }
 
         Ok(())
@@ -0,0 +1,25 @@
+// Copyright 2021 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub use matrix_sdk_common::unstable::space::SpaceHierarchyRoom;
+
+/// A page of a space hierarchy.
+#[derive(Clone, Debug)]
+pub struct SpaceHierarchy {
+    /// The rooms of this page, in depth-first order starting with the space
+    /// itself.
+    pub rooms: Vec<SpaceHierarchyRoom>,
+    /// The token to fetch the next page, `None` if this is the last page.
+    pub next_batch: Option<String>,
+}
@@ -13,6 +13,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+#[cfg(feature = "encryption")]
+use std::collections::VecDeque;
 use std::{
     collections::{BTreeMap, BTreeSet},
     convert::TryFrom,
@@ -27,7 +29,7 @@ use matrix_sdk_common::{
     deserialized_responses::{
         AccountData, AmbiguityChanges, Ephemeral, InviteState, InvitedRoom, JoinedRoom, LeftRoom,
         MemberEvent, MembersResponse, Presence, Rooms, State, StrippedMemberEvent, SyncResponse,
-        Timeline,
+        SyncRoomEvent, Timeline,
     },
     events::{
         presence::PresenceEvent,
@@ -36,7 +38,7 @@ use matrix_sdk_common::{
             member::{MemberEventContent, MembershipState},
         },
         AnyBasicEvent, AnyStrippedStateEvent, AnySyncRoomEvent, AnySyncStateEvent,
-        AnyToDeviceEvent, EventContent, StateEvent,
+        AnyToDeviceEvent, EventContent, EventType, StateEvent,
     },
     identifiers::{RoomId, UserId},
     instant::Instant,
@@ -46,23 +48,32 @@ use matrix_sdk_common::{
 #[cfg(feature = "encryption")]
 use matrix_sdk_common::{
     api::r0::keys::claim_keys::Request as KeysClaimRequest,
-    events::{room::encrypted::EncryptedEventContent, AnyMessageEventContent, AnySyncMessageEvent},
-    identifiers::DeviceId,
+    deserialized_responses::{
+        EventEncryptionInfo, ToDevice, UnableToDecryptReason, UndecryptableEvent,
+    },
+    events::{
+        room::encrypted::EncryptedEventContent, AnyMessageEventContent, AnySyncMessageEvent,
+        SyncMessageEvent,
+    },
+    identifiers::{DeviceId, DeviceIdBox},
     locks::Mutex,
     uuid::Uuid,
 };
 #[cfg(feature = "encryption")]
 use matrix_sdk_crypto::{
+    olm::{
+        RoomKeyWithheldContent, ROOM_KEY_WITHHELD_EVENT_TYPE, UNSTABLE_ROOM_KEY_WITHHELD_EVENT_TYPE,
+    },
     store::{CryptoStore, CryptoStoreError},
-    Device, EncryptionSettings, IncomingResponse, MegolmError, OlmError, OlmMachine,
-    OutgoingRequest, Sas, ToDeviceRequest, UserDevices,
+    Device, EncryptedToDeviceRequests, EncryptionSettings, IncomingResponse, MegolmError, OlmError,
+    OlmMachine, OutgoingRequest, RehydratedDevice, Sas, ToDeviceRequest, UserDevices,
 };
 use tracing::{info, warn};
 use zeroize::Zeroizing;
 
 use crate::{
     error::Result,
-    rooms::{Room, RoomInfo, RoomType},
+    rooms::{Room, RoomInfo, RoomNameFormatter, RoomType},
     session::Session,
     store::{ambiguity_map::AmbiguityCache, Result as StoreResult, StateChanges, Store},
 };
@@ -151,6 +162,96 @@ fn hoist_room_event_prev_content(
     Ok(ev)
 }
 
+/// The maximal number of undecryptable events that are remembered per room,
+/// the oldest events are forgotten first.
+#[cfg(feature = "encryption")]
+const MAX_UNDECRYPTABLE_EVENTS: usize = 100;
+
+/// Record an encrypted event that couldn't be decrypted because of the given
+/// error.
+///
+/// Returns `None` if the error isn't one that a room key arriving later could
+/// fix.
+#[cfg(feature = "encryption")]
+fn undecryptable_event(
+    event: &SyncMessageEvent<EncryptedEventContent>,
+    error: &MegolmError,
+) -> Option<UndecryptableEvent> {
+    let reason = match error {
+        MegolmError::MissingSession => UnableToDecryptReason::MissingSession,
+        MegolmError::UnknownMessageIndex => UnableToDecryptReason::UnknownMessageIndex,
+        MegolmError::SessionWedged(..) => UnableToDecryptReason::WedgedOlmSession,
+        MegolmError::Withheld { code, reason } => UnableToDecryptReason::Withheld {
+            code: code.to_string(),
+            reason: reason.clone(),
+        },
+        _ => return None,
+    };
+
+    match &event.content {
+        EncryptedEventContent::MegolmV1AesSha2(c) => Some(UndecryptableEvent {
+            event: event.clone(),
+            sender_key: c.sender_key.clone(),
+            session_id: c.session_id.clone(),
+            reason,
+        }),
+        _ => None,
+    }
+}
+
+/// Collect the room id and session id pairs of the room keys, forwarded room
+/// keys and room key withheld notices that we received as to-device events.
+#[cfg(feature = "encryption")]
+fn received_room_key_sessions(to_device: &ToDevice) -> BTreeSet<(RoomId, String)> {
+    to_device
+        .events
+        .iter()
+        .filter_map(|e| match e {
+            AnyToDeviceEvent::RoomKey(e) => {
+                Some((e.content.room_id.clone(), e.content.session_id.clone()))
+            }
+            AnyToDeviceEvent::ForwardedRoomKey(e) => {
+                Some((e.content.room_id.clone(), e.content.session_id.clone()))
+            }
+            AnyToDeviceEvent::Custom(e)
+                if e.content.event_type == ROOM_KEY_WITHHELD_EVENT_TYPE
+                    || e.content.event_type == UNSTABLE_ROOM_KEY_WITHHELD_EVENT_TYPE =>
+            {
+                serde_json::to_value(&e.content.data)
+                    .and_then(serde_json::from_value::<RoomKeyWithheldContent>)
+                    .ok()
+                    .and_then(|c| c.room_id.zip(c.session_id))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Get the ignored users out of a `m.ignored_user_list` account data event.
+///
+/// Returns `None` if the event is of a different type.
+fn parse_ignored_users(event: &AnyBasicEvent) -> Option<BTreeSet<UserId>> {
+    if let AnyBasicEvent::IgnoredUserList(e) = event {
+        Some(e.content.ignored_users.iter().cloned().collect())
+    } else {
+        None
+    }
+}
+
+/// Get the sender of the event that invited the given user out of the
+/// stripped state of a room.
+fn inviter(user_id: &UserId, events: &[Raw<AnyStrippedStateEvent>]) -> Option<UserId> {
+    events
+        .iter()
+        .filter_map(|e| e.deserialize().ok())
+        .find_map(|e| match e {
+            AnyStrippedStateEvent::RoomMember(m) if m.state_key == user_id.as_str() => {
+                Some(m.sender)
+            }
+            _ => None,
+        })
+}
+
 /// A no IO Client implementation.
 ///
 /// This Client is a state machine that receives responses and events and
@@ -168,6 +269,13 @@ pub struct BaseClient {
     olm: Arc<Mutex<Option<OlmMachine>>>,
     #[cfg(feature = "encryption")]
     cryptostore: Arc<Mutex<Option<Box<dyn CryptoStore>>>>,
+    /// Encrypted timeline events that couldn't be decrypted yet, keyed by the
+    /// room id of the events.
+    ///
+    /// These are only kept in memory and at most `MAX_UNDECRYPTABLE_EVENTS`
+    /// per room.
+    #[cfg(feature = "encryption")]
+    undecryptable_events: Arc<Mutex<BTreeMap<RoomId, VecDeque<UndecryptableEvent>>>>,
     store_path: Arc<Option<PathBuf>>,
     store_passphrase: Arc<Option<Zeroizing<String>>>,
 }
@@ -199,6 +307,7 @@ pub struct BaseClientConfig {
     crypto_store: Option<Box<dyn CryptoStore>>,
     store_path: Option<PathBuf>,
     passphrase: Option<Zeroizing<String>>,
+    room_name_formatter: Option<Arc<dyn RoomNameFormatter>>,
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -251,6 +360,17 @@ impl BaseClientConfig {
         self.passphrase = Some(Zeroizing::new(passphrase));
         self
     }
+
+    /// Set the formatter that names rooms without a name or canonical alias.
+    ///
+    /// # Arguments
+    ///
+    /// * `formatter` - The formatter that should be used instead of the
+    /// default English room names.
+    pub fn room_name_formatter(mut self, formatter: impl RoomNameFormatter + 'static) -> Self {
+        self.room_name_formatter = Some(Arc::new(formatter));
+        self
+    }
 }
 
 impl BaseClient {
@@ -301,9 +421,13 @@ impl BaseClient {
         let crypto_store = config.crypto_store;
 
         #[cfg(feature = "sled_state_store")]
-        let store = stores.0;
+        let mut store = stores.0;
         #[cfg(not(feature = "sled_state_store"))]
-        let store = stores;
+        let mut store = stores;
+
+        if let Some(formatter) = config.room_name_formatter {
+            store.name_formatter = formatter;
+        }
 
         Ok(BaseClient {
             session: store.session.clone(),
@@ -313,6 +437,8 @@ impl BaseClient {
             olm: Mutex::new(None).into(),
             #[cfg(feature = "encryption")]
             cryptostore: Mutex::new(crypto_store).into(),
+            #[cfg(feature = "encryption")]
+            undecryptable_events: Mutex::new(BTreeMap::new()).into(),
             store_path: config.store_path.into(),
             store_passphrase: config.passphrase.into(),
         })
@@ -407,6 +533,47 @@ impl BaseClient {
         Ok(())
     }
 
+    /// Continue the current session as the given rehydrated device.
+    ///
+    /// This should be called once the homeserver confirmed that the
+    /// dehydrated device was claimed, from then on our access token belongs
+    /// to the rehydrated device.
+    ///
+    /// # Arguments
+    ///
+    /// * `device` - The device that was rehydrated and claimed.
+    #[cfg(feature = "encryption")]
+    #[cfg_attr(feature = "docs", doc(cfg(encryption)))]
+    pub async fn restore_rehydrated_login(&self, device: RehydratedDevice) -> Result<()> {
+        let mut session = self
+            .session
+            .read()
+            .await
+            .clone()
+            .ok_or(Error::AuthenticationRequired)?;
+        session.device_id = device.device_id().into();
+
+        let mut olm = self.olm.lock().await;
+        *olm = Some(device.into_machine().await?);
+        self.store.restore_session(session.clone()).await?;
+        *self.session.write().await = Some(session);
+
+        Ok(())
+    }
+
+    /// Get the users that are ignored in the `m.ignored_user_list` account
+    /// data.
+    ///
+    /// Messages and invites of ignored users are dropped when syncing.
+    pub async fn ignored_users(&self) -> Result<BTreeSet<UserId>> {
+        Ok(self
+            .store
+            .get_account_data_event(EventType::IgnoredUserList)
+            .await?
+            .and_then(|e| parse_ignored_users(&e))
+            .unwrap_or_default())
+    }
+
     /// Get the current, if any, sync token of the client.
     /// This will be None if the client didn't sync at least once.
     pub async fn sync_token(&self) -> Option<String> {
@@ -421,12 +588,25 @@ impl BaseClient {
         changes: &mut StateChanges,
         ambiguity_cache: &mut AmbiguityCache,
         user_ids: &mut BTreeSet<UserId>,
+        ignored_users: &BTreeSet<UserId>,
     ) -> StoreResult<Timeline> {
         let mut timeline = Timeline::new(ruma_timeline.limited, ruma_timeline.prev_batch.clone());
 
         for event in ruma_timeline.events {
             match hoist_room_event_prev_content(&event) {
                 Ok(mut e) => {
+                    let encryption_info = {
+                        #[cfg(feature = "encryption")]
+                        {
+                            self.decrypt_sync_room_event(&mut e, room_id, ignored_users)
+                                .await
+                        }
+                        #[cfg(not(feature = "encryption"))]
+                        {
+                            None
+                        }
+                    };
+
                     match &mut e {
                         AnySyncRoomEvent::State(s) => match s {
                             AnySyncStateEvent::RoomMember(member) => {
@@ -464,28 +644,15 @@ impl BaseClient {
                                 }
                             }
                             _ => {
-                                room_info.handle_state_event(&s.content());
+                                room_info.handle_state_event(
+                                    s.state_key(),
+                                    &s.content(),
+                                    event.json(),
+                                );
                                 changes.add_state_event(room_id, s.clone());
                             }
                         },
 
-                        #[cfg(feature = "encryption")]
-                        AnySyncRoomEvent::Message(AnySyncMessageEvent::RoomEncrypted(
-                            encrypted,
-                        )) => {
-                            if let Some(olm) = self.olm_machine().await {
-                                if let Ok(decrypted) =
-                                    olm.decrypt_room_event(encrypted, room_id).await
-                                {
-                                    match decrypted.deserialize() {
-                                        Ok(decrypted) => e = decrypted,
-                                        Err(e) => {
-                                            warn!("Error deserializing a decrypted event {:?} ", e)
-                                        }
-                                    }
-                                }
-                            }
-                        }
                         // TODO if there is redacted state save the room id,
                         // event type and state key, add a method to get the
                         // requests that are needed to be called to heal this
@@ -493,7 +660,22 @@ impl BaseClient {
                         _ => (),
                     }
 
-                    timeline.events.push(e);
+                    // State events of ignored users are still applied and
+                    // kept in the timeline, all their other events are hidden.
+                    let sender = match &e {
+                        AnySyncRoomEvent::Message(m) => Some(m.sender()),
+                        AnySyncRoomEvent::RedactedMessage(m) => Some(m.sender()),
+                        _ => None,
+                    };
+
+                    if sender.map_or(false, |s| ignored_users.contains(s)) {
+                        continue;
+                    }
+
+                    timeline.events.push(SyncRoomEvent {
+                        event: e,
+                        encryption_info,
+                    });
                 }
                 Err(e) => {
                     warn!("Error deserializing event {:?}", e);
@@ -504,9 +686,69 @@ impl BaseClient {
         Ok(timeline)
     }
 
+    /// Decrypt an encrypted timeline event in place.
+    ///
+    /// Events that can't be decrypted yet are remembered so they can be
+    /// retried once their room key arrives, unless they were sent by an
+    /// ignored user.
+    ///
+    /// Returns the encryption info of the event if it was decrypted.
+    #[cfg(feature = "encryption")]
+    async fn decrypt_sync_room_event(
+        &self,
+        event: &mut AnySyncRoomEvent,
+        room_id: &RoomId,
+        ignored_users: &BTreeSet<UserId>,
+    ) -> Option<EventEncryptionInfo> {
+        let encrypted = match event {
+            AnySyncRoomEvent::Message(AnySyncMessageEvent::RoomEncrypted(e)) => e,
+            _ => return None,
+        };
+
+        let olm = self.olm_machine().await?;
+
+        match olm.decrypt_room_event(encrypted, room_id).await {
+            Ok(decrypted) => {
+                *event = decrypted.event;
+                return decrypted.encryption_info;
+            }
+            Err(MegolmError::JsonError(e)) => {
+                warn!("Error deserializing a decrypted event {:?} ", e)
+            }
+            Err(_) if ignored_users.contains(&encrypted.sender) => (),
+            Err(err) => {
+                if let Some(utd) = undecryptable_event(encrypted, &err) {
+                    info!(
+                        "Unable to decrypt event {} in room {}: {:?}",
+                        encrypted.event_id, room_id, utd.reason
+                    );
+
+                    let mut undecryptable_events = self.undecryptable_events.lock().await;
+                    let events = undecryptable_events
+                        .entry(room_id.clone())
+                        .or_insert_with(VecDeque::new);
+
+                    if events.len() == MAX_UNDECRYPTABLE_EVENTS {
+                        events.pop_front();
+                    }
+
+                    events.push_back(utd);
+                } else {
+                    warn!(
+                        "Failed to decrypt event {} in room {}: {}",
+                        encrypted.event_id, room_id, err
+                    );
+                }
+            }
+        }
+
+        None
+    }
+
     #[allow(clippy::type_complexity)]
     fn handle_invited_state(
         &self,
+        own_user_id: &UserId,
         events: Vec<Raw<AnyStrippedStateEvent>>,
         room_info: &mut RoomInfo,
     ) -> (
@@ -516,14 +758,20 @@ impl BaseClient {
     ) {
         events.into_iter().fold(
             (InviteState::default(), BTreeMap::new(), BTreeMap::new()),
-            |(mut state, mut members, mut state_events), e| {
-                match e.deserialize() {
+            |(mut state, mut members, mut state_events), raw_event| {
+                match raw_event.deserialize() {
                     Ok(e) => {
                         state.events.push(e.clone());
 
                         if let AnyStrippedStateEvent::RoomMember(member) = e {
                             match StrippedMemberEvent::try_from(member) {
                                 Ok(m) => {
+                                    if &m.state_key == own_user_id {
+                                        room_info
+                                            .base_info
+                                            .handle_raw_invite_event(raw_event.json());
+                                    }
+
                                     members.insert(m.state_key.clone(), m);
                                 }
                                 Err(e) => warn!(
@@ -532,7 +780,11 @@ impl BaseClient {
                                 ),
                             }
                         } else {
-                            room_info.handle_state_event(&e.content());
+                            room_info.handle_state_event(
+                                e.state_key(),
+                                &e.content(),
+                                raw_event.json(),
+                            );
                             state_events
                                 .entry(e.content().event_type().to_owned())
                                 .or_insert_with(BTreeMap::new)
@@ -566,11 +818,11 @@ impl BaseClient {
 
         let room_id = room_info.room_id.clone();
 
-        for event in
+        for (raw_event, event) in
             events
                 .into_iter()
                 .filter_map(|e| match hoist_and_deserialize_state_event(&e) {
-                    Ok(e) => Some(e),
+                    Ok(event) => Some((e, event)),
                     Err(err) => {
                         warn!(
                             "Couldn't deserialize state event for room {}: {:?} {:#?}",
@@ -581,7 +833,7 @@ impl BaseClient {
                 })
         {
             state.events.push(event.clone());
-            room_info.handle_state_event(&event.content());
+            room_info.handle_state_event(event.state_key(), &event.content(), raw_event.json());
 
             if let AnySyncStateEvent::RoomMember(member) = event {
                 match MemberEvent::try_from(member) {
@@ -629,12 +881,14 @@ impl BaseClient {
         &self,
         room_id: &RoomId,
         events: &[Raw<AnyBasicEvent>],
+        room_info: &mut RoomInfo,
         changes: &mut StateChanges,
     ) -> AccountData {
         let events: Vec<AnyBasicEvent> =
             events.iter().filter_map(|e| e.deserialize().ok()).collect();
 
         for event in &events {
+            room_info.base_info.handle_account_data_event(event);
             changes.add_room_account_data(room_id, event.clone());
         }
 
@@ -651,6 +905,21 @@ impl BaseClient {
 
         for event in &events {
             if let AnyBasicEvent::Direct(e) = event {
+                // Rooms that were removed from `m.direct` aren't direct
+                // messages anymore.
+                let direct_rooms: BTreeSet<&RoomId> = e.content.values().flatten().collect();
+
+                for room in self.store.get_rooms() {
+                    if room.is_direct() && !direct_rooms.contains(room.room_id()) {
+                        let mut info = changes
+                            .room_infos
+                            .remove(room.room_id())
+                            .unwrap_or_else(|| room.clone_info());
+                        info.base_info.dm_target = None;
+                        changes.add_room(info);
+                    }
+                }
+
                 for (user_id, rooms) in e.content.iter() {
                     for room_id in rooms {
                         if let Some(room) = changes.room_infos.get_mut(room_id) {
@@ -704,6 +973,7 @@ impl BaseClient {
                     &response.to_device,
                     &response.device_lists,
                     &response.device_one_time_keys_count,
+                    response.device_unused_fallback_key_types.as_deref(),
                 )
                 .await?
             } else {
@@ -725,9 +995,42 @@ impl BaseClient {
             .collect::<Vec<AnyToDeviceEvent>>()
             .into();
 
+        // Retry to decrypt the events of earlier syncs if their room keys
+        // arrived, before the new timeline events are handled.
+        #[cfg(feature = "encryption")]
+        let decrypted_events = {
+            let sessions = received_room_key_sessions(&to_device);
+
+            match self.olm_machine().await {
+                Some(olm) if !sessions.is_empty() => {
+                    self.retry_decryption(&olm, Some(&sessions)).await
+                }
+                _ => BTreeMap::new(),
+            }
+        };
+        #[cfg(not(feature = "encryption"))]
+        let decrypted_events = BTreeMap::new();
+
         let mut changes = StateChanges::new(response.next_batch.clone());
         let mut ambiguity_cache = AmbiguityCache::new(self.store.clone());
 
+        let own_user_id = self
+            .session
+            .read()
+            .await
+            .as_ref()
+            .map(|s| s.user_id.clone());
+        let ignored_users = match response
+            .account_data
+            .events
+            .iter()
+            .filter_map(|e| e.deserialize().ok())
+            .find_map(|e| parse_ignored_users(&e))
+        {
+            Some(ignored_users) => ignored_users,
+            None => self.ignored_users().await?,
+        };
+
         let mut rooms = Rooms::default();
 
         for (room_id, new_info) in response.rooms.join {
@@ -762,11 +1065,17 @@ impl BaseClient {
                     &mut changes,
                     &mut ambiguity_cache,
                     &mut user_ids,
+                    &ignored_users,
                 )
                 .await?;
 
             let account_data = self
-                .handle_room_account_data(&room_id, &new_info.account_data.events, &mut changes)
+                .handle_room_account_data(
+                    &room_id,
+                    &new_info.account_data.events,
+                    &mut room_info,
+                    &mut changes,
+                )
                 .await;
 
             #[cfg(feature = "encryption")]
@@ -832,11 +1141,17 @@ impl BaseClient {
                     &mut changes,
                     &mut ambiguity_cache,
                     &mut user_ids,
+                    &ignored_users,
                 )
                 .await?;
 
             let account_data = self
-                .handle_room_account_data(&room_id, &new_info.account_data.events, &mut changes)
+                .handle_room_account_data(
+                    &room_id,
+                    &new_info.account_data.events,
+                    &mut room_info,
+                    &mut changes,
+                )
                 .await;
 
             changes.add_room(room_info);
@@ -846,6 +1161,18 @@ impl BaseClient {
         }
 
         for (room_id, new_info) in response.rooms.invite {
+            let inviter = own_user_id
+                .as_ref()
+                .and_then(|u| inviter(u, &new_info.invite_state.events));
+
+            if let Some(inviter) = inviter.filter(|i| ignored_users.contains(i)) {
+                info!(
+                    "Dropping the invite to room {} from the ignored user {}",
+                    room_id, inviter
+                );
+                continue;
+            }
+
             {
                 let room = self
                     .store
@@ -859,8 +1186,11 @@ impl BaseClient {
             let room = self.store.get_or_create_stripped_room(&room_id).await;
             let mut room_info = room.clone_info();
 
-            let (state, members, state_events) =
-                self.handle_invited_state(new_info.invite_state.events, &mut room_info);
+            let (state, members, state_events) = self.handle_invited_state(
+                room.own_user_id(),
+                new_info.invite_state.events,
+                &mut room_info,
+            );
 
             changes.stripped_members.insert(room_id.clone(), members);
             changes.stripped_state.insert(room_id.clone(), state_events);
@@ -915,6 +1245,7 @@ impl BaseClient {
             ambiguity_changes: AmbiguityChanges {
                 changes: ambiguity_cache.changes,
             },
+            decrypted_events,
         };
 
         Ok(response)
@@ -1119,6 +1450,38 @@ impl BaseClient {
         }
     }
 
+    /// Encrypt an arbitrary to-device event for the given devices.
+    ///
+    /// Devices that we don't have an Olm session with are skipped, use
+    /// [`get_missing_sessions`] to establish them first. The skipped devices
+    /// are returned together with the requests.
+    ///
+    /// # Arguments
+    ///
+    /// * `event_type` - The type of the event that should be encrypted.
+    ///
+    /// * `content` - The content of the event that should be encrypted.
+    ///
+    /// * `recipients` - The devices that should receive the event, an empty
+    /// list of devices sends the event to all known devices of the user.
+    ///
+    /// [`get_missing_sessions`]: #method.get_missing_sessions
+    #[cfg(feature = "encryption")]
+    #[cfg_attr(feature = "docs", doc(cfg(encryption)))]
+    pub async fn encrypt_to_device(
+        &self,
+        event_type: EventType,
+        content: serde_json::Value,
+        recipients: &BTreeMap<UserId, Vec<DeviceIdBox>>,
+    ) -> Result<EncryptedToDeviceRequests> {
+        let olm = self.olm.lock().await;
+
+        match &*olm {
+            Some(o) => Ok(o.encrypt_to_device(event_type, content, recipients).await?),
+            None => Ok(EncryptedToDeviceRequests::default()),
+        }
+    }
+
     /// Get a to-device request that will share a group session for a room.
     #[cfg(feature = "encryption")]
     #[cfg_attr(feature = "docs", doc(cfg(encryption)))]
@@ -1144,7 +1507,8 @@ impl BaseClient {
                 };
 
                 let settings = settings.ok_or(MegolmError::EncryptionNotEnabled)?;
-                let settings = EncryptionSettings::new(settings, history_visibility);
+                let mut settings = EncryptionSettings::new(settings, history_visibility);
+                settings.share_strategy = o.share_strategy(room_id).await?;
 
                 Ok(o.share_group_session(room_id, members, settings).await?)
             }
@@ -1323,7 +1687,279 @@ impl BaseClient {
         let olm = self.olm.lock().await;
         olm.as_ref().cloned()
     }
+
+    /// Get the encrypted events of the given room that couldn't be decrypted
+    /// yet.
+    ///
+    /// The events are only remembered in memory, they are lost when the client
+    /// is restarted, and only the 100 most recent events of a room are kept.
+    /// Events that are forgotten aren't retried when their room key arrives.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The id of the room the events belong to.
+    #[cfg(feature = "encryption")]
+    #[cfg_attr(feature = "docs", doc(cfg(encryption)))]
+    pub async fn undecryptable_events(&self, room_id: &RoomId) -> Vec<UndecryptableEvent> {
+        self.undecryptable_events
+            .lock()
+            .await
+            .get(room_id)
+            .map(|events| events.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Retry to decrypt all the events that couldn't be decrypted yet, e.g.
+    /// after room keys were imported.
+    ///
+    /// Events are retried automatically when a room key arrives in a sync.
+    /// Only the events returned by [`undecryptable_events`] are retried.
+    ///
+    /// [`undecryptable_events`]: #method.undecryptable_events
+    ///
+    /// Returns the events that could be decrypted, keyed by their room id.
+    #[cfg(feature = "encryption")]
+    #[cfg_attr(feature = "docs", doc(cfg(encryption)))]
+    pub async fn retry_undecryptable_events(&self) -> BTreeMap<RoomId, Vec<SyncRoomEvent>> {
+        if let Some(olm) = self.olm_machine().await {
+            self.retry_decryption(&olm, None).await
+        } else {
+            BTreeMap::new()
+        }
+    }
+
+    /// Retry to decrypt the undecryptable events, only events encrypted with
+    /// one of the given room id, session id pairs are retried if `sessions` is
+    /// set.
+    #[cfg(feature = "encryption")]
+    async fn retry_decryption(
+        &self,
+        olm: &OlmMachine,
+        sessions: Option<&BTreeSet<(RoomId, String)>>,
+    ) -> BTreeMap<RoomId, Vec<SyncRoomEvent>> {
+        let mut undecryptable_events = self.undecryptable_events.lock().await;
+        let mut decrypted_events = BTreeMap::new();
+
+        for (room_id, events) in undecryptable_events.iter_mut() {
+            let mut remaining = VecDeque::new();
+
+            for utd in events.drain(..) {
+                let has_new_key = sessions.map_or(true, |s| {
+                    s.contains(&(room_id.clone(), utd.session_id.clone()))
+                });
+
+                if !has_new_key {
+                    remaining.push_back(utd);
+                    continue;
+                }
+
+                match olm.decrypt_room_event(&utd.event, room_id).await {
+                    Ok(decrypted) => {
+                        info!(
+                            "Successfully decrypted event {} in room {} on retry",
+                            utd.event.event_id, room_id
+                        );
+
+                        decrypted_events
+                            .entry(room_id.clone())
+                            .or_insert_with(Vec::new)
+                            .push(decrypted);
+                    }
+                    Err(e) => {
+                        if let Some(utd) = undecryptable_event(&utd.event, &e) {
+                            remaining.push_back(utd);
+                        } else {
+                            warn!(
+                                "Failed to decrypt event {} in room {}: {}",
+                                utd.event.event_id, room_id, e
+                            );
+                        }
+                    }
+                }
+            }
+
+            *events = remaining;
+        }
+
+        undecryptable_events.retain(|_, events| !events.is_empty());
+
+        decrypted_events
+    }
 }
 
-#[cfg(test)]
-mod test {}
+#[cfg(all(test, feature = "encryption"))]
+mod test {
+    use matrix_sdk_common::{
+        api::r0::{
+            keys::{claim_keys, get_keys},
+            sync::sync_events,
+            to_device::{send_event_to_device, DeviceIdOrAllDevices},
+        },
+        events::{
+            room::message::MessageEventContent, AnyMessageEventContent, AnySyncMessageEvent,
+            AnySyncRoomEvent,
+        },
+        identifiers::{room_id, user_id},
+        uuid::Uuid,
+        IncomingResponse,
+    };
+    use matrix_sdk_crypto::{EncryptionSettings, OlmMachine, OutgoingRequests};
+    use matrix_sdk_test::{async_test, response_from_file};
+    use serde_json::{json, Value as JsonValue};
+
+    use super::BaseClient;
+    use crate::Session;
+
+    /// Get the keys upload request of the given machine and let the other
+    /// machine know about the uploaded device keys.
+    async fn exchange_device_keys(machine: &OlmMachine, other: &OlmMachine) -> JsonValue {
+        let upload = machine
+            .outgoing_requests()
+            .await
+            .into_iter()
+            .find_map(|r| match r.request() {
+                OutgoingRequests::KeysUpload(r) => Some(r.clone()),
+                _ => None,
+            })
+            .unwrap();
+
+        let response = get_keys::Response::try_from_http_response(response_from_file(&json!({
+            "device_keys": {
+                machine.user_id().as_str(): {
+                    machine.device_id().as_str(): upload.device_keys.unwrap(),
+                }
+            },
+            "failures": {}
+        })))
+        .unwrap();
+
+        other
+            .mark_request_as_sent(&Uuid::new_v4(), &response)
+            .await
+            .unwrap();
+
+        json!(upload.one_time_keys.unwrap())
+    }
+
+    fn sync_response(json: JsonValue) -> sync_events::Response {
+        sync_events::Response::try_from_http_response(response_from_file(&json)).unwrap()
+    }
+
+    #[async_test]
+    async fn undecryptable_events_are_retried() {
+        let room_id = room_id!("!test:example.org");
+        let alice_id = user_id!("@alice:example.org");
+        let bob_id = user_id!("@bob:example.org");
+
+        let client = BaseClient::new().unwrap();
+        client
+            .restore_login(Session {
+                access_token: "1234".to_owned(),
+                user_id: bob_id.clone(),
+                device_id: "BOBDEVICE".into(),
+            })
+            .await
+            .unwrap();
+
+        let bob = client.olm_machine().await.unwrap();
+        let alice = OlmMachine::new(&alice_id, "ALICEDEVICE".into());
+
+        exchange_device_keys(&alice, &bob).await;
+        let one_time_keys = exchange_device_keys(&bob, &alice).await;
+        let (key_id, key) = one_time_keys.as_object().unwrap().iter().next().unwrap();
+
+        let (request_id, _) = alice
+            .get_missing_sessions([bob_id.clone()].iter())
+            .await
+            .unwrap()
+            .unwrap();
+        let response = claim_keys::Response::try_from_http_response(response_from_file(&json!({
+            "one_time_keys": { bob_id.as_str(): { "BOBDEVICE": { key_id: key } } },
+            "failures": {}
+        })))
+        .unwrap();
+        alice
+            .mark_request_as_sent(&request_id, &response)
+            .await
+            .unwrap();
+
+        let requests = alice
+            .share_group_session(
+                &room_id,
+                [bob_id.clone()].iter(),
+                EncryptionSettings::default(),
+            )
+            .await
+            .unwrap();
+        let room_key = requests[0].messages[&bob_id]
+            [&DeviceIdOrAllDevices::DeviceId("BOBDEVICE".into())]
+            .get()
+            .to_owned();
+
+        for request in &requests {
+            alice
+                .mark_request_as_sent(&request.txn_id, &send_event_to_device::Response::new())
+                .await
+                .unwrap();
+        }
+
+        let content = alice
+            .encrypt(
+                &room_id,
+                AnyMessageEventContent::RoomMessage(MessageEventContent::text_plain("Hello")),
+            )
+            .await
+            .unwrap();
+
+        // The event arrives before the room key.
+        let response = client
+            .receive_sync_response(sync_response(json!({
+                "next_batch": "s1",
+                "rooms": {
+                    "join": {
+                        room_id.as_str(): {
+                            "timeline": {
+                                "events": [{
+                                    "content": content,
+                                    "event_id": "$encrypted:example.org",
+                                    "origin_server_ts": 152037280,
+                                    "sender": alice_id,
+                                    "type": "m.room.encrypted"
+                                }],
+                                "limited": false
+                            }
+                        }
+                    }
+                }
+            })))
+            .await
+            .unwrap();
+
+        assert!(response.decrypted_events.is_empty());
+        assert_eq!(client.undecryptable_events(&room_id).await.len(), 1);
+
+        let room_key: JsonValue = serde_json::from_str(&room_key).unwrap();
+        let response = client
+            .receive_sync_response(sync_response(json!({
+                "next_batch": "s2",
+                "to_device": {
+                    "events": [{
+                        "content": room_key,
+                        "sender": alice_id,
+                        "type": "m.room.encrypted"
+                    }]
+                }
+            })))
+            .await
+            .unwrap();
+
+        let decrypted = &response.decrypted_events[&room_id];
+        assert_eq!(decrypted.len(), 1);
+        assert!(matches!(
+            &decrypted[0].event,
+            AnySyncRoomEvent::Message(AnySyncMessageEvent::RoomMessage(m))
+                if m.event_id.as_str() == "$encrypted:example.org"
+        ));
+        assert!(client.undecryptable_events(&room_id).await.is_empty());
+    }
+}
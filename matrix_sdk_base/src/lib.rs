@@ -50,7 +50,11 @@ mod rooms;
 mod session;
 mod store;
 
-pub use rooms::{Room, RoomInfo, RoomMember, RoomType};
+pub use rooms::{
+    DefaultRoomNameFormatter, Room, RoomInfo, RoomMember, RoomNameFormatter, RoomNameParts,
+    RoomType, SpaceChild, SpaceParent, SpecRoomNameFormatter, FAVOURITE_TAG, LOW_PRIORITY_TAG,
+    SPACE_CHILD_EVENT_TYPES, SPACE_PARENT_EVENT_TYPES, SPACE_ROOM_TYPE,
+};
 pub use store::{StateChanges, StateStore, Store, StoreError};
 
 pub use client::{BaseClient, BaseClientConfig};
@@ -0,0 +1,257 @@
+// Copyright 2021 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::Debug;
+
+use matrix_sdk_common::identifiers::UserId;
+
+use super::RoomMember;
+
+/// The inputs for the display name of a room that has neither a name nor a
+/// canonical alias.
+#[derive(Debug)]
+pub struct RoomNameParts<'a> {
+    /// Our own user id.
+    pub own_user_id: &'a UserId,
+    /// The members that should be used to name the room, this never contains
+    /// our own user.
+    ///
+    /// These are the heroes of the room summary, or the first few joined and
+    /// invited members if the server didn't send any heroes.
+    pub heroes: &'a [RoomMember],
+    /// The number of members that are joined to the room, including our own
+    /// user, as sent in the room summary.
+    ///
+    /// The server only sends this if members are lazy loaded, it's `0`
+    /// otherwise. Use [`known_joined_member_count`] for a count that is
+    /// always available.
+    ///
+    /// [`known_joined_member_count`]: #structfield.known_joined_member_count
+    pub joined_member_count: u64,
+    /// The number of members that are invited to the room, as sent in the
+    /// room summary.
+    pub invited_member_count: u64,
+    /// The number of members that are joined to the room, including our own
+    /// user. This is the count of the room summary if the server sent one,
+    /// the number of joined members the client knows about otherwise.
+    pub known_joined_member_count: u64,
+    /// The number of members that are invited to the room. This is the count
+    /// of the room summary if the server sent one, the number of invited
+    /// members the client knows about otherwise.
+    pub known_invited_member_count: u64,
+}
+
+impl RoomNameParts<'_> {
+    /// The number of joined and invited members, not counting our own user.
+    pub fn other_member_count(&self) -> u64 {
+        (self.known_joined_member_count + self.known_invited_member_count).saturating_sub(1)
+    }
+
+    /// The names of the first three heroes, sorted to keep the name stable.
+    fn hero_names(&self) -> Vec<&str> {
+        let mut names = self
+            .heroes
+            .iter()
+            .take(3)
+            .map(|m| m.name())
+            .collect::<Vec<&str>>();
+        // stabilize ordering
+        names.sort_unstable();
+
+        names
+    }
+}
+
+/// A formatter producing the display name of rooms that have neither a name
+/// nor a canonical alias.
+///
+/// Implement this to localize or customize room display names and set it
+/// using [`BaseClientConfig::room_name_formatter`].
+///
+/// [`BaseClientConfig::room_name_formatter`]: struct.BaseClientConfig.html#method.room_name_formatter
+pub trait RoomNameFormatter: Debug + Send + Sync {
+    /// Produce the display name of a room from its members.
+    fn format_name(&self, parts: RoomNameParts<'_>) -> String;
+}
+
+/// The default room name formatter, producing English names.
+///
+/// The names only take the member counts of the room summary into account, use
+/// the [`SpecRoomNameFormatter`] for names that follow the [spec] more closely.
+///
+/// [spec]: <https://matrix.org/docs/spec/client_server/latest#calculating-the-display-name-for-a-room>
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultRoomNameFormatter;
+
+impl RoomNameFormatter for DefaultRoomNameFormatter {
+    fn format_name(&self, parts: RoomNameParts<'_>) -> String {
+        let heroes_count = parts.heroes.len() as u64;
+        let invited_joined =
+            (parts.invited_member_count + parts.joined_member_count).saturating_sub(1);
+
+        if heroes_count >= invited_joined {
+            parts.hero_names().join(", ")
+        } else if invited_joined > 1 {
+            // TODO: What length does the spec want us to use here and in
+            // the `else`?
+            format!(
+                "{}, and {} others",
+                parts.hero_names().join(", "),
+                (parts.joined_member_count + parts.invited_member_count)
+            )
+        } else {
+            "Empty room".to_string()
+        }
+    }
+}
+
+/// A room name formatter producing English names as recommended by the
+/// [spec].
+///
+/// Unlike the [`DefaultRoomNameFormatter`] it counts the members the client
+/// knows about if the server doesn't send member counts, only counts the
+/// members that aren't named as others and names rooms that everyone else
+/// left after their former members.
+///
+/// [spec]: <https://matrix.org/docs/spec/client_server/latest#calculating-the-display-name-for-a-room>
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SpecRoomNameFormatter;
+
+impl RoomNameFormatter for SpecRoomNameFormatter {
+    fn format_name(&self, parts: RoomNameParts<'_>) -> String {
+        let other_members = parts.other_member_count();
+        let names = parts.hero_names();
+        let named_members = names.len() as u64;
+
+        if names.is_empty() {
+            "Empty room".to_string()
+        } else if other_members == 0 {
+            format!("Empty room (was {})", names.join(", "))
+        } else if named_members >= other_members {
+            names.join(", ")
+        } else {
+            format!(
+                "{}, and {} others",
+                names.join(", "),
+                other_members - named_members
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use matrix_sdk_common::identifiers::{user_id, UserId};
+    use serde_json::json;
+
+    use super::{
+        DefaultRoomNameFormatter, RoomNameFormatter, RoomNameParts, SpecRoomNameFormatter,
+    };
+    use crate::{deserialized_responses::MemberEvent, rooms::RoomMember};
+
+    fn member(name: &str) -> RoomMember {
+        let event: MemberEvent = serde_json::from_value(json!({
+            "content": {
+                "displayname": name,
+                "membership": "join",
+            },
+            "event_id": "$h29iv0s8:example.com",
+            "origin_server_ts": 1,
+            "sender": format!("@{}:example.com", name.to_lowercase()),
+            "state_key": format!("@{}:example.com", name.to_lowercase()),
+            "type": "m.room.member",
+        }))
+        .unwrap();
+
+        RoomMember {
+            event: Arc::new(event),
+            profile: Arc::new(None),
+            presence: Arc::new(None),
+            presence_received_at: None,
+            power_levels: Arc::new(None),
+            max_power_level: 100,
+            is_room_creator: false,
+            display_name_ambiguous: false,
+        }
+    }
+
+    fn format(
+        formatter: &dyn RoomNameFormatter,
+        heroes: &[RoomMember],
+        joined: u64,
+        invited: u64,
+    ) -> String {
+        let own_user_id: UserId = user_id!("@me:example.com");
+
+        formatter.format_name(RoomNameParts {
+            own_user_id: &own_user_id,
+            heroes,
+            joined_member_count: joined,
+            invited_member_count: invited,
+            known_joined_member_count: joined,
+            known_invited_member_count: invited,
+        })
+    }
+
+    #[test]
+    fn default_room_names() {
+        let f = &DefaultRoomNameFormatter;
+        let alice = member("Alice");
+        let bob = member("Bob");
+        let carol = member("Carol");
+        let dan = member("Dan");
+
+        assert_eq!(format(f, &[], 1, 0), "");
+        assert_eq!(format(f, &[], 3, 0), "Empty room");
+        assert_eq!(format(f, &[bob.clone(), alice.clone()], 2, 1), "Alice, Bob");
+        assert_eq!(
+            format(f, &[alice.clone(), bob.clone(), carol.clone(), dan], 5, 0),
+            "Alice, Bob, Carol"
+        );
+        assert_eq!(
+            format(f, &[alice.clone(), bob.clone()], 8, 2),
+            "Alice, Bob, and 10 others"
+        );
+        // The server didn't send member counts.
+        assert_eq!(format(f, &[alice, bob], 0, 0), "Alice, Bob");
+    }
+
+    #[test]
+    fn spec_room_names() {
+        let f = &SpecRoomNameFormatter;
+        let alice = member("Alice");
+        let bob = member("Bob");
+        let carol = member("Carol");
+        let dan = member("Dan");
+
+        assert_eq!(format(f, &[], 1, 0), "Empty room");
+        assert_eq!(format(f, &[bob.clone(), alice.clone()], 2, 1), "Alice, Bob");
+        assert_eq!(
+            format(f, &[alice.clone(), bob.clone(), carol.clone(), dan], 5, 0),
+            "Alice, Bob, Carol, and 1 others"
+        );
+        // Only the members that aren't named are counted as others.
+        assert_eq!(
+            format(f, &[alice.clone(), bob.clone()], 8, 2),
+            "Alice, Bob, and 7 others"
+        );
+        // Everyone else left the room.
+        assert_eq!(
+            format(f, &[alice, bob], 1, 0),
+            "Empty room (was Alice, Bob)"
+        );
+    }
+}
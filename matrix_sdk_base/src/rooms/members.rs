@@ -12,7 +12,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use matrix_sdk_common::{
     events::{
@@ -21,6 +24,7 @@ use matrix_sdk_common::{
         SyncStateEvent,
     },
     identifiers::{MxcUri, UserId},
+    presence::PresenceState,
 };
 
 use crate::deserialized_responses::MemberEvent;
@@ -31,6 +35,7 @@ pub struct RoomMember {
     pub(crate) event: Arc<MemberEvent>,
     pub(crate) profile: Arc<Option<MemberEventContent>>,
     pub(crate) presence: Arc<Option<PresenceEvent>>,
+    pub(crate) presence_received_at: Option<SystemTime>,
     pub(crate) power_levels: Arc<Option<SyncStateEvent<PowerLevelsEventContent>>>,
     pub(crate) max_power_level: i64,
     pub(crate) is_room_creator: bool,
@@ -72,6 +77,50 @@ impl RoomMember {
         }
     }
 
+    /// Get the latest presence event of the member, if we received one.
+    pub fn presence(&self) -> Option<&PresenceEvent> {
+        self.presence.as_ref().as_ref()
+    }
+
+    /// Get the presence state of the member, if we received a presence event
+    /// for the member.
+    pub fn presence_state(&self) -> Option<&PresenceState> {
+        self.presence().map(|p| &p.content.presence)
+    }
+
+    /// Get the status message the member set, if any.
+    pub fn status_msg(&self) -> Option<&str> {
+        self.presence()
+            .and_then(|p| p.content.status_msg.as_deref())
+    }
+
+    /// Is the member currently using a client.
+    pub fn currently_active(&self) -> bool {
+        self.presence()
+            .and_then(|p| p.content.currently_active)
+            .unwrap_or(false)
+    }
+
+    /// Get how long ago the member was last active.
+    ///
+    /// The `last_active_ago` of the presence event is relative to the time the
+    /// event was received, the time that passed since then is added to it.
+    /// `None` is returned if the member is currently active or the server
+    /// didn't tell us.
+    pub fn last_active_ago(&self) -> Option<Duration> {
+        if self.currently_active() {
+            return None;
+        }
+
+        let ago = Duration::from_millis(self.presence()?.content.last_active_ago?.into());
+        let since_received = self
+            .presence_received_at
+            .and_then(|t| t.elapsed().ok())
+            .unwrap_or_default();
+
+        Some(ago + since_received)
+    }
+
     /// Get the normalized power level of this member.
     ///
     /// The normalized power level depends on the maximum power level that can
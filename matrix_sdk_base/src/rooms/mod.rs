@@ -1,3 +1,4 @@
+mod display_name;
 mod members;
 mod normal;
 
@@ -10,19 +11,99 @@ use matrix_sdk_common::{
 };
 pub use normal::{Room, RoomInfo, RoomType};
 
+pub use display_name::{
+    DefaultRoomNameFormatter, RoomNameFormatter, RoomNameParts, SpecRoomNameFormatter,
+};
 pub use members::RoomMember;
 
 use serde::{Deserialize, Serialize};
-use std::cmp::max;
+use serde_json::value::RawValue;
+use std::{cmp::max, collections::BTreeMap, convert::TryFrom};
 
 use matrix_sdk_common::{
     events::{
+        custom::CustomEventContent,
         room::{encryption::EncryptionEventContent, tombstone::TombstoneEventContent},
-        AnyStateEventContent,
+        tag::TagInfo,
+        AnyBasicEvent, AnyStateEventContent,
     },
-    identifiers::RoomAliasId,
+    identifiers::{RoomAliasId, RoomId},
 };
 
+/// The tag of rooms the user marked as favourite.
+pub const FAVOURITE_TAG: &str = "m.favourite";
+
+/// The tag of rooms the user marked as low priority.
+pub const LOW_PRIORITY_TAG: &str = "m.lowpriority";
+
+pub use matrix_sdk_common::unstable::space::SPACE_ROOM_TYPE;
+
+/// The event types of `m.space.child` events, including the unstable prefixed
+/// variant.
+pub const SPACE_CHILD_EVENT_TYPES: &[&str] = &["m.space.child", "org.matrix.msc1772.space.child"];
+
+/// The event types of `m.space.parent` events, including the unstable prefixed
+/// variant.
+pub const SPACE_PARENT_EVENT_TYPES: &[&str] =
+    &["m.space.parent", "org.matrix.msc1772.space.parent"];
+
+/// A child room of a space, as announced by a `m.space.child` event in the
+/// space.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SpaceChild {
+    /// The servers that can be used to join the child room.
+    pub via: Vec<String>,
+    /// The string the children of a space should be ordered by.
+    pub order: Option<String>,
+    /// Is the child room suggested to members of the space.
+    pub suggested: bool,
+}
+
+/// A parent space of a room, as announced by a `m.space.parent` event in the
+/// room.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SpaceParent {
+    /// The servers that can be used to join the parent space.
+    pub via: Vec<String>,
+    /// Is this the main parent of the room.
+    pub canonical: bool,
+}
+
+/// The content of a `m.space.child` or `m.space.parent` event, ruma doesn't
+/// model those events yet.
+#[derive(Deserialize)]
+struct SpaceEventContent {
+    via: Option<Vec<String>>,
+    order: Option<String>,
+    #[serde(default)]
+    suggested: bool,
+    #[serde(default)]
+    canonical: bool,
+}
+
+/// The parts of a `m.room.create` event that ruma doesn't model yet.
+#[derive(Deserialize)]
+struct CreateEventType {
+    content: CreateEventTypeContent,
+}
+
+#[derive(Deserialize)]
+struct CreateEventTypeContent {
+    #[serde(rename = "type")]
+    room_type: Option<String>,
+}
+
+/// The parts of a `m.room.member` event that ruma doesn't model yet.
+#[derive(Deserialize)]
+struct MemberEventReason {
+    content: MemberEventReasonContent,
+}
+
+#[derive(Deserialize)]
+struct MemberEventReasonContent {
+    reason: Option<String>,
+}
+
 /// A base room info struct that is the backbone of normal as well as stripped
 /// rooms. Holds all the state events that are important to present a room to
 /// users.
@@ -34,6 +115,10 @@ pub struct BaseRoomInfo {
     pub canonical_alias: Option<RoomAliasId>,
     /// The `m.room.create` event content of this room.
     pub create: Option<CreateEventContent>,
+    /// The `type` of the room as set in its `m.room.create` event, e.g.
+    /// `m.space`.
+    #[serde(default)]
+    pub create_type: Option<String>,
     /// The user id this room is sharing the direct message with, if the room is
     /// a direct message.
     pub dm_target: Option<UserId>,
@@ -43,12 +128,27 @@ pub struct BaseRoomInfo {
     pub guest_access: GuestAccess,
     /// The history visibility policy of this room.
     pub history_visibility: HistoryVisibility,
+    /// The reason the inviter gave for inviting us, only set for rooms we're
+    /// invited to.
+    #[serde(default)]
+    pub invite_reason: Option<String>,
     /// The join rule policy of this room.
     pub join_rule: JoinRule,
     /// The maximal power level that can be found in this room.
     pub max_power_level: i64,
     /// The `m.room.name` of this room.
     pub name: Option<String>,
+    /// The child rooms of this room if it's a space, keyed by the room id of
+    /// the child.
+    #[serde(default)]
+    pub space_children: BTreeMap<RoomId, SpaceChild>,
+    /// The spaces this room is part of, keyed by the room id of the space.
+    #[serde(default)]
+    pub space_parents: BTreeMap<RoomId, SpaceParent>,
+    /// The tags of this room from the `m.tag` room account data, keyed by
+    /// the tag name.
+    #[serde(default)]
+    pub tags: BTreeMap<String, TagInfo>,
     /// The `m.room.tombstone` event content of this room.
     pub tombstone: Option<TombstoneEventContent>,
     /// The topic of this room.
@@ -61,44 +161,6 @@ impl BaseRoomInfo {
         Self::default()
     }
 
-    pub(crate) fn calculate_room_name(
-        &self,
-        joined_member_count: u64,
-        invited_member_count: u64,
-        heroes: Vec<RoomMember>,
-    ) -> String {
-        let heroes_count = heroes.len() as u64;
-        let invited_joined = (invited_member_count + joined_member_count).saturating_sub(1);
-
-        if heroes_count >= invited_joined {
-            let mut names = heroes
-                .iter()
-                .take(3)
-                .map(|mem| mem.name())
-                .collect::<Vec<&str>>();
-            // stabilize ordering
-            names.sort_unstable();
-            names.join(", ")
-        } else if heroes_count < invited_joined && invited_joined > 1 {
-            let mut names = heroes
-                .iter()
-                .take(3)
-                .map(|mem| mem.name())
-                .collect::<Vec<&str>>();
-            names.sort_unstable();
-
-            // TODO: What length does the spec want us to use here and in
-            // the `else`?
-            format!(
-                "{}, and {} others",
-                names.join(", "),
-                (joined_member_count + invited_member_count)
-            )
-        } else {
-            "Empty room".to_string()
-        }
-    }
-
     /// Handle a state event for this room and update our info accordingly.
     ///
     /// Returns true if the event modified the info, false otherwise.
@@ -159,6 +221,90 @@ impl BaseRoomInfo {
             _ => false,
         }
     }
+
+    /// Handle a room account data event for this room and update our info
+    /// accordingly.
+    ///
+    /// Returns true if the event modified the info, false otherwise.
+    pub fn handle_account_data_event(&mut self, event: &AnyBasicEvent) -> bool {
+        match event {
+            AnyBasicEvent::Tag(t) => {
+                self.tags = t.content.tags.clone();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Handle a `m.space.child` or `m.space.parent` event for this room and
+    /// update our info accordingly.
+    ///
+    /// Events without a `via` key remove the child or parent.
+    ///
+    /// Returns true if the event modified the info, false otherwise.
+    pub fn handle_space_event(&mut self, state_key: &str, content: &CustomEventContent) -> bool {
+        let event_type = content.event_type.as_str();
+
+        let is_child = SPACE_CHILD_EVENT_TYPES.contains(&event_type);
+        let is_parent = SPACE_PARENT_EVENT_TYPES.contains(&event_type);
+
+        if !is_child && !is_parent {
+            return false;
+        }
+
+        let room_id = if let Ok(r) = RoomId::try_from(state_key) {
+            r
+        } else {
+            return false;
+        };
+
+        let space_content = serde_json::to_value(&content.data)
+            .and_then(serde_json::from_value::<SpaceEventContent>)
+            .ok()
+            .and_then(|c| c.via.filter(|v| !v.is_empty()).map(|v| (v, c)));
+
+        match (space_content, is_child) {
+            (Some((via, c)), true) => {
+                let child = SpaceChild {
+                    via,
+                    order: c.order,
+                    suggested: c.suggested,
+                };
+                self.space_children.insert(room_id, child);
+            }
+            (Some((via, c)), false) => {
+                let parent = SpaceParent {
+                    via,
+                    canonical: c.canonical,
+                };
+                self.space_parents.insert(room_id, parent);
+            }
+            (None, true) => {
+                self.space_children.remove(&room_id);
+            }
+            (None, false) => {
+                self.space_parents.remove(&room_id);
+            }
+        }
+
+        true
+    }
+
+    /// Remember the `type` of the room from the raw JSON of its `m.room.create`
+    /// event.
+    pub(crate) fn handle_raw_create_event(&mut self, event: &RawValue) {
+        if let Ok(event) = serde_json::from_str::<CreateEventType>(event.get()) {
+            self.create_type = event.content.room_type;
+        }
+    }
+
+    /// Remember the invite reason from the raw JSON of the stripped
+    /// `m.room.member` event that invited us.
+    pub(crate) fn handle_raw_invite_event(&mut self, event: &RawValue) {
+        if let Ok(event) = serde_json::from_str::<MemberEventReason>(event.get()) {
+            self.invite_reason = event.content.reason;
+        }
+    }
 }
 
 impl Default for BaseRoomInfo {
@@ -167,13 +313,18 @@ impl Default for BaseRoomInfo {
             avatar_url: None,
             canonical_alias: None,
             create: None,
+            create_type: None,
             dm_target: None,
             encryption: None,
             guest_access: GuestAccess::CanJoin,
             history_visibility: HistoryVisibility::WorldReadable,
+            invite_reason: None,
             join_rule: JoinRule::Public,
             max_power_level: 100,
             name: None,
+            space_children: BTreeMap::new(),
+            space_parents: BTreeMap::new(),
+            tags: BTreeMap::new(),
             tombstone: None,
             topic: None,
         }
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use std::{
+    collections::BTreeMap,
     convert::TryFrom,
     sync::{Arc, RwLock as SyncRwLock},
 };
@@ -27,13 +28,15 @@ use matrix_sdk_common::{
         room::{
             create::CreateEventContent, encryption::EncryptionEventContent,
             guest_access::GuestAccess, history_visibility::HistoryVisibility, join_rules::JoinRule,
-            tombstone::TombstoneEventContent,
+            power_levels::PowerLevelsEventContent, tombstone::TombstoneEventContent,
         },
+        tag::TagInfo,
         AnyStateEventContent, AnySyncStateEvent, EventType,
     },
     identifiers::{MxcUri, RoomAliasId, RoomId, UserId},
 };
 use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
 use tracing::info;
 
 use crate::{
@@ -41,7 +44,10 @@ use crate::{
     store::{Result as StoreResult, StateStore},
 };
 
-use super::{BaseRoomInfo, RoomMember};
+use super::{
+    BaseRoomInfo, RoomMember, RoomNameFormatter, RoomNameParts, SpaceChild, SpaceParent,
+    FAVOURITE_TAG, LOW_PRIORITY_TAG, SPACE_ROOM_TYPE,
+};
 
 /// The underlying room data structure collecting state for joined, left and invtied rooms.
 #[derive(Debug, Clone)]
@@ -50,6 +56,7 @@ pub struct Room {
     own_user_id: Arc<UserId>,
     inner: Arc<SyncRwLock<RoomInfo>>,
     store: Arc<Box<dyn StateStore>>,
+    name_formatter: Arc<dyn RoomNameFormatter>,
 }
 
 /// The room summary containing member counts and members that should be used to
@@ -81,6 +88,7 @@ impl Room {
     pub(crate) fn new(
         own_user_id: &UserId,
         store: Arc<Box<dyn StateStore>>,
+        name_formatter: Arc<dyn RoomNameFormatter>,
         room_id: &RoomId,
         room_type: RoomType,
     ) -> Self {
@@ -96,18 +104,20 @@ impl Room {
             base_info: BaseRoomInfo::new(),
         };
 
-        Self::restore(own_user_id, store, room_info)
+        Self::restore(own_user_id, store, name_formatter, room_info)
     }
 
     pub(crate) fn restore(
         own_user_id: &UserId,
         store: Arc<Box<dyn StateStore>>,
+        name_formatter: Arc<dyn RoomNameFormatter>,
         room_info: RoomInfo,
     ) -> Self {
         Self {
             own_user_id: Arc::new(own_user_id.clone()),
             room_id: room_info.room_id.clone(),
             store,
+            name_formatter,
             inner: Arc::new(SyncRwLock::new(room_info)),
         }
     }
@@ -181,6 +191,43 @@ impl Room {
         self.inner.read().unwrap().base_info.dm_target.clone()
     }
 
+    /// Get the tags of this room, keyed by the tag name.
+    pub fn tags(&self) -> BTreeMap<String, TagInfo> {
+        self.inner.read().unwrap().base_info.tags.clone()
+    }
+
+    /// Get the tag info of the given tag if this room has the tag.
+    pub fn tag(&self, tag: &str) -> Option<TagInfo> {
+        self.inner.read().unwrap().base_info.tags.get(tag).cloned()
+    }
+
+    /// Is this room marked as favourite.
+    pub fn is_favourite(&self) -> bool {
+        self.inner
+            .read()
+            .unwrap()
+            .base_info
+            .tags
+            .contains_key(FAVOURITE_TAG)
+    }
+
+    /// Is this room marked as low priority.
+    pub fn is_low_priority(&self) -> bool {
+        self.inner
+            .read()
+            .unwrap()
+            .base_info
+            .tags
+            .contains_key(LOW_PRIORITY_TAG)
+    }
+
+    /// Get the reason we were invited to this room, if the inviter gave one.
+    ///
+    /// This is only set for rooms we're invited to.
+    pub fn invite_reason(&self) -> Option<String> {
+        self.inner.read().unwrap().base_info.invite_reason.clone()
+    }
+
     /// Is the room encrypted.
     pub fn is_encrypted(&self) -> bool {
         self.inner.read().unwrap().is_encrypted()
@@ -240,19 +287,105 @@ impl Room {
         self.inner.read().unwrap().base_info.tombstone.clone()
     }
 
+    /// Get the id of the room that replaced this room after it was upgraded.
+    ///
+    /// This is the `replacement_room` of the `m.room.tombstone` event of this
+    /// room.
+    pub fn successor(&self) -> Option<RoomId> {
+        self.inner
+            .read()
+            .unwrap()
+            .base_info
+            .tombstone
+            .as_ref()
+            .map(|t| t.replacement_room.clone())
+    }
+
+    /// Get the id of the room this room replaced when it was created by an
+    /// upgrade.
+    ///
+    /// This is the `predecessor` of the `m.room.create` event of this room.
+    pub fn predecessor(&self) -> Option<RoomId> {
+        self.inner
+            .read()
+            .unwrap()
+            .base_info
+            .create
+            .as_ref()
+            .and_then(|c| c.predecessor.as_ref())
+            .map(|p| p.room_id.clone())
+    }
+
     /// Get the topic of the room.
     pub fn topic(&self) -> Option<String> {
         self.inner.read().unwrap().base_info.topic.clone()
     }
 
+    /// Get the `type` of the room as set in its `m.room.create` event.
+    pub fn create_type(&self) -> Option<String> {
+        self.inner.read().unwrap().base_info.create_type.clone()
+    }
+
+    /// Is this room a space.
+    pub fn is_space(&self) -> bool {
+        self.inner.read().unwrap().base_info.create_type.as_deref() == Some(SPACE_ROOM_TYPE)
+    }
+
+    /// Get the child rooms of this space, ordered by their `order` key
+    /// followed by their room id.
+    ///
+    /// Children with an `order` are sorted before children without one.
+    pub fn space_children(&self) -> Vec<(RoomId, SpaceChild)> {
+        let mut children: Vec<_> = self
+            .inner
+            .read()
+            .unwrap()
+            .base_info
+            .space_children
+            .iter()
+            .map(|(r, c)| (r.clone(), c.clone()))
+            .collect();
+
+        children.sort_by(|(room_a, a), (room_b, b)| match (&a.order, &b.order) {
+            (Some(a), Some(b)) => a.cmp(b).then_with(|| room_a.cmp(room_b)),
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (None, None) => room_a.cmp(room_b),
+        });
+
+        children
+    }
+
+    /// Get the spaces this room claims to be part of.
+    pub fn space_parents(&self) -> BTreeMap<RoomId, SpaceParent> {
+        self.inner.read().unwrap().base_info.space_parents.clone()
+    }
+
     /// Calculate the canonical display name of the room, taking into account
     /// its name, aliases and members.
     ///
-    /// The display name is calculated according to [this algorithm][spec].
+    /// The display name is calculated according to [this algorithm][spec],
+    /// rooms without a name or canonical alias are named by the configured
+    /// [`RoomNameFormatter`].
     ///
     /// [spec]: <https://matrix.org/docs/spec/client_server/latest#calculating-the-display-name-for-a-room>
+    /// [`RoomNameFormatter`]: trait.RoomNameFormatter.html
     pub async fn display_name(&self) -> StoreResult<String> {
-        self.calculate_name().await
+        self.calculate_name(self.name_formatter.as_ref()).await
+    }
+
+    /// Calculate the display name of the room using the given formatter
+    /// instead of the configured one.
+    ///
+    /// # Arguments
+    ///
+    /// * `formatter` - The formatter that should name the room if it has
+    /// neither a name nor a canonical alias.
+    pub async fn display_name_with(
+        &self,
+        formatter: &dyn RoomNameFormatter,
+    ) -> StoreResult<String> {
+        self.calculate_name(formatter).await
     }
 
     /// Get the list of users ids that are considered to be joined members of
@@ -313,7 +446,7 @@ impl Room {
         Ok(members)
     }
 
-    async fn calculate_name(&self) -> StoreResult<String> {
+    async fn calculate_name(&self, formatter: &dyn RoomNameFormatter) -> StoreResult<String> {
         let summary = {
             let inner = self.inner.read().unwrap();
 
@@ -326,12 +459,19 @@ impl Room {
             }
             inner.summary.clone()
         };
-        // TODO what should we do here? We have correct counts only if lazy
-        // loading is used.
-        let joined = summary.joined_member_count;
-        let invited = summary.invited_member_count;
         let heroes_count = summary.heroes.len() as u64;
 
+        // The server only sends member counts if lazy loading is used, count
+        // the members we know about otherwise.
+        let (known_joined, known_invited) = if summary.joined_member_count == 0 {
+            let joined = self.store.get_joined_user_ids(self.room_id()).await?;
+            let invited = self.store.get_invited_user_ids(self.room_id()).await?;
+
+            (joined.len() as u64, invited.len() as u64)
+        } else {
+            (summary.joined_member_count, summary.invited_member_count)
+        };
+
         let is_own_member = |m: &RoomMember| m.user_id() == &*self.own_user_id;
         let is_own_user_id = |u: &str| u == self.own_user_id().as_str();
 
@@ -365,10 +505,14 @@ impl Room {
             summary.heroes
         );
 
-        let inner = self.inner.read().unwrap();
-        Ok(inner
-            .base_info
-            .calculate_room_name(joined, invited, members))
+        Ok(formatter.format_name(RoomNameParts {
+            own_user_id: self.own_user_id(),
+            heroes: &members,
+            joined_member_count: summary.joined_member_count,
+            invited_member_count: summary.invited_member_count,
+            known_joined_member_count: known_joined,
+            known_invited_member_count: known_invited,
+        }))
     }
 
     pub(crate) fn clone_info(&self) -> RoomInfo {
@@ -394,6 +538,7 @@ impl Room {
             };
 
         let presence = self.store.get_presence_event(user_id).await?;
+        let presence_received_at = self.store.get_presence_received_at(user_id).await?;
         let profile = self.store.get_profile(self.room_id(), user_id).await?;
         let max_power_level = self.max_power_level();
         let is_room_creator = self
@@ -436,12 +581,149 @@ impl Room {
             event: member_event.into(),
             profile: profile.into(),
             presence: presence.into(),
+            presence_received_at,
             power_levels: power.into(),
             max_power_level,
             is_room_creator,
             display_name_ambiguous: ambiguous,
         }))
     }
+
+    /// Get the `m.room.power_levels` content of this room, if there is one.
+    pub async fn power_levels(&self) -> StoreResult<Option<PowerLevelsEventContent>> {
+        Ok(self
+            .store
+            .get_state_event(self.room_id(), EventType::RoomPowerLevels, "")
+            .await?
+            .and_then(|e| {
+                if let AnySyncStateEvent::RoomPowerLevels(e) = e {
+                    Some(e.content)
+                } else {
+                    None
+                }
+            }))
+    }
+
+    /// Get the power levels that are in effect in this room.
+    ///
+    /// If the room doesn't have a `m.room.power_levels` event, the creator of
+    /// the room has a power level of 100 and state events can be sent by
+    /// anyone, as the spec dictates.
+    pub async fn power_levels_or_default(&self) -> StoreResult<PowerLevelsEventContent> {
+        Ok(if let Some(p) = self.power_levels().await? {
+            p
+        } else {
+            let mut power_levels = PowerLevelsEventContent {
+                state_default: 0.into(),
+                ..Default::default()
+            };
+
+            if let Some(create) = self.create_content() {
+                power_levels.users.insert(create.creator, 100.into());
+            }
+
+            power_levels
+        })
+    }
+
+    /// Get the power level of the user with the given `user_id` in this room.
+    ///
+    /// Unlike [`RoomMember::power_level`] this doesn't require the member
+    /// event of the user to be known.
+    ///
+    /// [`RoomMember::power_level`]: struct.RoomMember.html#method.power_level
+    pub async fn user_power_level(&self, user_id: &UserId) -> StoreResult<i64> {
+        let power_levels = self.power_levels_or_default().await?;
+        Ok(user_power_level(&power_levels, user_id))
+    }
+
+    /// Can the user with the given `user_id` ban users from this room.
+    ///
+    /// This doesn't take the power level of the banned user into account, a
+    /// user can only ban users with a lower power level than their own.
+    pub async fn can_user_ban(&self, user_id: &UserId) -> StoreResult<bool> {
+        let power_levels = self.power_levels_or_default().await?;
+        Ok(user_power_level(&power_levels, user_id) >= i64::from(power_levels.ban))
+    }
+
+    /// Can the user with the given `user_id` kick users from this room.
+    ///
+    /// This doesn't take the power level of the kicked user into account, a
+    /// user can only kick users with a lower power level than their own.
+    pub async fn can_user_kick(&self, user_id: &UserId) -> StoreResult<bool> {
+        let power_levels = self.power_levels_or_default().await?;
+        Ok(user_power_level(&power_levels, user_id) >= i64::from(power_levels.kick))
+    }
+
+    /// Can the user with the given `user_id` invite users to this room.
+    pub async fn can_user_invite(&self, user_id: &UserId) -> StoreResult<bool> {
+        let power_levels = self.power_levels_or_default().await?;
+        Ok(user_power_level(&power_levels, user_id) >= i64::from(power_levels.invite))
+    }
+
+    /// Can the user with the given `user_id` redact events of other users in
+    /// this room.
+    ///
+    /// Users can always redact their own events.
+    pub async fn can_user_redact(&self, user_id: &UserId) -> StoreResult<bool> {
+        let power_levels = self.power_levels_or_default().await?;
+        Ok(user_power_level(&power_levels, user_id) >= i64::from(power_levels.redact))
+    }
+
+    /// Can the user with the given `user_id` send message events of the given
+    /// type to this room.
+    pub async fn can_user_send_message(
+        &self,
+        user_id: &UserId,
+        event_type: EventType,
+    ) -> StoreResult<bool> {
+        let power_levels = self.power_levels_or_default().await?;
+        let required = power_levels
+            .events
+            .get(&event_type)
+            .copied()
+            .unwrap_or(power_levels.events_default);
+
+        Ok(user_power_level(&power_levels, user_id) >= i64::from(required))
+    }
+
+    /// Can the user with the given `user_id` send state events of the given
+    /// type to this room.
+    pub async fn can_user_send_state(
+        &self,
+        user_id: &UserId,
+        event_type: EventType,
+    ) -> StoreResult<bool> {
+        Ok(self.user_power_level(user_id).await? >= self.state_power_level(event_type).await?)
+    }
+
+    /// Get the power level that is required to send state events of the given
+    /// type to this room.
+    pub async fn state_power_level(&self, event_type: EventType) -> StoreResult<i64> {
+        let power_levels = self.power_levels_or_default().await?;
+        Ok(power_levels
+            .events
+            .get(&event_type)
+            .copied()
+            .unwrap_or(power_levels.state_default)
+            .into())
+    }
+
+    /// Can the user with the given `user_id` notify the whole room using
+    /// `@room`.
+    pub async fn can_user_trigger_room_notification(&self, user_id: &UserId) -> StoreResult<bool> {
+        let power_levels = self.power_levels_or_default().await?;
+        Ok(user_power_level(&power_levels, user_id) >= i64::from(power_levels.notifications.room))
+    }
+}
+
+fn user_power_level(power_levels: &PowerLevelsEventContent, user_id: &UserId) -> i64 {
+    power_levels
+        .users
+        .get(user_id)
+        .copied()
+        .unwrap_or(power_levels.users_default)
+        .into()
 }
 
 /// The underlying pure data structure for joined and left rooms.
@@ -500,8 +782,25 @@ impl RoomInfo {
         self.base_info.encryption.is_some()
     }
 
-    pub(crate) fn handle_state_event(&mut self, event: &AnyStateEventContent) -> bool {
-        self.base_info.handle_state_event(&event)
+    pub(crate) fn handle_state_event(
+        &mut self,
+        state_key: &str,
+        content: &AnyStateEventContent,
+        raw_event: &RawValue,
+    ) -> bool {
+        match content {
+            AnyStateEventContent::Custom(c) => self.base_info.handle_space_event(state_key, c),
+            AnyStateEventContent::RoomCreate(_) => {
+                let changed = self.base_info.handle_state_event(content);
+
+                if changed {
+                    self.base_info.handle_raw_create_event(raw_event);
+                }
+
+                changed
+            }
+            _ => self.base_info.handle_state_event(content),
+        }
     }
 
     pub(crate) fn update_notification_count(
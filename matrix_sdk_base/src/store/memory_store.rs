@@ -15,6 +15,7 @@
 use std::{
     collections::BTreeSet,
     sync::{Arc, RwLock},
+    time::SystemTime,
 };
 
 use dashmap::{DashMap, DashSet};
@@ -55,6 +56,7 @@ pub struct MemoryStore {
         Arc<DashMap<RoomId, DashMap<String, DashMap<String, AnyStrippedStateEvent>>>>,
     stripped_members: Arc<DashMap<RoomId, DashMap<UserId, StrippedMemberEvent>>>,
     presence: Arc<DashMap<UserId, PresenceEvent>>,
+    presence_received_at: Arc<DashMap<UserId, SystemTime>>,
 }
 
 impl MemoryStore {
@@ -76,6 +78,7 @@ impl MemoryStore {
             stripped_room_state: DashMap::new().into(),
             stripped_members: DashMap::new().into(),
             presence: DashMap::new().into(),
+            presence_received_at: DashMap::new().into(),
         }
     }
 
@@ -192,8 +195,12 @@ impl MemoryStore {
             self.room_info.insert(room_id.clone(), room_info.clone());
         }
 
+        let received_at = SystemTime::now();
+
         for (sender, event) in &changes.presence {
             self.presence.insert(sender.clone(), event.clone());
+            self.presence_received_at
+                .insert(sender.clone(), received_at);
         }
 
         for (room_id, info) in &changes.invited_room_info {
@@ -233,6 +240,11 @@ impl MemoryStore {
         Ok(self.presence.get(user_id).map(|p| p.clone()))
     }
 
+    async fn get_presence_received_at(&self, user_id: &UserId) -> Result<Option<SystemTime>> {
+        #[allow(clippy::map_clone)]
+        Ok(self.presence_received_at.get(user_id).map(|t| *t))
+    }
+
     async fn get_state_event(
         &self,
         room_id: &RoomId,
@@ -270,6 +282,18 @@ impl MemoryStore {
             .and_then(|m| m.get(state_key).map(|m| m.clone())))
     }
 
+    async fn get_stripped_member_event(
+        &self,
+        room_id: &RoomId,
+        state_key: &UserId,
+    ) -> Result<Option<StrippedMemberEvent>> {
+        #[allow(clippy::map_clone)]
+        Ok(self
+            .stripped_members
+            .get(room_id)
+            .and_then(|m| m.get(state_key).map(|m| m.clone())))
+    }
+
     fn get_user_ids(&self, room_id: &RoomId) -> Vec<UserId> {
         #[allow(clippy::map_clone)]
         self.members
@@ -328,6 +352,10 @@ impl StateStore for MemoryStore {
         self.get_presence_event(user_id).await
     }
 
+    async fn get_presence_received_at(&self, user_id: &UserId) -> Result<Option<SystemTime>> {
+        self.get_presence_received_at(user_id).await
+    }
+
     async fn get_state_event(
         &self,
         room_id: &RoomId,
@@ -353,6 +381,14 @@ impl StateStore for MemoryStore {
         self.get_member_event(room_id, state_key).await
     }
 
+    async fn get_stripped_member_event(
+        &self,
+        room_id: &RoomId,
+        state_key: &UserId,
+    ) -> Result<Option<StrippedMemberEvent>> {
+        self.get_stripped_member_event(room_id, state_key).await
+    }
+
     async fn get_user_ids(&self, room_id: &RoomId) -> Result<Vec<UserId>> {
         Ok(self.get_user_ids(room_id))
     }
@@ -385,4 +421,24 @@ impl StateStore for MemoryStore {
             .and_then(|d| d.get(display_name).map(|d| d.clone()))
             .unwrap_or_default())
     }
+
+    async fn get_account_data_event(&self, event_type: EventType) -> Result<Option<AnyBasicEvent>> {
+        #[allow(clippy::map_clone)]
+        Ok(self
+            .account_data
+            .get(event_type.as_ref())
+            .map(|e| e.clone()))
+    }
+
+    async fn get_room_account_data_event(
+        &self,
+        room_id: &RoomId,
+        event_type: EventType,
+    ) -> Result<Option<AnyBasicEvent>> {
+        #[allow(clippy::map_clone)]
+        Ok(self
+            .room_account_data
+            .get(room_id)
+            .and_then(|m| m.get(event_type.as_ref()).map(|e| e.clone())))
+    }
 }
@@ -17,6 +17,7 @@ use std::{
     ops::Deref,
     path::Path,
     sync::Arc,
+    time::SystemTime,
 };
 
 use dashmap::DashMap;
@@ -35,7 +36,7 @@ use sled::Db;
 
 use crate::{
     deserialized_responses::{MemberEvent, StrippedMemberEvent},
-    rooms::{RoomInfo, RoomType},
+    rooms::{DefaultRoomNameFormatter, RoomInfo, RoomNameFormatter, RoomType},
     Room, Session,
 };
 
@@ -113,6 +114,20 @@ pub trait StateStore: AsyncTraitDeps {
     /// event for.
     async fn get_presence_event(&self, user_id: &UserId) -> Result<Option<PresenceEvent>>;
 
+    /// Get the time the stored presence event for the given user was
+    /// received.
+    ///
+    /// The default implementation doesn't know the time, the `last_active_ago`
+    /// of a member is then reported as it was received from the server.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The id of the user for which we wish to fetch the receive
+    /// time of the presence event for.
+    async fn get_presence_received_at(&self, _user_id: &UserId) -> Result<Option<SystemTime>> {
+        Ok(None)
+    }
+
     /// Get a state event out of the state store.
     ///
     /// # Arguments
@@ -153,6 +168,23 @@ pub trait StateStore: AsyncTraitDeps {
         state_key: &UserId,
     ) -> Result<Option<MemberEvent>>;
 
+    /// Get a `StrippedMemberEvent` for the given state key in the given room
+    /// id.
+    ///
+    /// Stripped member events are part of the preview of rooms we're invited
+    /// to.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The room id the member event belongs to.
+    ///
+    /// * `state_key` - The user id that the member event defines the state for.
+    async fn get_stripped_member_event(
+        &self,
+        room_id: &RoomId,
+        state_key: &UserId,
+    ) -> Result<Option<StrippedMemberEvent>>;
+
     /// Get all the user ids of members for a given room.
     async fn get_user_ids(&self, room_id: &RoomId) -> Result<Vec<UserId>>;
 
@@ -183,6 +215,26 @@ pub trait StateStore: AsyncTraitDeps {
         room_id: &RoomId,
         display_name: &str,
     ) -> Result<BTreeSet<UserId>>;
+
+    /// Get an event out of the global account data store.
+    ///
+    /// # Arguments
+    ///
+    /// * `event_type` - The event type of the account data event.
+    async fn get_account_data_event(&self, event_type: EventType) -> Result<Option<AnyBasicEvent>>;
+
+    /// Get an event out of the room account data store.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The id of the room the account data event belongs to.
+    ///
+    /// * `event_type` - The event type of the account data event.
+    async fn get_room_account_data_event(
+        &self,
+        room_id: &RoomId,
+        event_type: EventType,
+    ) -> Result<Option<AnyBasicEvent>>;
 }
 
 /// A state store wrapper for the SDK.
@@ -196,6 +248,7 @@ pub struct Store {
     pub(crate) sync_token: Arc<RwLock<Option<String>>>,
     rooms: Arc<DashMap<RoomId, Room>>,
     stripped_rooms: Arc<DashMap<RoomId, Room>>,
+    pub(crate) name_formatter: Arc<dyn RoomNameFormatter>,
 }
 
 impl Store {
@@ -209,17 +262,28 @@ impl Store {
             sync_token,
             rooms: DashMap::new().into(),
             stripped_rooms: DashMap::new().into(),
+            name_formatter: Arc::new(DefaultRoomNameFormatter),
         }
     }
 
     pub(crate) async fn restore_session(&self, session: Session) -> Result<()> {
         for info in self.inner.get_room_infos().await? {
-            let room = Room::restore(&session.user_id, self.inner.clone(), info);
+            let room = Room::restore(
+                &session.user_id,
+                self.inner.clone(),
+                self.name_formatter.clone(),
+                info,
+            );
             self.rooms.insert(room.room_id().to_owned(), room);
         }
 
         for info in self.inner.get_stripped_room_infos().await? {
-            let room = Room::restore(&session.user_id, self.inner.clone(), info);
+            let room = Room::restore(
+                &session.user_id,
+                self.inner.clone(),
+                self.name_formatter.clone(),
+                info,
+            );
             self.stripped_rooms.insert(room.room_id().to_owned(), room);
         }
 
@@ -301,7 +365,15 @@ impl Store {
 
         self.stripped_rooms
             .entry(room_id.clone())
-            .or_insert_with(|| Room::new(user_id, self.inner.clone(), room_id, RoomType::Invited))
+            .or_insert_with(|| {
+                Room::new(
+                    user_id,
+                    self.inner.clone(),
+                    self.name_formatter.clone(),
+                    room_id,
+                    RoomType::Invited,
+                )
+            })
             .clone()
     }
 
@@ -314,7 +386,15 @@ impl Store {
 
         self.rooms
             .entry(room_id.clone())
-            .or_insert_with(|| Room::new(user_id, self.inner.clone(), room_id, room_type))
+            .or_insert_with(|| {
+                Room::new(
+                    user_id,
+                    self.inner.clone(),
+                    self.name_formatter.clone(),
+                    room_id,
+                    room_type,
+                )
+            })
             .clone()
     }
 }
@@ -31,7 +31,7 @@ use matrix_sdk_common::{
     events::{
         presence::PresenceEvent,
         room::member::{MemberEventContent, MembershipState},
-        AnySyncStateEvent, EventContent, EventType,
+        AnyBasicEvent, AnySyncStateEvent, EventContent, EventType,
     },
     identifiers::{RoomId, UserId},
 };
@@ -43,12 +43,21 @@ use sled::{
 };
 use tracing::info;
 
-use crate::deserialized_responses::MemberEvent;
+use crate::deserialized_responses::{MemberEvent, StrippedMemberEvent};
 
 use self::store_key::{EncryptedEvent, StoreKey};
 
 use super::{Result, RoomInfo, StateChanges, StateStore, StoreError};
 
+/// A presence event as it's stored, together with the time it was received.
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredPresence<E> {
+    #[serde(flatten)]
+    event: E,
+    /// Missing for presence events that were stored by older versions.
+    received_at: Option<SystemTime>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum DatabaseType {
     Unencrypted,
@@ -424,6 +433,11 @@ impl SledStore {
                     }
 
                     for (sender, event) in &changes.presence {
+                        let event = StoredPresence {
+                            event,
+                            received_at: Some(now),
+                        };
+
                         presence.insert(
                             sender.encode(),
                             self.serialize_event(&event)
@@ -479,7 +493,10 @@ impl SledStore {
         Ok(())
     }
 
-    pub async fn get_presence_event(&self, user_id: &UserId) -> Result<Option<PresenceEvent>> {
+    fn get_stored_presence(
+        &self,
+        user_id: &UserId,
+    ) -> Result<Option<StoredPresence<PresenceEvent>>> {
         Ok(self
             .presence
             .get(user_id.encode())?
@@ -487,6 +504,16 @@ impl SledStore {
             .transpose()?)
     }
 
+    pub async fn get_presence_event(&self, user_id: &UserId) -> Result<Option<PresenceEvent>> {
+        Ok(self.get_stored_presence(user_id)?.map(|p| p.event))
+    }
+
+    pub async fn get_presence_received_at(&self, user_id: &UserId) -> Result<Option<SystemTime>> {
+        Ok(self
+            .get_stored_presence(user_id)?
+            .and_then(|p| p.received_at))
+    }
+
     pub async fn get_state_event(
         &self,
         room_id: &RoomId,
@@ -524,6 +551,18 @@ impl SledStore {
             .transpose()?)
     }
 
+    pub async fn get_stripped_member_event(
+        &self,
+        room_id: &RoomId,
+        state_key: &UserId,
+    ) -> Result<Option<StrippedMemberEvent>> {
+        Ok(self
+            .stripped_members
+            .get((room_id.as_str(), state_key.as_str()).encode())?
+            .map(|v| self.deserialize_event(&v))
+            .transpose()?)
+    }
+
     pub async fn get_user_ids(&self, room_id: &RoomId) -> impl Stream<Item = Result<UserId>> {
         stream::iter(self.members.scan_prefix(room_id.encode()).map(|u| {
             UserId::try_from(String::from_utf8_lossy(&u?.1).to_string())
@@ -587,6 +626,29 @@ impl SledStore {
             .transpose()?
             .unwrap_or_default())
     }
+
+    pub async fn get_account_data_event(
+        &self,
+        event_type: EventType,
+    ) -> Result<Option<AnyBasicEvent>> {
+        Ok(self
+            .account_data
+            .get(event_type.as_ref().encode())?
+            .map(|m| self.deserialize_event(&m))
+            .transpose()?)
+    }
+
+    pub async fn get_room_account_data_event(
+        &self,
+        room_id: &RoomId,
+        event_type: EventType,
+    ) -> Result<Option<AnyBasicEvent>> {
+        Ok(self
+            .room_account_data
+            .get((room_id.as_str(), event_type.as_ref()).encode())?
+            .map(|m| self.deserialize_event(&m))
+            .transpose()?)
+    }
 }
 
 #[async_trait]
@@ -611,6 +673,10 @@ impl StateStore for SledStore {
         self.get_presence_event(user_id).await
     }
 
+    async fn get_presence_received_at(&self, user_id: &UserId) -> Result<Option<SystemTime>> {
+        self.get_presence_received_at(user_id).await
+    }
+
     async fn get_state_event(
         &self,
         room_id: &RoomId,
@@ -636,6 +702,14 @@ impl StateStore for SledStore {
         self.get_member_event(room_id, state_key).await
     }
 
+    async fn get_stripped_member_event(
+        &self,
+        room_id: &RoomId,
+        state_key: &UserId,
+    ) -> Result<Option<StrippedMemberEvent>> {
+        self.get_stripped_member_event(room_id, state_key).await
+    }
+
     async fn get_user_ids(&self, room_id: &RoomId) -> Result<Vec<UserId>> {
         self.get_user_ids(room_id).await.try_collect().await
     }
@@ -664,6 +738,18 @@ impl StateStore for SledStore {
         self.get_users_with_display_name(room_id, display_name)
             .await
     }
+
+    async fn get_account_data_event(&self, event_type: EventType) -> Result<Option<AnyBasicEvent>> {
+        self.get_account_data_event(event_type).await
+    }
+
+    async fn get_room_account_data_event(
+        &self,
+        room_id: &RoomId,
+        event_type: EventType,
+    ) -> Result<Option<AnyBasicEvent>> {
+        self.get_room_account_data_event(room_id, event_type).await
+    }
 }
 
 #[cfg(test)]
@@ -672,14 +758,16 @@ mod test {
 
     use matrix_sdk_common::{
         events::{
+            presence::PresenceEvent,
             room::member::{MemberEventContent, MembershipState},
             Unsigned,
         },
         identifiers::{room_id, user_id, EventId, UserId},
     };
     use matrix_sdk_test::async_test;
+    use serde_json::json;
 
-    use super::{SledStore, StateChanges};
+    use super::{EncodeKey, SledStore, StateChanges};
     use crate::deserialized_responses::MemberEvent;
 
     fn user_id() -> UserId {
@@ -731,4 +819,46 @@ mod test {
             .unwrap()
             .is_some());
     }
+
+    #[async_test]
+    async fn test_presence_saving() {
+        let store = SledStore::open().unwrap();
+        let user_id = user_id();
+
+        let event: PresenceEvent = serde_json::from_value(json!({
+            "content": {
+                "last_active_ago": 1000,
+                "presence": "online"
+            },
+            "sender": user_id,
+            "type": "m.presence"
+        }))
+        .unwrap();
+
+        // Presence events stored by older versions don't have a receive time.
+        store
+            .presence
+            .insert(user_id.encode(), store.serialize_event(&event).unwrap())
+            .unwrap();
+
+        assert!(store.get_presence_event(&user_id).await.unwrap().is_some());
+        assert!(store
+            .get_presence_received_at(&user_id)
+            .await
+            .unwrap()
+            .is_none());
+
+        let before = SystemTime::now();
+        let mut changes = StateChanges::default();
+        changes.add_presence_event(event);
+        store.save_changes(&changes).await.unwrap();
+
+        assert!(store.get_presence_event(&user_id).await.unwrap().is_some());
+        let received_at = store
+            .get_presence_received_at(&user_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(received_at >= before);
+    }
 }
@@ -6,11 +6,13 @@ use super::{
         DeviceLists, UnreadNotificationsCount as RumaUnreadNotificationsCount,
     },
     events::{
-        presence::PresenceEvent, room::member::MemberEventContent, AnyBasicEvent,
-        AnyStrippedStateEvent, AnySyncEphemeralRoomEvent, AnySyncRoomEvent, AnySyncStateEvent,
-        AnyToDeviceEvent, StateEvent, StrippedStateEvent, SyncStateEvent, Unsigned,
+        presence::PresenceEvent,
+        room::{encrypted::EncryptedEventContent, member::MemberEventContent},
+        AnyBasicEvent, AnyStrippedStateEvent, AnySyncEphemeralRoomEvent, AnySyncRoomEvent,
+        AnySyncStateEvent, AnyToDeviceEvent, StateEvent, StrippedStateEvent, SyncMessageEvent,
+        SyncStateEvent, Unsigned,
     },
-    identifiers::{DeviceKeyAlgorithm, EventId, RoomId, UserId},
+    identifiers::{DeviceIdBox, DeviceKeyAlgorithm, EventId, RoomId, UserId},
 };
 
 /// A change in ambiguity of room members that an `m.room.member` event
@@ -55,6 +57,9 @@ pub struct SyncResponse {
     pub device_one_time_keys_count: BTreeMap<DeviceKeyAlgorithm, u64>,
     /// Collection of ambiguioty changes that room member events trigger.
     pub ambiguity_changes: AmbiguityChanges,
+    /// Events of earlier syncs that could be decrypted because the room keys
+    /// for them arrived in this sync, keyed by the room id of the events.
+    pub decrypted_events: BTreeMap<RoomId, Vec<SyncRoomEvent>>,
 }
 
 impl SyncResponse {
@@ -204,6 +209,107 @@ impl LeftRoom {
     }
 }
 
+/// The verification state of the device that sent an event to us.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum VerificationState {
+    /// The device that sent the event is trusted, either because we verified
+    /// it ourselves or because its owner signed it with a trusted
+    /// cross-signing identity.
+    Trusted,
+    /// The device that sent the event isn't trusted or doesn't own the key the
+    /// event was encrypted with.
+    Untrusted,
+    /// The device that sent the event isn't known to us.
+    UnknownDevice,
+}
+
+/// The algorithm specific information of a decrypted event.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum AlgorithmInfo {
+    /// The info if the event was encrypted using `m.megolm.v1.aes-sha2`.
+    MegolmV1AesSha2 {
+        /// The curve25519 key of the device that created the megolm decryption
+        /// key originally.
+        curve25519_key: String,
+        /// The signing keys that the device that created the megolm key
+        /// claims to own.
+        sender_claimed_keys: BTreeMap<DeviceKeyAlgorithm, String>,
+        /// The unique id of the megolm session that was used to decrypt the
+        /// event.
+        session_id: String,
+    },
+}
+
+/// Information about the encryption of a decrypted event.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct EventEncryptionInfo {
+    /// The user that sent the event.
+    pub sender: UserId,
+    /// The device that claims to have sent the event.
+    pub sender_device: DeviceIdBox,
+    /// Information about the algorithm that was used to encrypt the event.
+    pub algorithm_info: AlgorithmInfo,
+    /// The verification state of the device that sent the event.
+    pub verification_state: VerificationState,
+}
+
+/// A timeline event of a room, including the encryption info of the event if
+/// it was decrypted.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SyncRoomEvent {
+    /// The actual event.
+    pub event: AnySyncRoomEvent,
+    /// The encryption info of the event, `None` if the event wasn't
+    /// encrypted.
+    pub encryption_info: Option<EventEncryptionInfo>,
+}
+
+impl From<AnySyncRoomEvent> for SyncRoomEvent {
+    fn from(event: AnySyncRoomEvent) -> Self {
+        Self {
+            event,
+            encryption_info: None,
+        }
+    }
+}
+
+/// The reason why an encrypted event couldn't be decrypted.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum UnableToDecryptReason {
+    /// We don't have the room key the event was encrypted with.
+    MissingSession,
+    /// We have the room key, but our copy of it starts at a later message
+    /// index than the one the event was encrypted with.
+    UnknownMessageIndex,
+    /// The sender of the event refused to send us the room key.
+    Withheld {
+        /// The machine readable code of the reason, e.g.
+        /// `m.unverified`.
+        code: String,
+        /// The human readable reason.
+        reason: Option<String>,
+    },
+    /// We don't have the room key and our Olm session with the device that
+    /// should have sent it to us is wedged.
+    WedgedOlmSession,
+}
+
+/// An encrypted event of a room that we couldn't decrypt yet.
+///
+/// The decryption is retried once a matching room key is received or
+/// imported.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UndecryptableEvent {
+    /// The encrypted event.
+    pub event: SyncMessageEvent<EncryptedEventContent>,
+    /// The curve25519 key of the device that created the room key.
+    pub sender_key: String,
+    /// The id of the session the event was encrypted with.
+    pub session_id: String,
+    /// The reason why the event couldn't be decrypted.
+    pub reason: UnableToDecryptReason,
+}
+
 /// Events in the room.
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Timeline {
@@ -215,7 +321,7 @@ pub struct Timeline {
     pub prev_batch: Option<String>,
 
     /// A list of events.
-    pub events: Vec<AnySyncRoomEvent>,
+    pub events: Vec<SyncRoomEvent>,
 }
 
 impl Timeline {
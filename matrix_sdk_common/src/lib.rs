@@ -13,9 +13,13 @@ pub use ruma::{
 
 pub use uuid;
 
+#[cfg(feature = "appservice")]
+pub use ruma::api::appservice as appservice_api;
+
 pub mod deserialized_responses;
 pub mod executor;
 pub mod locks;
+pub mod unstable;
 
 /// Super trait that is used for our store traits, this trait will differ if
 /// it's used on WASM. WASM targets will not require `Send` and `Sync` to have
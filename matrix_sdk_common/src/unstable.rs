@@ -0,0 +1,432 @@
+//! Endpoints of unstable Matrix spec proposals that aren't supported by ruma
+//! yet.
+
+/// Endpoints for end-to-end encryption keys.
+pub mod keys {
+    /// [POST /_matrix/client/r0/keys/upload](https://matrix.org/docs/spec/client_server/r0.6.1#post-matrix-client-r0-keys-upload)
+    /// with support for the fallback keys of [MSC2732].
+    ///
+    /// [MSC2732]: https://github.com/matrix-org/matrix-doc/pull/2732
+    pub mod upload_keys {
+        use std::collections::BTreeMap;
+
+        use ruma::{
+            api::{client::r0::keys::OneTimeKey, ruma_api},
+            encryption::DeviceKeys,
+            identifiers::{DeviceKeyAlgorithm, DeviceKeyId},
+            UInt,
+        };
+
+        ruma_api! {
+            metadata: {
+                description: "Publishes end-to-end encryption keys for the device.",
+                method: POST,
+                name: "upload_keys",
+                path: "/_matrix/client/r0/keys/upload",
+                rate_limited: false,
+                authentication: AccessToken,
+            }
+
+            request: {
+                /// Identity keys for the device. May be absent if no new
+                /// identity keys are required.
+                #[serde(skip_serializing_if = "Option::is_none")]
+                pub device_keys: Option<DeviceKeys>,
+
+                /// One-time public keys for "pre-key" messages.
+                #[serde(skip_serializing_if = "Option::is_none")]
+                pub one_time_keys: Option<BTreeMap<DeviceKeyId, OneTimeKey>>,
+
+                /// A fallback key that is handed out once the one-time keys of
+                /// the device are used up.
+                #[serde(
+                    rename = "org.matrix.msc2732.fallback_keys",
+                    skip_serializing_if = "Option::is_none"
+                )]
+                pub fallback_keys: Option<BTreeMap<DeviceKeyId, OneTimeKey>>,
+            }
+
+            response: {
+                /// For each key algorithm, the number of unclaimed one-time
+                /// keys of that type currently held on the server for this
+                /// device.
+                pub one_time_key_counts: BTreeMap<DeviceKeyAlgorithm, UInt>,
+            }
+
+            error: ruma::api::client::Error
+        }
+
+        impl Request {
+            /// Creates an empty `Request`.
+            pub fn new() -> Self {
+                Self {
+                    device_keys: None,
+                    one_time_keys: None,
+                    fallback_keys: None,
+                }
+            }
+        }
+
+        impl Default for Request {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+    }
+
+    /// `POST /_matrix/client/r0/keys/upload/{deviceId}`, uploads the keys of
+    /// a dehydrated device, as defined in [MSC2697].
+    ///
+    /// [MSC2697]: https://github.com/matrix-org/matrix-doc/pull/2697
+    pub mod upload_device_keys {
+        use std::collections::BTreeMap;
+
+        use ruma::{
+            api::{client::r0::keys::OneTimeKey, ruma_api},
+            encryption::DeviceKeys,
+            identifiers::{DeviceIdBox, DeviceKeyAlgorithm, DeviceKeyId},
+            UInt,
+        };
+
+        ruma_api! {
+            metadata: {
+                description: "Publishes end-to-end encryption keys for a dehydrated device.",
+                method: POST,
+                name: "upload_device_keys",
+                path: "/_matrix/client/r0/keys/upload/:device_id",
+                rate_limited: false,
+                authentication: AccessToken,
+            }
+
+            request: {
+                /// The id of the dehydrated device the keys belong to.
+                #[ruma_api(path)]
+                pub device_id: DeviceIdBox,
+
+                /// Identity keys for the device.
+                pub device_keys: DeviceKeys,
+
+                /// One-time public keys for "pre-key" messages.
+                pub one_time_keys: BTreeMap<DeviceKeyId, OneTimeKey>,
+
+                /// A fallback key that is handed out once the one-time keys of
+                /// the device are used up.
+                #[serde(rename = "org.matrix.msc2732.fallback_keys")]
+                pub fallback_keys: BTreeMap<DeviceKeyId, OneTimeKey>,
+            }
+
+            response: {
+                /// For each key algorithm, the number of unclaimed one-time
+                /// keys of that type currently held on the server for this
+                /// device.
+                pub one_time_key_counts: BTreeMap<DeviceKeyAlgorithm, UInt>,
+            }
+
+            error: ruma::api::client::Error
+        }
+
+        impl Request {
+            /// Creates a new `Request` with the given device id and keys.
+            pub fn new(
+                device_id: DeviceIdBox,
+                device_keys: DeviceKeys,
+                one_time_keys: BTreeMap<DeviceKeyId, OneTimeKey>,
+                fallback_keys: BTreeMap<DeviceKeyId, OneTimeKey>,
+            ) -> Self {
+                Self {
+                    device_id,
+                    device_keys,
+                    one_time_keys,
+                    fallback_keys,
+                }
+            }
+        }
+    }
+}
+
+/// Endpoints and types for dehydrated devices, as defined in [MSC2697].
+///
+/// [MSC2697]: https://github.com/matrix-org/matrix-doc/pull/2697
+pub mod dehydrated_device {
+    use serde::{Deserialize, Serialize};
+
+    /// The parameters that were used to derive the pickle key of a dehydrated
+    /// device from the user secret.
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    pub struct DehydrationPassphraseInfo {
+        /// The unpadded base64 encoded salt for the key derivation.
+        pub salt: String,
+        /// The number of PBKDF2 rounds.
+        pub iterations: u32,
+    }
+
+    /// The `device_data` of a dehydrated device, as it is stored on the
+    /// server.
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    pub struct DehydratedDeviceData {
+        /// The algorithm that was used to store the account.
+        pub algorithm: String,
+        /// The encrypted pickle of the Olm account.
+        pub account: String,
+        /// Info on how the pickle key was derived from the user secret.
+        pub passphrase: DehydrationPassphraseInfo,
+    }
+
+    /// `PUT /_matrix/client/unstable/org.matrix.msc2697.v2/dehydrated_device`,
+    /// uploads a new dehydrated device, replacing the existing one.
+    pub mod put_dehydrated_device {
+        use ruma::{api::ruma_api, identifiers::DeviceIdBox};
+
+        use super::DehydratedDeviceData;
+
+        ruma_api! {
+            metadata: {
+                description: "Uploads a new dehydrated device.",
+                method: PUT,
+                name: "put_dehydrated_device",
+                path: "/_matrix/client/unstable/org.matrix.msc2697.v2/dehydrated_device",
+                rate_limited: false,
+                authentication: AccessToken,
+            }
+
+            request: {
+                /// The pickled account of the dehydrated device.
+                pub device_data: DehydratedDeviceData,
+
+                /// The display name the dehydrated device should have.
+                pub initial_device_display_name: String,
+            }
+
+            response: {
+                /// The device id the homeserver assigned to the dehydrated
+                /// device.
+                pub device_id: DeviceIdBox,
+            }
+
+            error: ruma::api::client::Error
+        }
+
+        impl Request {
+            /// Creates a new `Request` with the given device data and display
+            /// name.
+            pub fn new(
+                device_data: DehydratedDeviceData,
+                initial_device_display_name: String,
+            ) -> Self {
+                Self {
+                    device_data,
+                    initial_device_display_name,
+                }
+            }
+        }
+    }
+
+    /// `GET /_matrix/client/unstable/org.matrix.msc2697.v2/dehydrated_device`,
+    /// fetches the current dehydrated device of the user.
+    pub mod get_dehydrated_device {
+        use ruma::{api::ruma_api, identifiers::DeviceIdBox};
+
+        use super::DehydratedDeviceData;
+
+        ruma_api! {
+            metadata: {
+                description: "Fetches the current dehydrated device.",
+                method: GET,
+                name: "get_dehydrated_device",
+                path: "/_matrix/client/unstable/org.matrix.msc2697.v2/dehydrated_device",
+                rate_limited: false,
+                authentication: AccessToken,
+            }
+
+            request: {}
+
+            response: {
+                /// The device id of the dehydrated device.
+                pub device_id: DeviceIdBox,
+
+                /// The pickled account of the dehydrated device.
+                pub device_data: DehydratedDeviceData,
+            }
+
+            error: ruma::api::client::Error
+        }
+
+        impl Request {
+            /// Creates an empty `Request`.
+            pub fn new() -> Self {
+                Self {}
+            }
+        }
+
+        impl Default for Request {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+    }
+
+    /// `POST /_matrix/client/unstable/org.matrix.msc2697.v2/dehydrated_device/claim`,
+    /// claims the current dehydrated device of the user.
+    pub mod claim_dehydrated_device {
+        use ruma::{api::ruma_api, identifiers::DeviceIdBox};
+
+        ruma_api! {
+            metadata: {
+                description: "Claims the current dehydrated device.",
+                method: POST,
+                name: "claim_dehydrated_device",
+                path: "/_matrix/client/unstable/org.matrix.msc2697.v2/dehydrated_device/claim",
+                rate_limited: false,
+                authentication: AccessToken,
+            }
+
+            request: {
+                /// The device id of the dehydrated device that should be
+                /// claimed.
+                pub device_id: DeviceIdBox,
+            }
+
+            response: {
+                /// Whether the device was claimed, false if another login
+                /// claimed it first.
+                pub success: bool,
+            }
+
+            error: ruma::api::client::Error
+        }
+
+        impl Request {
+            /// Creates a new `Request` for the given device id.
+            pub fn new(device_id: DeviceIdBox) -> Self {
+                Self { device_id }
+            }
+        }
+    }
+}
+
+/// Endpoints and types for spaces, as defined in [MSC1772] and [MSC2946].
+///
+/// [MSC1772]: https://github.com/matrix-org/matrix-doc/pull/1772
+/// [MSC2946]: https://github.com/matrix-org/matrix-doc/pull/2946
+pub mod space {
+    use ruma::{
+        events::AnyStrippedStateEvent,
+        identifiers::{MxcUri, RoomAliasId, RoomId},
+        serde::Raw,
+    };
+    use serde::{Deserialize, Serialize};
+
+    /// The room type a `m.room.create` event uses to mark a room as a space.
+    pub const SPACE_ROOM_TYPE: &str = "m.space";
+
+    /// A room that is part of a space hierarchy.
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    pub struct SpaceHierarchyRoom {
+        /// The id of the room.
+        pub room_id: RoomId,
+        /// The name of the room, if any.
+        pub name: Option<String>,
+        /// The topic of the room, if any.
+        pub topic: Option<String>,
+        /// The canonical alias of the room, if any.
+        pub canonical_alias: Option<RoomAliasId>,
+        /// The avatar of the room, if any.
+        pub avatar_url: Option<MxcUri>,
+        /// The number of members that are joined to the room.
+        #[serde(default)]
+        pub num_joined_members: u64,
+        /// Can the room be previewed without joining it.
+        #[serde(default)]
+        pub world_readable: bool,
+        /// Can guests join the room.
+        #[serde(default)]
+        pub guest_can_join: bool,
+        /// The join rule of the room.
+        pub join_rule: Option<String>,
+        /// The `type` of the room as set in its `m.room.create` event, e.g.
+        /// `m.space`.
+        pub room_type: Option<String>,
+        /// The stripped `m.space.child` events of the room.
+        #[serde(default)]
+        pub children_state: Vec<Raw<AnyStrippedStateEvent>>,
+    }
+
+    impl SpaceHierarchyRoom {
+        /// Is the room a space.
+        pub fn is_space(&self) -> bool {
+            self.room_type.as_deref() == Some(SPACE_ROOM_TYPE)
+        }
+    }
+
+    /// `GET /_matrix/client/unstable/org.matrix.msc2946/rooms/{roomId}/hierarchy`,
+    /// fetches a page of the room hierarchy of a space.
+    pub mod get_hierarchy {
+        use ruma::{api::ruma_api, identifiers::RoomId, UInt};
+
+        use super::SpaceHierarchyRoom;
+
+        ruma_api! {
+            metadata: {
+                description: "Fetches a page of the room hierarchy of a space.",
+                method: GET,
+                name: "get_hierarchy",
+                path: "/_matrix/client/unstable/org.matrix.msc2946/rooms/:room_id/hierarchy",
+                rate_limited: true,
+                authentication: AccessToken,
+            }
+
+            request: {
+                /// The room id of the space.
+                #[ruma_api(path)]
+                pub room_id: RoomId,
+
+                /// The `next_batch` token of the previous page.
+                #[ruma_api(query)]
+                #[serde(skip_serializing_if = "Option::is_none")]
+                pub from: Option<String>,
+
+                /// The maximum number of rooms the page should contain.
+                #[ruma_api(query)]
+                #[serde(skip_serializing_if = "Option::is_none")]
+                pub limit: Option<UInt>,
+
+                /// Should only rooms that are suggested by the space be
+                /// returned.
+                #[ruma_api(query)]
+                #[serde(default, skip_serializing_if = "is_false")]
+                pub suggested_only: bool,
+            }
+
+            response: {
+                /// The rooms of this page, in depth-first order starting with
+                /// the space itself.
+                pub rooms: Vec<SpaceHierarchyRoom>,
+
+                /// The token to fetch the next page, `None` if this is the
+                /// last page.
+                #[serde(skip_serializing_if = "Option::is_none")]
+                pub next_batch: Option<String>,
+            }
+
+            error: ruma::api::client::Error
+        }
+
+        impl Request {
+            /// Creates a new `Request` for the first page of the hierarchy of
+            /// the given space.
+            pub fn new(room_id: RoomId) -> Self {
+                Self {
+                    room_id,
+                    from: None,
+                    limit: None,
+                    suggested_only: false,
+                }
+            }
+        }
+
+        #[allow(clippy::trivially_copy_pass_by_ref)]
+        fn is_false(b: &bool) -> bool {
+            !*b
+        }
+    }
+}
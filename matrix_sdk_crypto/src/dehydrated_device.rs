@@ -0,0 +1,308 @@
+// Copyright 2021 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Support for dehydrated devices, as defined in [MSC2697].
+//!
+//! A dehydrated device is an Olm account that is stored, encrypted with a key
+//! derived from a user secret, on the homeserver. Other devices share room
+//! keys with it as with any other device. A new login can claim the dehydrated
+//! device, after that the login continues as the dehydrated device and
+//! receives the room keys that were sent to it while all of the user's devices
+//! were offline.
+//!
+//! [MSC2697]: https://github.com/matrix-org/matrix-doc/pull/2697
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use getrandom::getrandom;
+use hmac::Hmac;
+use olm_rs::{errors::OlmAccountError, PicklingMode};
+use pbkdf2::pbkdf2;
+use serde::Serialize;
+use sha2::Sha512;
+use thiserror::Error;
+use tracing::debug;
+use zeroize::Zeroizing;
+
+pub use matrix_sdk_common::unstable::dehydrated_device::{
+    DehydratedDeviceData, DehydrationPassphraseInfo,
+};
+use matrix_sdk_common::{
+    api::r0::keys::{DeviceKeys, OneTimeKey},
+    identifiers::{DeviceId, DeviceKeyId, UserId},
+    locks::Mutex,
+};
+
+use crate::{
+    olm::{PrivateCrossSigningIdentity, ReadOnlyAccount},
+    store::{CryptoStore, CryptoStoreError},
+    OlmMachine,
+};
+
+/// The algorithm that is used to store the account of a dehydrated device.
+pub const DEHYDRATION_ALGORITHM: &str = "org.matrix.msc2697.v1.olm.libolm_pickle";
+
+/// The homeserver assigns the device id of a dehydrated device once it gets
+/// uploaded, until then the account uses this placeholder.
+const PLACEHOLDER_DEVICE_ID: &str = "DEHYDRATED";
+
+/// The number of PBKDF2 rounds of a dehydrated device is chosen by the server
+/// when the device gets rehydrated, more rounds than this are refused so the
+/// server can't stall the key derivation.
+const MAX_ROUNDS: u32 = 1_000_000;
+
+const KEY_SIZE: usize = 32;
+const SALT_SIZE: usize = 16;
+
+/// Error type describing failures while rehydrating a device.
+#[derive(Error, Debug)]
+pub enum DehydrationError {
+    /// The dehydrated device uses an algorithm we don't support.
+    #[error("the dehydrated device uses an unsupported algorithm {0}")]
+    UnsupportedAlgorithm(String),
+
+    /// The dehydrated device uses no or too many PBKDF2 rounds.
+    #[error("the dehydrated device uses an invalid number of PBKDF2 rounds {0}")]
+    InvalidIterations(u32),
+
+    /// The salt of the dehydrated device isn't valid base64.
+    #[error(transparent)]
+    Decode(#[from] base64::DecodeError),
+
+    /// The account couldn't be unpickled, most likely because the wrong
+    /// secret was used.
+    #[error("can't unpickle the dehydrated account {0}")]
+    Pickle(#[from] OlmAccountError),
+}
+
+/// The body of the `PUT /dehydrated_device` request that uploads a new
+/// dehydrated device.
+#[derive(Clone, Debug, Serialize)]
+pub struct DehydratedDeviceRequest {
+    /// The pickled account of the dehydrated device.
+    pub device_data: DehydratedDeviceData,
+    /// The display name the dehydrated device should have.
+    pub initial_device_display_name: String,
+}
+
+/// The body of the `POST /keys/upload/{deviceId}` request that uploads the
+/// keys of a dehydrated device.
+#[derive(Clone, Debug, Serialize)]
+pub struct DehydratedDeviceKeys {
+    /// The signed identity keys of the dehydrated device.
+    pub device_keys: DeviceKeys,
+    /// Signed one-time keys so other devices can establish Olm sessions with
+    /// the dehydrated device.
+    pub one_time_keys: BTreeMap<DeviceKeyId, OneTimeKey>,
+    /// A signed fallback key, used once the one-time keys run out.
+    #[serde(rename = "org.matrix.msc2732.fallback_keys")]
+    pub fallback_keys: BTreeMap<DeviceKeyId, OneTimeKey>,
+}
+
+/// A new dehydrated device that wasn't uploaded to the homeserver yet.
+///
+/// Send the [`request`] to the homeserver first, the keys of the device can
+/// only be signed once the homeserver assigned a device id to it. After that
+/// upload the keys returned by [`keys_for_upload`].
+///
+/// [`request`]: #method.request
+/// [`keys_for_upload`]: #method.keys_for_upload
+#[derive(Clone)]
+pub struct DehydratedDevice {
+    account: ReadOnlyAccount,
+    user_identity: Arc<Mutex<PrivateCrossSigningIdentity>>,
+    one_time_keys: Vec<(String, String)>,
+    fallback_key: Vec<(String, String)>,
+    request: DehydratedDeviceRequest,
+}
+
+impl std::fmt::Debug for DehydratedDevice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DehydratedDevice")
+            .field("request", &self.request)
+            .finish()
+    }
+}
+
+impl DehydratedDevice {
+    pub(crate) async fn new(
+        user_id: &UserId,
+        user_identity: Arc<Mutex<PrivateCrossSigningIdentity>>,
+        secret: &str,
+        display_name: &str,
+        rounds: u32,
+    ) -> Self {
+        let account = ReadOnlyAccount::new(user_id, PLACEHOLDER_DEVICE_ID.into());
+
+        // The keys are part of the pickle, they need to be marked as
+        // published before the account gets pickled, otherwise the
+        // rehydrated device would upload them again.
+        let _ = account.generate_one_time_keys().await;
+        let one_time_keys = account
+            .one_time_keys()
+            .await
+            .curve25519()
+            .iter()
+            .map(|(key_id, key)| (key_id.to_owned(), key.to_owned()))
+            .collect();
+        account.generate_fallback_key().await;
+        let fallback_key = account.fallback_key().await.into_iter().collect();
+        account.mark_keys_as_published().await;
+
+        let (pickle_mode, passphrase) = new_pickle_mode(secret, rounds);
+        let pickle = account.pickle(pickle_mode).await;
+
+        Self {
+            account,
+            user_identity,
+            one_time_keys,
+            fallback_key,
+            request: DehydratedDeviceRequest {
+                device_data: DehydratedDeviceData {
+                    algorithm: DEHYDRATION_ALGORITHM.to_owned(),
+                    account: pickle.pickle.as_str().to_owned(),
+                    passphrase,
+                },
+                initial_device_display_name: display_name.to_owned(),
+            },
+        }
+    }
+
+    /// The request that uploads the dehydrated device to the homeserver.
+    pub fn request(&self) -> &DehydratedDeviceRequest {
+        &self.request
+    }
+
+    /// Get the signed keys of the dehydrated device, they need to be uploaded
+    /// using the `/keys/upload/{deviceId}` endpoint.
+    ///
+    /// # Arguments
+    ///
+    /// * `device_id` - The device id that the homeserver assigned to the
+    /// dehydrated device.
+    pub async fn keys_for_upload(&self, device_id: &DeviceId) -> DehydratedDeviceKeys {
+        let account = self.account.with_device_id(device_id);
+        let mut device_keys = account.device_keys().await;
+
+        // Sign the device with our self-signing key, if we have one, so the
+        // dehydrated device is trusted by devices that only share room keys
+        // with verified devices.
+        if let Err(e) = self
+            .user_identity
+            .lock()
+            .await
+            .sign_device_keys(&mut device_keys)
+            .await
+        {
+            debug!("Can't cross-sign the dehydrated device {:?}", e);
+        }
+
+        DehydratedDeviceKeys {
+            device_keys,
+            one_time_keys: account.signed_keys(self.one_time_keys.clone()).await,
+            fallback_keys: account.signed_keys(self.fallback_key.clone()).await,
+        }
+    }
+}
+
+/// A dehydrated device that was restored from the server.
+///
+/// The dehydrated device needs to be claimed using the
+/// `/dehydrated_device/claim` endpoint, once the homeserver confirms the
+/// claim our access token belongs to the rehydrated device and the machine
+/// that is returned by [`into_machine`] should replace the current one.
+///
+/// [`into_machine`]: #method.into_machine
+#[derive(Clone)]
+pub struct RehydratedDevice {
+    pub(crate) account: ReadOnlyAccount,
+    pub(crate) store: Arc<Box<dyn CryptoStore>>,
+    pub(crate) user_identity: PrivateCrossSigningIdentity,
+}
+
+impl std::fmt::Debug for RehydratedDevice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RehydratedDevice")
+            .field("device_id", &self.device_id())
+            .finish()
+    }
+}
+
+impl RehydratedDevice {
+    /// The device id of the rehydrated device.
+    pub fn device_id(&self) -> &DeviceId {
+        self.account.device_id()
+    }
+
+    /// Create a machine that uses the account of the rehydrated device.
+    ///
+    /// The account replaces the account in the store of the machine that
+    /// restored the device, the store keeps the rest of its data.
+    pub async fn into_machine(self) -> Result<OlmMachine, CryptoStoreError> {
+        self.store.save_account(self.account.clone()).await?;
+
+        let user_id = self.account.user_id().to_owned();
+        let device_id = self.account.device_id().into();
+
+        Ok(OlmMachine::new_helper(
+            &user_id,
+            device_id,
+            self.store,
+            self.account,
+            self.user_identity,
+        ))
+    }
+}
+
+/// Derive a pickle key from the user secret using a fresh random salt.
+fn new_pickle_mode(secret: &str, rounds: u32) -> (PicklingMode, DehydrationPassphraseInfo) {
+    let mut salt = [0u8; SALT_SIZE];
+    getrandom(&mut salt).expect("Can't generate a random salt");
+
+    let info = DehydrationPassphraseInfo {
+        salt: base64::encode_config(&salt, base64::STANDARD_NO_PAD),
+        iterations: rounds,
+    };
+
+    (pickle_mode(secret, &salt, rounds), info)
+}
+
+/// Derive the pickle key of an existing dehydrated device.
+pub(crate) fn existing_pickle_mode(
+    secret: &str,
+    device_data: &DehydratedDeviceData,
+) -> Result<PicklingMode, DehydrationError> {
+    if device_data.algorithm != DEHYDRATION_ALGORITHM {
+        return Err(DehydrationError::UnsupportedAlgorithm(
+            device_data.algorithm.clone(),
+        ));
+    }
+
+    let rounds = device_data.passphrase.iterations;
+
+    if rounds == 0 || rounds > MAX_ROUNDS {
+        return Err(DehydrationError::InvalidIterations(rounds));
+    }
+
+    let salt = base64::decode_config(&device_data.passphrase.salt, base64::STANDARD_NO_PAD)?;
+
+    Ok(pickle_mode(secret, &salt, rounds))
+}
+
+fn pickle_mode(secret: &str, salt: &[u8], rounds: u32) -> PicklingMode {
+    let mut key = Zeroizing::new(vec![0u8; KEY_SIZE]);
+    pbkdf2::<Hmac<Sha512>>(secret.as_bytes(), salt, rounds, &mut *key);
+
+    PicklingMode::Encrypted { key: key.to_vec() }
+}
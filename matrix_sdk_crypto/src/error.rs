@@ -17,7 +17,7 @@ use olm_rs::errors::{OlmGroupSessionError, OlmSessionError};
 use serde_json::Error as SerdeError;
 use thiserror::Error;
 
-use super::store::CryptoStoreError;
+use super::{olm::WithheldCode, store::CryptoStoreError};
 
 pub type OlmResult<T> = Result<T, OlmError>;
 pub type MegolmResult<T> = Result<T, MegolmError>;
@@ -82,6 +82,30 @@ pub enum MegolmError {
     #[error("decryption failed because the session to decrypt the message is missing")]
     MissingSession,
 
+    /// Decryption failed because the session to decrypt the event is missing
+    /// and our Olm session with the device that should have sent us the
+    /// session is wedged.
+    #[error(
+        "decryption failed because the session is missing, likely because an Olm session from \
+            {0} with sender key {1} was wedged"
+    )]
+    SessionWedged(UserId, String),
+
+    /// Decryption failed because the sender of the event refused to share the
+    /// session with us.
+    #[error("decryption failed because the sender withheld the session: {code} {reason:?}")]
+    Withheld {
+        /// The machine readable reason why the session was withheld.
+        code: WithheldCode,
+        /// The human readable reason why the session was withheld.
+        reason: Option<String>,
+    },
+
+    /// Decryption failed because our copy of the session doesn't contain
+    /// the message index the event was encrypted with.
+    #[error("decryption failed because the message index of the event is unknown")]
+    UnknownMessageIndex,
+
     /// The underlying group session operation returned an error.
     #[error("can't finish Olm group session operation {0}")]
     OlmGroupSession(#[from] OlmGroupSessionError),
@@ -41,7 +41,9 @@ use tracing::warn;
 
 use crate::{
     olm::{InboundGroupSession, PrivateCrossSigningIdentity, Session},
+    requests::OutgoingRequest,
     store::{Changes, DeviceChanges},
+    verification::{save_signature_upload, sign_verified},
     OutgoingVerificationRequest,
 };
 #[cfg(test)]
@@ -166,6 +168,53 @@ impl Device {
             .trust_state(&self.own_identity, &self.device_owner_identity)
     }
 
+    /// Is this device cross-signed by its owner.
+    ///
+    /// Unlike `trust_state()` this doesn't require us to have verified the
+    /// owner of the device, it only checks that the current self-signing key
+    /// of the owner signed the device.
+    pub fn is_cross_signed_by_owner(&self) -> bool {
+        self.device_owner_identity
+            .as_ref()
+            .map_or(false, |identity| match identity {
+                UserIdentities::Own(i) => i.is_device_signed(&self.inner).is_ok(),
+                UserIdentities::Other(i) => i.is_device_signed(&self.inner).is_ok(),
+            })
+    }
+
+    /// Get the cross-signing identity of the owner of this device, if the
+    /// owner has set up cross-signing.
+    pub fn owner_identity(&self) -> Option<&UserIdentities> {
+        self.device_owner_identity.as_ref()
+    }
+
+    /// Mark the device as verified after it was verified out of band, e.g. by
+    /// comparing the fingerprint of its ed25519 key.
+    ///
+    /// This marks the device as locally verified and, if it's one of our own
+    /// devices, signs it with our self signing key, the same way an
+    /// interactive verification would.
+    ///
+    /// Returns a signature upload request if there are signatures that need
+    /// to be uploaded. The request is stored and returned by
+    /// `OlmMachine::outgoing_requests()` as well until it's marked as sent.
+    pub async fn verify(&self) -> StoreResult<Option<OutgoingRequest>> {
+        let request = {
+            let private_identity = self.private_identity.lock().await;
+            sign_verified(&private_identity, Some(&self.inner), None).await
+        };
+
+        let request = if let Some(r) = request {
+            Some(save_signature_upload(&**self.verification_machine.store, r).await?)
+        } else {
+            None
+        };
+
+        self.set_local_trust(LocalTrust::Verified).await?;
+
+        Ok(request)
+    }
+
     /// Set the local trust state of the device to the given state.
     ///
     /// This won't affect any cross signing trust state, this only sets a flag
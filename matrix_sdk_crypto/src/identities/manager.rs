@@ -16,7 +16,8 @@ use futures::future::join_all;
 use std::{
     collections::{BTreeMap, HashSet},
     convert::TryFrom,
-    sync::Arc,
+    fmt,
+    sync::{Arc, RwLock},
 };
 use tracing::{trace, warn};
 
@@ -30,8 +31,8 @@ use matrix_sdk_common::{
 use crate::{
     error::OlmResult,
     identities::{
-        MasterPubkey, OwnUserIdentity, ReadOnlyDevice, SelfSigningPubkey, UserIdentities,
-        UserIdentity, UserSigningPubkey,
+        IdentityChange, IdentityChangeCallback, MasterPubkey, OwnUserIdentity, ReadOnlyDevice,
+        SelfSigningPubkey, UserIdentities, UserIdentity, UserSigningPubkey,
     },
     requests::KeysQueryRequest,
     store::{Changes, DeviceChanges, IdentityChanges, Result as StoreResult, Store},
@@ -43,11 +44,22 @@ enum DeviceChange {
     None,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub(crate) struct IdentityManager {
     user_id: Arc<UserId>,
     device_id: Arc<DeviceIdBox>,
     store: Store,
+    identity_change_callback: Arc<RwLock<Option<IdentityChangeCallback>>>,
+}
+
+impl fmt::Debug for IdentityManager {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IdentityManager")
+            .field("user_id", &self.user_id)
+            .field("device_id", &self.device_id)
+            .field("store", &self.store)
+            .finish()
+    }
 }
 
 impl IdentityManager {
@@ -58,9 +70,16 @@ impl IdentityManager {
             user_id,
             device_id,
             store,
+            identity_change_callback: Arc::new(RwLock::new(None)),
         }
     }
 
+    /// Set the callback that gets notified about newly detected identity
+    /// changes, `None` removes the current callback.
+    pub fn set_identity_change_callback(&self, callback: Option<IdentityChangeCallback>) {
+        *self.identity_change_callback.write().unwrap() = callback;
+    }
+
     fn user_id(&self) -> &UserId {
         &self.user_id
     }
@@ -261,7 +280,14 @@ impl IdentityManager {
                 continue;
             };
 
-            let result = if let Some(mut i) = self.store.get_user_identity(user_id).await? {
+            let previous = self.store.get_user_identity(user_id).await?;
+            let was_verified = if let Some(i) = &previous {
+                self.is_identity_verified(i).await?
+            } else {
+                false
+            };
+
+            let result = if let Some(mut i) = previous {
                 match &mut i {
                     UserIdentities::Own(ref mut identity) => {
                         let user_signing = if let Some(s) = response.user_signing_keys.get(user_id)
@@ -327,6 +353,15 @@ impl IdentityManager {
                         user_id,
                         i
                     );
+
+                    if let Some(change) = self.check_master_key(&i, was_verified).await? {
+                        let callback = self.identity_change_callback.read().unwrap().clone();
+
+                        if let Some(callback) = callback {
+                            callback(&change);
+                        }
+                    }
+
                     if new {
                         changes.new.push(i);
                     } else {
@@ -346,6 +381,82 @@ impl IdentityManager {
         Ok(changes)
     }
 
+    /// Check if we verified the given identity.
+    async fn is_identity_verified(&self, identity: &UserIdentities) -> StoreResult<bool> {
+        Ok(match identity {
+            UserIdentities::Own(i) => i.is_verified(),
+            UserIdentities::Other(i) => self
+                .store
+                .get_user_identity(self.user_id())
+                .await?
+                .and_then(|o| o.own().map(|o| o.is_identity_signed(i).is_ok()))
+                .unwrap_or(false),
+        })
+    }
+
+    /// Pin the master key of the given identity if it's the first one we see
+    /// for the user, otherwise record a change if it differs from the pinned
+    /// master key.
+    ///
+    /// # Arguments
+    ///
+    /// * `identity` - The identity that was received in a keys query.
+    ///
+    /// * `was_verified` - Did we verify the identity before it got updated.
+    ///
+    /// Returns the identity change if a new one was detected.
+    async fn check_master_key(
+        &self,
+        identity: &UserIdentities,
+        was_verified: bool,
+    ) -> StoreResult<Option<IdentityChange>> {
+        let user_id = identity.user_id();
+        let master_key = identity.master_key().keys();
+
+        let pinned = if let Some(k) = self.store.get_pinned_master_key(user_id).await? {
+            k
+        } else {
+            self.store.pin_master_key(user_id, master_key).await?;
+            return Ok(None);
+        };
+
+        let pending = self.store.get_identity_change(user_id).await?;
+
+        if &pinned == master_key {
+            // The user went back to the pinned identity, there's nothing to
+            // acknowledge anymore.
+            if pending.is_some() {
+                self.store.remove_identity_change(user_id).await?;
+            }
+
+            Ok(None)
+        } else if pending
+            .as_ref()
+            .map_or(false, |c| &c.new_master_key == master_key)
+        {
+            Ok(None)
+        } else {
+            let change = IdentityChange {
+                user_id: user_id.clone(),
+                pinned_master_key: pinned,
+                new_master_key: master_key.clone(),
+                // If the identity changed multiple times in a row we care
+                // about the identity that was pinned.
+                was_verified: pending.map_or(was_verified, |c| c.was_verified),
+            };
+
+            warn!(
+                "The cross signing identity of {} has changed, the previous \
+                 identity was verified: {}",
+                user_id, change.was_verified
+            );
+
+            self.store.save_identity_change(&change).await?;
+
+            Ok(Some(change))
+        }
+    }
+
     /// Get a key query request if one is needed.
     ///
     /// Returns a key query reqeust if the client should query E2E keys,
@@ -416,8 +527,9 @@ impl IdentityManager {
 
 #[cfg(test)]
 pub(crate) mod test {
-    use std::sync::Arc;
+    use std::sync::{Arc, Mutex as StdMutex};
 
+    use futures::future::join_all;
     use matrix_sdk_common::{
         api::r0::keys::get_keys::Response as KeyQueryResponse,
         identifiers::{user_id, DeviceIdBox, UserId},
@@ -430,7 +542,7 @@ pub(crate) mod test {
     use serde_json::json;
 
     use crate::{
-        identities::IdentityManager,
+        identities::{IdentityChange, IdentityManager, UserIdentities, UserIdentity},
         machine::test::response_from_file,
         olm::{PrivateCrossSigningIdentity, ReadOnlyAccount},
         store::{CryptoStore, MemoryStore, Store},
@@ -693,4 +805,165 @@ pub(crate) mod test {
 
         assert!(identity.is_device_signed(&device).is_ok())
     }
+
+    #[async_test]
+    async fn test_identity_change_detection() {
+        let manager = manager();
+        let other_user = other_user_id();
+
+        manager
+            .receive_keys_query_response(&other_key_query())
+            .await
+            .unwrap();
+
+        let identity = manager
+            .store
+            .get_user_identity(&other_user)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let pinned = manager
+            .store
+            .get_pinned_master_key(&other_user)
+            .await
+            .unwrap();
+
+        assert_eq!(pinned.as_ref(), Some(identity.master_key().keys()));
+        assert!(manager
+            .store
+            .get_identity_changes()
+            .await
+            .unwrap()
+            .is_empty());
+
+        let private_identity = PrivateCrossSigningIdentity::new(other_user.clone()).await;
+        let new_identity =
+            UserIdentities::Other(UserIdentity::from_private(&private_identity).await);
+
+        let change = manager
+            .check_master_key(&new_identity, false)
+            .await
+            .unwrap()
+            .expect("The identity change wasn't detected");
+
+        assert_eq!(&change.new_master_key, new_identity.master_key().keys());
+        assert!(!change.was_verified);
+
+        // The same change isn't reported twice.
+        assert!(manager
+            .check_master_key(&new_identity, false)
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            manager.store.get_identity_changes().await.unwrap(),
+            vec![change]
+        );
+
+        // Going back to the pinned identity removes the change.
+        assert!(manager
+            .check_master_key(&identity, false)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(manager
+            .store
+            .get_identity_changes()
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[async_test]
+    async fn test_concurrent_identity_changes() {
+        let manager = manager();
+        let users = vec![other_user_id(), user_id!("@example3:localhost")];
+
+        let mut identities = Vec::new();
+
+        for user in &users {
+            let pinned = PrivateCrossSigningIdentity::new(user.clone()).await;
+            let pinned = UserIdentities::Other(UserIdentity::from_private(&pinned).await);
+            manager.check_master_key(&pinned, false).await.unwrap();
+
+            let changed = PrivateCrossSigningIdentity::new(user.clone()).await;
+            identities.push(UserIdentities::Other(
+                UserIdentity::from_private(&changed).await,
+            ));
+        }
+
+        // Changes of different users that are detected at the same time are
+        // all kept.
+        let changes = join_all(
+            identities
+                .iter()
+                .map(|i| manager.check_master_key(i, false)),
+        )
+        .await;
+        assert!(changes.iter().all(|c| c.as_ref().unwrap().is_some()));
+
+        let mut changed_users: Vec<UserId> = manager
+            .store
+            .get_identity_changes()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|c| c.user_id)
+            .collect();
+        changed_users.sort();
+
+        assert_eq!(changed_users, users);
+    }
+
+    #[async_test]
+    async fn test_identity_change_callback() {
+        let manager = manager();
+        let other_user = other_user_id();
+
+        let reported = Arc::new(StdMutex::new(Vec::new()));
+        let callback_reported = reported.clone();
+        manager.set_identity_change_callback(Some(Arc::new(move |change: &IdentityChange| {
+            callback_reported.lock().unwrap().push(change.clone())
+        })));
+
+        // The first identity we see gets pinned, this isn't a change.
+        manager
+            .receive_keys_query_response(&other_key_query())
+            .await
+            .unwrap();
+        assert!(reported.lock().unwrap().is_empty());
+
+        let private_identity = PrivateCrossSigningIdentity::new(other_user.clone()).await;
+        let keys = private_identity.as_upload_request().await;
+        let data = response_from_file(&json!({
+            "device_keys": {},
+            "failures": {},
+            "master_keys": {
+                other_user.as_str(): keys.master_key,
+            },
+            "self_signing_keys": {
+                other_user.as_str(): keys.self_signing_key,
+            },
+            "user_signing_keys": {}
+        }));
+        let response = KeyQueryResponse::try_from_http_response(data)
+            .expect("Can't parse the keys query response");
+
+        manager
+            .receive_keys_query_response(&response)
+            .await
+            .unwrap();
+
+        let changes = manager.store.get_identity_changes().await.unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(*reported.lock().unwrap(), changes);
+
+        // Receiving the same identity again doesn't report the change again.
+        manager
+            .receive_keys_query_response(&response)
+            .await
+            .unwrap();
+        assert_eq!(reported.lock().unwrap().len(), 1);
+    }
 }
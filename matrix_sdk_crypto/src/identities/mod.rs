@@ -47,8 +47,8 @@ pub(crate) mod user;
 pub use device::{Device, LocalTrust, ReadOnlyDevice, UserDevices};
 pub(crate) use manager::IdentityManager;
 pub use user::{
-    MasterPubkey, OwnUserIdentity, SelfSigningPubkey, UserIdentities, UserIdentity,
-    UserSigningPubkey,
+    IdentityChange, IdentityChangeCallback, MasterPubkey, OwnUserIdentity, SelfSigningPubkey,
+    UserIdentities, UserIdentity, UserSigningPubkey,
 };
 
 use serde::{Deserialize, Deserializer, Serializer};
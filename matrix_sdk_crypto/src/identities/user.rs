@@ -445,6 +445,32 @@ impl PartialEq for UserIdentities {
     }
 }
 
+/// A change of the cross signing identity of a user that wasn't acknowledged
+/// yet.
+///
+/// The first master key we see for a user gets pinned, if a keys query later
+/// returns a different master key the change is recorded until it gets
+/// acknowledged using `OlmMachine::acknowledge_identity_change()`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct IdentityChange {
+    /// The user whose identity changed.
+    pub user_id: UserId,
+    /// The public keys of the master key that we pinned for the user.
+    pub pinned_master_key: BTreeMap<String, String>,
+    /// The public keys of the master key that replaced the pinned one.
+    pub new_master_key: BTreeMap<String, String>,
+    /// Did we verify the identity that got replaced.
+    pub was_verified: bool,
+}
+
+/// A callback that is notified about newly detected changes of cross signing
+/// identities.
+///
+/// The callback is called while a keys query response is processed, every
+/// change is only reported once, until the user switches to yet another
+/// master key.
+pub type IdentityChangeCallback = Arc<dyn Fn(&IdentityChange) + Send + Sync>;
+
 /// Struct representing a cross signing identity of a user.
 ///
 /// This is the user identity of a user that isn't our own. Other users will
@@ -23,7 +23,10 @@
 use dashmap::{mapref::entry::Entry, DashMap, DashSet};
 use serde::{Deserialize, Serialize};
 use serde_json::value::to_raw_value;
-use std::{collections::BTreeMap, sync::Arc};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, Mutex, RwLock},
+};
 use thiserror::Error;
 use tracing::{error, info, trace, warn};
 
@@ -57,9 +60,129 @@ pub enum KeyshareDecision {
     /// meant to receive the session in the original key share.
     #[error("outbound session wasn't shared with the requesting device")]
     OutboundSessionNotShared,
-    /// The key request is from a device we own, yet we don't trust it.
+    /// The requesting device isn't trusted.
     #[error("requesting device isn't trusted")]
     UntrustedDevice,
+    /// The key request is from a device that we don't own and the key share
+    /// policy only allows sharing with our own devices.
+    #[error("requesting device doesn't belong to us")]
+    NotOwnDevice,
+    /// The key share policy doesn't allow sharing room keys at all.
+    #[error("key sharing is disabled")]
+    SharingDisabled,
+    /// A custom key share policy rejected the key request.
+    #[error("rejected by the key share policy: {0}")]
+    RejectedByPolicy(String),
+}
+
+/// The maximum number of key share decisions that are kept around.
+const MAX_KEY_SHARE_DECISIONS: usize = 100;
+
+/// Information about an incoming room key request, passed to a custom
+/// [`KeySharePolicy`].
+#[derive(Clone, Debug)]
+pub struct KeyShareRequestInfo {
+    /// The device that is requesting the room key.
+    pub device: Device,
+    /// The room the requested session is used in.
+    pub room_id: RoomId,
+    /// The id of the requested session.
+    pub session_id: String,
+    /// The curve25519 key of the device that created the session.
+    pub sender_key: String,
+    /// The first message index we know of the requested session.
+    pub first_known_index: u32,
+    /// The devices we originally shared the session with, together with the
+    /// message index they received it at.
+    ///
+    /// This is `None` if we didn't create the session or if our outbound
+    /// session has been rotated since.
+    pub recipients: Option<BTreeMap<UserId, BTreeMap<DeviceIdBox, u32>>>,
+}
+
+impl KeyShareRequestInfo {
+    /// The message index the requesting device originally received the
+    /// session at, if it was one of the recipients.
+    pub fn shared_index(&self) -> Option<u32> {
+        self.recipients
+            .as_ref()?
+            .get(self.device.user_id())?
+            .get(self.device.device_id())
+            .copied()
+    }
+}
+
+/// A callback deciding if a room key request should be answered.
+///
+/// Returning an error rejects the request, the error is logged and recorded
+/// as the reason of the decision.
+pub type KeyShareCallback =
+    Arc<dyn Fn(&KeyShareRequestInfo) -> Result<(), KeyshareDecision> + Send + Sync>;
+
+/// Policy deciding which incoming room key requests get answered.
+#[derive(Clone)]
+pub enum KeySharePolicy {
+    /// Share sessions with our own verified devices, and with the devices of
+    /// other users that we originally shared the session with. This is the
+    /// default.
+    OwnVerifiedDevicesAndRecipients,
+    /// Only share sessions with our own verified devices.
+    OwnVerifiedDevices,
+    /// Share sessions with our own verified devices, and with the verified
+    /// devices of other users that we originally shared the session with.
+    VerifiedDevices,
+    /// Never answer room key requests.
+    Never,
+    /// Let a callback decide.
+    ///
+    /// If the callback accepts a request from a device we originally shared
+    /// the session with, the session is shared starting from the message
+    /// index the device originally received, otherwise starting from the
+    /// first known index.
+    Custom(KeyShareCallback),
+}
+
+impl Default for KeySharePolicy {
+    fn default() -> Self {
+        KeySharePolicy::OwnVerifiedDevicesAndRecipients
+    }
+}
+
+impl std::fmt::Debug for KeySharePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeySharePolicy::OwnVerifiedDevicesAndRecipients => {
+                f.write_str("OwnVerifiedDevicesAndRecipients")
+            }
+            KeySharePolicy::OwnVerifiedDevices => f.write_str("OwnVerifiedDevices"),
+            KeySharePolicy::VerifiedDevices => f.write_str("VerifiedDevices"),
+            KeySharePolicy::Never => f.write_str("Never"),
+            KeySharePolicy::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+/// A record of a decision we made about an incoming room key request.
+#[derive(Clone, Debug)]
+pub struct KeyShareDecisionRecord {
+    /// The user that requested the room key.
+    pub user_id: UserId,
+    /// The device that requested the room key.
+    pub device_id: DeviceIdBox,
+    /// The room the requested session is used in.
+    pub room_id: RoomId,
+    /// The id of the requested session.
+    pub session_id: String,
+    /// The message index the session was shared at, or the reason why it
+    /// wasn't shared.
+    pub decision: Result<Option<u32>, KeyshareDecision>,
+}
+
+impl KeyShareDecisionRecord {
+    /// Did we decide to share the session with the requesting device.
+    pub fn shared(&self) -> bool {
+        self.decision.is_ok()
+    }
 }
 
 /// A queue where we store room key requests that we want to serve but the
@@ -135,6 +258,8 @@ pub(crate) struct KeyRequestMachine {
     >,
     wait_queue: WaitQueue,
     users_for_key_claim: Arc<DashMap<UserId, DashSet<DeviceIdBox>>>,
+    key_share_policy: Arc<RwLock<KeySharePolicy>>,
+    key_share_decisions: Arc<Mutex<VecDeque<KeyShareDecisionRecord>>>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -208,7 +333,40 @@ impl KeyRequestMachine {
             incoming_key_requests: Arc::new(DashMap::new()),
             wait_queue: WaitQueue::new(),
             users_for_key_claim,
+            key_share_policy: Arc::new(RwLock::new(KeySharePolicy::default())),
+            key_share_decisions: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    /// The policy deciding which incoming room key requests get answered.
+    pub fn key_share_policy(&self) -> KeySharePolicy {
+        self.key_share_policy.read().unwrap().clone()
+    }
+
+    /// Set the policy deciding which incoming room key requests get answered.
+    pub fn set_key_share_policy(&self, policy: KeySharePolicy) {
+        *self.key_share_policy.write().unwrap() = policy;
+    }
+
+    /// The most recent decisions we made about incoming room key requests,
+    /// oldest first.
+    pub fn key_share_decisions(&self) -> Vec<KeyShareDecisionRecord> {
+        self.key_share_decisions
+            .lock()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    fn record_key_share_decision(&self, record: KeyShareDecisionRecord) {
+        let mut decisions = self.key_share_decisions.lock().unwrap();
+
+        if decisions.len() >= MAX_KEY_SHARE_DECISIONS {
+            decisions.pop_front();
         }
+
+        decisions.push_back(record);
     }
 
     /// Our own user id.
@@ -363,17 +521,28 @@ impl KeyRequestMachine {
             .await?;
 
         if let Some(device) = device {
-            match self.should_share_session(
-                &device,
-                self.outbound_group_sessions
-                    .get(&key_info.room_id)
-                    .as_deref(),
-            ) {
+            let outbound_session = self
+                .outbound_group_sessions
+                .get(&key_info.room_id)
+                .filter(|o| o.session_id() == key_info.session_id)
+                .map(|o| o.clone());
+            let decision = self.decide_key_share(&device, &session, outbound_session.as_ref());
+
+            self.record_key_share_decision(KeyShareDecisionRecord {
+                user_id: device.user_id().to_owned(),
+                device_id: device.device_id().into(),
+                room_id: key_info.room_id.clone(),
+                session_id: key_info.session_id.clone(),
+                decision: decision.clone(),
+            });
+
+            match decision {
                 Err(e) => {
                     info!(
-                        "Received a key request from {} {} that we won't serve: {}",
+                        "Received a key request from {} {} for {} that we won't serve: {}",
                         device.user_id(),
                         device.device_id(),
+                        key_info.session_id,
                         e
                     );
 
@@ -496,6 +665,71 @@ impl KeyRequestMachine {
         }
     }
 
+    /// Decide if a session should be shared with the given device using the
+    /// current key share policy.
+    ///
+    /// # Arguments
+    ///
+    /// * `device` - The device that is requesting a session from us.
+    ///
+    /// * `session` - The inbound group session that is being requested.
+    ///
+    /// * `outbound_session` - If one still exists, the matching outbound
+    /// session that was used to create the inbound session that is being
+    /// requested.
+    fn decide_key_share(
+        &self,
+        device: &Device,
+        session: &InboundGroupSession,
+        outbound_session: Option<&OutboundGroupSession>,
+    ) -> Result<Option<u32>, KeyshareDecision> {
+        match self.key_share_policy() {
+            KeySharePolicy::OwnVerifiedDevicesAndRecipients => {
+                self.should_share_session(device, outbound_session)
+            }
+            KeySharePolicy::OwnVerifiedDevices => {
+                if device.user_id() == self.user_id() {
+                    self.should_share_session(device, None)
+                } else {
+                    Err(KeyshareDecision::NotOwnDevice)
+                }
+            }
+            KeySharePolicy::VerifiedDevices => {
+                if device.trust_state() {
+                    self.should_share_session(device, outbound_session)
+                } else {
+                    Err(KeyshareDecision::UntrustedDevice)
+                }
+            }
+            KeySharePolicy::Never => Err(KeyshareDecision::SharingDisabled),
+            KeySharePolicy::Custom(callback) => {
+                let info = KeyShareRequestInfo {
+                    device: device.clone(),
+                    room_id: session.room_id().clone(),
+                    session_id: session.session_id().to_owned(),
+                    sender_key: session.sender_key().to_owned(),
+                    first_known_index: session.first_known_index(),
+                    recipients: outbound_session.map(|o| {
+                        o.shared_with_set
+                            .iter()
+                            .map(|u| {
+                                (
+                                    u.key().clone(),
+                                    u.value()
+                                        .iter()
+                                        .map(|d| (d.key().clone(), *d.value()))
+                                        .collect(),
+                                )
+                            })
+                            .collect()
+                    }),
+                };
+
+                callback(&info).map(|_| info.shared_index())
+            }
+        }
+    }
+
     /// Create a new outgoing key request for the key with the given session id.
     ///
     /// This will queue up a new to-device request and store the key info so
@@ -726,7 +960,7 @@ mod test {
         verification::VerificationMachine,
     };
 
-    use super::{KeyRequestMachine, KeyshareDecision};
+    use super::{KeyRequestMachine, KeySharePolicy, KeyShareRequestInfo, KeyshareDecision};
 
     fn alice_id() -> UserId {
         user_id!("@alice:example.org")
@@ -1027,6 +1261,161 @@ mod test {
             .is_ok());
     }
 
+    #[async_test]
+    async fn key_share_policy_test() {
+        let machine = get_machine().await;
+        let account = account();
+
+        let own_device = machine
+            .store
+            .get_device(&alice_id(), &alice_device_id())
+            .await
+            .unwrap()
+            .unwrap();
+        own_device.set_trust_state(LocalTrust::Verified);
+
+        let bob_device = ReadOnlyDevice::from_account(&bob_account()).await;
+        machine.store.save_devices(&[bob_device]).await.unwrap();
+
+        let bob_device = machine
+            .store
+            .get_device(&bob_id(), &bob_device_id())
+            .await
+            .unwrap()
+            .unwrap();
+
+        let (outbound, inbound) = account
+            .create_group_session_pair_with_defaults(&room_id())
+            .await
+            .unwrap();
+        outbound.mark_shared_with(bob_device.user_id(), bob_device.device_id());
+
+        // By default we share with the recipients of the session, even if
+        // they aren't verified.
+        assert_eq!(
+            machine.decide_key_share(&bob_device, &inbound, Some(&outbound)),
+            Ok(Some(0))
+        );
+
+        machine.set_key_share_policy(KeySharePolicy::VerifiedDevices);
+        assert_eq!(
+            machine.decide_key_share(&bob_device, &inbound, Some(&outbound)),
+            Err(KeyshareDecision::UntrustedDevice)
+        );
+        bob_device.set_trust_state(LocalTrust::Verified);
+        assert_eq!(
+            machine.decide_key_share(&bob_device, &inbound, Some(&outbound)),
+            Ok(Some(0))
+        );
+
+        machine.set_key_share_policy(KeySharePolicy::OwnVerifiedDevices);
+        assert_eq!(
+            machine.decide_key_share(&bob_device, &inbound, Some(&outbound)),
+            Err(KeyshareDecision::NotOwnDevice)
+        );
+        assert_eq!(
+            machine.decide_key_share(&own_device, &inbound, Some(&outbound)),
+            Ok(None)
+        );
+
+        machine.set_key_share_policy(KeySharePolicy::Never);
+        assert_eq!(
+            machine.decide_key_share(&own_device, &inbound, Some(&outbound)),
+            Err(KeyshareDecision::SharingDisabled)
+        );
+
+        // A custom policy gets the session metadata, here we only share with
+        // the original recipients of the session.
+        machine.set_key_share_policy(KeySharePolicy::Custom(Arc::new(
+            |info: &KeyShareRequestInfo| {
+                assert_eq!(info.room_id, room_id());
+                assert_eq!(info.first_known_index, 0);
+
+                if info.shared_index().is_some() {
+                    Ok(())
+                } else {
+                    Err(KeyshareDecision::RejectedByPolicy(
+                        "not a recipient".to_owned(),
+                    ))
+                }
+            },
+        )));
+        assert_eq!(
+            machine.decide_key_share(&bob_device, &inbound, Some(&outbound)),
+            Ok(Some(0))
+        );
+        assert_eq!(
+            machine.decide_key_share(&own_device, &inbound, Some(&outbound)),
+            Err(KeyshareDecision::RejectedByPolicy(
+                "not a recipient".to_owned()
+            ))
+        );
+        assert_eq!(
+            machine.decide_key_share(&bob_device, &inbound, None),
+            Err(KeyshareDecision::RejectedByPolicy(
+                "not a recipient".to_owned()
+            ))
+        );
+    }
+
+    #[async_test]
+    async fn own_verified_devices_key_share_policy() {
+        let machine = get_machine().await;
+        let account = account();
+
+        assert!(matches!(
+            machine.key_share_policy(),
+            KeySharePolicy::OwnVerifiedDevicesAndRecipients
+        ));
+        machine.set_key_share_policy(KeySharePolicy::OwnVerifiedDevices);
+
+        let own_device = machine
+            .store
+            .get_device(&alice_id(), &alice_device_id())
+            .await
+            .unwrap()
+            .unwrap();
+
+        let bob_device = ReadOnlyDevice::from_account(&bob_account()).await;
+        machine.store.save_devices(&[bob_device]).await.unwrap();
+
+        let bob_device = machine
+            .store
+            .get_device(&bob_id(), &bob_device_id())
+            .await
+            .unwrap()
+            .unwrap();
+
+        let (outbound, inbound) = account
+            .create_group_session_pair_with_defaults(&room_id())
+            .await
+            .unwrap();
+        outbound.mark_shared_with(bob_device.user_id(), bob_device.device_id());
+
+        // Our own devices still need to be verified.
+        assert_eq!(
+            machine.decide_key_share(&own_device, &inbound, Some(&outbound)),
+            Err(KeyshareDecision::UntrustedDevice)
+        );
+        own_device.set_trust_state(LocalTrust::Verified);
+        assert_eq!(
+            machine.decide_key_share(&own_device, &inbound, Some(&outbound)),
+            Ok(None)
+        );
+
+        // Other users never get the session, not even the original recipients
+        // and not even if their device is verified.
+        assert_eq!(
+            machine.decide_key_share(&bob_device, &inbound, Some(&outbound)),
+            Err(KeyshareDecision::NotOwnDevice)
+        );
+        bob_device.set_trust_state(LocalTrust::Verified);
+        assert_eq!(
+            machine.decide_key_share(&bob_device, &inbound, Some(&outbound)),
+            Err(KeyshareDecision::NotOwnDevice)
+        );
+    }
+
     #[async_test]
     async fn key_share_cycle() {
         let alice_machine = get_machine().await;
@@ -1133,6 +1522,13 @@ mod test {
         // Now bob does have an outgoing request.
         assert!(!bob_machine.outgoing_to_device_requests.is_empty());
 
+        // The decision to share the session was recorded.
+        let decisions = bob_machine.key_share_decisions();
+        assert_eq!(decisions.len(), 1);
+        assert_eq!(decisions[0].user_id, alice_id());
+        assert_eq!(decisions[0].session_id, group_session.session_id());
+        assert!(decisions[0].shared());
+
         // Get the request and convert it to a encrypted to-device event.
         let request = bob_machine
             .outgoing_to_device_requests
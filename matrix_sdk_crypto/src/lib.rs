@@ -27,6 +27,7 @@
 )]
 #![cfg_attr(feature = "docs", feature(doc_cfg))]
 
+mod dehydrated_device;
 mod error;
 mod file_encryption;
 mod identities;
@@ -39,20 +40,29 @@ pub mod store;
 mod utilities;
 mod verification;
 
+pub use dehydrated_device::{
+    DehydratedDevice, DehydratedDeviceData, DehydratedDeviceKeys, DehydratedDeviceRequest,
+    DehydrationError, DehydrationPassphraseInfo, RehydratedDevice, DEHYDRATION_ALGORITHM,
+};
 pub use error::{MegolmError, OlmError};
 pub use file_encryption::{
     decrypt_key_export, encrypt_key_export, AttachmentDecryptor, AttachmentEncryptor,
     DecryptorError, EncryptionInfo, KeyExportError,
 };
 pub use identities::{
-    Device, LocalTrust, OwnUserIdentity, ReadOnlyDevice, UserDevices, UserIdentities, UserIdentity,
+    Device, IdentityChange, IdentityChangeCallback, LocalTrust, OwnUserIdentity, ReadOnlyDevice,
+    UserDevices, UserIdentities, UserIdentity,
+};
+pub use key_request::{
+    KeyShareCallback, KeyShareDecisionRecord, KeySharePolicy, KeyShareRequestInfo, KeyshareDecision,
 };
 pub use machine::OlmMachine;
-pub use olm::EncryptionSettings;
 pub(crate) use olm::ReadOnlyAccount;
+pub use olm::{EncryptionSettings, ShareStrategy};
 pub use requests::{
-    IncomingResponse, KeysQueryRequest, OutgoingRequest, OutgoingRequests,
-    OutgoingVerificationRequest, RoomMessageRequest, ToDeviceRequest,
+    EncryptedToDeviceRequests, IncomingResponse, KeysQueryRequest, KeysUploadRequest,
+    OutgoingRequest, OutgoingRequests, OutgoingVerificationRequest, RoomMessageRequest, SkipReason,
+    SkippedDevice, ToDeviceRequest,
 };
 pub use store::CryptoStoreError;
 pub use verification::{AcceptSettings, Sas, VerificationRequest};
@@ -17,6 +17,9 @@ use std::path::Path;
 use std::{collections::BTreeMap, mem, sync::Arc};
 
 use dashmap::DashMap;
+use olm_rs::errors::OlmGroupSessionError;
+use serde::Deserialize;
+use serde_json::{value::RawValue as RawJsonValue, Value};
 use tracing::{debug, error, info, trace, warn};
 
 use matrix_sdk_common::{
@@ -29,12 +32,12 @@ use matrix_sdk_common::{
         },
         sync::sync_events::{DeviceLists, ToDevice as RumaToDevice},
     },
-    assign,
-    deserialized_responses::ToDevice,
+    deserialized_responses::{
+        AlgorithmInfo, EventEncryptionInfo, SyncRoomEvent, ToDevice, VerificationState,
+    },
     events::{
         room::encrypted::EncryptedEventContent, room_key::RoomKeyEventContent,
-        AnyMessageEventContent, AnySyncRoomEvent, AnyToDeviceEvent, SyncMessageEvent,
-        ToDeviceEvent,
+        AnyMessageEventContent, AnyToDeviceEvent, EventType, SyncMessageEvent, ToDeviceEvent,
     },
     identifiers::{
         DeviceId, DeviceIdBox, DeviceKeyAlgorithm, EventEncryptionAlgorithm, EventId, RoomId,
@@ -42,30 +45,69 @@ use matrix_sdk_common::{
     },
     locks::Mutex,
     uuid::Uuid,
-    Raw, UInt,
+    UInt,
 };
 
 #[cfg(feature = "sled_cryptostore")]
 use crate::store::sled::SledStore;
 use crate::{
+    dehydrated_device::{
+        existing_pickle_mode, DehydratedDevice, DehydratedDeviceData, DehydrationError,
+        RehydratedDevice,
+    },
     error::{EventError, MegolmError, MegolmResult, OlmError, OlmResult},
-    identities::{Device, IdentityManager, UserDevices},
-    key_request::KeyRequestMachine,
+    identities::{
+        Device, IdentityChange, IdentityChangeCallback, IdentityManager, UserDevices,
+        UserIdentities,
+    },
+    key_request::{KeyRequestMachine, KeyShareDecisionRecord, KeySharePolicy},
     olm::{
         Account, EncryptionSettings, ExportedRoomKey, GroupSessionKey, IdentityKeys,
-        InboundGroupSession, OlmDecryptionInfo, PrivateCrossSigningIdentity, ReadOnlyAccount,
-        SessionType,
+        InboundGroupSession, OlmDecryptionInfo, PickledAccount, PrivateCrossSigningIdentity,
+        ReadOnlyAccount, RoomKeyWithheldContent, SessionType, ShareStrategy,
+        ROOM_KEY_WITHHELD_EVENT_TYPE, UNSTABLE_ROOM_KEY_WITHHELD_EVENT_TYPE,
+    },
+    requests::{
+        EncryptedToDeviceRequests, IncomingResponse, KeysUploadRequest, OutgoingRequest,
+        SkipReason, SkippedDevice, UploadSigningKeysRequest,
     },
-    requests::{IncomingResponse, OutgoingRequest, UploadSigningKeysRequest},
     session_manager::{GroupSessionManager, SessionManager},
     store::{
         Changes, CryptoStore, DeviceChanges, IdentityChanges, MemoryStore, Result as StoreResult,
         Store,
     },
-    verification::{Sas, VerificationMachine, VerificationRequest},
+    verification::{
+        save_signature_upload, sign_verified, Sas, VerificationMachine, VerificationRequest,
+    },
     ToDeviceRequest,
 };
 
+/// A `m.room_key.withheld` to-device event, ruma doesn't model those events
+/// yet.
+#[derive(Deserialize)]
+struct RoomKeyWithheldEvent {
+    sender: UserId,
+    content: RoomKeyWithheldContent,
+}
+
+/// The key under which a `m.room_key.withheld` notice is stored.
+fn withheld_key(sender_key: &str, room_id: Option<&RoomId>, session_id: Option<&str>) -> String {
+    match (room_id, session_id) {
+        (Some(room_id), Some(session_id)) => {
+            format!("withheld|{}|{}|{}", sender_key, room_id, session_id)
+        }
+        _ => format!("withheld|{}", sender_key),
+    }
+}
+
+/// The key under which the global share strategy is stored.
+const SHARE_STRATEGY_KEY: &str = "share_strategy";
+
+/// The key under which the share strategy of a single room is stored.
+fn room_share_strategy_key(room_id: &RoomId) -> String {
+    format!("{}|{}", SHARE_STRATEGY_KEY, room_id)
+}
+
 /// State machine implementation of the Olm/Megolm encryption protocol used for
 /// Matrix end to end encryption.
 #[derive(Clone)]
@@ -123,7 +165,7 @@ impl OlmMachine {
     ///
     /// * `device_id` - The unique id of the device that owns this machine.
     pub fn new(user_id: &UserId, device_id: &DeviceId) -> Self {
-        let store: Box<dyn CryptoStore> = Box::new(MemoryStore::new());
+        let store: Arc<Box<dyn CryptoStore>> = Arc::new(Box::new(MemoryStore::new()));
         let device_id: DeviceIdBox = device_id.into();
         let account = ReadOnlyAccount::new(&user_id, &device_id);
 
@@ -136,17 +178,16 @@ impl OlmMachine {
         )
     }
 
-    fn new_helper(
+    pub(crate) fn new_helper(
         user_id: &UserId,
         device_id: DeviceIdBox,
-        store: Box<dyn CryptoStore>,
+        store: Arc<Box<dyn CryptoStore>>,
         account: ReadOnlyAccount,
         user_identity: PrivateCrossSigningIdentity,
     ) -> Self {
         let user_id = Arc::new(user_id.clone());
         let user_identity = Arc::new(Mutex::new(user_identity));
 
-        let store = Arc::new(store);
         let verification_machine =
             VerificationMachine::new(account.clone(), user_identity.clone(), store.clone());
         let store = Store::new(
@@ -163,7 +204,7 @@ impl OlmMachine {
             user_id.clone(),
             device_id.clone(),
             store.clone(),
-            outbound_group_sessions,
+            outbound_group_sessions.clone(),
             users_for_key_claim.clone(),
         );
 
@@ -178,7 +219,8 @@ impl OlmMachine {
             key_request_machine.clone(),
             store.clone(),
         );
-        let group_session_manager = GroupSessionManager::new(account.clone(), store.clone());
+        let group_session_manager =
+            GroupSessionManager::new(account.clone(), store.clone(), outbound_group_sessions);
         let identity_manager =
             IdentityManager::new(user_id.clone(), device_id.clone(), store.clone());
 
@@ -246,7 +288,11 @@ impl OlmMachine {
         };
 
         Ok(OlmMachine::new_helper(
-            &user_id, device_id, store, account, identity,
+            &user_id,
+            device_id,
+            Arc::new(store),
+            account,
+            identity,
         ))
     }
 
@@ -319,6 +365,16 @@ impl OlmMachine {
 
         requests.append(&mut self.outgoing_to_device_requests());
         requests.append(&mut self.verification_machine.outgoing_room_message_requests());
+
+        match self
+            .verification_machine
+            .outgoing_signature_upload_requests()
+            .await
+        {
+            Ok(mut r) => requests.append(&mut r),
+            Err(e) => error!("Error loading the pending signature uploads {:?}", e),
+        }
+
         requests.append(&mut self.key_request_machine.outgoing_to_device_requests());
 
         requests
@@ -355,7 +411,9 @@ impl OlmMachine {
                 self.receive_cross_signing_upload_response().await?;
             }
             IncomingResponse::SignatureUpload(_) => {
-                self.verification_machine.mark_request_as_sent(request_id);
+                self.verification_machine
+                    .mark_signature_upload_as_sent(request_id)
+                    .await?;
             }
             IncomingResponse::RoomMessage(_) => {
                 self.verification_machine.mark_request_as_sent(request_id);
@@ -547,9 +605,14 @@ impl OlmMachine {
     ///
     /// [`receive_keys_upload_response`]: #method.receive_keys_upload_response
     /// [`OlmMachine`]: struct.OlmMachine.html
-    async fn keys_for_upload(&self) -> Option<upload_keys::Request> {
-        let (device_keys, one_time_keys) = self.account.keys_for_upload().await?;
-        Some(assign!(upload_keys::Request::new(), { device_keys, one_time_keys }))
+    async fn keys_for_upload(&self) -> Option<KeysUploadRequest> {
+        let (device_keys, one_time_keys, fallback_keys) = self.account.keys_for_upload().await?;
+
+        Some(KeysUploadRequest {
+            device_keys,
+            one_time_keys,
+            fallback_keys,
+        })
     }
 
     /// Decrypt a to-device event.
@@ -686,6 +749,10 @@ impl OlmMachine {
     /// used.
     ///
     /// `users` - The list of users that should receive the group session.
+    ///
+    /// `encryption_settings` - The settings that should be used for the group
+    /// session, the `share_strategy` of the settings decides which devices of
+    /// the users receive the session.
     pub async fn share_group_session(
         &self,
         room_id: &RoomId,
@@ -697,6 +764,213 @@ impl OlmMachine {
             .await
     }
 
+    /// Encrypt an arbitrary to-device event for the given devices.
+    ///
+    /// Olm sessions with the devices need to be established first using
+    /// [`get_missing_sessions`], devices we don't have an Olm session with are
+    /// skipped. Our own device and blacklisted devices are always left out.
+    ///
+    /// Returns to-device requests that need to be sent out, the responses
+    /// should be passed to [`mark_request_as_sent`]. The devices that are
+    /// unknown, blacklisted or that we don't have an Olm session with are
+    /// returned as skipped devices.
+    ///
+    /// # Arguments
+    ///
+    /// * `event_type` - The type of the event that should be encrypted.
+    ///
+    /// * `content` - The content of the event that should be encrypted.
+    ///
+    /// * `recipients` - The devices that should receive the event, grouped by
+    /// their owner. An empty list of devices sends the event to all known
+    /// devices of the user.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::collections::BTreeMap;
+    /// # use std::convert::TryFrom;
+    /// # use matrix_sdk_crypto::OlmMachine;
+    /// # use matrix_sdk_common::{events::EventType, identifiers::UserId};
+    /// # use serde_json::json;
+    /// # use futures::executor::block_on;
+    /// # let alice = UserId::try_from("@alice:example.org").unwrap();
+    /// # let machine = OlmMachine::new(&alice, "DEVICEID".into());
+    /// # block_on(async {
+    /// let mut recipients = BTreeMap::new();
+    /// recipients.insert(alice.clone(), vec![]);
+    ///
+    /// let encrypted = machine
+    ///     .encrypt_to_device(
+    ///         EventType::Custom("org.example.sync".to_owned()),
+    ///         json!({ "position": 42 }),
+    ///         &recipients,
+    ///     )
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// for device in encrypted.skipped_devices {
+    ///     println!("Skipped {} {}: {:?}", device.user_id, device.device_id, device.reason);
+    /// }
+    /// # });
+    /// ```
+    ///
+    /// [`get_missing_sessions`]: #method.get_missing_sessions
+    /// [`mark_request_as_sent`]: #method.mark_request_as_sent
+    pub async fn encrypt_to_device(
+        &self,
+        event_type: EventType,
+        content: Value,
+        recipients: &BTreeMap<UserId, Vec<DeviceIdBox>>,
+    ) -> OlmResult<EncryptedToDeviceRequests> {
+        let mut devices = Vec::new();
+        let mut encrypted = EncryptedToDeviceRequests::default();
+
+        for (user_id, device_ids) in recipients {
+            if device_ids.is_empty() {
+                devices.extend(self.store.get_user_devices(user_id).await?.devices());
+            } else {
+                for device_id in device_ids {
+                    match self.store.get_device(user_id, device_id).await? {
+                        Some(device) => devices.push(device),
+                        None => encrypted.skipped_devices.push(SkippedDevice {
+                            user_id: user_id.clone(),
+                            device_id: device_id.clone(),
+                            reason: SkipReason::UnknownDevice,
+                        }),
+                    }
+                }
+            }
+        }
+
+        devices.retain(|d| d.user_id() != self.user_id() || d.device_id() != self.device_id());
+
+        let (blacklisted, devices): (Vec<_>, Vec<_>) =
+            devices.into_iter().partition(|d| d.is_blacklisted());
+
+        encrypted
+            .skipped_devices
+            .extend(blacklisted.iter().map(|d| SkippedDevice {
+                user_id: d.user_id().clone(),
+                device_id: d.device_id().into(),
+                reason: SkipReason::Blacklisted,
+            }));
+
+        let mut changes = Changes::default();
+
+        for chunk in devices.chunks(GroupSessionManager::MAX_TO_DEVICE_MESSAGES) {
+            let (_, request, used_sessions, no_olm_devices) =
+                GroupSessionManager::encrypt_for_devices(
+                    event_type.clone(),
+                    content.clone(),
+                    chunk.to_vec(),
+                )
+                .await?;
+
+            encrypted
+                .skipped_devices
+                .extend(no_olm_devices.iter().map(|d| SkippedDevice {
+                    user_id: d.user_id().clone(),
+                    device_id: d.device_id().into(),
+                    reason: SkipReason::MissingSession,
+                }));
+
+            changes.sessions.extend(used_sessions);
+
+            if !request.messages.is_empty() {
+                encrypted.requests.push(request);
+            }
+        }
+
+        self.store.save_changes(changes).await?;
+
+        Ok(encrypted)
+    }
+
+    /// Get the share strategy that should be used for the given room.
+    ///
+    /// This is the strategy set for the room using
+    /// `set_room_share_strategy()`, or the global strategy set using
+    /// `set_share_strategy()` if the room doesn't have one. Defaults to
+    /// `ShareStrategy::AllDevices`.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The id of the room for which the strategy should be
+    /// fetched.
+    pub async fn share_strategy(&self, room_id: &RoomId) -> StoreResult<ShareStrategy> {
+        if let Some(strategy) = self
+            .store
+            .get_object(&room_share_strategy_key(room_id))
+            .await?
+        {
+            Ok(strategy)
+        } else {
+            Ok(self
+                .store
+                .get_object(SHARE_STRATEGY_KEY)
+                .await?
+                .unwrap_or_default())
+        }
+    }
+
+    /// Set the share strategy that should be used for rooms that don't have
+    /// their own strategy.
+    ///
+    /// # Arguments
+    ///
+    /// * `strategy` - The strategy deciding which devices receive our room
+    /// keys.
+    pub async fn set_share_strategy(&self, strategy: ShareStrategy) -> StoreResult<()> {
+        self.store.save_object(SHARE_STRATEGY_KEY, &strategy).await
+    }
+
+    /// Set the share strategy for a single room, overriding the global one.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The id of the room for which the strategy should be set.
+    ///
+    /// * `strategy` - The strategy deciding which devices receive our room
+    /// keys in the room, `None` to fall back to the global strategy.
+    pub async fn set_room_share_strategy(
+        &self,
+        room_id: &RoomId,
+        strategy: Option<ShareStrategy>,
+    ) -> StoreResult<()> {
+        let key = room_share_strategy_key(room_id);
+
+        if let Some(strategy) = strategy {
+            self.store.save_object(&key, &strategy).await
+        } else {
+            self.store.delete_object(&key).await
+        }
+    }
+
+    /// Get the policy deciding which incoming room key requests get answered.
+    pub fn key_share_policy(&self) -> KeySharePolicy {
+        self.key_request_machine.key_share_policy()
+    }
+
+    /// Set the policy deciding which incoming room key requests get answered.
+    ///
+    /// The policy isn't persisted, it needs to be set again every time the
+    /// machine is created. Defaults to
+    /// `KeySharePolicy::OwnVerifiedDevicesAndRecipients`.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - The policy that should be used for new key requests.
+    pub fn set_key_share_policy(&self, policy: KeySharePolicy) {
+        self.key_request_machine.set_key_share_policy(policy)
+    }
+
+    /// Get the most recent decisions that were made about incoming room key
+    /// requests, oldest first.
+    pub fn key_share_decisions(&self) -> Vec<KeyShareDecisionRecord> {
+        self.key_request_machine.key_share_decisions()
+    }
+
     /// Receive and properly handle a decrypted to-device event.
     ///
     /// # Arguments
@@ -736,6 +1010,118 @@ impl OlmMachine {
         }
     }
 
+    /// Remember that the sender of a `m.room_key.withheld` event refused to
+    /// share a room key with us.
+    async fn receive_room_key_withheld(&self, event: &RawJsonValue) {
+        let event = match serde_json::from_str::<RoomKeyWithheldEvent>(event.get()) {
+            Ok(e) => e,
+            Err(e) => {
+                warn!("Received an invalid room key withheld event {:?}", e);
+                return;
+            }
+        };
+
+        info!(
+            "Received a room key withheld event from {} for room {:?} with session id {:?}: {}",
+            event.sender, event.content.room_id, event.content.session_id, event.content.code
+        );
+
+        // Only the owner of the sender key may tell us that its sessions are
+        // withheld.
+        match self
+            .store
+            .get_device_from_curve_key(&event.sender, &event.content.sender_key)
+            .await
+        {
+            Ok(Some(_)) => (),
+            Ok(None) => {
+                warn!(
+                    "Received a room key withheld event from {} for the sender key {} which \
+                     doesn't belong to any of the sender's devices",
+                    event.sender, event.content.sender_key
+                );
+                return;
+            }
+            Err(e) => {
+                error!("Couldn't load the devices of {} {:?}", event.sender, e);
+                return;
+            }
+        }
+
+        let key = withheld_key(
+            &event.content.sender_key,
+            event.content.room_id.as_ref(),
+            event.content.session_id.as_deref(),
+        );
+
+        if let Err(e) = self.store.save_object(&key, &event.content).await {
+            error!("Couldn't store a room key withheld event {:?}", e);
+        }
+    }
+
+    /// Forget the `m.room_key.withheld` notices that are outdated because we
+    /// received an Olm message or a room key from the given sender key.
+    ///
+    /// # Arguments
+    ///
+    /// * `sender_key` - The curve25519 key of the device that sent us the Olm
+    /// message.
+    ///
+    /// * `session` - The room key that was received with the Olm message, if
+    /// there was one.
+    async fn clear_withheld_notices(
+        &self,
+        sender_key: &str,
+        session: Option<&InboundGroupSession>,
+    ) {
+        // Once we have an Olm session with the sender, a `m.no_olm` notice
+        // doesn't apply anymore.
+        let mut keys = vec![withheld_key(sender_key, None, None)];
+
+        if let Some(session) = session {
+            keys.push(withheld_key(
+                session.sender_key(),
+                Some(session.room_id()),
+                Some(session.session_id()),
+            ));
+        }
+
+        for key in keys {
+            if let Err(e) = self.store.delete_object(&key).await {
+                error!("Couldn't remove a room key withheld event {:?}", e);
+            }
+        }
+    }
+
+    /// Get the `m.room_key.withheld` notice the sender of the given session
+    /// sent us, if the sender refused to share the session with us.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The id of the room the session is used in.
+    ///
+    /// * `sender_key` - The curve25519 key of the device that created the
+    /// session.
+    ///
+    /// * `session_id` - The id of the session.
+    pub async fn get_withheld_info(
+        &self,
+        room_id: &RoomId,
+        sender_key: &str,
+        session_id: &str,
+    ) -> StoreResult<Option<RoomKeyWithheldContent>> {
+        let key = withheld_key(sender_key, Some(room_id), Some(session_id));
+
+        if let Some(content) = self.store.get_object(&key).await? {
+            Ok(Some(content))
+        } else {
+            // `m.no_olm` notices might not be bound to a specific session.
+            self.store
+                .get_object(&withheld_key(sender_key, None, None))
+                .await
+        }
+    }
+
     async fn handle_verification_event(&self, event: &AnyToDeviceEvent) {
         if let Err(e) = self.verification_machine.receive_event(&event).await {
             error!("Error handling a verification event: {:?}", e);
@@ -776,6 +1162,18 @@ impl OlmMachine {
         self.account.update_uploaded_key_count(key_count).await;
     }
 
+    async fn update_unused_fallback_keys(
+        &self,
+        unused_fallback_keys: Option<&[DeviceKeyAlgorithm]>,
+    ) {
+        // If the field is missing the server doesn't support fallback keys.
+        if let Some(unused_fallback_keys) = unused_fallback_keys {
+            self.account
+                .update_unused_fallback_keys(unused_fallback_keys)
+                .await;
+        }
+    }
+
     /// Handle a to-device and one-time key counts from a sync response.
     ///
     /// This will decrypt and handle to-device events returning the decrypted
@@ -794,12 +1192,18 @@ impl OlmMachine {
     /// * `one_time_keys_count` - The current one-time keys counts that the sync
     /// response returned.
     ///
+    /// * `unused_fallback_keys` - The types of the fallback keys that the
+    /// server still holds for us and that weren't used yet, `None` if the sync
+    /// response didn't contain them. A new fallback key is uploaded with the
+    /// next keys upload request if our current one was used.
+    ///
     /// [`decrypt_room_event`]: #method.decrypt_room_event
     pub async fn receive_sync_changes(
         &self,
         to_device_events: &RumaToDevice,
         changed_devices: &DeviceLists,
         one_time_keys_counts: &BTreeMap<DeviceKeyAlgorithm, UInt>,
+        unused_fallback_keys: Option<&[DeviceKeyAlgorithm]>,
     ) -> OlmResult<ToDevice> {
         // Remove verification objects that have expired or are done.
         self.verification_machine.garbage_collect();
@@ -812,6 +1216,7 @@ impl OlmMachine {
         };
 
         self.update_one_time_key_count(one_time_keys_counts).await;
+        self.update_unused_fallback_keys(unused_fallback_keys).await;
 
         for user_id in &changed_devices.changed {
             if let Err(e) = self.identity_manager.mark_user_as_changed(&user_id).await {
@@ -876,6 +1281,12 @@ impl OlmMachine {
 
                     changes.message_hashes.push(decrypted.message_hash);
 
+                    self.clear_withheld_notices(
+                        &decrypted.sender_key,
+                        decrypted.inbound_group_session.as_ref(),
+                    )
+                    .await;
+
                     if let Some(group_session) = decrypted.inbound_group_session {
                         changes.inbound_group_sessions.push(group_session);
                     }
@@ -887,6 +1298,12 @@ impl OlmMachine {
                 AnyToDeviceEvent::RoomKeyRequest(e) => {
                     self.key_request_machine.receive_incoming_key_request(e)
                 }
+                AnyToDeviceEvent::Custom(e)
+                    if e.content.event_type == ROOM_KEY_WITHHELD_EVENT_TYPE
+                        || e.content.event_type == UNSTABLE_ROOM_KEY_WITHHELD_EVENT_TYPE =>
+                {
+                    self.receive_room_key_withheld(event_result.json()).await
+                }
                 AnyToDeviceEvent::KeyVerificationAccept(..)
                 | AnyToDeviceEvent::KeyVerificationCancel(..)
                 | AnyToDeviceEvent::KeyVerificationKey(..)
@@ -920,11 +1337,15 @@ impl OlmMachine {
     /// * `event` - The event that should be decrypted.
     ///
     /// * `room_id` - The ID of the room where the event was sent to.
+    ///
+    /// Returns the decrypted event together with information about its
+    /// encryption, e.g. the sender key and the verification state of the
+    /// sending device.
     pub async fn decrypt_room_event(
         &self,
         event: &SyncMessageEvent<EncryptedEventContent>,
         room_id: &RoomId,
-    ) -> MegolmResult<Raw<AnySyncRoomEvent>> {
+    ) -> MegolmResult<SyncRoomEvent> {
         let content = match &event.content {
             EncryptedEventContent::MegolmV1AesSha2(c) => c,
             _ => return Err(EventError::UnsupportedAlgorithm.into()),
@@ -934,34 +1355,110 @@ impl OlmMachine {
             .store
             .get_inbound_group_session(room_id, &content.sender_key, &content.session_id)
             .await?;
-        // TODO check if the Olm session is wedged and re-request the key.
         let session = if let Some(s) = session {
             s
         } else {
             self.key_request_machine
                 .create_outgoing_key_request(room_id, &content.sender_key, &content.session_id)
                 .await?;
+
+            if let Some(withheld) = self
+                .get_withheld_info(room_id, &content.sender_key, &content.session_id)
+                .await?
+            {
+                return Err(MegolmError::Withheld {
+                    code: withheld.code,
+                    reason: withheld.reason,
+                });
+            }
+
+            // The room key likely got lost because our Olm session with the
+            // sender is wedged, the key request will succeed once the
+            // session is unwedged.
+            if self
+                .session_manager
+                .is_sender_wedged(&event.sender, &content.sender_key)
+                .await?
+            {
+                return Err(MegolmError::SessionWedged(
+                    event.sender.clone(),
+                    content.sender_key.clone(),
+                ));
+            }
+
             return Err(MegolmError::MissingSession);
         };
 
-        // TODO check the message index.
-        // TODO check if this is from a verified device.
-        let (decrypted_event, _) = session.decrypt(event).await?;
+        let (decrypted_event, _) = match session.decrypt(event).await {
+            Ok(d) => d,
+            Err(MegolmError::OlmGroupSession(OlmGroupSessionError::UnknownMessageIndex)) => {
+                // Our copy of the session starts at a later message index,
+                // another device might have a better copy of it.
+                self.key_request_machine
+                    .create_outgoing_key_request(room_id, &content.sender_key, &content.session_id)
+                    .await?;
+
+                return Err(MegolmError::UnknownMessageIndex);
+            }
+            Err(e) => return Err(e),
+        };
 
         trace!(
             "Successfully decrypted a Megolm event {:?}",
             decrypted_event
         );
-        // TODO set the encryption info on the event (is it verified, was it
-        // decrypted, sender key...)
 
-        if let Ok(e) = decrypted_event.deserialize() {
-            self.verification_machine
-                .receive_room_event(room_id, &e)
-                .await?;
-        }
+        let decrypted_event = decrypted_event.deserialize()?;
+
+        self.verification_machine
+            .receive_room_event(room_id, &decrypted_event)
+            .await?;
+
+        let encryption_info = self
+            .get_encryption_info(&session, &event.sender, &content.device_id)
+            .await?;
+
+        Ok(SyncRoomEvent {
+            event: decrypted_event,
+            encryption_info: Some(encryption_info),
+        })
+    }
+
+    /// Collect the encryption info of an event that was decrypted using the
+    /// given inbound group session.
+    async fn get_encryption_info(
+        &self,
+        session: &InboundGroupSession,
+        sender: &UserId,
+        device_id: &DeviceId,
+    ) -> StoreResult<EventEncryptionInfo> {
+        let verification_state = if let Some(device) = self.get_device(sender, device_id).await? {
+            // The device needs to own the key that created the session,
+            // otherwise anyone could claim to be a verified device.
+            let owns_session = device
+                .get_key(DeviceKeyAlgorithm::Curve25519)
+                .map(|k| k.as_str())
+                == Some(session.sender_key());
+
+            if owns_session && device.trust_state() {
+                VerificationState::Trusted
+            } else {
+                VerificationState::Untrusted
+            }
+        } else {
+            VerificationState::UnknownDevice
+        };
 
-        Ok(decrypted_event)
+        Ok(EventEncryptionInfo {
+            sender: sender.clone(),
+            sender_device: device_id.into(),
+            algorithm_info: AlgorithmInfo::MegolmV1AesSha2 {
+                curve25519_key: session.sender_key().to_owned(),
+                sender_claimed_keys: (&*session.signing_key).clone(),
+                session_id: session.session_id().to_owned(),
+            },
+            verification_state,
+        })
     }
 
     /// Update the tracked users.
@@ -1041,6 +1538,133 @@ impl OlmMachine {
         self.store.get_user_devices(user_id).await
     }
 
+    /// Mark the cross signing identity of the given user as verified after it
+    /// was verified out of band, e.g. by comparing the fingerprint of its
+    /// master key.
+    ///
+    /// Our own identity gets marked as verified, identities of other users get
+    /// signed with our user signing key, the same way an interactive
+    /// verification would. A pending change of the identity gets acknowledged
+    /// as well.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The id of the user whose identity should be verified.
+    ///
+    /// Returns a signature upload request if there are signatures that need
+    /// to be uploaded. The request is stored and returned by
+    /// `outgoing_requests()` as well until it's marked as sent.
+    pub async fn verify_user_identity(
+        &self,
+        user_id: &UserId,
+    ) -> StoreResult<Option<OutgoingRequest>> {
+        let identity = if let Some(i) = self.store.get_user_identity(user_id).await? {
+            i
+        } else {
+            warn!(
+                "Can't verify the identity of {}, no identity found",
+                user_id
+            );
+            return Ok(None);
+        };
+
+        let request = {
+            let private_identity = self.user_identity.lock().await;
+            sign_verified(&private_identity, None, Some(&identity)).await
+        };
+
+        let request = if let Some(r) = request {
+            Some(save_signature_upload(&*self.store, r).await?)
+        } else {
+            None
+        };
+
+        if let UserIdentities::Own(i) = &identity {
+            i.mark_as_verified();
+
+            let changes = Changes {
+                identities: IdentityChanges {
+                    changed: vec![identity.clone()],
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+
+            self.store.save_changes(changes).await?;
+        }
+
+        if self
+            .identity_change(user_id)
+            .await?
+            .map_or(false, |c| &c.new_master_key == identity.master_key().keys())
+        {
+            self.acknowledge_identity_change(user_id).await?;
+        }
+
+        Ok(request)
+    }
+
+    /// Get the changes of cross signing identities that weren't acknowledged
+    /// yet.
+    ///
+    /// The first master key we see for a user is pinned, if the user later
+    /// switches to a different master key the change is recorded and returned
+    /// here until it gets acknowledged using `acknowledge_identity_change()`.
+    /// Room keys are withheld from users with unacknowledged identity changes
+    /// unless the share strategy is `ShareStrategy::AllDevices`.
+    pub async fn identity_changes(&self) -> StoreResult<Vec<IdentityChange>> {
+        self.store.get_identity_changes().await
+    }
+
+    /// Set a callback that gets called every time a new identity change is
+    /// detected while a keys query response is received.
+    ///
+    /// The callback isn't persisted, it needs to be set again every time the
+    /// machine is created.
+    ///
+    /// # Arguments
+    ///
+    /// * `callback` - The callback that should be notified about new identity
+    /// changes, `None` removes the current callback.
+    pub fn set_identity_change_callback(&self, callback: Option<IdentityChangeCallback>) {
+        self.identity_manager.set_identity_change_callback(callback)
+    }
+
+    /// Get the unacknowledged change of the cross signing identity of the
+    /// given user, if there is one.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The id of the user whose identity change should be
+    /// fetched.
+    pub async fn identity_change(&self, user_id: &UserId) -> StoreResult<Option<IdentityChange>> {
+        self.store.get_identity_change(user_id).await
+    }
+
+    /// Acknowledge the change of the cross signing identity of the given user.
+    ///
+    /// This pins the new master key of the user and allows room keys to be
+    /// shared with the new identity again.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The id of the user whose identity change should be
+    /// acknowledged.
+    ///
+    /// Returns true if there was a change to acknowledge, false otherwise.
+    pub async fn acknowledge_identity_change(&self, user_id: &UserId) -> StoreResult<bool> {
+        if let Some(change) = self.store.get_identity_change(user_id).await? {
+            self.store
+                .pin_master_key(user_id, &change.new_master_key)
+                .await?;
+            self.store.remove_identity_change(user_id).await?;
+
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
     /// Import the given room keys into our store.
     ///
     /// # Arguments
@@ -1181,15 +1805,86 @@ impl OlmMachine {
 
         Ok(exported)
     }
-}
-
-#[cfg(test)]
-pub(crate) mod test {
-    static USER_ID: &str = "@bob:example.org";
 
-    use std::{
-        collections::BTreeMap,
-        convert::{TryFrom, TryInto},
+    /// Create a new dehydrated device.
+    ///
+    /// The account of the dehydrated device is pickled with a key that is
+    /// derived from the given secret. The returned device needs to be uploaded
+    /// to the homeserver, after that other devices will share room keys with
+    /// it.
+    ///
+    /// # Arguments
+    ///
+    /// * `secret` - The user secret that protects the dehydrated device.
+    ///
+    /// * `display_name` - The display name the dehydrated device should have.
+    ///
+    /// * `rounds` - The number of rounds that should be used for the key
+    /// derivation when the secret gets turned into a pickle key. Should be at
+    /// least `10000`, while values in the `100000` ranges should be preferred.
+    /// Devices that use more than `1000000` rounds can't be rehydrated.
+    pub async fn create_dehydrated_device(
+        &self,
+        secret: &str,
+        display_name: &str,
+        rounds: u32,
+    ) -> DehydratedDevice {
+        DehydratedDevice::new(
+            self.user_id(),
+            self.user_identity.clone(),
+            secret,
+            display_name,
+            rounds,
+        )
+        .await
+    }
+
+    /// Restore a dehydrated device that was fetched from the homeserver.
+    ///
+    /// The returned [`RehydratedDevice`] needs to be claimed before it can be
+    /// used, after that it replaces this machine. The store of this machine
+    /// will be reused for the rehydrated device.
+    ///
+    /// # Arguments
+    ///
+    /// * `secret` - The user secret that protects the dehydrated device.
+    ///
+    /// * `device_id` - The device id of the dehydrated device.
+    ///
+    /// * `device_data` - The device data of the dehydrated device.
+    pub async fn rehydrate_device(
+        &self,
+        secret: &str,
+        device_id: &DeviceId,
+        device_data: &DehydratedDeviceData,
+    ) -> Result<RehydratedDevice, DehydrationError> {
+        let pickle_mode = existing_pickle_mode(secret, device_data)?;
+
+        let pickle = PickledAccount {
+            user_id: self.user_id().to_owned(),
+            device_id: device_id.into(),
+            pickle: device_data.account.clone().into(),
+            shared: true,
+            uploaded_signed_key_count: 0,
+            unpublished_fallback_key: false,
+        };
+        let account = ReadOnlyAccount::from_pickle(pickle, pickle_mode)?;
+
+        Ok(RehydratedDevice {
+            account,
+            store: self.verification_machine.store.clone(),
+            user_identity: self.user_identity.lock().await.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    static USER_ID: &str = "@bob:example.org";
+
+    use std::{
+        collections::BTreeMap,
+        convert::{TryFrom, TryInto},
         sync::Arc,
         time::SystemTime,
     };
@@ -1198,14 +1893,24 @@ pub(crate) mod test {
     use serde_json::json;
 
     use crate::{
+        error::MegolmError,
         machine::OlmMachine,
-        olm::Utility,
+        olm::{
+            ReadOnlyAccount, RoomKeyWithheldContent, Utility, WithheldCode,
+            ROOM_KEY_WITHHELD_EVENT_TYPE,
+        },
         verification::test::{outgoing_request_to_event, request_to_event},
-        EncryptionSettings, ReadOnlyDevice, ToDeviceRequest,
+        EncryptionSettings, LocalTrust, OutgoingRequests, ReadOnlyDevice, SkipReason,
+        SkippedDevice, ToDeviceRequest, DEHYDRATION_ALGORITHM,
     };
 
     use matrix_sdk_common::{
-        api::r0::keys::{claim_keys, get_keys, upload_keys, OneTimeKey},
+        api::r0::{
+            keys::{claim_keys, get_keys, upload_keys, OneTimeKey},
+            sync::sync_events::{DeviceLists, ToDevice as RumaToDevice},
+            to_device::DeviceIdOrAllDevices,
+        },
+        deserialized_responses::VerificationState,
         events::{
             room::{
                 encrypted::EncryptedEventContent,
@@ -1561,6 +2266,89 @@ pub(crate) mod test {
         assert!(ret.is_none());
     }
 
+    #[tokio::test]
+    async fn test_fallback_key_rotation() {
+        let machine = OlmMachine::new(&user_id(), &alice_device_id());
+        machine.account.inner.update_uploaded_key_count(0);
+
+        let identity_keys = machine.account.identity_keys();
+        let ed25519_key = identity_keys.ed25519();
+
+        let mut request = machine
+            .keys_for_upload()
+            .await
+            .expect("Can't prepare initial key upload");
+
+        let fallback_keys = request.fallback_keys.as_mut().unwrap();
+        assert_eq!(fallback_keys.len(), 1);
+        let first_fallback_key = fallback_keys.keys().next().unwrap().clone();
+
+        let utility = Utility::new();
+        let ret = utility.verify_json(
+            &machine.user_id,
+            &DeviceKeyId::from_parts(DeviceKeyAlgorithm::Ed25519, machine.device_id()),
+            ed25519_key,
+            &mut json!(&mut fallback_keys.values_mut().next()),
+        );
+        assert!(ret.is_ok());
+
+        let mut response = keys_upload_response();
+        response.one_time_key_counts.insert(
+            DeviceKeyAlgorithm::SignedCurve25519,
+            (request.one_time_keys.unwrap().len() as u64)
+                .try_into()
+                .unwrap(),
+        );
+        machine
+            .receive_keys_upload_response(&response)
+            .await
+            .unwrap();
+
+        // The fallback key is still unused, nothing to upload.
+        machine
+            .receive_sync_changes(
+                &RumaToDevice::default(),
+                &DeviceLists::default(),
+                &BTreeMap::new(),
+                Some(&[DeviceKeyAlgorithm::SignedCurve25519]),
+            )
+            .await
+            .unwrap();
+        assert!(machine.keys_for_upload().await.is_none());
+
+        // A server that doesn't support fallback keys doesn't rotate them.
+        machine
+            .receive_sync_changes(
+                &RumaToDevice::default(),
+                &DeviceLists::default(),
+                &BTreeMap::new(),
+                None,
+            )
+            .await
+            .unwrap();
+        assert!(machine.keys_for_upload().await.is_none());
+
+        // The fallback key was used, a new one needs to be uploaded.
+        machine
+            .receive_sync_changes(
+                &RumaToDevice::default(),
+                &DeviceLists::default(),
+                &BTreeMap::new(),
+                Some(&[]),
+            )
+            .await
+            .unwrap();
+        let request = machine
+            .keys_for_upload()
+            .await
+            .expect("The rotated fallback key isn't uploaded");
+
+        assert!(request.device_keys.is_none());
+        let fallback_keys = request.fallback_keys.unwrap();
+        assert_eq!(fallback_keys.len(), 1);
+        assert_ne!(fallback_keys.keys().next().unwrap(), &first_fallback_key);
+    }
+
     #[tokio::test]
     async fn test_keys_query() {
         let (machine, _) = get_prepared_machine().await;
@@ -1669,6 +2457,111 @@ pub(crate) mod test {
         }
     }
 
+    #[tokio::test]
+    async fn test_encrypt_to_device() {
+        let (alice, bob) = get_machine_pair_with_session().await;
+        let event_type = EventType::Custom("org.example.sync".to_owned());
+
+        let mut recipients = BTreeMap::new();
+        recipients.insert(bob.user_id().clone(), vec!["UNKNOWNDEVICE".into()]);
+
+        let encrypted = alice
+            .encrypt_to_device(event_type.clone(), json!({ "position": 42 }), &recipients)
+            .await
+            .unwrap();
+        assert!(encrypted.requests.is_empty());
+        assert_eq!(
+            encrypted.skipped_devices,
+            vec![SkippedDevice {
+                user_id: bob.user_id().clone(),
+                device_id: "UNKNOWNDEVICE".into(),
+                reason: SkipReason::UnknownDevice,
+            }]
+        );
+
+        // A second device of Bob that we don't have an Olm session with.
+        let bob_other = ReadOnlyAccount::new(bob.user_id(), "BOBOTHER".into());
+        alice
+            .store
+            .save_devices(&[ReadOnlyDevice::from_account(&bob_other).await])
+            .await
+            .unwrap();
+
+        recipients.insert(bob.user_id().clone(), vec![]);
+        let encrypted = alice
+            .encrypt_to_device(event_type, json!({ "position": 42 }), &recipients)
+            .await
+            .unwrap();
+        assert_eq!(
+            encrypted.skipped_devices,
+            vec![SkippedDevice {
+                user_id: bob.user_id().clone(),
+                device_id: "BOBOTHER".into(),
+                reason: SkipReason::MissingSession,
+            }]
+        );
+
+        let requests = encrypted.requests;
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].event_type, EventType::RoomEncrypted);
+
+        let event = ToDeviceEvent {
+            sender: alice.user_id().clone(),
+            content: to_device_requests_to_content(requests.into_iter().map(Arc::new).collect()),
+        };
+
+        let event = bob
+            .decrypt_to_device_event(&event)
+            .await
+            .unwrap()
+            .event
+            .deserialize()
+            .unwrap();
+
+        if let AnyToDeviceEvent::Custom(e) = event {
+            assert_eq!(&e.sender, alice.user_id());
+            assert_eq!(e.content.event_type, "org.example.sync");
+        } else {
+            panic!("Wrong event type found {:?}", event);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_encrypt_to_device_skips_blacklisted() {
+        let (alice, bob) = get_machine_pair_with_session().await;
+
+        alice
+            .get_device(bob.user_id(), bob.device_id())
+            .await
+            .unwrap()
+            .unwrap()
+            .set_local_trust(LocalTrust::BlackListed)
+            .await
+            .unwrap();
+
+        let mut recipients = BTreeMap::new();
+        recipients.insert(bob.user_id().clone(), vec![]);
+
+        let encrypted = alice
+            .encrypt_to_device(
+                EventType::Custom("org.example.sync".to_owned()),
+                json!({ "position": 42 }),
+                &recipients,
+            )
+            .await
+            .unwrap();
+
+        assert!(encrypted.requests.is_empty());
+        assert_eq!(
+            encrypted.skipped_devices,
+            vec![SkippedDevice {
+                user_id: bob.user_id().clone(),
+                device_id: bob.device_id().into(),
+                reason: SkipReason::Blacklisted,
+            }]
+        );
+    }
+
     #[tokio::test]
     async fn test_room_key_sharing() {
         let (alice, bob) = get_machine_pair_with_session().await;
@@ -1774,14 +2667,17 @@ pub(crate) mod test {
             unsigned: Unsigned::default(),
         };
 
-        let decrypted_event = bob
-            .decrypt_room_event(&event, &room_id)
-            .await
-            .unwrap()
-            .deserialize()
-            .unwrap();
+        let decrypted_event = bob.decrypt_room_event(&event, &room_id).await.unwrap();
 
-        match decrypted_event {
+        let encryption_info = decrypted_event.encryption_info.unwrap();
+        assert_eq!(&encryption_info.sender, alice.user_id());
+        assert_eq!(&*encryption_info.sender_device, alice.device_id());
+        assert_eq!(
+            encryption_info.verification_state,
+            VerificationState::Untrusted
+        );
+
+        match decrypted_event.event {
             AnySyncRoomEvent::Message(AnySyncMessageEvent::RoomMessage(SyncMessageEvent {
                 sender,
                 content,
@@ -1798,6 +2694,366 @@ pub(crate) mod test {
         }
     }
 
+    #[tokio::test]
+    async fn test_megolm_missing_session() {
+        let (alice, bob) = get_machine_pair_with_setup_sessions().await;
+        let room_id = room_id!("!test:example.org");
+
+        alice
+            .share_group_session(
+                &room_id,
+                [bob.user_id().clone()].iter(),
+                EncryptionSettings::default(),
+            )
+            .await
+            .unwrap();
+
+        let content = MessageEventContent::text_plain("It is a secret to everybody");
+
+        let encrypted_content = alice
+            .encrypt(&room_id, AnyMessageEventContent::RoomMessage(content))
+            .await
+            .unwrap();
+
+        let event = SyncMessageEvent {
+            event_id: event_id!("$xxxxx:example.org"),
+            origin_server_ts: SystemTime::now(),
+            sender: alice.user_id().clone(),
+            content: encrypted_content,
+            unsigned: Unsigned::default(),
+        };
+
+        // Bob never received the room key.
+        assert!(matches!(
+            bob.decrypt_room_event(&event, &room_id).await,
+            Err(MegolmError::MissingSession)
+        ));
+
+        // A key request for the missing session was queued up.
+        assert!(bob.outgoing_requests().await.iter().any(|r| matches!(
+            r.request(),
+            OutgoingRequests::ToDeviceRequest(r) if r.event_type == EventType::RoomKeyRequest
+        )));
+    }
+
+    #[tokio::test]
+    async fn test_room_key_withheld() {
+        let (alice, bob) = get_machine_pair_with_setup_sessions().await;
+        let room_id = room_id!("!test:example.org");
+
+        alice
+            .get_device(bob.user_id(), bob.device_id())
+            .await
+            .unwrap()
+            .unwrap()
+            .set_local_trust(LocalTrust::BlackListed)
+            .await
+            .unwrap();
+
+        let requests = alice
+            .share_group_session(
+                &room_id,
+                [bob.user_id().clone()].iter(),
+                EncryptionSettings::default(),
+            )
+            .await
+            .unwrap();
+
+        // Bob only gets told that the session is withheld from him.
+        assert_eq!(requests.len(), 1);
+        assert_eq!(
+            requests[0].event_type,
+            EventType::from(ROOM_KEY_WITHHELD_EVENT_TYPE)
+        );
+
+        let content = requests[0].messages[bob.user_id()]
+            [&DeviceIdOrAllDevices::DeviceId(bob.device_id().into())]
+            .get();
+        let content: RoomKeyWithheldContent = serde_json::from_str(content).unwrap();
+        assert_eq!(content.code, WithheldCode::Blacklisted);
+
+        let mut to_device = RumaToDevice::default();
+        to_device.events.push(Raw::from_json(
+            serde_json::value::to_raw_value(&json!({
+                "sender": alice.user_id(),
+                "type": ROOM_KEY_WITHHELD_EVENT_TYPE,
+                "content": content,
+            }))
+            .unwrap(),
+        ));
+
+        bob.receive_sync_changes(&to_device, &DeviceLists::default(), &BTreeMap::new(), None)
+            .await
+            .unwrap();
+
+        let encrypted_content = alice
+            .encrypt(
+                &room_id,
+                AnyMessageEventContent::RoomMessage(MessageEventContent::text_plain("Hello")),
+            )
+            .await
+            .unwrap();
+
+        let event = SyncMessageEvent {
+            event_id: event_id!("$xxxxx:example.org"),
+            origin_server_ts: SystemTime::now(),
+            sender: alice.user_id().clone(),
+            content: encrypted_content,
+            unsigned: Unsigned::default(),
+        };
+
+        assert!(matches!(
+            bob.decrypt_room_event(&event, &room_id).await,
+            Err(MegolmError::Withheld {
+                code: WithheldCode::Blacklisted,
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_no_olm_withheld_notice() {
+        let (alice, bob) = get_machine_pair_with_setup_sessions().await;
+        let room_id = room_id!("!test:example.org");
+        let sender_key = alice.identity_keys().curve25519().to_owned();
+
+        let withheld_event = |sender: &UserId| {
+            Raw::from_json(
+                serde_json::value::to_raw_value(&json!({
+                    "sender": sender,
+                    "type": ROOM_KEY_WITHHELD_EVENT_TYPE,
+                    "content": {
+                        "algorithm": "m.megolm.v1.aes-sha2",
+                        "sender_key": sender_key,
+                        "code": "m.no_olm",
+                    },
+                }))
+                .unwrap(),
+            )
+        };
+
+        // Only the owner of the sender key can withhold its sessions.
+        let mut to_device = RumaToDevice::default();
+        to_device
+            .events
+            .push(withheld_event(&user_id!("@mallory:example.org")));
+        bob.receive_sync_changes(&to_device, &DeviceLists::default(), &BTreeMap::new(), None)
+            .await
+            .unwrap();
+
+        assert!(bob
+            .get_withheld_info(&room_id, &sender_key, "session_id")
+            .await
+            .unwrap()
+            .is_none());
+
+        let mut to_device = RumaToDevice::default();
+        to_device.events.push(withheld_event(alice.user_id()));
+        bob.receive_sync_changes(&to_device, &DeviceLists::default(), &BTreeMap::new(), None)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            bob.get_withheld_info(&room_id, &sender_key, "session_id")
+                .await
+                .unwrap()
+                .unwrap()
+                .code,
+            WithheldCode::NoOlm
+        );
+
+        // Receiving a room key from the sender shows that the notice is
+        // outdated.
+        let requests = alice
+            .share_group_session(
+                &room_id,
+                [bob.user_id().clone()].iter(),
+                EncryptionSettings::default(),
+            )
+            .await
+            .unwrap();
+
+        let mut to_device = RumaToDevice::default();
+        to_device.events.push(Raw::from_json(
+            serde_json::value::to_raw_value(&json!({
+                "sender": alice.user_id(),
+                "type": "m.room.encrypted",
+                "content": to_device_requests_to_content(requests),
+            }))
+            .unwrap(),
+        ));
+        bob.receive_sync_changes(&to_device, &DeviceLists::default(), &BTreeMap::new(), None)
+            .await
+            .unwrap();
+
+        assert!(bob
+            .get_withheld_info(&room_id, &sender_key, "session_id")
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_out_of_band_device_verification() {
+        let (machine, _) = get_prepared_machine().await;
+        let second_device_id: Box<DeviceId> = "SECONDDEVICE".into();
+        let second = OlmMachine::new(&user_id(), &second_device_id);
+        let second_device = ReadOnlyDevice::from_machine(&second).await;
+        machine.store.save_devices(&[second_device]).await.unwrap();
+
+        machine.bootstrap_cross_signing(false).await.unwrap();
+
+        let device = machine
+            .get_device(second.user_id(), second.device_id())
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert!(!device.is_trusted());
+
+        let request = device
+            .verify()
+            .await
+            .unwrap()
+            .expect("Verifying our own device didn't produce a signature upload");
+
+        let device = machine
+            .get_device(second.user_id(), second.device_id())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(device.is_trusted());
+
+        // The signature upload is stored until the server confirms it.
+        assert!(machine
+            .outgoing_requests()
+            .await
+            .iter()
+            .any(|r| r.request_id() == request.request_id()));
+
+        machine
+            .verification_machine
+            .mark_signature_upload_as_sent(request.request_id())
+            .await
+            .unwrap();
+
+        assert!(!machine
+            .outgoing_requests()
+            .await
+            .iter()
+            .any(|r| r.request_id() == request.request_id()));
+    }
+
+    #[tokio::test]
+    async fn test_dehydrated_device() {
+        let (bob, _) = get_prepared_machine().await;
+        let alice = OlmMachine::new(&alice_id(), &alice_device_id());
+        let room_id = room_id!("!test:example.org");
+        let secret = "It's a secret to everybody";
+        let device_id: Box<DeviceId> = "DEHYDRATED1".into();
+
+        let dehydrated = bob
+            .create_dehydrated_device(secret, "Dehydrated device", 1000)
+            .await;
+        let device_data = dehydrated.request().device_data.clone();
+        assert_eq!(device_data.algorithm, DEHYDRATION_ALGORITHM);
+        assert_eq!(device_data.passphrase.iterations, 1000);
+
+        // The keys are signed for the device id the server assigned.
+        let keys = dehydrated.keys_for_upload(&device_id).await;
+        assert_eq!(keys.device_keys.device_id, device_id);
+        assert!(!keys.one_time_keys.is_empty());
+        assert_eq!(keys.fallback_keys.len(), 1);
+
+        let dehydrated_device = ReadOnlyDevice::try_from(&keys.device_keys).unwrap();
+        alice
+            .store
+            .save_devices(&[dehydrated_device])
+            .await
+            .unwrap();
+
+        let one_time_key = keys.one_time_keys.iter().next().unwrap();
+        let mut one_time_key_map = BTreeMap::new();
+        one_time_key_map.insert(one_time_key.0.clone(), one_time_key.1.clone());
+        let mut bob_keys = BTreeMap::new();
+        bob_keys.insert(device_id.clone(), one_time_key_map);
+        let mut one_time_keys = BTreeMap::new();
+        one_time_keys.insert(bob.user_id().clone(), bob_keys);
+        let response = claim_keys::Response::new(one_time_keys);
+        alice.receive_keys_claim_response(&response).await.unwrap();
+
+        let to_device_requests = alice
+            .share_group_session(
+                &room_id,
+                [bob.user_id().clone()].iter(),
+                EncryptionSettings::default(),
+            )
+            .await
+            .unwrap();
+
+        let event = json!({
+            "sender": alice.user_id(),
+            "type": "m.room.encrypted",
+            "content": to_device_requests_to_content(to_device_requests),
+        });
+        let event: Raw<AnyToDeviceEvent> = serde_json::from_value(event).unwrap();
+
+        assert!(bob
+            .rehydrate_device("Wrong secret", &device_id, &device_data)
+            .await
+            .is_err());
+
+        // The server can't make us derive the pickle key with an unbounded
+        // number of rounds.
+        let mut stalling_data = device_data.clone();
+        stalling_data.passphrase.iterations = u32::MAX;
+        assert!(matches!(
+            bob.rehydrate_device(secret, &device_id, &stalling_data)
+                .await,
+            Err(DehydrationError::InvalidIterations(u32::MAX))
+        ));
+
+        let rehydrated = bob
+            .rehydrate_device(secret, &device_id, &device_data)
+            .await
+            .unwrap();
+        assert_eq!(rehydrated.device_id(), &*device_id);
+
+        // Once the device is claimed the to-device events that were sent to
+        // it arrive in the sync response.
+        let rehydrated = rehydrated.into_machine().await.unwrap();
+        assert_eq!(rehydrated.device_id(), &*device_id);
+        assert_eq!(
+            rehydrated.identity_keys().curve25519(),
+            keys.device_keys.keys
+                [&DeviceKeyId::from_parts(DeviceKeyAlgorithm::Curve25519, &device_id)]
+        );
+
+        let mut to_device = RumaToDevice::default();
+        to_device.events = vec![event];
+        rehydrated
+            .receive_sync_changes(&to_device, &DeviceLists::default(), &BTreeMap::new(), None)
+            .await
+            .unwrap();
+
+        let alice_session = alice
+            .group_session_manager
+            .get_outbound_group_session(&room_id)
+            .unwrap();
+
+        let session = rehydrated
+            .store
+            .get_inbound_group_session(
+                &room_id,
+                alice.account.identity_keys().curve25519(),
+                alice_session.session_id(),
+            )
+            .await
+            .unwrap();
+
+        assert!(session.is_some());
+    }
+
     #[tokio::test]
     #[cfg(feature = "sled_cryptostore")]
     async fn test_machine_with_default_store() {
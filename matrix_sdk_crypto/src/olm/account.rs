@@ -451,6 +451,8 @@ pub struct ReadOnlyAccount {
     /// needs to set this for us, depending on the count we will suggest the
     /// client to upload new keys.
     uploaded_signed_key_count: Arc<AtomicI64>,
+    /// Do we have a fallback key that wasn't uploaded to the server yet.
+    unpublished_fallback_key: Arc<AtomicBool>,
 }
 
 /// A typed representation of a base64 encoded string containing the account
@@ -487,6 +489,9 @@ pub struct PickledAccount {
     pub shared: bool,
     /// The number of uploaded one-time keys we have on the server.
     pub uploaded_signed_key_count: i64,
+    /// Do we have a fallback key that wasn't uploaded to the server yet.
+    #[serde(default)]
+    pub unpublished_fallback_key: bool,
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -518,6 +523,16 @@ impl ReadOnlyAccount {
             identity_keys: Arc::new(identity_keys),
             shared: Arc::new(AtomicBool::new(false)),
             uploaded_signed_key_count: Arc::new(AtomicI64::new(0)),
+            unpublished_fallback_key: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Get a copy of the account that uses the given device id, both copies
+    /// share the same Olm account.
+    pub(crate) fn with_device_id(&self, device_id: &DeviceId) -> Self {
+        Self {
+            device_id: Arc::new(device_id.into()),
+            ..self.clone()
         }
     }
 
@@ -603,7 +618,7 @@ impl ReadOnlyAccount {
 
     /// Should account or one-time keys be uploaded to the server.
     pub(crate) async fn should_upload_keys(&self) -> bool {
-        if !self.shared() {
+        if !self.shared() || self.unpublished_fallback_key.load(Ordering::SeqCst) {
             return true;
         }
 
@@ -622,7 +637,8 @@ impl ReadOnlyAccount {
         key_count > 0
     }
 
-    /// Get a tuple of device and one-time keys that need to be uploaded.
+    /// Get a tuple of device, one-time and fallback keys that need to be
+    /// uploaded.
     ///
     /// Returns None if no keys need to be uploaded.
     pub(crate) async fn keys_for_upload(
@@ -630,25 +646,87 @@ impl ReadOnlyAccount {
     ) -> Option<(
         Option<DeviceKeys>,
         Option<BTreeMap<DeviceKeyId, OneTimeKey>>,
+        Option<BTreeMap<DeviceKeyId, OneTimeKey>>,
     )> {
         if !self.should_upload_keys().await {
             return None;
         }
 
         let device_keys = if !self.shared() {
+            // A new device gets a fallback key right away, so other devices
+            // can establish Olm sessions with it even if it runs out of
+            // one-time keys.
+            if self.fallback_key().await.is_empty() {
+                self.generate_fallback_key().await;
+            }
+
             Some(self.device_keys().await)
         } else {
             None
         };
 
         let one_time_keys = self.signed_one_time_keys().await.ok();
+        let fallback_keys = if self.unpublished_fallback_key.load(Ordering::SeqCst) {
+            Some(self.signed_fallback_keys().await)
+        } else {
+            None
+        };
 
-        Some((device_keys, one_time_keys))
+        Some((device_keys, one_time_keys, fallback_keys))
     }
 
-    /// Mark the current set of one-time keys as being published.
+    /// Mark the current set of one-time keys and the fallback key as being
+    /// published.
     pub(crate) async fn mark_keys_as_published(&self) {
         self.inner.lock().await.mark_keys_as_published();
+        self.unpublished_fallback_key.store(false, Ordering::SeqCst);
+    }
+
+    /// Generate a new fallback key, replacing the current one.
+    ///
+    /// The previous fallback key is kept around by libolm so pre-key messages
+    /// that were created using it can still be decrypted.
+    pub(crate) async fn generate_fallback_key(&self) {
+        self.inner.lock().await.generate_fallback_key();
+        self.unpublished_fallback_key.store(true, Ordering::SeqCst);
+    }
+
+    /// Get the current curve25519 fallback key of the account, keyed by its
+    /// key id.
+    ///
+    /// This is empty if no fallback key was generated yet.
+    pub(crate) async fn fallback_key(&self) -> BTreeMap<String, String> {
+        let fallback_key = self.inner.lock().await.fallback_key();
+
+        serde_json::from_str::<BTreeMap<String, BTreeMap<String, String>>>(&fallback_key)
+            .ok()
+            .and_then(|mut keys| keys.remove("curve25519"))
+            .unwrap_or_default()
+    }
+
+    /// Sign the current fallback key so it can be uploaded.
+    pub(crate) async fn signed_fallback_keys(&self) -> BTreeMap<DeviceKeyId, OneTimeKey> {
+        let fallback_key = self.fallback_key().await;
+        self.signed_keys(fallback_key.into_iter().collect()).await
+    }
+
+    /// Rotate the fallback key if the server told us that it was used.
+    ///
+    /// # Arguments
+    ///
+    /// * `unused_fallback_keys` - The key types of the fallback keys that the
+    /// server still holds and that weren't used yet.
+    pub(crate) async fn update_unused_fallback_keys(
+        &self,
+        unused_fallback_keys: &[DeviceKeyAlgorithm],
+    ) {
+        if self.shared()
+            && !unused_fallback_keys.contains(&DeviceKeyAlgorithm::SignedCurve25519)
+            && !self.unpublished_fallback_key.load(Ordering::SeqCst)
+        {
+            debug!("The fallback key was used, generating a new one");
+            self.generate_fallback_key().await;
+        }
     }
 
     /// Sign the given string using the accounts signing key.
@@ -673,6 +751,7 @@ impl ReadOnlyAccount {
             pickle,
             shared: self.shared(),
             uploaded_signed_key_count: self.uploaded_key_count(),
+            unpublished_fallback_key: self.unpublished_fallback_key.load(Ordering::SeqCst),
         }
     }
 
@@ -698,6 +777,7 @@ impl ReadOnlyAccount {
             identity_keys: Arc::new(identity_keys),
             shared: Arc::new(AtomicBool::from(pickle.shared)),
             uploaded_signed_key_count: Arc::new(AtomicI64::new(pickle.uploaded_signed_key_count)),
+            unpublished_fallback_key: Arc::new(AtomicBool::new(pickle.unpublished_fallback_key)),
         })
     }
 
@@ -781,9 +861,24 @@ impl ReadOnlyAccount {
         &self,
     ) -> Result<BTreeMap<DeviceKeyId, OneTimeKey>, ()> {
         let one_time_keys = self.one_time_keys().await;
+        let one_time_keys = one_time_keys
+            .curve25519()
+            .iter()
+            .map(|(key_id, key)| (key_id.to_owned(), key.to_owned()))
+            .collect();
+
+        Ok(self.signed_keys(one_time_keys).await)
+    }
+
+    /// Sign the given curve25519 keys, turning them into signed_curve25519
+    /// keys.
+    pub(crate) async fn signed_keys(
+        &self,
+        keys: Vec<(String, String)>,
+    ) -> BTreeMap<DeviceKeyId, OneTimeKey> {
         let mut one_time_key_map = BTreeMap::new();
 
-        for (key_id, key) in one_time_keys.curve25519().iter() {
+        for (key_id, key) in keys.iter() {
             let key_json = json!({
                 "key": key,
             });
@@ -811,7 +906,7 @@ impl ReadOnlyAccount {
             );
         }
 
-        Ok(one_time_key_map)
+        one_time_key_map
     }
 
     /// Generate, sign and prepare one-time keys to be uploaded.
@@ -948,13 +1043,15 @@ impl ReadOnlyAccount {
             .await
             .create_inbound_session_from(their_identity_key, message)?;
 
-        self.inner
-            .lock()
-            .await
-            .remove_one_time_keys(&session)
-            .expect(
-            "Session was successfully created but the account doesn't hold a matching one-time key",
-        );
+        // Sessions that were created using our fallback key don't have a
+        // matching one-time key, the fallback key stays around until it gets
+        // rotated.
+        if let Err(e) = self.inner.lock().await.remove_one_time_keys(&session) {
+            debug!(
+                "No one-time key matches the new Olm session, the fallback key was used {:?}",
+                e
+            );
+        }
 
         let now = Instant::now();
         let session_id = session.session_id();
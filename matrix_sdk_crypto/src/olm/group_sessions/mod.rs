@@ -19,7 +19,7 @@ use matrix_sdk_common::{
     identifiers::{DeviceKeyAlgorithm, EventEncryptionAlgorithm, RoomId},
 };
 use serde::{Deserialize, Serialize};
-use std::{collections::BTreeMap, convert::TryInto};
+use std::{collections::BTreeMap, convert::TryInto, fmt};
 use zeroize::Zeroize;
 
 mod inbound;
@@ -28,8 +28,131 @@ mod outbound;
 pub use inbound::{InboundGroupSession, InboundGroupSessionPickle, PickledInboundGroupSession};
 pub use outbound::{
     EncryptionSettings, OutboundGroupSession, PickledOutboundGroupSession, ShareState,
+    ShareStrategy,
 };
 
+/// The event type of `m.room_key.withheld` to-device events.
+pub const ROOM_KEY_WITHHELD_EVENT_TYPE: &str = "m.room_key.withheld";
+
+/// The event type that older clients use for `m.room_key.withheld` to-device
+/// events.
+pub const UNSTABLE_ROOM_KEY_WITHHELD_EVENT_TYPE: &str = "org.matrix.room_key.withheld";
+
+/// The reason why a room key was withheld from a device.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum WithheldCode {
+    /// The device was blacklisted by the sender.
+    Blacklisted,
+    /// The device isn't verified by the sender, and the sender only shares
+    /// room keys with verified devices.
+    Unverified,
+    /// The device isn't allowed to see the messages, e.g. because the user
+    /// wasn't a member of the room when the messages were sent.
+    Unauthorised,
+    /// The sender of a key request doesn't have the requested key.
+    Unavailable,
+    /// The sender couldn't establish an Olm session with the device.
+    NoOlm,
+    /// A code that isn't known to us.
+    Custom(String),
+}
+
+impl WithheldCode {
+    /// Get the code as it appears in the `m.room_key.withheld` event.
+    pub fn as_str(&self) -> &str {
+        match self {
+            WithheldCode::Blacklisted => "m.blacklisted",
+            WithheldCode::Unverified => "m.unverified",
+            WithheldCode::Unauthorised => "m.unauthorised",
+            WithheldCode::Unavailable => "m.unavailable",
+            WithheldCode::NoOlm => "m.no_olm",
+            WithheldCode::Custom(c) => c,
+        }
+    }
+
+    /// The human readable reason we send along with the code.
+    fn default_reason(&self) -> Option<&'static str> {
+        match self {
+            WithheldCode::Blacklisted => Some("The sender has blocked you."),
+            WithheldCode::Unverified => {
+                Some("The sender has disabled encrypting to unverified devices.")
+            }
+            WithheldCode::Unauthorised => Some("You are not authorised to read the message."),
+            WithheldCode::Unavailable => Some("The requested key was not found."),
+            WithheldCode::NoOlm => Some("Unable to establish a secure channel."),
+            WithheldCode::Custom(_) => None,
+        }
+    }
+}
+
+impl From<String> for WithheldCode {
+    fn from(code: String) -> Self {
+        match code.as_str() {
+            "m.blacklisted" => WithheldCode::Blacklisted,
+            "m.unverified" => WithheldCode::Unverified,
+            "m.unauthorised" => WithheldCode::Unauthorised,
+            "m.unavailable" => WithheldCode::Unavailable,
+            "m.no_olm" => WithheldCode::NoOlm,
+            _ => WithheldCode::Custom(code),
+        }
+    }
+}
+
+impl From<WithheldCode> for String {
+    fn from(code: WithheldCode) -> Self {
+        code.as_str().to_owned()
+    }
+}
+
+impl fmt::Display for WithheldCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The content of a `m.room_key.withheld` to-device event, telling the
+/// recipient that the sender won't share a room key with them.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RoomKeyWithheldContent {
+    /// The encryption algorithm of the withheld session.
+    pub algorithm: EventEncryptionAlgorithm,
+    /// The room the withheld session is used in, may be missing if the code
+    /// is `m.no_olm`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub room_id: Option<RoomId>,
+    /// The id of the withheld session, may be missing if the code is
+    /// `m.no_olm`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    /// The curve25519 key of the device that created the session.
+    pub sender_key: String,
+    /// The machine readable reason why the session was withheld.
+    pub code: WithheldCode,
+    /// The human readable reason why the session was withheld.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl RoomKeyWithheldContent {
+    /// Create a new withheld content for the given megolm session.
+    pub(crate) fn new(
+        room_id: &RoomId,
+        session_id: &str,
+        sender_key: &str,
+        code: WithheldCode,
+    ) -> Self {
+        Self {
+            algorithm: EventEncryptionAlgorithm::MegolmV1AesSha2,
+            room_id: Some(room_id.to_owned()),
+            session_id: Some(session_id.to_owned()),
+            sender_key: sender_key.to_owned(),
+            reason: code.default_reason().map(ToOwned::to_owned),
+            code,
+        }
+    }
+}
+
 /// The private session key of a group session.
 /// Can be used to create a new inbound group session.
 #[derive(Clone, Debug, Serialize, Deserialize, Zeroize)]
@@ -12,7 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use dashmap::DashMap;
+use dashmap::{DashMap, DashSet};
 use matrix_sdk_common::{
     api::r0::to_device::DeviceIdOrAllDevices,
     events::room::{
@@ -24,7 +24,7 @@ use matrix_sdk_common::{
 };
 use std::{
     cmp::max,
-    collections::BTreeMap,
+    collections::{BTreeMap, BTreeSet},
     fmt,
     sync::{
         atomic::{AtomicBool, AtomicU64, Ordering},
@@ -37,7 +37,7 @@ use tracing::{debug, error, trace};
 use matrix_sdk_common::{
     events::{
         room::{encrypted::EncryptedEventContent, encryption::EncryptionEventContent},
-        AnyMessageEventContent, EventContent,
+        AnyMessageEventContent, EventContent, EventType,
     },
     identifiers::{DeviceId, DeviceIdBox, EventEncryptionAlgorithm, RoomId, UserId},
     instant::Instant,
@@ -70,6 +70,34 @@ pub enum ShareState {
     Shared(u32),
 }
 
+/// Strategy deciding which devices of the room members receive a room key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ShareStrategy {
+    /// Share the room key with every device that isn't blacklisted.
+    AllDevices,
+    /// Only share the room key with devices we trust, either because they
+    /// were verified locally or because they are cross-signed by an identity
+    /// that we verified.
+    VerifiedDevices,
+    /// Only share the room key with devices that are signed by the
+    /// cross-signing identity of their owner, whether or not we verified that
+    /// identity.
+    CrossSignedDevices,
+    /// Share the room key with every device that isn't blacklisted, unless
+    /// the master key of its owner changed since we first saw it and the
+    /// change wasn't acknowledged.
+    ///
+    /// The other strategies, except `AllDevices`, withhold room keys from
+    /// users with unacknowledged identity changes as well.
+    TrustOnFirstUse,
+}
+
+impl Default for ShareStrategy {
+    fn default() -> Self {
+        Self::AllDevices
+    }
+}
+
 /// Settings for an encrypted room.
 ///
 /// This determines the algorithm and rotation periods of a group session.
@@ -83,6 +111,9 @@ pub struct EncryptionSettings {
     pub rotation_period_msgs: u64,
     /// The history visibilty of the room when the session was created.
     pub history_visibility: HistoryVisibility,
+    /// Which devices of the room members should receive the room key.
+    #[serde(default)]
+    pub share_strategy: ShareStrategy,
 }
 
 impl Default for EncryptionSettings {
@@ -92,6 +123,7 @@ impl Default for EncryptionSettings {
             rotation_period: ROTATION_PERIOD,
             rotation_period_msgs: ROTATION_MESSAGES,
             history_visibility: HistoryVisibility::Shared,
+            share_strategy: ShareStrategy::default(),
         }
     }
 }
@@ -112,6 +144,7 @@ impl EncryptionSettings {
             rotation_period,
             rotation_period_msgs,
             history_visibility,
+            share_strategy: ShareStrategy::default(),
         }
     }
 }
@@ -134,6 +167,7 @@ pub struct OutboundGroupSession {
     invalidated: Arc<AtomicBool>,
     settings: Arc<EncryptionSettings>,
     pub(crate) shared_with_set: Arc<DashMap<UserId, DashMap<DeviceIdBox, u32>>>,
+    withheld_from_set: Arc<DashMap<UserId, DashSet<DeviceIdBox>>>,
     to_share_with_set: Arc<DashMap<Uuid, (Arc<ToDeviceRequest>, u32)>>,
 }
 
@@ -174,6 +208,7 @@ impl OutboundGroupSession {
             invalidated: Arc::new(AtomicBool::new(false)),
             settings: Arc::new(settings),
             shared_with_set: Arc::new(DashMap::new()),
+            withheld_from_set: Arc::new(DashMap::new()),
             to_share_with_set: Arc::new(DashMap::new()),
         }
     }
@@ -188,6 +223,39 @@ impl OutboundGroupSession {
             .insert(request_id, (request, message_index));
     }
 
+    /// Add a request carrying `m.room_key.withheld` events for this session.
+    ///
+    /// The recipients of the request are remembered right away so the session
+    /// isn't withheld from them multiple times.
+    pub(crate) fn add_withheld_request(
+        &self,
+        request_id: Uuid,
+        request: Arc<ToDeviceRequest>,
+        message_index: u32,
+    ) {
+        for (user_id, devices) in &request.messages {
+            let withheld = self
+                .withheld_from_set
+                .entry(user_id.clone())
+                .or_insert_with(DashSet::new);
+
+            for device_id in devices.keys() {
+                if let DeviceIdOrAllDevices::DeviceId(d) = device_id {
+                    withheld.insert(d.clone());
+                }
+            }
+        }
+
+        self.add_request(request_id, request, message_index);
+    }
+
+    /// Has the session been withheld from the given user/device pair.
+    pub(crate) fn is_withheld_from(&self, user_id: &UserId, device_id: &DeviceId) -> bool {
+        self.withheld_from_set
+            .get(user_id)
+            .map_or(false, |d| d.contains(device_id))
+    }
+
     /// This should be called if an the user wishes to rotate this session.
     pub fn invalidate_session(&self) {
         self.invalidated.store(true, Ordering::Relaxed)
@@ -209,25 +277,29 @@ impl OutboundGroupSession {
                 "Marking to-device request carrying a room key as sent"
             );
 
-            let user_pairs = r.0.messages.iter().map(|(u, v)| {
-                (
-                    u.clone(),
-                    v.iter().filter_map(|d| {
-                        if let DeviceIdOrAllDevices::DeviceId(d) = d.0 {
-                            Some((d.clone(), r.1))
-                        } else {
-                            None
-                        }
-                    }),
-                )
-            });
-
-            user_pairs.for_each(|(u, d)| {
-                self.shared_with_set
-                    .entry(u)
-                    .or_insert_with(DashMap::new)
-                    .extend(d);
-            });
+            // Requests carrying `m.room_key.withheld` events don't share the
+            // session.
+            if r.0.event_type == EventType::RoomEncrypted {
+                let user_pairs = r.0.messages.iter().map(|(u, v)| {
+                    (
+                        u.clone(),
+                        v.iter().filter_map(|d| {
+                            if let DeviceIdOrAllDevices::DeviceId(d) = d.0 {
+                                Some((d.clone(), r.1))
+                            } else {
+                                None
+                            }
+                        }),
+                    )
+                });
+
+                user_pairs.for_each(|(u, d)| {
+                    self.shared_with_set
+                        .entry(u)
+                        .or_insert_with(DashMap::new)
+                        .extend(d);
+                });
+            }
 
             if self.to_share_with_set.is_empty() {
                 debug!(
@@ -412,11 +484,12 @@ impl OutboundGroupSession {
                 let request = &item.value().0;
                 let message_index = item.value().1;
 
-                if request
-                    .messages
-                    .get(user_id)
-                    .map(|e| e.contains_key(&device_id))
-                    .unwrap_or(false)
+                if request.event_type == EventType::RoomEncrypted
+                    && request
+                        .messages
+                        .get(user_id)
+                        .map(|e| e.contains_key(&device_id))
+                        .unwrap_or(false)
                 {
                     Some(ShareState::Shared(message_index))
                 } else {
@@ -495,6 +568,13 @@ impl OutboundGroupSession {
                     .map(|(k, v)| (k, v.into_iter().collect()))
                     .collect(),
             ),
+            withheld_from_set: Arc::new(
+                pickle
+                    .withheld_from_set
+                    .into_iter()
+                    .map(|(k, v)| (k, v.into_iter().collect()))
+                    .collect(),
+            ),
             to_share_with_set: Arc::new(pickle.requests.into_iter().collect()),
         })
     }
@@ -532,6 +612,16 @@ impl OutboundGroupSession {
                     )
                 })
                 .collect(),
+            withheld_from_set: self
+                .withheld_from_set
+                .iter()
+                .map(|u| {
+                    (
+                        u.key().clone(),
+                        u.value().iter().map(|d| d.key().clone()).collect(),
+                    )
+                })
+                .collect(),
             requests: self
                 .to_share_with_set
                 .iter()
@@ -588,6 +678,9 @@ pub struct PickledOutboundGroupSession {
     pub invalidated: bool,
     /// The set of users the session has been already shared with.
     pub shared_with_set: BTreeMap<UserId, BTreeMap<DeviceIdBox, u32>>,
+    /// The set of users/devices the session has been withheld from.
+    #[serde(default)]
+    pub withheld_from_set: BTreeMap<UserId, BTreeSet<DeviceIdBox>>,
     /// Requests that need to be sent out to share the session.
     pub requests: BTreeMap<Uuid, (Arc<ToDeviceRequest>, u32)>,
 }
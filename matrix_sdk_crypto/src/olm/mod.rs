@@ -28,6 +28,8 @@ pub use account::{AccountPickle, OlmMessageHash, PickledAccount, ReadOnlyAccount
 pub use group_sessions::{
     EncryptionSettings, ExportedRoomKey, InboundGroupSession, InboundGroupSessionPickle,
     OutboundGroupSession, PickledInboundGroupSession, PickledOutboundGroupSession,
+    RoomKeyWithheldContent, ShareStrategy, WithheldCode, ROOM_KEY_WITHHELD_EVENT_TYPE,
+    UNSTABLE_ROOM_KEY_WITHHELD_EVENT_TYPE,
 };
 pub(crate) use group_sessions::{GroupSessionKey, ShareState};
 pub use olm_rs::{account::IdentityKeys, PicklingMode};
@@ -207,6 +209,49 @@ pub(crate) mod test {
         assert_eq!(plaintext, decyrpted);
     }
 
+    #[tokio::test]
+    async fn fallback_key_session_creation() {
+        let alice = ReadOnlyAccount::new(&alice_id(), &alice_device_id());
+        let bob = ReadOnlyAccount::new(&bob_id(), &bob_device_id());
+        let alice_keys = alice.identity_keys();
+
+        assert!(alice.fallback_key().await.is_empty());
+        alice.generate_fallback_key().await;
+        alice.mark_keys_as_published().await;
+
+        let fallback_key = alice
+            .fallback_key()
+            .await
+            .values()
+            .next()
+            .unwrap()
+            .to_owned();
+        let fallback_key = SignedKey::new(fallback_key, BTreeMap::new());
+
+        let mut bob_session = bob
+            .create_outbound_session_helper(alice_keys.curve25519(), &fallback_key)
+            .await
+            .unwrap();
+
+        let plaintext = "Hello world";
+        let message = bob_session.encrypt_helper(plaintext).await;
+
+        let prekey_message = match message.clone() {
+            OlmMessage::PreKey(m) => m,
+            OlmMessage::Message(_) => panic!("Incorrect message type"),
+        };
+
+        let bob_keys = bob.identity_keys();
+        let mut alice_session = alice
+            .create_inbound_session(bob_keys.curve25519(), prekey_message)
+            .await
+            .unwrap();
+
+        assert_eq!(plaintext, alice_session.decrypt(message).await.unwrap());
+        // The fallback key stays around until it gets rotated.
+        assert!(!alice.fallback_key().await.is_empty());
+    }
+
     #[tokio::test]
     async fn group_session_creation() {
         let alice = ReadOnlyAccount::new(&alice_id(), &alice_device_id());
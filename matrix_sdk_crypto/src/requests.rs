@@ -21,18 +21,19 @@ use matrix_sdk_common::{
         keys::{
             claim_keys::Response as KeysClaimResponse,
             get_keys::Response as KeysQueryResponse,
-            upload_keys::{Request as KeysUploadRequest, Response as KeysUploadResponse},
+            upload_keys::Response as KeysUploadResponse,
             upload_signatures::{
                 Request as SignatureUploadRequest, Response as SignatureUploadResponse,
             },
             upload_signing_keys::Response as SigningKeysUploadResponse,
-            CrossSigningKey,
+            CrossSigningKey, OneTimeKey,
         },
         message::send_message_event::Response as RoomMessageResponse,
         to_device::{send_event_to_device::Response as ToDeviceResponse, DeviceIdOrAllDevices},
     },
+    encryption::DeviceKeys,
     events::{AnyMessageEventContent, EventType},
-    identifiers::{DeviceIdBox, RoomId, UserId},
+    identifiers::{DeviceIdBox, DeviceKeyId, RoomId, UserId},
     uuid::Uuid,
 };
 
@@ -73,6 +74,40 @@ impl ToDeviceRequest {
     }
 }
 
+/// The to-device requests of an encrypted to-device event, together with the
+/// devices that the event couldn't be encrypted for.
+#[derive(Clone, Debug, Default)]
+pub struct EncryptedToDeviceRequests {
+    /// The requests that need to be sent out.
+    pub requests: Vec<ToDeviceRequest>,
+    /// The devices that won't receive the event.
+    pub skipped_devices: Vec<SkippedDevice>,
+}
+
+/// A device that a to-device event wasn't encrypted for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SkippedDevice {
+    /// The user id of the owner of the device.
+    pub user_id: UserId,
+    /// The id of the device.
+    pub device_id: DeviceIdBox,
+    /// Why the event wasn't encrypted for the device.
+    pub reason: SkipReason,
+}
+
+/// The reason why a to-device event wasn't encrypted for a device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SkipReason {
+    /// We don't know the device, the keys of its owner might need to be
+    /// queried.
+    UnknownDevice,
+    /// We don't have an Olm session with the device, one-time keys of the
+    /// device need to be claimed first.
+    MissingSession,
+    /// The device was blacklisted by us.
+    Blacklisted,
+}
+
 /// Request that will publish a cross signing identity.
 ///
 /// This uploads the public cross signing key triplet.
@@ -117,6 +152,30 @@ impl KeysQueryRequest {
     }
 }
 
+/// Customized version of `ruma_client_api::r0::keys::upload_keys::Request`,
+/// with support for the fallback keys of [MSC2732].
+///
+/// [MSC2732]: https://github.com/matrix-org/matrix-doc/pull/2732
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct KeysUploadRequest {
+    /// Identity keys for the device. May be absent if no new identity keys
+    /// are required.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_keys: Option<DeviceKeys>,
+
+    /// One-time public keys for "pre-key" messages.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub one_time_keys: Option<BTreeMap<DeviceKeyId, OneTimeKey>>,
+
+    /// A fallback key that is handed out once the one-time keys of the
+    /// device are used up.
+    #[serde(
+        rename = "org.matrix.msc2732.fallback_keys",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub fallback_keys: Option<BTreeMap<DeviceKeyId, OneTimeKey>>,
+}
+
 /// Enum over the different outgoing requests we can have.
 #[derive(Debug)]
 pub enum OutgoingRequests {
@@ -22,10 +22,7 @@ use futures::future::join_all;
 use dashmap::DashMap;
 use matrix_sdk_common::{
     api::r0::to_device::DeviceIdOrAllDevices,
-    events::{
-        room::{encrypted::EncryptedEventContent, history_visibility::HistoryVisibility},
-        AnyMessageEventContent, EventType,
-    },
+    events::{room::encrypted::EncryptedEventContent, AnyMessageEventContent, EventType},
     executor::spawn,
     identifiers::{DeviceId, DeviceIdBox, RoomId, UserId},
     uuid::Uuid,
@@ -35,7 +32,10 @@ use tracing::{debug, info, trace};
 
 use crate::{
     error::{EventError, MegolmResult, OlmResult},
-    olm::{Account, InboundGroupSession, OutboundGroupSession, Session, ShareState},
+    olm::{
+        Account, InboundGroupSession, OutboundGroupSession, RoomKeyWithheldContent, Session,
+        ShareState, ShareStrategy, WithheldCode, ROOM_KEY_WITHHELD_EVENT_TYPE,
+    },
     store::{Changes, Result as StoreResult, Store},
     Device, EncryptionSettings, OlmError, ToDeviceRequest,
 };
@@ -55,13 +55,17 @@ pub struct GroupSessionManager {
 }
 
 impl GroupSessionManager {
-    const MAX_TO_DEVICE_MESSAGES: usize = 250;
+    pub(crate) const MAX_TO_DEVICE_MESSAGES: usize = 250;
 
-    pub(crate) fn new(account: Account, store: Store) -> Self {
+    pub(crate) fn new(
+        account: Account,
+        store: Store,
+        outbound_group_sessions: Arc<DashMap<RoomId, OutboundGroupSession>>,
+    ) -> Self {
         Self {
             account,
             store,
-            outbound_group_sessions: Arc::new(DashMap::new()),
+            outbound_group_sessions,
             outbound_sessions_being_shared: Arc::new(DashMap::new()),
         }
     }
@@ -195,17 +199,22 @@ impl GroupSessionManager {
 
     /// Encrypt the given content for the given devices and create a to-device
     /// requests that sends the encrypted content to them.
-    async fn encrypt_session_for(
+    ///
+    /// Returns the devices that we don't have an Olm session with as well,
+    /// the content isn't sent to them.
+    pub(crate) async fn encrypt_for_devices(
+        event_type: EventType,
         content: Value,
         devices: Vec<Device>,
-    ) -> OlmResult<(Uuid, ToDeviceRequest, Vec<Session>)> {
+    ) -> OlmResult<(Uuid, ToDeviceRequest, Vec<Session>, Vec<Device>)> {
         let mut messages = BTreeMap::new();
         let mut changed_sessions = Vec::new();
+        let mut no_olm_devices = Vec::new();
 
-        let encrypt = |device: Device, content: Value| async move {
+        let encrypt = |device: Device, event_type: EventType, content: Value| async move {
             let mut message = BTreeMap::new();
 
-            let encrypted = device.encrypt(EventType::RoomKey, content.clone()).await;
+            let encrypted = device.encrypt(event_type, content.clone()).await;
 
             let used_session = match encrypted {
                 Ok((session, encrypted)) => {
@@ -216,11 +225,10 @@ impl GroupSessionManager {
                             DeviceIdOrAllDevices::DeviceId(device.device_id().into()),
                             serde_json::value::to_raw_value(&encrypted)?,
                         );
-                    Some(session)
+                    Ok(session)
                 }
-                // TODO we'll want to create m.room_key.withheld here.
                 Err(OlmError::MissingSession)
-                | Err(OlmError::EventError(EventError::MissingSenderKey)) => None,
+                | Err(OlmError::EventError(EventError::MissingSenderKey)) => Err(device),
                 Err(e) => return Err(e),
             };
 
@@ -229,7 +237,7 @@ impl GroupSessionManager {
 
         let tasks: Vec<_> = devices
             .iter()
-            .map(|d| spawn(encrypt(d.clone(), content.clone())))
+            .map(|d| spawn(encrypt(d.clone(), event_type.clone(), content.clone())))
             .collect();
 
         let results = join_all(tasks).await;
@@ -237,8 +245,9 @@ impl GroupSessionManager {
         for result in results {
             let (used_session, message) = result.expect("Encryption task panicked")?;
 
-            if let Some(session) = used_session {
-                changed_sessions.push(session);
+            match used_session {
+                Ok(session) => changed_sessions.push(session),
+                Err(device) => no_olm_devices.push(device),
             }
 
             for (user, device_messages) in message.into_iter() {
@@ -260,29 +269,112 @@ impl GroupSessionManager {
         trace!(
             recipient_count = request.message_count(),
             transaction_id = ?id,
-            "Created a to-device request carrying a room_key"
+            event_type = %event_type,
+            "Created an encrypted to-device request"
+        );
+
+        Ok((id, request, changed_sessions, no_olm_devices))
+    }
+
+    /// Create a to-device request that tells the given devices that the given
+    /// session is withheld from them.
+    fn withheld_request(
+        &self,
+        outbound: &OutboundGroupSession,
+        devices: Vec<(Device, WithheldCode)>,
+    ) -> OlmResult<(Uuid, ToDeviceRequest)> {
+        let sender_key = self.account.identity_keys().curve25519();
+        let mut messages = BTreeMap::new();
+
+        for (device, code) in devices {
+            let content = RoomKeyWithheldContent::new(
+                outbound.room_id(),
+                outbound.session_id(),
+                sender_key,
+                code,
+            );
+
+            messages
+                .entry(device.user_id().clone())
+                .or_insert_with(BTreeMap::new)
+                .insert(
+                    DeviceIdOrAllDevices::DeviceId(device.device_id().into()),
+                    serde_json::value::to_raw_value(&content)?,
+                );
+        }
+
+        let id = Uuid::new_v4();
+
+        let request = ToDeviceRequest {
+            event_type: EventType::from(ROOM_KEY_WITHHELD_EVENT_TYPE),
+            txn_id: id,
+            messages,
+        };
+
+        trace!(
+            recipient_count = request.message_count(),
+            transaction_id = ?id,
+            "Created a to-device request carrying a room_key.withheld"
         );
 
-        Ok((id, request, changed_sessions))
+        Ok((id, request))
+    }
+
+    /// Check if the given device should be excluded from receiving a room key
+    /// under the given share strategy.
+    ///
+    /// Returns the reason the room key will be withheld from the device, or
+    /// `None` if the device should receive the room key.
+    fn withheld_code(
+        device: &Device,
+        strategy: ShareStrategy,
+        identity_changed: bool,
+    ) -> Option<WithheldCode> {
+        if device.is_blacklisted() {
+            return Some(WithheldCode::Blacklisted);
+        }
+
+        let allowed = match strategy {
+            ShareStrategy::AllDevices => true,
+            // Unless the change of the owner's identity was acknowledged,
+            // other strategies don't share with it either.
+            _ if identity_changed => false,
+            ShareStrategy::TrustOnFirstUse => true,
+            ShareStrategy::VerifiedDevices => device.trust_state(),
+            ShareStrategy::CrossSignedDevices => device.is_cross_signed_by_owner(),
+        };
+
+        if allowed {
+            None
+        } else {
+            Some(WithheldCode::Unverified)
+        }
     }
 
     /// Given a list of user and an outbound session, return the list of users
     /// and their devices that this session should be shared with.
     ///
-    /// Returns a boolean indicating whether the session needs to be rotated and
-    /// the list of users/devices that should receive the session.
+    /// Returns a boolean indicating whether the session needs to be rotated,
+    /// the list of users/devices that should receive the session and the list
+    /// of devices the session should be withheld from.
     pub async fn collect_session_recipients(
         &self,
         users: impl Iterator<Item = &UserId>,
-        history_visibility: HistoryVisibility,
+        settings: &EncryptionSettings,
         outbound: &OutboundGroupSession,
-    ) -> OlmResult<(bool, HashMap<UserId, Vec<Device>>)> {
+    ) -> OlmResult<(
+        bool,
+        HashMap<UserId, Vec<Device>>,
+        Vec<(Device, WithheldCode)>,
+    )> {
         let users: HashSet<&UserId> = users.collect();
         let mut devices: HashMap<UserId, Vec<Device>> = HashMap::new();
+        let mut withheld_devices = Vec::new();
 
         debug!(
             users = ?users,
-            history_visibility = ?history_visibility,
+            history_visibility = ?settings.history_visibility,
+            share_strategy = ?settings.share_strategy,
             session_id = outbound.session_id(),
             "Calculating group session recipients"
         );
@@ -302,34 +394,49 @@ impl GroupSessionManager {
             .collect::<HashSet<_>>()
             .is_empty();
 
-        let visibility_changed = outbound.settings().history_visibility != history_visibility;
+        let visibility_changed =
+            outbound.settings().history_visibility != settings.history_visibility;
+        let strategy_changed = outbound.settings().share_strategy != settings.share_strategy;
 
         // To protect the room history we need to rotate the session if either:
         //
         // 1. Any user left the room.
-        // 2. Any of the users' devices got deleted or blacklisted.
+        // 2. Any of the users' devices got deleted, blacklisted or doesn't
+        //    satisfy the share strategy anymore.
         // 3. The history visibility changed.
+        // 4. The share strategy changed.
         //
         // This is calculated in the following code and stored in this variable.
-        let mut should_rotate = user_left || visibility_changed;
+        let mut should_rotate = user_left || visibility_changed || strategy_changed;
 
         for user_id in users {
             let user_devices = self.store.get_user_devices(&user_id).await?;
-            let non_blacklisted_devices: Vec<Device> = user_devices
-                .devices()
-                .filter(|d| !d.is_blacklisted())
-                .collect();
+            // If the identity of the user changed since we pinned it, the
+            // change needs to be acknowledged before we share room keys with
+            // the new identity.
+            let identity_changed = settings.share_strategy != ShareStrategy::AllDevices
+                && self.store.get_identity_change(user_id).await?.is_some();
+            let mut recipients = Vec::new();
+
+            for device in user_devices.devices() {
+                if let Some(code) =
+                    Self::withheld_code(&device, settings.share_strategy, identity_changed)
+                {
+                    withheld_devices.push((device, code));
+                } else {
+                    recipients.push(device);
+                }
+            }
 
             // If we haven't already concluded that the session should be
             // rotated for other reasons, we also need to check whether any
-            // of the devices in the session got deleted or blacklisted in the
-            // meantime. If so, we should also rotate the session.
+            // of the devices in the session got deleted, blacklisted or
+            // stopped being trusted in the meantime. If so, we should also
+            // rotate the session.
             if !should_rotate {
                 // Device IDs that should receive this session
-                let non_blacklisted_device_ids: HashSet<&DeviceId> = non_blacklisted_devices
-                    .iter()
-                    .map(|d| d.device_id())
-                    .collect();
+                let recipient_device_ids: HashSet<&DeviceId> =
+                    recipients.iter().map(|d| d.device_id()).collect();
 
                 if let Some(shared) = outbound.shared_with_set.get(user_id) {
                     #[allow(clippy::map_clone)]
@@ -343,13 +450,13 @@ impl GroupSessionManager {
                     // 1. Devices that had previously received the session, and
                     // 2. Devices that would now receive the session
                     //
-                    // represents newly deleted or blacklisted devices. If this
-                    // set is non-empty, we must rotate.
-                    let newly_deleted_or_blacklisted = shared
-                        .difference(&non_blacklisted_device_ids)
+                    // represents newly deleted, blacklisted or untrusted
+                    // devices. If this set is non-empty, we must rotate.
+                    let newly_excluded = shared
+                        .difference(&recipient_device_ids)
                         .collect::<HashSet<_>>();
 
-                    if !newly_deleted_or_blacklisted.is_empty() {
+                    if !newly_excluded.is_empty() {
                         should_rotate = true;
                     }
                 };
@@ -358,7 +465,7 @@ impl GroupSessionManager {
             devices
                 .entry(user_id.clone())
                 .or_insert_with(Vec::new)
-                .extend(non_blacklisted_devices);
+                .extend(recipients);
         }
 
         debug!(
@@ -367,7 +474,7 @@ impl GroupSessionManager {
             "Done calculating group session recipients"
         );
 
-        Ok((should_rotate, devices))
+        Ok((should_rotate, devices, withheld_devices))
     }
 
     pub async fn encrypt_request(
@@ -376,16 +483,16 @@ impl GroupSessionManager {
         outbound: OutboundGroupSession,
         message_index: u32,
         being_shared: Arc<DashMap<Uuid, OutboundGroupSession>>,
-    ) -> OlmResult<Vec<Session>> {
-        let (id, request, used_sessions) =
-            Self::encrypt_session_for(content.clone(), chunk).await?;
+    ) -> OlmResult<(Vec<Session>, Vec<Device>)> {
+        let (id, request, used_sessions, no_olm_devices) =
+            Self::encrypt_for_devices(EventType::RoomKey, content.clone(), chunk).await?;
 
         if !request.messages.is_empty() {
             outbound.add_request(id, request.into(), message_index);
             being_shared.insert(id, outbound.clone());
         }
 
-        Ok(used_sessions)
+        Ok((used_sessions, no_olm_devices))
     }
 
     /// Get to-device requests to share a group session with users in a room.
@@ -411,7 +518,6 @@ impl GroupSessionManager {
         );
 
         let encryption_settings = encryption_settings.into();
-        let history_visibility = encryption_settings.history_visibility.clone();
         let mut changes = Changes::default();
 
         let (outbound, inbound) = self
@@ -423,8 +529,8 @@ impl GroupSessionManager {
             changes.inbound_group_sessions.push(inbound);
         }
 
-        let (should_rotate, devices) = self
-            .collect_session_recipients(users, history_visibility, &outbound)
+        let (should_rotate, devices, mut withheld_devices) = self
+            .collect_session_recipients(users, &encryption_settings, &outbound)
             .await?;
 
         let outbound = if should_rotate {
@@ -495,9 +601,34 @@ impl GroupSessionManager {
             .collect();
 
         for result in join_all(tasks).await {
-            let used_sessions: OlmResult<Vec<Session>> = result.expect("Encryption task paniced");
+            let (used_sessions, no_olm_devices) = result.expect("Encryption task paniced")?;
+
+            changes.sessions.extend(used_sessions);
+            withheld_devices.extend(no_olm_devices.into_iter().map(|d| (d, WithheldCode::NoOlm)));
+        }
+
+        // Let the devices that won't receive the session know why, but only
+        // once per session.
+        let withheld_devices: Vec<(Device, WithheldCode)> = withheld_devices
+            .into_iter()
+            .filter(|(d, _)| !outbound.is_withheld_from(d.user_id(), d.device_id()))
+            .collect();
+
+        if !withheld_devices.is_empty() {
+            info!(
+                room_id = room_id.as_str(),
+                session_id = outbound.session_id(),
+                withheld = ?withheld_devices
+                    .iter()
+                    .map(|(d, c)| (d.user_id(), d.device_id(), c.as_str()))
+                    .collect::<Vec<_>>(),
+                "Withholding an outbound group session from some devices",
+            );
 
-            changes.sessions.extend(used_sessions?);
+            let (id, request) = self.withheld_request(&outbound, withheld_devices)?;
+            outbound.add_withheld_request(id, request.into(), message_index);
+            self.outbound_sessions_being_shared
+                .insert(id, outbound.clone());
         }
 
         let requests = outbound.pending_requests();
@@ -536,8 +667,14 @@ impl GroupSessionManager {
 
 #[cfg(test)]
 mod test {
+    use std::collections::BTreeMap;
+
     use matrix_sdk_common::{
-        api::r0::keys::{claim_keys, get_keys},
+        api::r0::{
+            keys::{claim_keys, get_keys},
+            to_device::DeviceIdOrAllDevices,
+        },
+        events::EventType,
         identifiers::{room_id, user_id, DeviceIdBox, UserId},
         uuid::Uuid,
         IncomingResponse,
@@ -545,7 +682,11 @@ mod test {
     use matrix_sdk_test::response_from_file;
     use serde_json::Value;
 
-    use crate::{EncryptionSettings, OlmMachine};
+    use super::GroupSessionManager;
+    use crate::{
+        olm::{WithheldCode, ROOM_KEY_WITHHELD_EVENT_TYPE},
+        EncryptionSettings, LocalTrust, OlmMachine, ShareStrategy,
+    };
 
     fn alice_id() -> UserId {
         user_id!("@alice:example.org")
@@ -607,11 +748,142 @@ mod test {
             .await
             .unwrap();
 
-        let event_count: usize = requests.iter().map(|r| r.message_count()).sum();
+        let event_count: usize = requests
+            .iter()
+            .filter(|r| r.event_type == EventType::RoomEncrypted)
+            .map(|r| r.message_count())
+            .sum();
 
         // The keys claim response has a couple of one-time keys with invalid
         // signatures, thus only 148 sessions are actually created, we check
         // that all 148 valid sessions get an room key.
         assert_eq!(event_count, 148);
+
+        // The two devices without a valid one-time key, and thus without a
+        // session, are told that the room key is withheld from them.
+        let withheld: BTreeMap<(UserId, DeviceIdOrAllDevices), String> = requests
+            .iter()
+            .filter(|r| r.event_type == EventType::from(ROOM_KEY_WITHHELD_EVENT_TYPE))
+            .flat_map(|r| r.messages.iter())
+            .flat_map(|(user_id, messages)| {
+                messages.iter().map(move |(device, content)| {
+                    let content: Value = serde_json::from_str(content.get()).unwrap();
+                    (
+                        (user_id.clone(), device.clone()),
+                        content["code"].as_str().unwrap().to_owned(),
+                    )
+                })
+            })
+            .collect();
+
+        let expected: BTreeMap<(UserId, DeviceIdOrAllDevices), String> =
+            ["BNYQQWUMXO", "WSKKLTJZCL"]
+                .iter()
+                .map(|device_id| {
+                    (
+                        (
+                            user_id!("@example:localhost"),
+                            DeviceIdOrAllDevices::DeviceId((*device_id).into()),
+                        ),
+                        "m.no_olm".to_owned(),
+                    )
+                })
+                .collect();
+
+        assert_eq!(withheld, expected);
+    }
+
+    #[tokio::test]
+    async fn test_sharing_with_verified_devices_only() {
+        let machine = machine().await;
+        let room_id = room_id!("!test:localhost");
+        let keys_claim = keys_claim_response();
+
+        assert_eq!(
+            machine.share_strategy(&room_id).await.unwrap(),
+            ShareStrategy::AllDevices
+        );
+
+        machine
+            .set_room_share_strategy(&room_id, Some(ShareStrategy::VerifiedDevices))
+            .await
+            .unwrap();
+
+        let strategy = machine.share_strategy(&room_id).await.unwrap();
+        assert_eq!(strategy, ShareStrategy::VerifiedDevices);
+
+        let users: Vec<_> = keys_claim.one_time_keys.keys().collect();
+        let settings = EncryptionSettings {
+            share_strategy: strategy,
+            ..Default::default()
+        };
+
+        let requests = machine
+            .share_group_session(&room_id, users.into_iter(), settings)
+            .await
+            .unwrap();
+
+        // None of the devices are verified, so none of them get the room key.
+        assert!(!requests
+            .iter()
+            .any(|r| r.event_type == EventType::RoomEncrypted));
+
+        let codes: Vec<String> = requests
+            .iter()
+            .flat_map(|r| r.messages.values())
+            .flat_map(|m| m.values())
+            .map(|c| {
+                let content: Value = serde_json::from_str(c.get()).unwrap();
+                content["code"].as_str().unwrap().to_owned()
+            })
+            .collect();
+
+        assert!(!codes.is_empty());
+        assert!(codes.iter().all(|c| c == "m.unverified"));
+    }
+
+    #[tokio::test]
+    async fn test_withholding_on_identity_changes() {
+        let machine = machine().await;
+        let keys_query = keys_query_response();
+
+        let user_id = keys_query.device_keys.keys().next().unwrap();
+        let device = machine
+            .get_user_devices(user_id)
+            .await
+            .unwrap()
+            .devices()
+            .next()
+            .unwrap();
+
+        // Sharing with every device ignores identity changes.
+        assert_eq!(
+            GroupSessionManager::withheld_code(&device, ShareStrategy::AllDevices, true),
+            None
+        );
+
+        // Trust on first use only withholds the room key if the identity
+        // changed.
+        assert_eq!(
+            GroupSessionManager::withheld_code(&device, ShareStrategy::TrustOnFirstUse, false),
+            None
+        );
+        assert_eq!(
+            GroupSessionManager::withheld_code(&device, ShareStrategy::TrustOnFirstUse, true),
+            Some(WithheldCode::Unverified)
+        );
+
+        device.set_trust_state(LocalTrust::Verified);
+
+        // A verified device doesn't get the room key either if the identity
+        // of its owner changed.
+        assert_eq!(
+            GroupSessionManager::withheld_code(&device, ShareStrategy::VerifiedDevices, false),
+            None
+        );
+        assert_eq!(
+            GroupSessionManager::withheld_code(&device, ShareStrategy::VerifiedDevices, true),
+            Some(WithheldCode::Unverified)
+        );
     }
 }
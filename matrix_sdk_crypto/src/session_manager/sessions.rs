@@ -108,7 +108,6 @@ impl SessionManager {
         Ok(())
     }
 
-    #[allow(dead_code)]
     pub fn is_device_wedged(&self, device: &ReadOnlyDevice) -> bool {
         self.wedged_devices
             .get(device.user_id())
@@ -116,6 +115,16 @@ impl SessionManager {
             .unwrap_or(false)
     }
 
+    /// Is the Olm session with the device of the given user that owns the
+    /// given curve25519 key wedged.
+    pub async fn is_sender_wedged(&self, sender: &UserId, curve_key: &str) -> StoreResult<bool> {
+        Ok(self
+            .store
+            .get_device_from_curve_key(sender, curve_key)
+            .await?
+            .map_or(false, |d| self.is_device_wedged(&d)))
+    }
+
     /// Check if the session was created to unwedge a Device.
     ///
     /// If the device was wedged this will queue up a dummy to-device message.
@@ -221,6 +221,15 @@ impl CryptoStore for MemoryStore {
         Ok(self.values.get(key).map(|v| v.to_owned()))
     }
 
+    async fn get_values_with_prefix(&self, prefix: &str) -> Result<Vec<String>> {
+        Ok(self
+            .values
+            .iter()
+            .filter(|v| v.key().starts_with(prefix))
+            .map(|v| v.value().to_owned())
+            .collect())
+    }
+
     async fn load_identity(&self) -> Result<Option<PrivateCrossSigningIdentity>> {
         Ok(None)
     }
@@ -49,7 +49,7 @@ pub use memorystore::MemoryStore;
 pub use pickle_key::{EncryptedPickleKey, PickleKey};
 
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     fmt::Debug,
     io::Error as IoError,
     ops::Deref,
@@ -73,7 +73,7 @@ use matrix_sdk_common::{
 
 use crate::{
     error::SessionUnpicklingError,
-    identities::{Device, ReadOnlyDevice, UserDevices, UserIdentities},
+    identities::{Device, IdentityChange, ReadOnlyDevice, UserDevices, UserIdentities},
     olm::{
         InboundGroupSession, OlmMessageHash, OutboundGroupSession, PrivateCrossSigningIdentity,
         ReadOnlyAccount, Session,
@@ -81,6 +81,20 @@ use crate::{
     verification::VerificationMachine,
 };
 
+/// The prefix of the keys under which the unacknowledged identity changes are
+/// stored, every user gets its own key.
+const IDENTITY_CHANGE_PREFIX: &str = "identity_change|";
+
+/// The key under which the unacknowledged identity change of a user is stored.
+fn identity_change_key(user_id: &UserId) -> String {
+    format!("{}{}", IDENTITY_CHANGE_PREFIX, user_id)
+}
+
+/// The key under which the pinned master key of a user is stored.
+fn pinned_master_key_key(user_id: &UserId) -> String {
+    format!("pinned_master_key|{}", user_id)
+}
+
 /// A `CryptoStore` specific result type.
 pub type Result<T, E = CryptoStoreError> = std::result::Result<T, E>;
 
@@ -275,6 +289,52 @@ impl Store {
         self.inner.remove_value(key).await?;
         Ok(())
     }
+
+    /// Get the public keys of the master key we pinned for the given user.
+    pub async fn get_pinned_master_key(
+        &self,
+        user_id: &UserId,
+    ) -> Result<Option<BTreeMap<String, String>>> {
+        self.get_object(&pinned_master_key_key(user_id)).await
+    }
+
+    /// Pin the master key of the given user.
+    pub async fn pin_master_key(
+        &self,
+        user_id: &UserId,
+        master_key: &BTreeMap<String, String>,
+    ) -> Result<()> {
+        self.save_object(&pinned_master_key_key(user_id), master_key)
+            .await
+    }
+
+    /// Get the identity changes that weren't acknowledged yet.
+    pub async fn get_identity_changes(&self) -> Result<Vec<IdentityChange>> {
+        self.inner
+            .get_values_with_prefix(IDENTITY_CHANGE_PREFIX)
+            .await?
+            .iter()
+            .map(|v| Ok(serde_json::from_str(v)?))
+            .collect()
+    }
+
+    /// Get the identity change of the given user that wasn't acknowledged
+    /// yet.
+    pub async fn get_identity_change(&self, user_id: &UserId) -> Result<Option<IdentityChange>> {
+        self.get_object(&identity_change_key(user_id)).await
+    }
+
+    /// Save an identity change that wasn't acknowledged yet, replacing the
+    /// previous change of the same user.
+    pub async fn save_identity_change(&self, change: &IdentityChange) -> Result<()> {
+        self.save_object(&identity_change_key(&change.user_id), change)
+            .await
+    }
+
+    /// Remove the identity change of the given user.
+    pub async fn remove_identity_change(&self, user_id: &UserId) -> Result<()> {
+        self.delete_object(&identity_change_key(user_id)).await
+    }
 }
 
 impl Deref for Store {
@@ -447,6 +507,16 @@ pub trait CryptoStore: AsyncTraitDeps {
     /// Load a serializeable object from the store.
     async fn get_value(&self, key: &str) -> Result<Option<String>>;
 
+    /// Load all the serializeable objects whose key starts with the given
+    /// prefix from the store.
+    ///
+    /// The default implementation doesn't find any values, stores that don't
+    /// override it won't resume pending signature uploads after a restart and
+    /// won't list the unacknowledged identity changes.
+    async fn get_values_with_prefix(&self, _prefix: &str) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
     /// Check if a hash for an Olm message stored in the database.
     async fn is_message_known(&self, message_hash: &OlmMessageHash) -> Result<bool>;
 }
@@ -623,6 +623,13 @@ impl CryptoStore for SledStore {
             .map(|v| String::from_utf8_lossy(&v).to_string()))
     }
 
+    async fn get_values_with_prefix(&self, prefix: &str) -> Result<Vec<String>> {
+        self.values
+            .scan_prefix(prefix.as_bytes())
+            .map(|v| Ok(String::from_utf8_lossy(&v?.1).to_string()))
+            .collect()
+    }
+
     async fn load_identity(&self) -> Result<Option<PrivateCrossSigningIdentity>> {
         if let Some(i) = self.private_identity.get("identity".encode())? {
             let pickle = serde_json::from_slice(&i)?;
@@ -1199,6 +1206,29 @@ mod test {
         assert!(store.get_value(&key).await.unwrap().is_none());
     }
 
+    #[async_test]
+    async fn prefixed_value_loading() {
+        let (_, store, _dir) = get_loaded_store().await;
+
+        store
+            .save_value("prefix/first".to_string(), "first".to_string())
+            .await
+            .unwrap();
+        store
+            .save_value("prefix/second".to_string(), "second".to_string())
+            .await
+            .unwrap();
+        store
+            .save_value("other".to_string(), "other".to_string())
+            .await
+            .unwrap();
+
+        let mut values = store.get_values_with_prefix("prefix/").await.unwrap();
+        values.sort();
+
+        assert_eq!(values, vec!["first".to_string(), "second".to_string()]);
+    }
+
     #[async_test]
     async fn olm_hash_saving() {
         let (_, store, _dir) = get_loaded_store().await;
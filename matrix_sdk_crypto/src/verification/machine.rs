@@ -29,8 +29,10 @@ use matrix_sdk_common::{
 };
 
 use super::{
+    remove_signature_upload,
     requests::VerificationRequest,
     sas::{content_to_request, OutgoingContent, Sas, VerificationResult},
+    signature_upload_requests,
 };
 
 use crate::{
@@ -183,6 +185,20 @@ impl VerificationMachine {
         self.outgoing_to_device_messages.remove(uuid);
     }
 
+    /// Forget the stored signature upload with the given request id, the
+    /// server confirmed the upload.
+    pub async fn mark_signature_upload_as_sent(&self, uuid: &Uuid) -> Result<(), CryptoStoreError> {
+        remove_signature_upload(&**self.store, uuid).await?;
+        Ok(())
+    }
+
+    /// Get the signature uploads that the server didn't confirm yet.
+    pub async fn outgoing_signature_upload_requests(
+        &self,
+    ) -> Result<Vec<OutgoingRequest>, CryptoStoreError> {
+        signature_upload_requests(&**self.store).await
+    }
+
     pub fn outgoing_room_message_requests(&self) -> Vec<OutgoingRequest> {
         self.outgoing_room_messages
             .iter()
@@ -329,7 +345,9 @@ impl VerificationMachine {
 
                         if s.is_done() {
                             match s.mark_as_done().await? {
-                                VerificationResult::Ok => {
+                                // Signature uploads are stored and handed out
+                                // with the rest of the outgoing requests.
+                                VerificationResult::Ok | VerificationResult::SignatureUpload(_) => {
                                     if let Some(c) = content {
                                         self.queue_up_content(
                                             s.other_user_id(),
@@ -342,20 +360,6 @@ impl VerificationMachine {
                                     self.outgoing_to_device_messages
                                         .insert(r.request_id(), r.into());
                                 }
-                                VerificationResult::SignatureUpload(r) => {
-                                    let request: OutgoingRequest = r.into();
-
-                                    self.outgoing_to_device_messages
-                                        .insert(request.request_id, request);
-
-                                    if let Some(c) = content {
-                                        self.queue_up_content(
-                                            s.other_user_id(),
-                                            s.other_device_id(),
-                                            c,
-                                        );
-                                    }
-                                }
                             }
                         }
                     };
@@ -430,7 +434,9 @@ impl VerificationMachine {
 
                     if s.is_done() {
                         match s.mark_as_done().await? {
-                            VerificationResult::Ok => (),
+                            // Signature uploads are stored and handed out with
+                            // the rest of the outgoing requests.
+                            VerificationResult::Ok | VerificationResult::SignatureUpload(_) => (),
                             VerificationResult::Cancel(r) => {
                                 self.outgoing_to_device_messages.insert(
                                     r.request_id(),
@@ -440,17 +446,6 @@ impl VerificationMachine {
                                     },
                                 );
                             }
-                            VerificationResult::SignatureUpload(r) => {
-                                let request_id = Uuid::new_v4();
-
-                                self.outgoing_to_device_messages.insert(
-                                    request_id,
-                                    OutgoingRequest {
-                                        request_id,
-                                        request: Arc::new(r.into()),
-                                    },
-                                );
-                            }
                         }
                     }
                 };
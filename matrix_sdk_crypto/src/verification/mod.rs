@@ -16,24 +16,235 @@ mod machine;
 mod requests;
 mod sas;
 
+use std::{collections::BTreeMap, sync::Arc};
+
+use matrix_sdk_common::{
+    api::r0::keys::upload_signatures::Request as SignatureUploadRequest, identifiers::UserId,
+    uuid::Uuid,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{error, warn};
+
 pub use machine::VerificationMachine;
 pub use requests::VerificationRequest;
 pub use sas::{AcceptSettings, Sas, VerificationResult};
 
+use crate::{
+    error::SignatureError,
+    identities::{ReadOnlyDevice, UserIdentities},
+    olm::PrivateCrossSigningIdentity,
+    requests::OutgoingRequest,
+    store::{CryptoStore, CryptoStoreError},
+};
+
+/// The key prefix under which signature uploads that the server didn't confirm
+/// yet are stored, every upload is stored under its own key so concurrent
+/// uploads don't overwrite each other.
+const PENDING_SIGNATURE_UPLOAD_PREFIX: &str = "pending_signature_upload/";
+
+fn pending_signature_upload_key(request_id: &Uuid) -> String {
+    format!("{}{}", PENDING_SIGNATURE_UPLOAD_PREFIX, request_id)
+}
+
+/// A signature upload that the server didn't confirm yet.
+#[derive(Debug, Deserialize, Serialize)]
+struct PendingSignatureUpload {
+    request_id: Uuid,
+    signed_keys: BTreeMap<UserId, BTreeMap<String, Value>>,
+}
+
+impl From<PendingSignatureUpload> for OutgoingRequest {
+    fn from(upload: PendingSignatureUpload) -> Self {
+        Self {
+            request_id: upload.request_id,
+            request: Arc::new(SignatureUploadRequest::new(upload.signed_keys).into()),
+        }
+    }
+}
+
+/// Store a signature upload request so it can be retried until the server
+/// confirms it.
+///
+/// Returns an outgoing request that needs to be marked as sent once the
+/// server confirms the upload.
+pub(crate) async fn save_signature_upload(
+    store: &dyn CryptoStore,
+    request: SignatureUploadRequest,
+) -> Result<OutgoingRequest, CryptoStoreError> {
+    let upload = PendingSignatureUpload {
+        request_id: Uuid::new_v4(),
+        signed_keys: request.signed_keys,
+    };
+
+    store
+        .save_value(
+            pending_signature_upload_key(&upload.request_id),
+            serde_json::to_string(&upload)?,
+        )
+        .await?;
+
+    Ok(upload.into())
+}
+
+/// Get the signature upload requests that the server didn't confirm yet.
+pub(crate) async fn signature_upload_requests(
+    store: &dyn CryptoStore,
+) -> Result<Vec<OutgoingRequest>, CryptoStoreError> {
+    store
+        .get_values_with_prefix(PENDING_SIGNATURE_UPLOAD_PREFIX)
+        .await?
+        .iter()
+        .map(|v| {
+            let upload: PendingSignatureUpload = serde_json::from_str(v)?;
+            Ok(upload.into())
+        })
+        .collect()
+}
+
+/// Forget the signature upload with the given request id, the server confirmed
+/// the upload.
+///
+/// Returns true if the request id belonged to a stored signature upload.
+pub(crate) async fn remove_signature_upload(
+    store: &dyn CryptoStore,
+    request_id: &Uuid,
+) -> Result<bool, CryptoStoreError> {
+    let key = pending_signature_upload_key(request_id);
+
+    if store.get_value(&key).await?.is_some() {
+        store.remove_value(&key).await?;
+        Ok(true)
+    } else {
+        Ok(false)
+    }
+}
+
+/// Create the cross signing signatures for a device and a user identity that
+/// we verified.
+///
+/// Devices of our own user get signed with our self signing key, identities
+/// of other users get signed with our user signing key, everything else
+/// doesn't need a signature.
+///
+/// Returns `None` if there's nothing to sign or if the needed private signing
+/// keys are missing.
+pub(crate) async fn sign_verified(
+    private_identity: &PrivateCrossSigningIdentity,
+    device: Option<&ReadOnlyDevice>,
+    identity: Option<&UserIdentities>,
+) -> Option<SignatureUploadRequest> {
+    let device_request =
+        if let Some(device) = device.filter(|d| d.user_id() == private_identity.user_id()) {
+            match private_identity.sign_device(device).await {
+                Ok(r) => Some(r),
+                Err(SignatureError::MissingSigningKey) => {
+                    warn!(
+                        "Can't sign the device keys for {} {}, \
+                          no private user signing key found",
+                        device.user_id(),
+                        device.device_id(),
+                    );
+
+                    None
+                }
+                Err(e) => {
+                    error!(
+                        "Error signing device keys for {} {} {:?}",
+                        device.user_id(),
+                        device.device_id(),
+                        e
+                    );
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+    let identity_request = if let Some(i) = identity.and_then(|i| i.other()) {
+        // Signing can fail if the user signing key is missing.
+        match private_identity.sign_user(&i).await {
+            Ok(r) => Some(r),
+            Err(SignatureError::MissingSigningKey) => {
+                warn!(
+                    "Can't sign the public cross signing keys for {}, \
+                          no private user signing key found",
+                    i.user_id()
+                );
+                None
+            }
+            Err(e) => {
+                error!(
+                    "Error signing the public cross signing keys for {} {:?}",
+                    i.user_id(),
+                    e
+                );
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // If there are two signature upload requests, merge them. Otherwise use
+    // the one we have or None.
+    match (device_request, identity_request) {
+        (Some(mut r), Some(user_request)) => {
+            r.signed_keys.extend(user_request.signed_keys);
+            Some(r)
+        }
+        (Some(r), None) | (None, Some(r)) => Some(r),
+        (None, None) => None,
+    }
+}
+
 #[cfg(test)]
 pub(crate) mod test {
+    use std::collections::BTreeMap;
+
     use crate::{
         requests::{OutgoingRequest, OutgoingRequests},
+        store::MemoryStore,
         OutgoingVerificationRequest,
     };
+    use futures::future::join_all;
     use serde_json::Value;
 
     use matrix_sdk_common::{
+        api::r0::keys::upload_signatures::Request as SignatureUploadRequest,
         events::{AnyToDeviceEvent, AnyToDeviceEventContent, EventType, ToDeviceEvent},
         identifiers::UserId,
     };
+    use matrix_sdk_test::async_test;
+
+    use super::{
+        remove_signature_upload, sas::OutgoingContent, save_signature_upload,
+        signature_upload_requests,
+    };
 
-    use super::sas::OutgoingContent;
+    #[async_test]
+    async fn pending_signature_uploads() {
+        let store = MemoryStore::new();
+
+        let requests =
+            join_all((0..10).map(|_| {
+                save_signature_upload(&store, SignatureUploadRequest::new(BTreeMap::new()))
+            }))
+            .await;
+        let requests: Vec<OutgoingRequest> = requests.into_iter().map(|r| r.unwrap()).collect();
+
+        // Concurrent uploads don't overwrite each other.
+        assert_eq!(signature_upload_requests(&store).await.unwrap().len(), 10);
+
+        let request_id = requests[0].request_id();
+        assert!(remove_signature_upload(&store, request_id).await.unwrap());
+        assert!(!remove_signature_upload(&store, request_id).await.unwrap());
+
+        let pending = signature_upload_requests(&store).await.unwrap();
+        assert_eq!(pending.len(), 9);
+        assert!(pending.iter().all(|r| r.request_id() != request_id));
+    }
 
     pub(crate) fn request_to_event(
         sender: &UserId,
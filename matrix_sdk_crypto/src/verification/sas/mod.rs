@@ -22,10 +22,9 @@ use std::time::Instant;
 
 use event_enums::AcceptContent;
 use std::sync::{Arc, Mutex};
-use tracing::{error, info, trace, warn};
+use tracing::{info, trace, warn};
 
 use matrix_sdk_common::{
-    api::r0::keys::upload_signatures::Request as SignatureUploadRequest,
     events::{
         key::verification::{
             accept::{AcceptEventContent, AcceptMethod, AcceptToDeviceEventContent},
@@ -39,15 +38,16 @@ use matrix_sdk_common::{
 };
 
 use crate::{
-    error::SignatureError,
     identities::{LocalTrust, ReadOnlyDevice, UserIdentities},
     olm::PrivateCrossSigningIdentity,
-    requests::{OutgoingVerificationRequest, RoomMessageRequest},
+    requests::{OutgoingRequest, OutgoingVerificationRequest, RoomMessageRequest},
     store::{Changes, CryptoStore, CryptoStoreError, DeviceChanges},
     ReadOnlyAccount, ToDeviceRequest,
 };
 
 pub use helpers::content_to_request;
+
+use super::{save_signature_upload, sign_verified};
 use inner_sas::InnerSas;
 pub use sas_state::FlowId;
 
@@ -64,7 +64,11 @@ pub enum VerificationResult {
     /// The verification was canceled.
     Cancel(OutgoingVerificationRequest),
     /// The verification is done and has signatures that need to be uploaded.
-    SignatureUpload(SignatureUploadRequest),
+    ///
+    /// The signatures are stored and returned by
+    /// `OlmMachine::outgoing_requests()` as well until the request is marked
+    /// as sent.
+    SignatureUpload(OutgoingRequest),
 }
 
 #[derive(Clone, Debug)]
@@ -295,15 +299,15 @@ impl Sas {
     /// Does nothing if we're not in a state where we can confirm the short auth
     /// string, otherwise returns a `MacEventContent` that needs to be sent to
     /// the server.
+    ///
+    /// If the verification is done, a signature upload request might be
+    /// returned as well. The request is stored until its response is passed
+    /// to `OlmMachine::mark_request_as_sent()`, it will be returned by
+    /// `OlmMachine::outgoing_requests()` until then.
     pub async fn confirm(
         &self,
-    ) -> Result<
-        (
-            Option<OutgoingVerificationRequest>,
-            Option<SignatureUploadRequest>,
-        ),
-        CryptoStoreError,
-    > {
+    ) -> Result<(Option<OutgoingVerificationRequest>, Option<OutgoingRequest>), CryptoStoreError>
+    {
         let (content, done) = {
             let mut guard = self.inner.lock().unwrap();
             let sas: InnerSas = (*guard).clone();
@@ -340,33 +344,8 @@ impl Sas {
         if let Some(device) = self.mark_device_as_verified().await? {
             let identity = self.mark_identity_as_verified().await?;
 
-            // We only sign devices of our own user here.
-            let signature_request = if device.user_id() == self.user_id() {
-                match self.private_identity.sign_device(&device).await {
-                    Ok(r) => Some(r),
-                    Err(SignatureError::MissingSigningKey) => {
-                        warn!(
-                            "Can't sign the device keys for {} {}, \
-                                  no private user signing key found",
-                            device.user_id(),
-                            device.device_id(),
-                        );
-
-                        None
-                    }
-                    Err(e) => {
-                        error!(
-                            "Error signing device keys for {} {} {:?}",
-                            device.user_id(),
-                            device.device_id(),
-                            e
-                        );
-                        None
-                    }
-                }
-            } else {
-                None
-            };
+            let signature_request =
+                sign_verified(&self.private_identity, Some(&device), identity.as_ref()).await;
 
             let mut changes = Changes {
                 devices: DeviceChanges {
@@ -376,61 +355,22 @@ impl Sas {
                 ..Default::default()
             };
 
-            let identity_signature_request = if let Some(i) = identity {
-                // We only sign other users here.
-                let request = if let Some(i) = i.other() {
-                    // Signing can fail if the user signing key is missing.
-                    match self.private_identity.sign_user(&i).await {
-                        Ok(r) => Some(r),
-                        Err(SignatureError::MissingSigningKey) => {
-                            warn!(
-                                "Can't sign the public cross signing keys for {}, \
-                                  no private user signing key found",
-                                i.user_id()
-                            );
-                            None
-                        }
-                        Err(e) => {
-                            error!(
-                                "Error signing the public cross signing keys for {} {:?}",
-                                i.user_id(),
-                                e
-                            );
-                            None
-                        }
-                    }
-                } else {
-                    None
-                };
-
+            if let Some(i) = identity {
                 changes.identities.changed.push(i);
+            }
 
-                request
-            } else {
-                None
-            };
-
-            // If there are two signature upload requests, merge them. Otherwise
-            // use the one we have or None.
-            //
-            // Realistically at most one reuqest will be used but let's make
-            // this future proof.
-            let merged_request = if let Some(mut r) = signature_request {
-                if let Some(user_request) = identity_signature_request {
-                    r.signed_keys.extend(user_request.signed_keys);
-                    Some(r)
-                } else {
-                    Some(r)
-                }
-            } else if let Some(r) = identity_signature_request {
-                Some(r)
+            // Store the signatures before the trust changes, if we crash in
+            // between the device won't be marked as verified but we won't lose
+            // any signatures either.
+            let signature_request = if let Some(r) = signature_request {
+                Some(save_signature_upload(&**self.store, r).await?)
             } else {
                 None
             };
 
-            // TODO store the request as well.
             self.store.save_changes(changes).await?;
-            Ok(merged_request
+
+            Ok(signature_request
                 .map(VerificationResult::SignatureUpload)
                 .unwrap_or(VerificationResult::Ok))
         } else {
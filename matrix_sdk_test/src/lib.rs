@@ -117,9 +117,9 @@ impl EventBuilder {
     }
 
     /// Add an event to the room events `Vec`.
-    #[allow(clippy::match_single_binding, unused)]
     pub fn add_account(&mut self, json: EventsJson) -> &mut Self {
         let val: &JsonValue = match json {
+            EventsJson::Tag => &test_json::TAG,
             _ => panic!("unknown account event {:?}", json),
         };
 
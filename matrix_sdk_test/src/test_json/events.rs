@@ -35,6 +35,28 @@ lazy_static! {
     });
 }
 
+lazy_static! {
+    pub static ref CAPABILITIES: JsonValue = json!({
+        "capabilities": {
+            "m.change_password": {
+                "enabled": false
+            },
+            "m.room_versions": {
+                "default": "6",
+                "available": {
+                    "1": "stable",
+                    "2": "stable",
+                    "3": "stable",
+                    "4": "stable",
+                    "5": "stable",
+                    "6": "stable",
+                    "org.matrix.msc2176": "unstable"
+                }
+            }
+        }
+    });
+}
+
 lazy_static! {
     pub static ref CREATE: JsonValue = json!({
         "content": {
@@ -595,6 +617,19 @@ lazy_static! {
     });
 }
 
+lazy_static! {
+    pub static ref THREEPIDS: JsonValue = json!({
+        "threepids": [
+            {
+                "medium": "email",
+                "address": "example@example.org",
+                "validated_at": 1535176800000u64,
+                "added_at": 1535336848756u64
+            }
+        ]
+    });
+}
+
 // TODO: Move `prev_content` into `unsigned` once ruma supports it
 lazy_static! {
     pub static ref TOPIC: JsonValue = json!({
@@ -629,3 +664,75 @@ lazy_static! {
         "type": "m.typing"
     });
 }
+
+lazy_static! {
+    pub static ref VERSIONS: JsonValue = json!({
+        "versions": [
+            "r0.0.1",
+            "r0.1.0",
+            "r0.2.0",
+            "r0.3.0",
+            "r0.4.0",
+            "r0.5.0",
+            "r0.6.0"
+        ],
+        "unstable_features": {
+            "org.matrix.label_based_filtering":true,
+            "org.matrix.e2e_cross_signing":true
+        }
+    });
+}
+
+lazy_static! {
+    pub static ref WELL_KNOWN: JsonValue = json!({
+        "m.homeserver": {
+            "base_url": "HOMESERVER_URL"
+        },
+        "m.identity_server": {
+            "base_url": "https://identity.example.org"
+        }
+    });
+}
+
+lazy_static! {
+    pub static ref WHOAMI: JsonValue = json!({
+        "user_id": "@example:localhost"
+    });
+}
+
+lazy_static! {
+    pub static ref SPACE_HIERARCHY: JsonValue = json!({
+        "rooms": [
+            {
+                "room_id": "!space:localhost",
+                "name": "Space",
+                "num_joined_members": 2,
+                "world_readable": false,
+                "guest_can_join": false,
+                "join_rule": "public",
+                "room_type": "m.space",
+                "children_state": [
+                    {
+                        "content": {
+                            "via": ["localhost"]
+                        },
+                        "sender": "@example:localhost",
+                        "state_key": "!first:localhost",
+                        "type": "m.space.child"
+                    }
+                ]
+            },
+            {
+                "room_id": "!first:localhost",
+                "name": "First",
+                "topic": "The first room",
+                "num_joined_members": 1,
+                "world_readable": true,
+                "guest_can_join": false,
+                "join_rule": "public",
+                "children_state": []
+            }
+        ],
+        "next_batch": "next_page"
+    });
+}
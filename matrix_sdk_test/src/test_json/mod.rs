@@ -12,13 +12,15 @@ pub mod members;
 pub mod sync;
 
 pub use events::{
-    ALIAS, ALIASES, EVENT_ID, KEYS_QUERY, KEYS_UPLOAD, LOGIN, LOGIN_RESPONSE_ERR, LOGIN_TYPES,
-    LOGOUT, MEMBER, MEMBER_NAME_CHANGE, MESSAGE_EDIT, MESSAGE_TEXT, NAME, POWER_LEVELS, PRESENCE,
-    PUBLIC_ROOMS, REACTION, REDACTED, REDACTED_INVALID, REDACTED_STATE, REDACTION,
-    REGISTRATION_RESPONSE_ERR, ROOM_ID, ROOM_MESSAGES, TYPING,
+    ALIAS, ALIASES, CAPABILITIES, EVENT_ID, KEYS_QUERY, KEYS_UPLOAD, LOGIN, LOGIN_RESPONSE_ERR,
+    LOGIN_TYPES, LOGOUT, MEMBER, MEMBER_NAME_CHANGE, MESSAGE_EDIT, MESSAGE_TEXT, NAME,
+    POWER_LEVELS, PRESENCE, PUBLIC_ROOMS, REACTION, REDACTED, REDACTED_INVALID, REDACTED_STATE,
+    REDACTION, REGISTRATION_RESPONSE_ERR, ROOM_ID, ROOM_MESSAGES, SPACE_HIERARCHY, THREEPIDS,
+    TYPING, VERSIONS, WELL_KNOWN, WHOAMI,
 };
 pub use sync::{
-    DEFAULT_SYNC_SUMMARY, INVITE_SYNC, LEAVE_SYNC, LEAVE_SYNC_EVENT, MORE_SYNC, SYNC, VOIP_SYNC,
+    DEFAULT_SYNC_SUMMARY, INVITE_DETAILS_SYNC, INVITE_SYNC, LEAVE_SYNC, LEAVE_SYNC_EVENT,
+    MORE_SYNC, SPACE_SYNC, SYNC, UPGRADE_SYNC, VOIP_SYNC,
 };
 
 pub use members::MEMBERS;
@@ -757,6 +757,85 @@ lazy_static! {
     });
 }
 
+lazy_static! {
+    pub static ref INVITE_DETAILS_SYNC: JsonValue = json!({
+        "device_one_time_keys_count": {},
+        "next_batch": "s526_47314_0_7_1_1_1_11444_1",
+        "device_lists": {
+            "changed": [
+                "@example:example.org"
+            ],
+            "left": []
+        },
+        "rooms": {
+            "invite": {
+                "!696r7674:example.com": {
+                  "invite_state": {
+                    "events": [
+                      {
+                        "sender": "@alice:example.com",
+                        "type": "m.room.name",
+                        "state_key": "",
+                        "content": {
+                          "name": "My Room Name"
+                        }
+                      },
+                      {
+                        "sender": "@alice:example.com",
+                        "type": "m.room.member",
+                        "state_key": "@bob:example.com",
+                        "content": {
+                          "membership": "invite"
+                        }
+                      },
+                      {
+                        "sender": "@alice:example.com",
+                        "type": "m.room.member",
+                        "state_key": "@alice:example.com",
+                        "content": {
+                          "avatar_url": "mxc://example.com/SEsfnsuifSDFSSEF",
+                          "displayname": "Alice Margatroid",
+                          "membership": "join"
+                        }
+                      },
+                      {
+                        "sender": "@alice:example.com",
+                        "type": "m.room.member",
+                        "state_key": "@example:localhost",
+                        "content": {
+                          "is_direct": true,
+                          "membership": "invite",
+                          "reason": "Let's talk about the cupcakes"
+                        }
+                      }
+                    ]
+                  }
+                }
+              },
+            "join": {},
+            "leave": {}
+        },
+        "to_device": {
+            "events": []
+        },
+        "presence": {
+            "events": [
+                {
+                    "content": {
+                        "avatar_url": "mxc://localhost/wefuiwegh8742w",
+                        "currently_active": false,
+                        "last_active_ago": 1,
+                        "presence": "online",
+                        "status_msg": "Making cupcakes"
+                    },
+                    "sender": "@example:localhost",
+                    "type": "m.presence"
+                }
+            ]
+        }
+    });
+}
+
 lazy_static! {
     pub static ref LEAVE_SYNC: JsonValue = json!({
         "device_one_time_keys_count": {},
@@ -1223,3 +1302,257 @@ lazy_static! {
         }
     });
 }
+
+lazy_static! {
+    pub static ref SPACE_SYNC: JsonValue = json!({
+        "device_one_time_keys_count": {},
+        "next_batch": "s526_47314_0_7_1_1_1_11444_2",
+        "device_lists": {
+            "changed": [],
+            "left": []
+        },
+        "rooms": {
+            "invite": {},
+            "join": {
+                "!space:localhost": {
+                    "summary": {},
+                    "account_data": {
+                        "events": []
+                    },
+                    "ephemeral": {
+                        "events": []
+                    },
+                    "state": {
+                        "events": [
+                            {
+                                "content": {
+                                    "creator": "@example:localhost",
+                                    "room_version": "6",
+                                    "type": "m.space"
+                                },
+                                "event_id": "$151957878228ekrDs:localhost",
+                                "origin_server_ts": 15195787,
+                                "sender": "@example:localhost",
+                                "state_key": "",
+                                "type": "m.room.create",
+                                "unsigned": {
+                                    "age": 1392989
+                                }
+                            },
+                            {
+                                "content": {
+                                    "via": ["localhost"],
+                                    "order": "b"
+                                },
+                                "event_id": "$151957878228ssbCh:localhost",
+                                "origin_server_ts": 15195788,
+                                "sender": "@example:localhost",
+                                "state_key": "!first:localhost",
+                                "type": "m.space.child",
+                                "unsigned": {
+                                    "age": 1392988
+                                }
+                            },
+                            {
+                                "content": {
+                                    "via": ["localhost"],
+                                    "order": "a",
+                                    "suggested": true
+                                },
+                                "event_id": "$151957878228ssbCi:localhost",
+                                "origin_server_ts": 15195789,
+                                "sender": "@example:localhost",
+                                "state_key": "!second:localhost",
+                                "type": "org.matrix.msc1772.space.child",
+                                "unsigned": {
+                                    "age": 1392987
+                                }
+                            },
+                            {
+                                "content": {
+                                    "via": ["example.org"]
+                                },
+                                "event_id": "$151957878228ssbCj:localhost",
+                                "origin_server_ts": 15195790,
+                                "sender": "@example:localhost",
+                                "state_key": "!unknown:example.org",
+                                "type": "m.space.child",
+                                "unsigned": {
+                                    "age": 1392986
+                                }
+                            },
+                            {
+                                "content": {},
+                                "event_id": "$151957878228ssbCk:localhost",
+                                "origin_server_ts": 15195791,
+                                "sender": "@example:localhost",
+                                "state_key": "!removed:localhost",
+                                "type": "m.space.child",
+                                "unsigned": {
+                                    "age": 1392985
+                                }
+                            }
+                        ]
+                    },
+                    "timeline": {
+                        "events": [],
+                        "limited": false,
+                        "prev_batch": "t392-516_47314_0_7_1_1_1_11444_1"
+                    },
+                    "unread_notifications": {}
+                },
+                "!first:localhost": {
+                    "summary": {},
+                    "account_data": {
+                        "events": []
+                    },
+                    "ephemeral": {
+                        "events": []
+                    },
+                    "state": {
+                        "events": [
+                            {
+                                "content": {
+                                    "via": ["localhost"],
+                                    "canonical": true
+                                },
+                                "event_id": "$151957878228ssbPa:localhost",
+                                "origin_server_ts": 15195792,
+                                "sender": "@example:localhost",
+                                "state_key": "!space:localhost",
+                                "type": "m.space.parent",
+                                "unsigned": {
+                                    "age": 1392984
+                                }
+                            }
+                        ]
+                    },
+                    "timeline": {
+                        "events": [],
+                        "limited": false,
+                        "prev_batch": "t392-516_47314_0_7_1_1_1_11444_1"
+                    },
+                    "unread_notifications": {}
+                },
+                "!second:localhost": {
+                    "summary": {},
+                    "account_data": {
+                        "events": []
+                    },
+                    "ephemeral": {
+                        "events": []
+                    },
+                    "state": {
+                        "events": []
+                    },
+                    "timeline": {
+                        "events": [],
+                        "limited": false,
+                        "prev_batch": "t392-516_47314_0_7_1_1_1_11444_1"
+                    },
+                    "unread_notifications": {}
+                }
+            },
+            "leave": {}
+        },
+        "to_device": {
+            "events": []
+        },
+        "presence": {
+            "events": []
+        }
+    });
+}
+
+lazy_static! {
+    pub static ref UPGRADE_SYNC: JsonValue = json!({
+        "device_one_time_keys_count": {},
+        "next_batch": "s526_47314_0_7_1_1_1_11444_3",
+        "device_lists": {
+            "changed": [],
+            "left": []
+        },
+        "rooms": {
+            "invite": {},
+            "join": {
+                "!old:localhost": {
+                    "summary": {},
+                    "account_data": {
+                        "events": []
+                    },
+                    "ephemeral": {
+                        "events": []
+                    },
+                    "state": {
+                        "events": []
+                    },
+                    "timeline": {
+                        "events": [
+                            {
+                                "content": {
+                                    "body": "This room has been replaced",
+                                    "replacement_room": "!new:localhost"
+                                },
+                                "event_id": "$151957878228tmbSt:localhost",
+                                "origin_server_ts": 15195787,
+                                "sender": "@example:localhost",
+                                "state_key": "",
+                                "type": "m.room.tombstone",
+                                "unsigned": {
+                                    "age": 1392989
+                                }
+                            }
+                        ],
+                        "limited": false,
+                        "prev_batch": "t392-516_47314_0_7_1_1_1_11444_1"
+                    },
+                    "unread_notifications": {}
+                },
+                "!new:localhost": {
+                    "summary": {},
+                    "account_data": {
+                        "events": []
+                    },
+                    "ephemeral": {
+                        "events": []
+                    },
+                    "state": {
+                        "events": [
+                            {
+                                "content": {
+                                    "creator": "@example:localhost",
+                                    "room_version": "6",
+                                    "predecessor": {
+                                        "room_id": "!old:localhost",
+                                        "event_id": "$151957878228tmbSt:localhost"
+                                    }
+                                },
+                                "event_id": "$151957878228crtNw:localhost",
+                                "origin_server_ts": 15195788,
+                                "sender": "@example:localhost",
+                                "state_key": "",
+                                "type": "m.room.create",
+                                "unsigned": {
+                                    "age": 1392988
+                                }
+                            }
+                        ]
+                    },
+                    "timeline": {
+                        "events": [],
+                        "limited": false,
+                        "prev_batch": "t392-516_47314_0_7_1_1_1_11444_1"
+                    },
+                    "unread_notifications": {}
+                }
+            },
+            "leave": {}
+        },
+        "to_device": {
+            "events": []
+        },
+        "presence": {
+            "events": []
+        }
+    });
+}